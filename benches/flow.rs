@@ -0,0 +1,71 @@
+// Times parsing a large colored and linked input, and flowing all of it at a couple of widths.
+// Run with cargo bench.
+//
+// These are timed by hand rather than with criterion. ate has to build offline from the crates
+// it already depends on, and cargo resolves dev-dependencies for every build, so adding criterion
+// would stop cargo build --offline from working.
+// DocumentView::flow is private, and views flow lines as they come into view, so flowing is timed
+// by scrolling a new view through all of the text.
+// Drawing the flowed lines with render_lines happens in the binary, which benches can't reach, so
+// it's timed by the ignored time_render_lines test in src/ui.rs.
+
+use std::hint::black_box;
+use std::io::Cursor;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use ate_pager::{Document, DocumentView, ParseOptions, ViewOptions};
+
+const ITERATIONS: u32 = 10;
+
+// Lines like a compiler's, with a colored label, a linked path and some long ones to wrap
+fn input(lines: usize) -> String {
+    (0..lines)
+        .map(|i| {
+            let path = format!("src/module_{}.rs", i % 97);
+            let message = "expected one thing but found another ".repeat(i % 5 + 1);
+            format!(
+                "\x1b[1;31merror\x1b[0m: \x1b]8;;file://host/{path}\x1b\\{path}:{i}\x1b]8;;\x1b\\ {message}\n"
+            )
+        })
+        .collect()
+}
+
+fn time(name: &str, mut run: impl FnMut()) {
+    run();
+    let mut total = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let started = Instant::now();
+        run();
+        total += started.elapsed();
+    }
+    println!("{name}: {:?} per iteration", total / ITERATIONS);
+}
+
+fn main() {
+    let input = input(100_000);
+    let parse = || {
+        let input = Box::new(Cursor::new(input.as_bytes()));
+        Document::new(input, ParseOptions::default()).expect("reading from memory can't fail")
+    };
+    time("parse", || {
+        black_box(parse());
+    });
+
+    let doc = Rc::new(parse());
+    let options = ViewOptions::default();
+    for width in [80, 200] {
+        time(&format!("flow at {width} columns"), || {
+            // Only what comes into view is flowed, so scroll through all of it
+            let mut view = DocumentView::new(Rc::clone(&doc), width, 50, &options);
+            view.forward(usize::MAX);
+            black_box(view.top_byte());
+        });
+    }
+    time("reflow from 80 to 120 columns", || {
+        let mut view = DocumentView::new(Rc::clone(&doc), 80, 50, &options);
+        view.show_percent(50);
+        view.set_size(120, 50);
+        black_box(view.top_byte());
+    });
+}
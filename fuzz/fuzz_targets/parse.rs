@@ -0,0 +1,42 @@
+// Feeds arbitrary bytes to the parser a piece at a time, the way input arrives from a pipe.
+// Run with cargo fuzz run parse, from the repository.
+#![no_main]
+
+use std::rc::Rc;
+
+use ate_pager::{DocumentBuilder, DocumentView, ParseOptions, Sanitize, ViewOptions};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the parse options and where the input is split
+    let Some((&settings, input)) = data.split_first() else {
+        return;
+    };
+    let parse = ParseOptions {
+        sanitize: if settings & 1 == 0 {
+            Sanitize::Controls
+        } else {
+            Sanitize::Strict
+        },
+        collapse_overwrites: settings & 2 != 0,
+        detect_links: settings & 4 != 0,
+        ..Default::default()
+    };
+    let split = (settings as usize >> 3) % (input.len() + 1);
+    let mut builder = DocumentBuilder::new(parse);
+    builder.feed(&input[..split]);
+    let partial = Rc::new(builder.snapshot());
+    builder.feed(&input[split..]);
+    let doc = Rc::new(builder.finish());
+
+    // Every link range should be in the text, and flowing it shouldn't panic
+    for link in &doc.links {
+        assert!(link.start <= link.end && link.end <= doc.text.len());
+    }
+    let mut view = DocumentView::new(partial, 7, 3, &ViewOptions::default());
+    view.update(Rc::clone(&doc));
+    view.forward(usize::MAX);
+    for idx in 0..doc.links.len() {
+        view.highlight_spans(doc.link_spans(idx));
+    }
+});
@@ -0,0 +1,186 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+use crate::links::LinkFormat;
+
+pub const USAGE: &str =
+    "Usage: ate [--links[=uri|text|full]] [--follow] [--quit-if-one-screen] [-S] FILE...\n   \
+    or: ate [--tee FILE] [--links[=uri|text|full]] [--follow] [--quit-if-one-screen] [-S] [-] < input\n   \
+    or: ate --session NAME [--follow] [-S]";
+
+// Settings given on the command line for this run, as opposed to Options from the environment
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    // Where to copy everything read from standard input
+    pub tee: Option<PathBuf>,
+    // Print the input's links in this format instead of viewing it
+    pub links: Option<LinkFormat>,
+    // Start out keeping the end of the input on screen as it's read, like ATE_FOLLOW
+    pub follow: bool,
+    // Print input that fits on the screen instead of paging it, like ATE_QUIT_IF_ONE_SCREEN
+    pub quit_if_one_screen: bool,
+    // Start out cutting long lines off at the edge of the screen, like ATE_CHOP_LONG_LINES
+    pub chop_long_lines: bool,
+    // The saved session to pick up, with its files in place of any given here
+    pub session: Option<String>,
+    // The files to view in place of standard input.
+    // Empty when viewing standard input, including when it's asked for with -.
+    pub files: Vec<PathBuf>,
+}
+
+impl Args {
+    // Parses args without the program name
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut files = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--tee" => match args.next() {
+                    Some(path) => parsed.tee = Some(PathBuf::from(path)),
+                    None => bail!("--tee needs a file to write to\n{USAGE}"),
+                },
+                "--session" => match args.next() {
+                    Some(name) => parsed.session = Some(name),
+                    None => bail!("--session needs the name of a saved session\n{USAGE}"),
+                },
+                "--links" => parsed.links = Some(LinkFormat::default()),
+                "--follow" => parsed.follow = true,
+                "--quit-if-one-screen" => parsed.quit_if_one_screen = true,
+                "-S" | "--chop-long-lines" => parsed.chop_long_lines = true,
+                // Everything after -- is a file, even if it starts with -, but - is still
+                // standard input like it is for cat and less
+                "--" => files.extend(args.by_ref()),
+                _ if !arg.starts_with('-') || arg == "-" => files.push(arg),
+                _ => {
+                    if let Some(path) = arg.strip_prefix("--tee=") {
+                        parsed.tee = Some(PathBuf::from(path));
+                    } else if let Some(name) = arg.strip_prefix("--session=") {
+                        parsed.session = Some(name.to_string());
+                    } else if let Some(format) = arg.strip_prefix("--links=") {
+                        match LinkFormat::parse(format) {
+                            Some(format) => parsed.links = Some(format),
+                            None => bail!("Unknown link format {format}\n{USAGE}"),
+                        }
+                    } else {
+                        bail!("Unknown argument {arg}\n{USAGE}");
+                    }
+                }
+            }
+        }
+        let stdin = files.iter().any(|f| f == "-");
+        parsed.files = files
+            .into_iter()
+            .filter(|f| f != "-")
+            .map(PathBuf::from)
+            .collect();
+        // Standard input can only be read once, so it can't be one of several files to move
+        // between
+        if stdin && !parsed.files.is_empty() {
+            bail!("- can't be viewed along with files\n{USAGE}");
+        }
+        if parsed.tee.is_some() && !parsed.files.is_empty() {
+            bail!("--tee only copies standard input\n{USAGE}");
+        }
+        // A session brings its own files, so it takes the place of standard input
+        if parsed.session.is_some()
+            && (stdin || !parsed.files.is_empty() || parsed.tee.is_some() || parsed.links.is_some())
+        {
+            bail!("--session views the files saved in it\n{USAGE}");
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn parse_tee() {
+        assert_eq!(Args::default(), parse(&[]).unwrap());
+        let tee = Some(PathBuf::from("out.log"));
+        assert_eq!(tee, parse(&["--tee", "out.log"]).unwrap().tee);
+        assert_eq!(tee, parse(&["--tee=out.log"]).unwrap().tee);
+        assert!(parse(&["--tee"]).is_err());
+        assert!(parse(&["--tee", "out.log", "in.log"]).is_err());
+    }
+
+    #[test]
+    fn parse_files() {
+        let files = |files: &[&str]| files.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(
+            files(&["a.txt", "b.txt"]),
+            parse(&["a.txt", "--follow", "b.txt"]).unwrap().files
+        );
+        assert_eq!(
+            files(&["--follow", "-S"]),
+            parse(&["--", "--follow", "-S"]).unwrap().files
+        );
+        assert!(parse(&["--x", "a.txt"]).is_err());
+        assert!(parse(&["-x", "a.txt"]).is_err());
+
+        // - is standard input, which can be copied but not viewed with files
+        assert_eq!(Args::default(), parse(&["-"]).unwrap());
+        assert_eq!(Args::default(), parse(&["--", "-"]).unwrap());
+        let tee = Some(PathBuf::from("out.log"));
+        assert_eq!(tee, parse(&["--tee", "out.log", "-"]).unwrap().tee);
+        assert!(parse(&["a.txt", "-"]).is_err());
+    }
+
+    #[test]
+    fn parse_links() {
+        assert_eq!(None, parse(&[]).unwrap().links);
+        assert_eq!(Some(LinkFormat::Uri), parse(&["--links"]).unwrap().links);
+        assert_eq!(
+            Some(LinkFormat::Full),
+            parse(&["--links=full", "--tee", "out.log"]).unwrap().links
+        );
+        assert_eq!(
+            Some(LinkFormat::Text),
+            parse(&["--links=text"]).unwrap().links
+        );
+        assert!(parse(&["--links=json"]).is_err());
+    }
+
+    #[test]
+    fn parse_session() {
+        assert_eq!(None, parse(&[]).unwrap().session);
+        let work = Some("work".to_string());
+        assert_eq!(work, parse(&["--session", "work"]).unwrap().session);
+        assert_eq!(work, parse(&["--session=work", "-S"]).unwrap().session);
+        assert!(parse(&["--session"]).is_err());
+        assert!(parse(&["--session", "work", "a.txt"]).is_err());
+        assert!(parse(&["--session", "work", "-"]).is_err());
+        assert!(parse(&["--session", "work", "--tee", "out.log"]).is_err());
+        assert!(parse(&["--session", "work", "--links"]).is_err());
+    }
+
+    #[test]
+    fn parse_follow() {
+        assert!(!parse(&[]).unwrap().follow);
+        assert!(parse(&["--tee", "out.log", "--follow"]).unwrap().follow);
+    }
+
+    #[test]
+    fn parse_quit_if_one_screen() {
+        assert!(!parse(&[]).unwrap().quit_if_one_screen);
+        assert!(parse(&["--quit-if-one-screen"]).unwrap().quit_if_one_screen);
+    }
+
+    #[test]
+    fn parse_chop_long_lines() {
+        assert!(!parse(&[]).unwrap().chop_long_lines);
+        assert!(parse(&["-S"]).unwrap().chop_long_lines);
+        assert!(
+            parse(&["--chop-long-lines", "a.txt"])
+                .unwrap()
+                .chop_long_lines
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::hash::Hasher;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use fnv::FnvHasher;
+use log::warn;
+
+// The bookmark recording where the document was left when ate last quit.
+// It can't be set with m since it isn't a letter.
+pub const LAST_POSITION: char = '"';
+
+// Bookmarks for documents beyond this many are dropped, oldest first
+pub const MAX_DOCUMENTS: usize = 1000;
+
+// Named positions in a document that are kept across runs.
+// Files are identified by their canonical path, so their bookmarks survive them growing or being
+// edited. Piped input has no name, so it's identified by a hash of its text.
+// Positions are byte offsets into the text so they survive reflowing to a new width.
+pub struct Bookmarks {
+    // Where bookmarks for every document are stored, or None to only keep them in memory
+    path: Option<PathBuf>,
+    doc_key: String,
+    marks: BTreeMap<char, usize>,
+}
+
+impl Bookmarks {
+    // Loads the bookmarks for text, which was read from file if it isn't piped input
+    pub fn load(path: Option<PathBuf>, file: Option<&Path>, text: &str) -> Self {
+        let doc_key = doc_key(file, text);
+        let mut marks = BTreeMap::new();
+        if let Some(path) = &path {
+            match read_entries(path) {
+                Ok(entries) => marks.extend(
+                    entries
+                        .into_iter()
+                        .filter(|(key, _, byte)| *key == doc_key && *byte <= text.len())
+                        .map(|(_, name, byte)| (name, byte)),
+                ),
+                Err(e) => warn!("Unable to read bookmarks from {:?}: {}", path, e),
+            }
+        }
+        Self {
+            path,
+            doc_key,
+            marks,
+        }
+    }
+
+    pub fn get(&self, name: char) -> Option<usize> {
+        self.marks.get(&name).copied()
+    }
+
+    // Records byte as name and saves it for the next time this document is opened
+    pub fn set(&mut self, name: char, byte: usize) -> Result<()> {
+        self.marks.insert(name, byte);
+        self.save()
+    }
+
+    // Adds the bookmarks in earlier, which were set before this document was loaded, and saves
+    // them with the rest.
+    // They replace loaded ones with the same name since they were set more recently.
+    pub fn merge(&mut self, earlier: Bookmarks) -> Result<()> {
+        if earlier.marks.is_empty() {
+            return Ok(());
+        }
+        self.marks.extend(earlier.marks);
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // Other documents' bookmarks may have changed since we loaded, so merge into the file
+        let mut entries: Vec<_> = read_entries(path)?
+            .into_iter()
+            .filter(|(key, _, _)| *key != self.doc_key)
+            .collect();
+        // Saved documents are kept in the order they were last saved
+        let mut keep = HashSet::new();
+        for (key, _, _) in entries.iter().rev() {
+            if keep.len() == MAX_DOCUMENTS - 1 {
+                break;
+            }
+            keep.insert(key.clone());
+        }
+        entries.retain(|(key, _, _)| keep.contains(key));
+        entries.extend(
+            self.marks
+                .iter()
+                .map(|(&name, &byte)| (self.doc_key.clone(), name, byte)),
+        );
+        let contents: String = entries
+            .iter()
+            .map(|(key, name, byte)| format!("{key} {name} {byte}\n"))
+            .collect();
+        if let Err(e) = fs::write(path, contents) {
+            bail!("Unable to save bookmarks to {:?}: {}", path, e);
+        }
+        Ok(())
+    }
+}
+
+fn doc_key(file: Option<&Path>, text: &str) -> String {
+    if let Some(file) = file {
+        match fs::canonicalize(file) {
+            // Keys are read back a line at a time
+            Ok(path) if !path.to_string_lossy().contains('\n') => {
+                return path.to_string_lossy().into_owned();
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Unable to find where {:?} is to key its bookmarks: {}",
+                file, e
+            ),
+        }
+    }
+    // Canonical paths start with / so these can't be mistaken for one
+    let mut hasher = FnvHasher::default();
+    hasher.write(text.as_bytes());
+    format!("{:016x}{:x}", hasher.finish(), text.len())
+}
+
+// Each line is a document key, a bookmark name, and a byte offset separated by spaces.
+// Keys that are paths may have spaces in them too.
+fn read_entries(path: &PathBuf) -> Result<Vec<(String, char, usize)>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => bail!("{}", e),
+    };
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.rsplitn(3, ' ');
+            let byte = fields.next()?.parse().ok()?;
+            let mut name = fields.next()?.chars();
+            let key = fields.next()?.to_string();
+            match (name.next(), name.next()) {
+                (Some(name), None) => Some((key, name, byte)),
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persist_per_document() {
+        let path = std::env::temp_dir().join(format!("ate-bookmarks-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut first = Bookmarks::load(Some(path.clone()), None, "first document");
+        first.set('a', 6).unwrap();
+        let mut second = Bookmarks::load(Some(path.clone()), None, "second document");
+        assert_eq!(None, second.get('a'));
+        second.set('a', 1).unwrap();
+        second.set('b', 2).unwrap();
+
+        let first = Bookmarks::load(Some(path.clone()), None, "first document");
+        assert_eq!(Some(6), first.get('a'));
+        assert_eq!(None, first.get('b'));
+        let second = Bookmarks::load(Some(path.clone()), None, "second document");
+        assert_eq!(Some(1), second.get('a'));
+        assert_eq!(Some(2), second.get('b'));
+
+        // Bookmarks past the end of the text can't be from this document
+        fs::write(
+            &path,
+            format!("{} c 100\nmalformed\n", doc_key(None, "short")),
+        )
+        .unwrap();
+        assert_eq!(
+            None,
+            Bookmarks::load(Some(path.clone()), None, "short").get('c')
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn persist_per_file() {
+        let dir = std::env::temp_dir().join(format!("ate bookmarked {}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bookmarks");
+        let _ = fs::remove_file(&path);
+        let log = dir.join("a.log");
+        fs::write(&log, "one\n").unwrap();
+
+        Bookmarks::load(Some(path.clone()), Some(&log), "one\n")
+            .set('a', 2)
+            .unwrap();
+        // The same file is found by any path to it, however its text has changed
+        let relative = dir.join(".").join("a.log");
+        let grown = Bookmarks::load(Some(path.clone()), Some(&relative), "one\ntwo\n");
+        assert_eq!(Some(2), grown.get('a'));
+        // Piped input with the same text isn't the file
+        assert_eq!(
+            None,
+            Bookmarks::load(Some(path.clone()), None, "one\n").get('a')
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drop_oldest_documents() {
+        let path = std::env::temp_dir().join(format!("ate-old-bookmarks-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        for i in 0..=MAX_DOCUMENTS {
+            Bookmarks::load(Some(path.clone()), None, &format!("document {i}"))
+                .set(LAST_POSITION, 1)
+                .unwrap();
+        }
+        let load = |i| Bookmarks::load(Some(path.clone()), None, &format!("document {i}"));
+        assert_eq!(None, load(0).get(LAST_POSITION));
+        assert_eq!(Some(1), load(1).get(LAST_POSITION));
+        assert_eq!(Some(1), load(MAX_DOCUMENTS).get(LAST_POSITION));
+        fs::remove_file(&path).unwrap();
+    }
+}
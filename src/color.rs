@@ -0,0 +1,195 @@
+use termwiz::caps::ColorLevel;
+use termwiz::cell::{AttributeChange, CellAttributes};
+use termwiz::color::{ColorAttribute, PaletteIndex, SrgbaTuple};
+use termwiz::surface::Change;
+
+// termwiz draws true colors as the default color on terminals without true color, and passes
+// 256 color indices through to terminals that only have 16.
+// Pick the closest color the terminal can show instead.
+pub fn downgrade_change(change: Change, level: ColorLevel) -> Change {
+    if level == ColorLevel::TrueColor {
+        return change;
+    }
+    match change {
+        Change::Attribute(AttributeChange::Foreground(c)) => {
+            Change::Attribute(AttributeChange::Foreground(downgrade(c, level)))
+        }
+        Change::Attribute(AttributeChange::Background(c)) => {
+            Change::Attribute(AttributeChange::Background(downgrade(c, level)))
+        }
+        Change::AllAttributes(attrs) => Change::AllAttributes(downgrade_attributes(attrs, level)),
+        change => change,
+    }
+}
+
+fn downgrade_attributes(mut attrs: CellAttributes, level: ColorLevel) -> CellAttributes {
+    attrs.set_foreground(downgrade(attrs.foreground(), level));
+    attrs.set_background(downgrade(attrs.background(), level));
+    attrs.set_underline_color(downgrade(attrs.underline_color(), level));
+    attrs
+}
+
+fn downgrade(color: ColorAttribute, level: ColorLevel) -> ColorAttribute {
+    match (level, color) {
+        (ColorLevel::TrueColor, color) => color,
+        (
+            ColorLevel::TwoFiftySix,
+            ColorAttribute::TrueColorWithPaletteFallback(rgb, _)
+            | ColorAttribute::TrueColorWithDefaultFallback(rgb),
+        ) => ColorAttribute::TrueColorWithPaletteFallback(rgb, nearest_256(to_rgb(rgb))),
+        (
+            ColorLevel::Sixteen,
+            ColorAttribute::TrueColorWithPaletteFallback(rgb, _)
+            | ColorAttribute::TrueColorWithDefaultFallback(rgb),
+        ) => ColorAttribute::TrueColorWithPaletteFallback(rgb, nearest_16(to_rgb(rgb))),
+        (ColorLevel::Sixteen, ColorAttribute::PaletteIndex(idx)) if idx >= 16 => {
+            ColorAttribute::PaletteIndex(nearest_16(palette_rgb(idx)))
+        }
+        (_, color) => color,
+    }
+}
+
+type Rgb = (u8, u8, u8);
+
+fn to_rgb(color: SrgbaTuple) -> Rgb {
+    let (r, g, b, _) = color.to_srgb_u8();
+    (r, g, b)
+}
+
+// xterm's default colors for the first 16 palette entries
+const ANSI_RGB: [Rgb; 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+// The levels of each component in the 6x6x6 color cube at indices 16 to 231
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn palette_rgb(idx: PaletteIndex) -> Rgb {
+    match idx {
+        0..=15 => ANSI_RGB[idx as usize],
+        16..=231 => {
+            let cube = idx - 16;
+            (
+                CUBE_LEVELS[(cube / 36) as usize],
+                CUBE_LEVELS[(cube / 6 % 6) as usize],
+                CUBE_LEVELS[(cube % 6) as usize],
+            )
+        }
+        // 24 greys from 8 to 238
+        232..=255 => {
+            let grey = 8 + 10 * (idx - 232);
+            (grey, grey, grey)
+        }
+    }
+}
+
+fn distance(a: Rgb, b: Rgb) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+fn nearest_16(rgb: Rgb) -> PaletteIndex {
+    (0..16)
+        .min_by_key(|&i| distance(rgb, ANSI_RGB[i as usize]))
+        .unwrap()
+}
+
+// Only considers the cube and greys because terminals often change the first 16 with themes
+fn nearest_256(rgb: Rgb) -> PaletteIndex {
+    let nearest_level = |c: u8| {
+        (0..6)
+            .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - c as i32).abs())
+            .unwrap() as u8
+    };
+    let cube = 16 + 36 * nearest_level(rgb.0) + 6 * nearest_level(rgb.1) + nearest_level(rgb.2);
+    let average = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let grey = 232 + (average.saturating_sub(3) / 10).min(23) as u8;
+    if distance(rgb, palette_rgb(grey)) < distance(rgb, palette_rgb(cube)) {
+        grey
+    } else {
+        cube
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use termwiz::color::AnsiColor;
+
+    fn rgb(r: u8, g: u8, b: u8) -> ColorAttribute {
+        ColorAttribute::TrueColorWithDefaultFallback(SrgbaTuple::from((r, g, b)))
+    }
+
+    fn fallback(color: ColorAttribute) -> PaletteIndex {
+        match color {
+            ColorAttribute::TrueColorWithPaletteFallback(_, idx)
+            | ColorAttribute::PaletteIndex(idx) => idx,
+            c => panic!("No palette color in {:?}", c),
+        }
+    }
+
+    #[test]
+    fn downgrade_true_color() {
+        let orange = rgb(255, 135, 0);
+        assert_eq!(orange, downgrade(orange, ColorLevel::TrueColor));
+        assert_eq!(208, fallback(downgrade(orange, ColorLevel::TwoFiftySix)));
+        assert_eq!(
+            AnsiColor::Olive as u8,
+            fallback(downgrade(orange, ColorLevel::Sixteen))
+        );
+        assert_eq!(
+            244,
+            fallback(downgrade(rgb(128, 129, 130), ColorLevel::TwoFiftySix))
+        );
+    }
+
+    #[test]
+    fn downgrade_palette() {
+        let navy = ColorAttribute::PaletteIndex(17);
+        assert_eq!(navy, downgrade(navy, ColorLevel::TwoFiftySix));
+        assert_eq!(
+            AnsiColor::Black as u8,
+            fallback(downgrade(navy, ColorLevel::Sixteen))
+        );
+        assert_eq!(
+            AnsiColor::Navy as u8,
+            fallback(downgrade(
+                ColorAttribute::PaletteIndex(21),
+                ColorLevel::Sixteen
+            ))
+        );
+        let grey = ColorAttribute::PaletteIndex(AnsiColor::Grey as u8);
+        assert_eq!(grey, downgrade(grey, ColorLevel::Sixteen));
+    }
+
+    #[test]
+    fn downgrade_all_attributes() {
+        let mut attrs = CellAttributes::default();
+        attrs
+            .set_foreground(rgb(0, 0, 255))
+            .set_background(rgb(255, 255, 255));
+        let Change::AllAttributes(attrs) =
+            downgrade_change(Change::AllAttributes(attrs), ColorLevel::Sixteen)
+        else {
+            panic!("Changed kind of change");
+        };
+        assert_eq!(AnsiColor::Navy as u8, fallback(attrs.foreground()));
+        assert_eq!(AnsiColor::White as u8, fallback(attrs.background()));
+    }
+}
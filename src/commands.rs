@@ -0,0 +1,551 @@
+use std::cmp::max;
+
+use crate::keymap::Binding;
+use crate::keys::{KeyBindings, CHORD_KEYS};
+use crate::state::{Action, Find, FindPrompt, Overlay, State};
+
+// Everything that can be done by name, either from a key or from the command palette.
+// Commands that need something more, like the letter naming a bookmark, only have keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Search,
+    FindText,
+    FilterLines,
+    NextLink,
+    PrevLink,
+    OpenLink,
+    RepeatLastAction,
+    CopyLine,
+    CopyLinkAddress,
+    CopyLinkText,
+    Save,
+    SaveSession,
+    LineDown,
+    LineUp,
+    PageDown,
+    PageUp,
+    HalfPageDown,
+    HalfPageUp,
+    Top,
+    Bottom,
+    JumpBack,
+    NextFile,
+    PrevFile,
+    Follow,
+    SelectionToTop,
+    ScrollLeft,
+    ScrollRight,
+    ToggleWrap,
+    ToggleScrollbar,
+    ToggleBidi,
+    ToggleStats,
+    ToggleDiagnostics,
+    ToggleMemory,
+    ToggleTimings,
+    StopRecording,
+    OpenPalette,
+    ShowHelp,
+    Suspend,
+    Quit,
+}
+
+// Stepping through links or matches with a count stops after this many, since each step can look
+// through every link
+const MAX_STEPS: usize = 10_000;
+
+// Every command with its name and the keys bound to it by default, in the order the palette lists
+// them
+pub const COMMANDS: &[(Command, &str, &[&str])] = &[
+    (Command::Search, "Search links", &["/"]),
+    (Command::FindText, "Search text", &["?"]),
+    (Command::FilterLines, "Filter lines", &["&"]),
+    (Command::NextLink, "Next link", &["n"]),
+    (Command::PrevLink, "Previous link", &["N"]),
+    (Command::OpenLink, "Open selected link", &["Enter"]),
+    (Command::RepeatLastAction, "Repeat last link action", &["."]),
+    (Command::CopyLine, "Copy line", &["Y"]),
+    (
+        Command::CopyLinkAddress,
+        "Copy selected link address",
+        &["y"],
+    ),
+    (Command::CopyLinkText, "Copy selected link text", &["Alt-y"]),
+    (Command::Save, "Save to a file", &["s"]),
+    (Command::SaveSession, "Save session", &["W"]),
+    (Command::LineDown, "Scroll down a line", &["Down", "j"]),
+    (Command::LineUp, "Scroll up a line", &["Up", "k"]),
+    (Command::PageDown, "Page down", &["Space", "Ctrl-F"]),
+    (Command::PageUp, "Page up", &["b", "Ctrl-B"]),
+    (Command::HalfPageDown, "Half a page down", &["d"]),
+    (Command::HalfPageUp, "Half a page up", &["u"]),
+    (Command::Top, "Go to top", &["gg"]),
+    (Command::Bottom, "Go to bottom", &["G"]),
+    (
+        Command::JumpBack,
+        "Go back to before the last jump",
+        &["''"],
+    ),
+    (Command::NextFile, "Next file", &[":n"]),
+    (Command::PrevFile, "Previous file", &[":p"]),
+    (Command::Follow, "Follow the end of the input", &["F"]),
+    (
+        Command::SelectionToTop,
+        "Scroll selected link to top",
+        &["zt"],
+    ),
+    (Command::ScrollLeft, "Scroll left", &["Left"]),
+    (Command::ScrollRight, "Scroll right", &["Right"]),
+    (Command::ToggleWrap, "Toggle wrapping long lines", &["S"]),
+    (Command::ToggleScrollbar, "Toggle scrollbar", &["|"]),
+    (
+        Command::ToggleBidi,
+        "Toggle right-to-left reordering",
+        &["Ctrl-T"],
+    ),
+    (Command::ToggleStats, "Toggle input stats", &["F9"]),
+    (Command::ToggleDiagnostics, "Toggle diagnostics", &["F10"]),
+    (Command::ToggleMemory, "Toggle memory usage", &["F11"]),
+    (Command::ToggleTimings, "Toggle timings", &["F12"]),
+    (Command::StopRecording, "Stop recording keys", &["Q"]),
+    (Command::OpenPalette, "Open command palette", &["Ctrl-P"]),
+    (Command::ShowHelp, "Show keys", &["h"]),
+    (Command::Suspend, "Suspend to the shell", &["Ctrl-Z"]),
+    (Command::Quit, "Quit", &["q"]),
+];
+
+// What keys do in the prompts for searching, filtering, saving, and picking a command.
+// Characters typed that aren't one of these keys are added to the prompt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PromptCommand {
+    Accept,
+    Cancel,
+    DeleteBack,
+    SelectPrev,
+    SelectNext,
+    RecallOlder,
+    RecallNewer,
+    ToggleRegex,
+    Paste,
+}
+
+// Every prompt command with what it does and the keys bound to it by default, in the order the
+// help screen lists them
+pub const PROMPT_COMMANDS: &[(PromptCommand, &str, &[&str])] = &[
+    (PromptCommand::Accept, "Finish a prompt", &["Enter"]),
+    (PromptCommand::Cancel, "Close a prompt", &["Esc"]),
+    (
+        PromptCommand::DeleteBack,
+        "Delete the last character typed",
+        &["Backspace"],
+    ),
+    (
+        PromptCommand::SelectPrev,
+        "Select the previous match or command",
+        &["Up"],
+    ),
+    (
+        PromptCommand::SelectNext,
+        "Select the next match or command",
+        &["Down"],
+    ),
+    (
+        PromptCommand::RecallOlder,
+        "Recall an earlier search",
+        &["Ctrl-P"],
+    ),
+    (
+        PromptCommand::RecallNewer,
+        "Recall a later search",
+        &["Ctrl-N"],
+    ),
+    (
+        PromptCommand::ToggleRegex,
+        "Switch prompts between text and regex",
+        &["Ctrl-R"],
+    ),
+    (
+        PromptCommand::Paste,
+        "Paste into the link search",
+        &["Ctrl-V"],
+    ),
+];
+
+// A command's variant name as it's written in the config file, like page_down for PageDown
+fn snake_case(name: &str) -> String {
+    let mut id = String::new();
+    for c in name.chars() {
+        if c.is_ascii_uppercase() && !id.is_empty() {
+            id.push('_');
+        }
+        id.push(c.to_ascii_lowercase());
+    }
+    id
+}
+
+impl Command {
+    pub fn run(self, state: &mut State) {
+        self.run_times(state, 1);
+    }
+
+    // Runs the command as if its key were pressed count times, for a number typed before the key.
+    // Moving goes count times as far, and commands that don't move run once.
+    pub fn run_times(self, state: &mut State, count: usize) {
+        let count = count.max(1);
+        let page = max(state.view.height().saturating_sub(2), 1);
+        let half_page = max(state.view.height() / 2, 1);
+        // Scrolling sideways goes half the screen at a time, like less
+        let half_width = max(state.view.width() / 2, 1);
+        // Files are counted from the current one, and a count can't go past one that's there
+        let files = isize::try_from(count).unwrap_or(isize::MAX);
+        match self {
+            Command::Search => state.open_search(),
+            Command::FindText => state.find_prompt = Some(FindPrompt::new(Find::Text)),
+            Command::FilterLines => state.find_prompt = Some(FindPrompt::new(Find::Lines)),
+            Command::NextLink | Command::PrevLink => {
+                let action = match self {
+                    Command::NextLink => Action::NextLink,
+                    _ => Action::PrevLink,
+                };
+                for _ in 0..count.min(MAX_STEPS) {
+                    state.perform(action);
+                }
+            }
+            Command::OpenLink => state.perform(Action::Open),
+            Command::RepeatLastAction => state.repeat_last_action(),
+            Command::CopyLine => state.copy_line(),
+            Command::CopyLinkAddress => state.copy_link_address(),
+            Command::CopyLinkText => state.copy_link_text(),
+            Command::Save => {
+                state.find_prompt = Some(FindPrompt::new(Find::Save { escapes: false }))
+            }
+            Command::SaveSession => state.find_prompt = Some(FindPrompt::new(Find::Session)),
+            Command::LineDown => state.view.forward(count),
+            Command::LineUp => state.view.backward(count),
+            Command::PageDown => state.view.forward(page.saturating_mul(count)),
+            Command::PageUp => state.view.backward(page.saturating_mul(count)),
+            Command::HalfPageDown => state.view.forward(half_page.saturating_mul(count)),
+            Command::HalfPageUp => state.view.backward(half_page.saturating_mul(count)),
+            Command::Top => state.view.show_byte(0),
+            Command::Bottom => state.view.show_end(),
+            Command::JumpBack => {
+                if !state.view.jump_back() {
+                    state.last_error = Some("No jump to go back from".to_string());
+                }
+            }
+            Command::NextFile => {
+                if !state.files.request(files) {
+                    state.last_error = Some("No next file".to_string());
+                }
+            }
+            Command::PrevFile => {
+                if !state.files.request(-files) {
+                    state.last_error = Some("No previous file".to_string());
+                }
+            }
+            Command::Follow => state.view.follow(),
+            Command::SelectionToTop => {
+                if let Some(&(start, _)) = state.view.highlights().first() {
+                    state.view.show_byte(start);
+                }
+            }
+            Command::ScrollLeft | Command::ScrollRight if state.view.wraps() => {
+                state.last_error = Some(format!(
+                    "Long lines wrap, so there's nothing to the side; {} stops wrapping",
+                    state.key_bindings.keys(Command::ToggleWrap)
+                ));
+            }
+            Command::ScrollLeft => state.view.scroll_left(half_width.saturating_mul(count)),
+            Command::ScrollRight => state.view.scroll_right(half_width.saturating_mul(count)),
+            Command::ToggleWrap => state.view.toggle_wrap(),
+            Command::ToggleScrollbar => state.scrollbar = !state.scrollbar,
+            Command::ToggleBidi => state.view.toggle_bidi(),
+            Command::ToggleStats => state.toggle_overlay(Overlay::Stats),
+            Command::ToggleDiagnostics => state.toggle_overlay(Overlay::Diagnostics),
+            Command::ToggleMemory => state.toggle_overlay(Overlay::Memory),
+            Command::ToggleTimings => state.toggle_overlay(Overlay::Timings),
+            Command::OpenPalette => state.palette = Some(Palette::new()),
+            Command::StopRecording => {
+                if state.macros.recording().is_some() {
+                    state.macros.stop_recording();
+                } else {
+                    state.last_error = Some("Not recording keys".to_string());
+                }
+            }
+            Command::ShowHelp => state.open_help(),
+            Command::Suspend => state.shared.borrow_mut().suspend = true,
+            Command::Quit => state.shared.borrow_mut().quit = true,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        COMMANDS.iter().find(|(c, _, _)| *c == self).unwrap().1
+    }
+
+    // The name for the command in the [keys] table of the config file, like page_down for
+    // PageDown
+    pub fn id(self) -> String {
+        snake_case(&format!("{self:?}"))
+    }
+
+    // Whether the command moves around the document or changes how it's shown, so its keys are only
+    // handled while the document has focus rather than also when prompts don't handle them
+    pub fn in_document(self) -> bool {
+        matches!(
+            self,
+            Command::LineUp
+                | Command::LineDown
+                | Command::PageDown
+                | Command::PageUp
+                | Command::HalfPageDown
+                | Command::HalfPageUp
+                | Command::Bottom
+                | Command::Follow
+                | Command::ScrollLeft
+                | Command::ScrollRight
+                | Command::ToggleWrap
+                | Command::ToggleScrollbar
+                | Command::ToggleBidi
+        )
+    }
+}
+
+impl PromptCommand {
+    // The name for the command in the [keys] table of the config file, like prompt_paste for
+    // Paste
+    pub fn id(self) -> String {
+        format!("prompt_{}", snake_case(&format!("{self:?}")))
+    }
+}
+
+// Commands whose names match what's been typed into the command palette
+pub struct Palette {
+    query: String,
+    matches: Vec<Command>,
+    selected_idx: usize,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        let mut palette = Self {
+            query: String::new(),
+            matches: vec![],
+            selected_idx: 0,
+        };
+        palette.update_matches();
+        palette
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn matches(&self) -> &[Command] {
+        &self.matches
+    }
+
+    pub fn selected_idx(&self) -> usize {
+        self.selected_idx
+    }
+
+    pub fn selected(&self) -> Option<Command> {
+        self.matches.get(self.selected_idx).copied()
+    }
+
+    pub fn push_query_char(&mut self, c: char) {
+        self.query.push(c);
+        self.update_matches();
+    }
+
+    pub fn pop_query_char(&mut self) {
+        self.query.pop();
+        self.update_matches();
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected_idx = if self.selected_idx + 1 < self.matches.len() {
+            self.selected_idx + 1
+        } else {
+            0
+        };
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected_idx = match self.selected_idx {
+            0 => self.matches.len().saturating_sub(1),
+            idx => idx - 1,
+        };
+    }
+
+    // Keeps the commands with the query's characters in their name in order, ignoring case.
+    // Names containing the query as is come first.
+    fn update_matches(&mut self) {
+        let query = self.query.to_lowercase();
+        self.matches = COMMANDS
+            .iter()
+            .filter(|(_, name, _)| is_subsequence(&query, &name.to_lowercase()))
+            .map(|(command, _, _)| *command)
+            .collect();
+        self.matches
+            .sort_by_key(|command| !command.name().to_lowercase().contains(&query));
+        self.selected_idx = 0;
+    }
+}
+
+// Keys that can't be given to other commands, for the help screen
+const FIXED_KEYS: &[(&str, &str)] = &[("Ctrl-C", "Quit from anywhere")];
+
+// Every key and what it does, in the order the help screen lists them
+pub struct Help {
+    rows: Vec<(String, String)>,
+    // The first row on screen, for scrolling through more than fits
+    first: usize,
+}
+
+impl Help {
+    // Keys bound for the kind of text being viewed come first, and the commands whose keys they
+    // take over are left out
+    pub fn new(keys: &KeyBindings, bindings: &[Binding]) -> Self {
+        let mut rows: Vec<(String, String)> = bindings
+            .iter()
+            .map(|b| (b.key.to_string(), b.description()))
+            .collect();
+        let commands = COMMANDS
+            .iter()
+            .map(|(command, name, _)| (keys.keys(*command), name.to_string()))
+            .filter(|(keys, _)| {
+                !keys.is_empty() && !bindings.iter().any(|b| *keys == b.key.to_string())
+            });
+        rows.extend(commands);
+        rows.extend(
+            CHORD_KEYS
+                .iter()
+                .map(|(keys, name)| (keys.to_string(), name.to_string())),
+        );
+        rows.extend(
+            PROMPT_COMMANDS
+                .iter()
+                .map(|(command, name, _)| (keys.prompt_keys(*command), name.to_string()))
+                .filter(|(keys, _)| !keys.is_empty()),
+        );
+        rows.extend(
+            FIXED_KEYS
+                .iter()
+                .map(|(keys, name)| (keys.to_string(), name.to_string())),
+        );
+        Self { rows, first: 0 }
+    }
+
+    pub fn rows(&self) -> &[(String, String)] {
+        &self.rows
+    }
+
+    pub fn first(&self) -> usize {
+        self.first
+    }
+
+    // Scrolls down without leaving any of the height rows shown empty
+    pub fn scroll_down(&mut self, rows: usize, height: usize) {
+        self.first = (self.first + rows).min(self.rows.len().saturating_sub(height));
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.first = self.first.saturating_sub(rows);
+    }
+}
+
+fn is_subsequence(query: &str, name: &str) -> bool {
+    let mut name = name.chars();
+    query.chars().all(|q| name.any(|c| c == q))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keymap::parse_bindings;
+
+    fn names(palette: &Palette) -> Vec<&str> {
+        palette.matches().iter().map(|c| c.name()).collect()
+    }
+
+    #[test]
+    fn filter_palette() {
+        let mut palette = Palette::new();
+        assert_eq!(COMMANDS.len(), palette.matches().len());
+        for c in "tgl".chars() {
+            palette.push_query_char(c);
+        }
+        assert_eq!(
+            vec![
+                "Toggle wrapping long lines",
+                "Toggle scrollbar",
+                "Toggle right-to-left reordering",
+                "Toggle input stats",
+                "Toggle diagnostics",
+                "Toggle memory usage",
+                "Toggle timings",
+            ],
+            names(&palette)
+        );
+
+        // Exact substrings rank ahead of scattered matches
+        let mut palette = Palette::new();
+        for c in "Link".chars() {
+            palette.push_query_char(c);
+        }
+        assert_eq!(
+            vec![
+                "Search links",
+                "Next link",
+                "Previous link",
+                "Open selected link",
+                "Repeat last link action",
+                "Copy selected link address",
+                "Copy selected link text",
+                "Scroll selected link to top",
+            ],
+            names(&palette)
+        );
+        palette.select_prev();
+        assert_eq!(Some(Command::SelectionToTop), palette.selected());
+        palette.select_next();
+        assert_eq!(Some(Command::Search), palette.selected());
+
+        palette.push_query_char('z');
+        assert!(palette.matches().is_empty());
+        assert_eq!(None, palette.selected());
+        palette.pop_query_char();
+        assert_eq!(8, palette.matches().len());
+    }
+
+    #[test]
+    fn list_keys_for_help() {
+        let keys = |help: &Help| -> Vec<String> {
+            help.rows().iter().map(|(keys, _)| keys.clone()).collect()
+        };
+        let help = Help::new(&KeyBindings::default(), &[]);
+        assert_eq!(
+            COMMANDS.len() + CHORD_KEYS.len() + PROMPT_COMMANDS.len() + FIXED_KEYS.len(),
+            help.rows().len()
+        );
+        assert_eq!(Some(&"n".to_string()), keys(&help).get(3));
+
+        // Bindings for the kind of text come first and hide the commands they take over
+        let bindings = parse_bindings("n=open {file}:{line}").unwrap();
+        let help = Help::new(&KeyBindings::default(), &bindings);
+        assert_eq!(
+            ("n".to_string(), "Open {file}:{line}".to_string()),
+            help.rows()[0]
+        );
+        assert_eq!(1, keys(&help).iter().filter(|k| *k == "n").count());
+        assert_eq!(
+            Help::new(&KeyBindings::default(), &[]).rows().len(),
+            help.rows().len()
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::env::{self, VarError};
+use std::fs;
+use std::sync::OnceLock;
+
+use log::{info, warn};
+
+use termwiz::caps::ColorLevel;
+
+use crate::doc::{ParseOptions, Sanitize};
+use crate::options::ViewOptions;
+use crate::style::{LinkStyle, LinkStyles};
+
+// Settings from $XDG_CONFIG_HOME/ate/config.toml.
+// Top-level settings stand in for the environment variables of the same name in capitals after
+// ATE_, like follow = true for ATE_FOLLOW, and the [keys] table gives commands keys in place of
+// their defaults.
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    // Values by the environment variable they stand in for.
+    // Settings set to false are left out, like unset variables.
+    vars: HashMap<String, String>,
+    // Commands by their name in the [keys] table and the keys given to them, in the file's order
+    keys: Vec<(String, Vec<String>)>,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// Reads the config file if there is one, keeping whatever parses in a file with mistakes
+pub fn load() {
+    let path = match xdg::BaseDirectories::with_prefix("ate") {
+        Ok(dirs) => dirs.find_config_file("config.toml"),
+        Err(e) => {
+            warn!("Not reading a config file: {}", e);
+            None
+        }
+    };
+    let config = match path.map(|p| (fs::read_to_string(&p), p)) {
+        Some((Ok(text), path)) => {
+            info!("Reading {}", path.display());
+            let (config, errors) = Config::parse(&text);
+            for e in errors {
+                warn!("Ignoring {}: {}", path.display(), e);
+            }
+            config
+        }
+        Some((Err(e), path)) => {
+            warn!("Unable to read {}: {}", path.display(), e);
+            Config::default()
+        }
+        None => Config::default(),
+    };
+    // Only set once, from main
+    let _ = CONFIG.set(config);
+}
+
+// The environment variable name, or the config file's setting for it if it isn't set
+pub fn var(name: &str) -> Result<String, VarError> {
+    match env::var(name) {
+        Err(VarError::NotPresent) => CONFIG
+            .get()
+            .and_then(|c| c.vars.get(name).cloned())
+            .ok_or(VarError::NotPresent),
+        result => result,
+    }
+}
+
+// The keys the config file gives commands, by the command's name
+pub fn keys() -> &'static [(String, Vec<String>)] {
+    CONFIG.get().map_or(&[], |c| &c.keys)
+}
+
+// Settings that change how ate displays and navigates documents
+#[derive(Clone, Copy)]
+pub struct Options {
+    pub view: ViewOptions,
+    pub parse: ParseOptions,
+    // Whether to ask the terminal to report keys with the kitty keyboard protocol
+    pub kitty_keyboard: bool,
+    // The colors the terminal can show, which colors in the document are brought down to
+    pub colors: ColorLevel,
+    // Whether to save where the document was left on quitting and offer to go back there
+    pub remember_position: bool,
+    // Whether to have the terminal notify when slow input has been read
+    pub notify: bool,
+    pub link_styles: LinkStyles,
+    // Whether to start out showing a scrollbar beside the document
+    pub scrollbar: bool,
+    // Whether n and N step over links to the same address as the selected one
+    pub skip_duplicate_links: bool,
+    // Whether to start out matching searches as regexes
+    pub regex: bool,
+    // Whether to print input that fits on the screen and exit instead of paging it
+    pub quit_if_one_screen: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            view: ViewOptions::default(),
+            parse: ParseOptions::default(),
+            kitty_keyboard: false,
+            colors: ColorLevel::TrueColor,
+            remember_position: false,
+            notify: false,
+            link_styles: LinkStyles::default(),
+            scrollbar: false,
+            skip_duplicate_links: false,
+            regex: false,
+            quit_if_one_screen: false,
+        }
+    }
+}
+
+// Wider tabs would turn each one into a screen of spaces
+const MAX_TAB_WIDTH: usize = 64;
+
+// The default options with the ATE_ variables in the environment and config file applied
+pub fn options() -> Options {
+    let mut options = Options::default();
+    // CJK terminals commonly draw East Asian ambiguous width characters across two cells
+    options.view.widths.unicode_version.ambiguous_are_wide = var("ATE_AMBIGUOUS_WIDE").is_ok();
+    options.view.widths.cluster_graphemes = var("ATE_NO_GRAPHEME_CLUSTERING").is_err();
+    options.view.bidi = var("ATE_BIDI").is_ok();
+    options.kitty_keyboard = var("ATE_NO_KITTY_KEYBOARD").is_err();
+    options.remember_position = var("ATE_NO_REMEMBER_POSITION").is_err();
+    options.notify = var("ATE_NOTIFY").is_ok();
+    options.link_styles = link_styles();
+    options.view.hanging_indent = var("ATE_HANGING_INDENT").is_ok();
+    options.view.chop_long_lines = var("ATE_CHOP_LONG_LINES").is_ok();
+    options.scrollbar = var("ATE_SCROLLBAR").is_ok();
+    options.skip_duplicate_links = var("ATE_SKIP_DUPLICATE_LINKS").is_ok();
+    options.view.follow = var("ATE_FOLLOW").is_ok();
+    options.regex = var("ATE_REGEX").is_ok();
+    options.quit_if_one_screen = var("ATE_QUIT_IF_ONE_SCREEN").is_ok();
+    if var("ATE_STRICT").is_ok() {
+        options.parse.sanitize = Sanitize::Strict;
+    }
+    options.parse.collapse_overwrites = var("ATE_COLLAPSE_OVERWRITES").is_ok();
+    options.parse.detect_links = var("ATE_NO_DETECT_LINKS").is_err();
+    match var("ATE_TAB_WIDTH").map(|w| w.parse::<usize>()) {
+        Ok(Ok(width)) if width <= MAX_TAB_WIDTH => options.parse.tab_width = width,
+        Ok(_) => {
+            warn!("Ignoring ATE_TAB_WIDTH that isn't a number of columns up to {MAX_TAB_WIDTH}")
+        }
+        Err(_) => {}
+    }
+    options
+}
+
+fn link_styles() -> LinkStyles {
+    let mut styles = LinkStyles::default();
+    for (name, style) in [
+        ("ATE_SELECTED_STYLE", &mut styles.selected),
+        ("ATE_HOVERED_STYLE", &mut styles.hovered),
+        ("ATE_VISITED_STYLE", &mut styles.visited),
+    ] {
+        if let Ok(value) = var(name) {
+            match LinkStyle::parse(&value) {
+                Ok(parsed) => *style = parsed,
+                Err(e) => warn!("Ignoring {}: {}", name, e),
+            }
+        }
+    }
+    styles
+}
+
+enum Value {
+    Text(String),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+impl Config {
+    // Parses the part of TOML that settings need: keys set to strings, numbers, booleans, and
+    // arrays of strings, with comments and a [keys] table.
+    // Lines that can't be parsed are skipped and described in the errors returned.
+    pub fn parse(text: &str) -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut errors = vec![];
+        // None in a table that isn't known, whose settings are skipped
+        let mut in_keys = Some(false);
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fail = |e: String| errors.push(format!("line {}: {}", i + 1, e));
+            if let Some(table) = line.strip_prefix('[') {
+                match table.split_once(']') {
+                    Some((name, rest)) if is_comment(rest) => match name.trim() {
+                        "keys" => in_keys = Some(true),
+                        name => {
+                            fail(format!("Unknown table [{name}]"));
+                            in_keys = None;
+                        }
+                    },
+                    _ => fail(format!("Expected [TABLE] in {line:?}")),
+                }
+                continue;
+            }
+            let Some(in_keys) = in_keys else {
+                continue;
+            };
+            let Some((name, value)) = line.split_once('=') else {
+                fail(format!("Expected NAME = VALUE in {line:?}"));
+                continue;
+            };
+            let name = name.trim();
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                fail(format!("Expected a name like opener_timeout in {line:?}"));
+                continue;
+            }
+            let value = match parse_value(value.trim()) {
+                Ok(v) => v,
+                Err(e) => {
+                    fail(format!("{e} in {line:?}"));
+                    continue;
+                }
+            };
+            match (in_keys, value) {
+                (true, Value::Text(key)) => config.keys.push((name.to_string(), vec![key])),
+                (true, Value::List(keys)) => config.keys.push((name.to_string(), keys)),
+                (true, Value::Bool(_)) => fail(format!("Expected keys for {name}")),
+                (false, Value::List(_)) => fail(format!("Expected a single value for {name}")),
+                (false, value) => {
+                    let var = format!("ATE_{}", name.to_ascii_uppercase().replace('-', "_"));
+                    match value {
+                        Value::Bool(false) => config.vars.remove(&var),
+                        Value::Bool(true) => config.vars.insert(var, "1".to_string()),
+                        Value::Text(text) => config.vars.insert(var, text),
+                        Value::List(_) => unreachable!(),
+                    };
+                }
+            }
+        }
+        (config, errors)
+    }
+}
+
+fn is_comment(rest: &str) -> bool {
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+// Numbers are kept as they're written, like environment variables
+fn parse_value(value: &str) -> Result<Value, String> {
+    if let Some(items) = value.strip_prefix('[') {
+        let mut list = vec![];
+        let mut rest = items.trim_start();
+        loop {
+            if let Some(after) = rest.strip_prefix(']') {
+                return match is_comment(after) {
+                    true => Ok(Value::List(list)),
+                    false => Err("Unexpected text after ]".to_string()),
+                };
+            }
+            let (item, after) = parse_string(rest)?;
+            list.push(item);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+            } else if !rest.starts_with(']') {
+                return Err("Expected , or ] in array".to_string());
+            }
+        }
+    }
+    if value.starts_with(['"', '\'']) {
+        let (text, rest) = parse_string(value)?;
+        return match is_comment(rest) {
+            true => Ok(Value::Text(text)),
+            false => Err("Unexpected text after string".to_string()),
+        };
+    }
+    let value = value.split('#').next().unwrap_or(value).trim();
+    match value {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ if value.replace('_', "").parse::<f64>().is_ok() => {
+            Ok(Value::Text(value.replace('_', "")))
+        }
+        _ => Err("Expected a string, number, boolean, or array".to_string()),
+    }
+}
+
+// Parses the quoted string at the start of text, returning it and the text after it.
+// Double-quoted strings take backslash escapes and single-quoted ones are taken as written.
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut chars = text.char_indices();
+    let quote = match chars.next() {
+        Some((_, q @ ('"' | '\''))) => q,
+        _ => return Err("Expected a quoted string".to_string()),
+    };
+    let mut parsed = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            _ if c == quote => return Ok((parsed, &text[i + 1..])),
+            '\\' if quote == '"' => match chars.next().map(|(_, e)| e) {
+                Some('n') => parsed.push('\n'),
+                Some('t') => parsed.push('\t'),
+                Some(e @ ('"' | '\\')) => parsed.push(e),
+                Some(e) => return Err(format!("Unknown escape \\{e}")),
+                None => break,
+            },
+            _ => parsed.push(c),
+        }
+    }
+    Err("Unclosed string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_settings_and_keys() {
+        let (config, errors) = Config::parse(
+            "# Settings\n\
+            follow = true\n\
+            hanging_indent = false\n\
+            opener_timeout = 2.5 # seconds\n\
+            diff_keys = \"o=open {file}:{line}; c=copy \\\"{file}\\\"\"\n\
+            opener = 'code --goto'\n\
+            \n\
+            [keys]\n\
+            line_down = [\"j\", \"Down\"]\n\
+            page_up = 'Ctrl-B'\n\
+            quit = []\n",
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        let vars = |names: &[&str]| -> Vec<Option<&str>> {
+            names
+                .iter()
+                .map(|n| config.vars.get(*n).map(String::as_str))
+                .collect()
+        };
+        assert_eq!(
+            vec![
+                Some("1"),
+                None,
+                Some("2.5"),
+                Some("o=open {file}:{line}; c=copy \"{file}\""),
+                Some("code --goto"),
+            ],
+            vars(&[
+                "ATE_FOLLOW",
+                "ATE_HANGING_INDENT",
+                "ATE_OPENER_TIMEOUT",
+                "ATE_DIFF_KEYS",
+                "ATE_OPENER"
+            ])
+        );
+        let keys = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("line_down".to_string(), keys(&["j", "Down"])),
+                ("page_up".to_string(), keys(&["Ctrl-B"])),
+                ("quit".to_string(), vec![]),
+            ],
+            config.keys
+        );
+    }
+
+    #[test]
+    fn skip_mistakes() {
+        let (config, errors) = Config::parse(
+            "follow = yes\n\
+            [colors]\n\
+            bidi = true\n\
+            [keys]\n\
+            top = \"gg\n\
+            bottom = [\"G\" \"End\"]\n\
+            follow = true\n\
+            regex\n",
+        );
+        assert_eq!(
+            vec![
+                "line 1: Expected a string, number, boolean, or array in \"follow = yes\"",
+                "line 2: Unknown table [colors]",
+                "line 5: Unclosed string in \"top = \\\"gg\"",
+                "line 6: Expected , or ] in array in \"bottom = [\\\"G\\\" \\\"End\\\"]\"",
+                "line 7: Expected keys for follow",
+                "line 8: Expected NAME = VALUE in \"regex\"",
+            ],
+            errors
+        );
+        // Settings in an unknown table aren't taken as top-level ones
+        assert_eq!(Config::default(), config);
+    }
+}
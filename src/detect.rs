@@ -0,0 +1,277 @@
+use std::env;
+use std::ffi::CStr;
+use std::path::PathBuf;
+
+use termwiz::hyperlink::Hyperlink;
+
+use crate::doc::LinkRange;
+
+// Where file references found in the text are linked to.
+// Paths are relative to the directory ate runs in, like the output of a command run there.
+pub struct FileBase {
+    host: String,
+    dir: PathBuf,
+}
+
+impl FileBase {
+    pub fn current() -> Self {
+        Self {
+            host: hostname(),
+            dir: env::current_dir().unwrap_or_default(),
+        }
+    }
+
+    // A link to path at position, which is a line or a line and column like "12:5", in the form
+    // openers expect
+    fn uri(&self, path: &str, position: &str) -> String {
+        let path = path.strip_prefix("./").unwrap_or(path);
+        let path = if path.starts_with('/') {
+            PathBuf::from(path)
+        } else {
+            self.dir.join(path)
+        };
+        format!("file://{}{}#{}", self.host, path.display(), position)
+    }
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: gethostname writes at most buf.len() bytes into buf
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    match CStr::from_bytes_until_nul(&buf) {
+        Ok(name) if result == 0 && !name.is_empty() => name.to_string_lossy().into_owned(),
+        _ => "localhost".to_string(),
+    }
+}
+
+// Finds web addresses and file references like "src/main.rs:12:5" in text that programs printed
+// without linking them, and adds links for them to links. Text that's already linked is left as
+// it is.
+pub fn detect_links(text: &str, links: &mut Vec<LinkRange>, base: &FileBase) {
+    let mut detected = find_urls(text);
+    let files: Vec<LinkRange> = find_file_references(text, base)
+        .into_iter()
+        .filter(|f| !overlaps_any(&detected, f.start, f.end))
+        .collect();
+    detected.extend(files);
+    detected.retain(|d| !overlaps_any(links, d.start, d.end));
+    if detected.is_empty() {
+        return;
+    }
+    links.extend(detected);
+    links.sort_by_key(|l| l.start);
+}
+
+// Whether any of the links, which are in order and don't overlap, overlap start to end
+fn overlaps_any(links: &[LinkRange], start: usize, end: usize) -> bool {
+    let idx = links.partition_point(|l| l.end <= start);
+    links.get(idx).is_some_and(|l| l.start < end)
+}
+
+// Characters that can't be part of an address or path printed in text
+fn ends_token(c: char) -> bool {
+    c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '`' | '<' | '>')
+}
+
+fn find_urls(text: &str) -> Vec<LinkRange> {
+    let mut urls = vec![];
+    let mut searched = 0;
+    while let Some(found) = text[searched..].find("http") {
+        let start = searched + found;
+        let rest = &text[start..];
+        searched = start + "http".len();
+        let preceded_by_word = text[..start]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_alphanumeric);
+        let Some(after_scheme) = rest
+            .strip_prefix("https://")
+            .or_else(|| rest.strip_prefix("http://"))
+        else {
+            continue;
+        };
+        if preceded_by_word || after_scheme.is_empty() {
+            continue;
+        }
+        let len = rest.find(ends_token).unwrap_or(rest.len());
+        let url = trim_url(&rest[..len]);
+        if url.len() == rest.len() - after_scheme.len() {
+            continue;
+        }
+        urls.push(LinkRange {
+            start,
+            end: start + url.len(),
+            link: Hyperlink::new_implicit(url),
+        });
+        searched = start + url.len();
+    }
+    urls
+}
+
+// Drops punctuation from the end of url that's more likely to end the sentence around it, and
+// closing brackets that don't close one opened in it, like those around "(see https://a.b)"
+fn trim_url(mut url: &str) -> &str {
+    loop {
+        let Some(last) = url.chars().next_back() else {
+            return url;
+        };
+        let unbalanced =
+            |open, close| last == close && url.matches(open).count() < url.matches(close).count();
+        if matches!(last, '.' | ',' | ';' | ':' | '!' | '?')
+            || unbalanced('(', ')')
+            || unbalanced('[', ']')
+            || unbalanced('{', '}')
+        {
+            url = &url[..url.len() - 1];
+        } else {
+            return url;
+        }
+    }
+}
+
+// Finds paths followed by a line number and an optional column, like compilers and grep -n print.
+// Paths need an extension to tell them apart from things like times.
+fn find_file_references(text: &str, base: &FileBase) -> Vec<LinkRange> {
+    let mut files = vec![];
+    let mut searched = 0;
+    // Where the last reference ended, since references can't overlap
+    let mut matched = 0;
+    while let Some(found) = text[searched..].find(':') {
+        let colon = searched + found;
+        searched = colon + 1;
+        let digits = |from: usize| {
+            text[from..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(text.len() - from)
+        };
+        let line_len = digits(colon + 1);
+        if line_len == 0 {
+            continue;
+        }
+        let mut end = colon + 1 + line_len;
+        let column_len = match text[end..].strip_prefix(':') {
+            Some(_) => digits(end + 1),
+            None => 0,
+        };
+        if column_len > 0 {
+            end += 1 + column_len;
+        }
+        let path_start = text[..colon]
+            .rfind(|c: char| ends_token(c) || matches!(c, '(' | '[' | '{' | '=' | ':'))
+            .map_or(0, |i| {
+                i + text[i..].chars().next().map_or(1, char::len_utf8)
+            })
+            .max(matched);
+        let path = &text[path_start..colon];
+        if !has_extension(path) || path.contains('#') {
+            continue;
+        }
+        files.push(LinkRange {
+            start: path_start,
+            end,
+            link: Hyperlink::new_implicit(base.uri(path, &text[colon + 1..end])),
+        });
+        searched = end;
+        matched = end;
+    }
+    files
+}
+
+// Whether the file name at the end of path ends in a dot and letters or digits, at least one of
+// them a letter
+fn has_extension(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((stem, extension)) => {
+            !stem.is_empty()
+                && extension.chars().all(|c| c.is_ascii_alphanumeric())
+                && extension.chars().any(|c| c.is_ascii_alphabetic())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> FileBase {
+        FileBase {
+            host: "host".to_string(),
+            dir: PathBuf::from("/work"),
+        }
+    }
+
+    // The text and address of each link detected in text
+    fn detected(text: &str) -> Vec<(&str, String)> {
+        let mut links = vec![];
+        detect_links(text, &mut links, &base());
+        links
+            .iter()
+            .map(|l| (&text[l.start..l.end], l.link.uri().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn detect_urls() {
+        assert_eq!(
+            vec![
+                ("https://a.b/c?d=e", "https://a.b/c?d=e".to_string()),
+                ("http://w.org/A_(b)", "http://w.org/A_(b)".to_string()),
+            ],
+            detected("See https://a.b/c?d=e. (or http://w.org/A_(b))")
+        );
+        assert!(detected("xhttp://a.b http:// https").is_empty());
+        assert_eq!(1, detected("<http://a.b>").len());
+    }
+
+    #[test]
+    fn detect_file_references() {
+        assert_eq!(
+            vec![
+                (
+                    "src/main.rs:12:5",
+                    "file://host/work/src/main.rs#12:5".to_string()
+                ),
+                ("/tmp/a.txt:3", "file://host/tmp/a.txt#3".to_string()),
+                ("./b.py:7", "file://host/work/b.py#7".to_string()),
+            ],
+            detected("  --> src/main.rs:12:5\n/tmp/a.txt:3:match\n(./b.py:7)")
+        );
+        // Times, versions, and ports aren't files
+        assert!(detected("at 12:30 for v1.2:3").is_empty());
+        assert_eq!(
+            vec!["http://a.b:8080/c.rs:1"],
+            detected("http://a.b:8080/c.rs:1")
+                .iter()
+                .map(|(text, _)| *text)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn detect_adjacent_file_references() {
+        assert_eq!(
+            vec![
+                ("a.rs:12", "file://host/work/a.rs#12".to_string()),
+                ("b.rs:3", "file://host/work/b.rs#3".to_string()),
+            ],
+            detected("a.rs:12b.rs:3")
+        );
+    }
+
+    #[test]
+    fn keep_existing_links() {
+        let text = "a.rs:1 b.rs:2";
+        let mut links = vec![LinkRange {
+            start: 0,
+            end: 4,
+            link: Hyperlink::new("x"),
+        }];
+        detect_links(text, &mut links, &base());
+        assert_eq!(
+            vec!["x", "file://host/work/b.rs#2"],
+            links.iter().map(|l| l.link.uri()).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,102 +1,985 @@
-use std::io::Read;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read};
+use std::mem::{self, discriminant};
+use std::ops::Range;
 
-use termwiz::cell::{AttributeChange, CellAttributes};
-use termwiz::escape::csi::Sgr;
+use termwiz::cell::{unicode_column_width, AttributeChange, CellAttributes, Intensity, Underline};
+use termwiz::escape::csi::{
+    Cursor, DecPrivateMode, DecPrivateModeCode, Edit, EraseInLine, Mode, Sgr,
+};
 use termwiz::escape::parser::Parser;
 use termwiz::escape::Action::{self, Control, Print};
-use termwiz::escape::ControlCode::LineFeed;
-use termwiz::escape::{OperatingSystemCommand, CSI};
+use termwiz::escape::ControlCode::{Backspace, CarriageReturn, HorizontalTab, LineFeed};
+use termwiz::escape::{Esc, EscCode, OperatingSystemCommand, CSI};
 use termwiz::hyperlink::Hyperlink;
 use termwiz::surface::Change;
 use termwiz::Error;
 
-#[derive(Debug)]
+use finl_unicode::grapheme_clusters::Graphemes;
+use log::warn;
+
+use crate::detect::{detect_links, FileBase};
+
+/// Where a link is in a document's text, as byte offsets from start up to end.
+/// A link the input splits up can take several ranges, which Document::link_group finds.
+#[derive(Clone, Debug)]
 pub struct LinkRange {
     pub start: usize,
     pub end: usize,
     pub link: Hyperlink,
 }
 
+/// The input's text with the colors, styles and links it was written with
+#[derive(Clone, Default)]
 pub struct Document {
-    // The displayed characters of the input
-    // i.e. input bytes with control characters stripped out
+    /// The displayed characters of the input
+    /// i.e. input bytes with control characters stripped out
     pub text: String,
-    // Display attrs to apply by byte index into text.
-    // Before the characters in text at .0 have been drawn,
-    // all changes with that offset should be applied.
-    // Stored in ascending order of .0
+    /// Display attrs to apply by byte index into text.
+    /// Before the characters in text at .0 have been drawn,
+    /// all changes with that offset should be applied.
+    /// Stored in ascending order of .0
     pub attrs: Vec<(usize, Change)>,
+    /// The links in text, in order and not overlapping
     pub links: Vec<LinkRange>,
+    /// Descriptions of input we couldn't represent and skipped.
+    /// Only the first MAX_DIAGNOSTICS are kept.
+    pub diagnostics: Vec<String>,
+    /// How many bytes were read from input, including the escape sequences not in text
+    pub input_bytes: usize,
+    /// Lines in text, counting a final line without a line feed
+    pub line_count: usize,
+    /// The text and attribute changes before this offset are the same as in the snapshot
+    /// before this one, so what was worked out from them can be kept
+    pub unchanged_before: usize,
+}
+
+pub const MAX_DIAGNOSTICS: usize = 100;
+
+/// What to do with characters in the input that could mislead the reader or the terminal
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sanitize {
+    /// Drop control characters the parser passes through, like DEL and C1 controls
+    #[default]
+    Controls,
+    /// Also replace invisible formatting characters, like bidi overrides and zero width spaces,
+    /// with U+FFFD so text can't display differently than it reads
+    Strict,
+}
+
+impl Sanitize {
+    fn filter(self, c: char) -> Option<char> {
+        if c.is_control() {
+            None
+        } else if self == Sanitize::Strict && is_suspicious(c) {
+            Some(char::REPLACEMENT_CHARACTER)
+        } else {
+            Some(c)
+        }
+    }
+}
+
+/// How to turn input into a Document
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub sanitize: Sanitize,
+    /// Whether to keep only the last of the text written over the same line after returning to its
+    /// start, like the frames of a spinner or progress bar
+    pub collapse_overwrites: bool,
+    /// Whether to link web addresses and file references in text that isn't already linked.
+    /// That takes a while for big input, so it can be left off to do with
+    /// [`Document::detect_links`] once the text is shown.
+    pub detect_links: bool,
+    /// How many columns apart tab stops are.
+    /// Tabs become the spaces up to the next one, or are dropped if this is 0.
+    pub tab_width: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            sanitize: Sanitize::default(),
+            collapse_overwrites: false,
+            detect_links: false,
+            tab_width: 8,
+        }
+    }
+}
+
+fn is_suspicious(c: char) -> bool {
+    matches!(c,
+        // Bidi embeddings, overrides, and isolates
+        '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}'
+        // Zero width space, word joiner, and invisible operators
+        | '\u{200b}' | '\u{2060}'..='\u{2064}'
+        | '\u{feff}'
+        // Tags, which can hide ASCII
+        | '\u{e0000}'..='\u{e007f}')
+}
+
+/// Removes control characters so text from outside ate can't send escape sequences to the terminal
+pub fn strip_controls(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+fn diagnose(diagnostics: &mut Vec<String>, diagnostic: String) {
+    if diagnostics.len() < MAX_DIAGNOSTICS {
+        warn!("{}", diagnostic);
+        diagnostics.push(diagnostic);
+    }
+}
+
+// Builds up Document::attrs while dropping redundant changes.
+// Some tools reset and reapply the same attributes around every character, so we skip changes
+// that don't alter the attributes in effect, collapse changes at the same offset whose net effect
+// is nothing, and drop earlier changes at an offset that a later one overrides.
+struct AttrsBuilder {
+    attrs: Vec<(usize, Change)>,
+    // The attributes in effect after applying everything in attrs
+    current: CellAttributes,
+    // The attributes in effect before the changes at the last offset in attrs
+    before_last_offset: CellAttributes,
+    // How many changes the last mark kept, which pushing can't drop since rewinding to it keeps
+    // them
+    marked: usize,
+}
+
+impl AttrsBuilder {
+    fn new() -> Self {
+        Self {
+            attrs: vec![(0, Change::AllAttributes(CellAttributes::default()))],
+            current: CellAttributes::default(),
+            before_last_offset: CellAttributes::default(),
+            marked: 1,
+        }
+    }
+
+    fn push(&mut self, offset: usize, change: Change) {
+        if self.attrs.last().is_none_or(|(o, _)| *o != offset) {
+            self.before_last_offset = self.current.clone();
+        }
+        let mut next = self.current.clone();
+        apply(&mut next, &change);
+        if next == self.current {
+            return;
+        }
+        let floor = self.marked.max(1);
+        if next == self.before_last_offset {
+            // Everything at this offset cancels out
+            while self.attrs.len() > floor && self.attrs[self.attrs.len() - 1].0 == offset {
+                self.attrs.pop();
+            }
+            // Unless some of it was marked
+            if self.attrs.len() > 1 && self.attrs[self.attrs.len() - 1].0 == offset {
+                self.attrs
+                    .push((offset, Change::AllAttributes(next.clone())));
+            }
+            self.current = next;
+            return;
+        }
+        while let Some((last_offset, last)) = self.attrs.last() {
+            let overridden = *last_offset == offset
+                && self.attrs.len() > floor
+                && match (last, &change) {
+                    (_, Change::AllAttributes(_)) => true,
+                    (Change::Attribute(l), Change::Attribute(c)) => {
+                        discriminant(l) == discriminant(c)
+                    }
+                    _ => false,
+                };
+            if !overridden {
+                break;
+            }
+            self.attrs.pop();
+        }
+        self.current = next;
+        self.attrs.push((offset, change));
+    }
+
+    // Remembers the changes so far, to rewind to later
+    fn mark(&mut self) -> AttrsMark {
+        self.marked = self.attrs.len();
+        AttrsMark {
+            len: self.attrs.len(),
+            current: self.current.clone(),
+            before_last_offset: self.before_last_offset.clone(),
+        }
+    }
+
+    // Drops the changes made since mark and applies their net effect at offset instead
+    fn rewind(&mut self, mark: &AttrsMark, offset: usize) {
+        let current = self.current.clone();
+        self.attrs.truncate(mark.len);
+        self.marked = mark.len;
+        self.current = mark.current.clone();
+        self.before_last_offset = mark.before_last_offset.clone();
+        self.push(offset, Change::AllAttributes(current));
+    }
+}
+
+fn apply(attrs: &mut CellAttributes, change: &Change) {
+    match change {
+        Change::AllAttributes(a) => *attrs = a.clone(),
+        Change::Attribute(a) => attrs.apply_change(a),
+        _ => unreachable!(),
+    }
+}
+
+#[derive(Clone)]
+struct AttrsMark {
+    len: usize,
+    current: CellAttributes,
+    before_last_offset: CellAttributes,
+}
+
+// A point in the document to go back to, dropping everything parsed after it
+#[derive(Clone)]
+struct Checkpoint {
+    offset: usize,
+    attrs: AttrsMark,
+}
+
+impl Checkpoint {
+    fn new(text: &str, attrs: &mut AttrsBuilder) -> Self {
+        Self {
+            offset: text.len(),
+            attrs: attrs.mark(),
+        }
+    }
+}
+
+// A character on the current line that the cursor moved back over, which what's drawn next
+// replaces
+struct Cell {
+    c: char,
+    attrs: CellAttributes,
+    link: Option<Hyperlink>,
+    // Whether the cursor backspaced over it, so drawing over it with the same character or an
+    // underscore emphasizes it like on a typewriter, as man pages do for bold and underlines
+    struck: bool,
 }
 
+impl Cell {
+    // The character and attributes left after drawing c over this, if they aren't c and the
+    // attributes it's drawn with
+    fn overstrike(self, c: char) -> Option<(char, CellAttributes)> {
+        if !self.struck {
+            return None;
+        }
+        let mut attrs = self.attrs;
+        if c == self.c {
+            attrs.set_intensity(Intensity::Bold);
+            Some((c, attrs))
+        } else if self.c == '_' {
+            attrs.set_underline(Underline::Single);
+            Some((c, attrs))
+        } else if c == '_' {
+            attrs.set_underline(Underline::Single);
+            Some((self.c, attrs))
+        } else {
+            None
+        }
+    }
+}
+
+// Where the last character drawn on the current line starts, so a backspace can move back over it
+struct LastChar {
+    offset: usize,
+    // The attributes before it, once they've changed since it was drawn
+    mark: Option<AttrsMark>,
+}
+
+/// Put in place of what a full-screen program drew on the alternate screen
+pub const SKIPPED_FULL_SCREEN: &str = "[skipped full-screen output]";
+
 impl Document {
-    pub fn new<'a>(mut input: Box<dyn Read + 'a>) -> Result<Document, Error> {
-        let mut buf = vec![];
+    /// Links web addresses and file references in text that isn't already linked, as parsing does
+    /// with [`ParseOptions::detect_links`]
+    pub fn detect_links(&mut self) {
+        detect_links(&self.text, &mut self.links, &FileBase::current());
+    }
+
+    /// The index of the link containing byte, if any
+    pub fn link_at(&self, byte: usize) -> Option<usize> {
+        // Links don't overlap, so they're in order of their ends as well as their starts
+        let idx = self.links.partition_point(|l| l.end <= byte);
+        self.links.get(idx).filter(|l| l.start <= byte).map(|_| idx)
+    }
+
+    /// Whether the link at idx starts a link rather than carrying on the one before it.
+    /// Output can split a link into several ranges, like when it's styled partway through, and
+    /// ranges with the same OSC 8 id, or going to the same address right after one another, are
+    /// one link to select, highlight, and open.
+    pub fn starts_link(&self, idx: usize) -> bool {
+        match idx.checked_sub(1) {
+            Some(prev) => !continues(&self.links[prev], &self.links[idx]),
+            None => true,
+        }
+    }
+
+    /// The indices of the ranges making up the link that the one at idx is part of
+    pub fn link_group(&self, idx: usize) -> Range<usize> {
+        let mut start = idx;
+        while !self.starts_link(start) {
+            start -= 1;
+        }
+        let mut end = idx + 1;
+        while end < self.links.len() && !self.starts_link(end) {
+            end += 1;
+        }
+        start..end
+    }
+
+    /// The text the link at idx is shown as, joining the ranges it's split into
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    ///
+    /// use ate_pager::{Document, ParseOptions};
+    ///
+    /// // Starting the link again partway through splits it into two ranges
+    /// let link = "\x1b]8;id=1;https://a.b\x1b\\";
+    /// let input = format!("{link}an {link}end\x1b]8;;\x1b\\");
+    /// let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default())?;
+    /// assert_eq!(0..2, doc.link_group(1));
+    /// assert_eq!("an end", doc.link_text(1));
+    /// assert_eq!(vec![(0, 6)], doc.link_spans(0));
+    /// # Ok::<(), termwiz::Error>(())
+    /// ```
+    pub fn link_text(&self, idx: usize) -> String {
+        self.links[self.link_group(idx)]
+            .iter()
+            .map(|l| &self.text[l.start..l.end])
+            .collect()
+    }
+
+    /// Where in the text the link at idx is, with ranges that touch joined, to highlight all of it
+    pub fn link_spans(&self, idx: usize) -> Vec<(usize, usize)> {
+        let mut spans: Vec<(usize, usize)> = vec![];
+        for l in &self.links[self.link_group(idx)] {
+            match spans.last_mut() {
+                Some((_, end)) if *end == l.start => *end = l.end,
+                _ => spans.push((l.start, l.end)),
+            }
+        }
+        spans
+    }
+
+    /// The text from start to end, widened to the nearest grapheme cluster boundaries and clamped
+    /// to the text.
+    /// Offsets worked out from what's on screen, like where a match or click is, can land inside a
+    /// cluster that's drawn as one cell, and slicing inside a char would panic.
+    /// Offsets of the document's own attrs and links are on char boundaries but may split a
+    /// cluster, so slice text directly with them rather than widening them.
+    pub fn text_between(&self, start: usize, end: usize) -> &str {
+        let end = self.grapheme_end(end);
+        let start = self.grapheme_start(start.min(end));
+        &self.text[start..end]
+    }
+
+    /// The start of the grapheme cluster byte is in, or byte if a cluster starts there
+    pub fn grapheme_start(&self, byte: usize) -> usize {
+        self.grapheme_boundary(byte).0
+    }
+
+    /// The end of the grapheme cluster byte is inside of, or byte if a cluster starts there
+    pub fn grapheme_end(&self, byte: usize) -> usize {
+        let (start, end) = self.grapheme_boundary(byte);
+        if start == byte.min(self.text.len()) {
+            start
+        } else {
+            end
+        }
+    }
+
+    // Where the grapheme cluster containing byte starts and ends, clamping byte to the text
+    fn grapheme_boundary(&self, byte: usize) -> (usize, usize) {
+        let text = self.text.as_bytes();
+        let byte = byte.min(text.len());
+        // Clusters only span ASCII for CR LF, so most boundaries don't need segmenting
+        if byte == 0
+            || byte == text.len()
+            || (text[byte - 1].is_ascii() && text[byte].is_ascii() && text[byte - 1] != b'\r')
+        {
+            return (byte, byte);
+        }
+        // A line feed always ends a cluster, so segment from the start of byte's line, only
+        // looking so far back that a long line doesn't make every slice of it slow
+        let mut from = byte.saturating_sub(GRAPHEME_LOOKBACK);
+        while !self.text.is_char_boundary(from) {
+            from += 1;
+        }
+        if let Some(newline) = text[from..byte].iter().rposition(|&b| b == b'\n') {
+            from += newline + 1;
+        }
+        for grapheme in Graphemes::new(&self.text[from..]) {
+            let end = from + grapheme.len();
+            if end > byte {
+                break;
+            }
+            from = end;
+        }
+        let end = Graphemes::new(&self.text[from..])
+            .next()
+            .map_or(from, |g| from + g.len());
+        (from, end)
+    }
+
+    /// Reads all of input and parses it
+    pub fn new<'a>(
+        mut input: Box<dyn Read + 'a>,
+        options: ParseOptions,
+    ) -> Result<Document, Error> {
+        let mut builder = DocumentBuilder::new(options);
         // TODO - lazily read past first gig or so
-        let read = input.read_to_end(&mut buf)?;
-        let mut text = String::new();
-        let mut links = vec![];
-        let mut attrs = vec![(0, Change::AllAttributes(CellAttributes::default()))];
-        let mut partial_link: Option<(usize, Hyperlink)> = None;
-        let mut complete_link = |start, link, end| links.push(LinkRange { start, link, end });
-        Parser::new().parse(&buf[0..read], |a| {
-            match a {
-                Print(c) => text.push(c),
-                Control(LineFeed) => text.push('\n'),
-                Action::CSI(CSI::Sgr(s)) => {
-                    let change = match s {
-                        Sgr::Reset => Change::AllAttributes(CellAttributes::default()),
-                        ac => Change::Attribute(match ac {
-                            Sgr::Intensity(i) => AttributeChange::Intensity(i),
-                            Sgr::Background(b) => AttributeChange::Background(b.into()),
-                            Sgr::Underline(u) => AttributeChange::Underline(u),
-                            Sgr::Blink(b) => AttributeChange::Blink(b),
-                            Sgr::Italic(i) => AttributeChange::Italic(i),
-                            Sgr::Invisible(i) => AttributeChange::Invisible(i),
-                            Sgr::StrikeThrough(s) => AttributeChange::StrikeThrough(s),
-                            Sgr::Foreground(f) => AttributeChange::Foreground(f.into()),
-                            Sgr::Inverse(i) => AttributeChange::Reverse(i),
-                            // TODO - add an Attribute change to termwiz for vertical align
-                            Sgr::VerticalAlign(_) => todo!(),
-                            Sgr::UnderlineColor(_) => todo!(),
-                            Sgr::Font(_) => todo!(),
-                            Sgr::Overline(_) => todo!(),
-                            Sgr::Reset => unreachable!(),
-                        }),
-                    };
-                    // This isn't parsing by grapheme, which may put this change in the middle of one.
-                    // We render by grapheme and changes in the middle of one will be applied
-                    // afterwards.
-                    // It's nonsensical to change graphical representation in the middle of a
-                    // grapheme, so I don't think that's an issue.
-                    // We do need to make sure to apply all graphical changes, not just those
-                    // that land on grapheme boundaries
-                    attrs.push((text.len(), change));
+        let mut buf = vec![0; READ_SIZE];
+        loop {
+            match input.read(&mut buf) {
+                Ok(0) => return Ok(builder.finish()),
+                Ok(read) => builder.feed(&buf[..read]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+// Whether next is more of the same link as prev, which it directly follows in the document's
+// links.
+// Links with ids are the same if their ids and addresses are, even with unlinked text between them
+// like the start of the next line, while links without go on only as long as nothing comes between
+// them.
+fn continues(prev: &LinkRange, next: &LinkRange) -> bool {
+    if prev.link.uri() != next.link.uri() {
+        return false;
+    }
+    match (prev.link.params().get("id"), next.link.params().get("id")) {
+        (Some(a), Some(b)) => a == b,
+        (None, None) => prev.end == next.start,
+        _ => false,
+    }
+}
+
+/// How much input to read at once
+pub const READ_SIZE: usize = 64 * 1024;
+
+// How far back from an offset to start segmenting grapheme clusters to find the one it's in.
+// Clusters longer than this are vanishingly rare, and are split wherever segmenting started.
+const GRAPHEME_LOOKBACK: usize = 1024;
+
+/// Parses input into a Document as it arrives, so what's been read so far can be shown before
+/// the rest has been
+pub struct DocumentBuilder {
+    parser: Parser,
+    parse: Parse,
+    // Where detected file references link to, if links are detected
+    file_base: Option<FileBase>,
+}
+
+impl DocumentBuilder {
+    /// Starts a document with nothing fed into it yet
+    pub fn new(options: ParseOptions) -> Self {
+        Self {
+            parser: Parser::new(),
+            parse: Parse::new(options),
+            file_base: options.detect_links.then(FileBase::current),
+        }
+    }
+
+    /// Parses more of the input.
+    /// An escape sequence or character split across calls is parsed once the rest of it is fed.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.parse.input_bytes += bytes.len();
+        let parse = &mut self.parse;
+        self.parser.parse(bytes, |a| parse.perform(a));
+    }
+
+    /// The document as parsed so far, with a link that hasn't ended yet ending at the end of it.
+    /// Text that may still be dropped, like what's been drawn on the alternate screen, is kept
+    /// until it is, while the end of a line being drawn over is left out until the cursor leaves
+    /// it.
+    pub fn snapshot(&mut self) -> Document {
+        let parse = &mut self.parse;
+        let unchanged_before = parse.unchanged_before;
+        parse.unchanged_before = parse.text.len();
+        let mut links = parse.links.clone();
+        if let Some((start, link)) = &parse.partial_link {
+            links.push(LinkRange {
+                start: *start,
+                link: link.clone(),
+                end: parse.text.len(),
+            });
+        }
+        if let Some(base) = &self.file_base {
+            detect_links(&parse.text, &mut links, base);
+        }
+        Document {
+            text: parse.text.clone(),
+            attrs: parse.attrs.attrs.clone(),
+            links,
+            diagnostics: parse.diagnostics.clone(),
+            input_bytes: parse.input_bytes,
+            line_count: count_lines(&parse.text),
+            unchanged_before,
+        }
+    }
+
+    /// Counts what doc was rewritten from as rewritten again in the next snapshot, for a
+    /// snapshot that was dropped without being shown
+    pub fn unsent(&mut self, doc: &Document) {
+        let parse = &mut self.parse;
+        parse.unchanged_before = parse.unchanged_before.min(doc.unchanged_before);
+    }
+
+    /// The document once all of the input has been fed
+    pub fn finish(self) -> Document {
+        let mut parse = self.parse;
+        parse.keep_overwritten();
+        if let Some((start, link)) = parse.partial_link {
+            parse.links.push(LinkRange {
+                start,
+                link,
+                end: parse.text.len(),
+            });
+        }
+        if let Some(base) = &self.file_base {
+            detect_links(&parse.text, &mut parse.links, base);
+        }
+        Document {
+            line_count: count_lines(&parse.text),
+            text: parse.text,
+            attrs: parse.attrs.attrs,
+            links: parse.links,
+            diagnostics: parse.diagnostics,
+            input_bytes: parse.input_bytes,
+            unchanged_before: parse.unchanged_before,
+        }
+    }
+}
+
+// Lines in text, counting a final line without a line feed
+fn count_lines(text: &str) -> usize {
+    let mut line_count = text.bytes().filter(|&b| b == b'\n').count();
+    if !text.is_empty() && !text.ends_with('\n') {
+        line_count += 1;
+    }
+    line_count
+}
+
+// What's been parsed so far and the state of the terminal the input was written for
+struct Parse {
+    options: ParseOptions,
+    text: String,
+    links: Vec<LinkRange>,
+    attrs: AttrsBuilder,
+    diagnostics: Vec<String>,
+    partial_link: Option<(usize, Hyperlink)>,
+    input_bytes: usize,
+    // Where the current line starts in text and the attributes in effect there
+    line: Checkpoint,
+    // Where the cursor was saved, so the text drawn elsewhere on the screen before it's
+    // restored can be dropped
+    saved_cursor: Option<Checkpoint>,
+    // Set when the cursor was restored, so the next text overwrites what was drawn since it
+    // was saved. A line feed right after leaves it be.
+    restored_cursor: Option<Checkpoint>,
+    // Where a full-screen program switched to the alternate screen.
+    // Everything it draws there is dropped when it switches back, since that's what's left
+    // on the terminal.
+    alternate_screen: Option<Checkpoint>,
+    // Set when the cursor moved back to the start of the line, so the next text overwrites it.
+    // A line feed right after just ends the line.
+    returned: bool,
+    last_char: Option<LastChar>,
+    // What the cursor moved back over on the current line, in order from the cursor, which is
+    // still on the line wherever it isn't drawn over
+    overwritten: VecDeque<Cell>,
+    // Where the text was last rewound to since the last snapshot, or the end of that snapshot
+    unchanged_before: usize,
+}
+
+impl Parse {
+    fn new(options: ParseOptions) -> Self {
+        let text = String::new();
+        let mut attrs = AttrsBuilder::new();
+        Self {
+            options,
+            line: Checkpoint::new(&text, &mut attrs),
+            text,
+            links: vec![],
+            attrs,
+            diagnostics: vec![],
+            partial_link: None,
+            input_bytes: 0,
+            saved_cursor: None,
+            restored_cursor: None,
+            alternate_screen: None,
+            returned: false,
+            last_char: None,
+            overwritten: VecDeque::new(),
+            unchanged_before: 0,
+        }
+    }
+
+    fn checkpoint(&mut self) -> Checkpoint {
+        Checkpoint::new(&self.text, &mut self.attrs)
+    }
+
+    // Drops the text, links, and attribute changes after checkpoint, though the attributes and
+    // any partial link they left in effect carry on
+    fn rewind(&mut self, checkpoint: &Checkpoint) {
+        let offset = checkpoint.offset;
+        self.text.truncate(offset);
+        self.unchanged_before = self.unchanged_before.min(offset);
+        self.attrs.rewind(&checkpoint.attrs, offset);
+        self.links.retain(|l| l.start < offset);
+        if let Some(last) = self.links.last_mut() {
+            last.end = last.end.min(offset);
+        }
+        if let Some((start, _)) = &mut self.partial_link {
+            *start = (*start).min(offset);
+        }
+        self.last_char = None;
+        self.overwritten.clear();
+        // What's dropped can't be gone back to, and going back to the same place later starts
+        // from the attribute changes left there
+        for saved in [&mut self.saved_cursor, &mut self.restored_cursor] {
+            if saved.as_ref().is_some_and(|c| c.offset > offset) {
+                *saved = None;
+            } else if let Some(saved) = saved {
+                if saved.attrs.len > checkpoint.attrs.len {
+                    saved.attrs = checkpoint.attrs.clone();
                 }
-                Action::OperatingSystemCommand(osc) => {
-                    match *osc {
-                        OperatingSystemCommand::SetHyperlink(parsed_link) => {
-                            // SetHyperlink may have the current partial link in it.
-                            // We may have just ended the link that's in there, too.
-                            // We don't try to collapse repeated links into a single range.
-                            // Instead we assume the output repeated links for some reason and
-                            // faithfully recreate it.
-                            if let Some((start, link)) = partial_link.take() {
-                                complete_link(start, link, text.len());
-                            }
-                            partial_link = parsed_link.map(|l| (text.len(), l));
-                        }
-                        _ => {}
-                    };
+            }
+        }
+    }
+
+    // The link the character at offset is in
+    fn link_at(&self, offset: usize) -> Option<Hyperlink> {
+        if let Some((start, link)) = &self.partial_link {
+            if *start <= offset {
+                return Some(link.clone());
+            }
+        }
+        let idx = self.links.partition_point(|l| l.end <= offset);
+        self.links
+            .get(idx)
+            .filter(|l| l.start <= offset)
+            .map(|l| l.link.clone())
+    }
+
+    // Moves the cursor back to checkpoint on the current line, so what's drawn next draws over
+    // the characters after it rather than following them
+    fn move_back(&mut self, to: &Checkpoint, struck: bool) {
+        let mut attrs = to.attrs.current.clone();
+        let mut changes = self
+            .attrs
+            .attrs
+            .get(to.attrs.len..)
+            .unwrap_or_default()
+            .iter()
+            .peekable();
+        let mut cells = VecDeque::new();
+        for (i, c) in self.text[to.offset..].char_indices() {
+            let offset = to.offset + i;
+            while let Some((_, change)) = changes.next_if(|(o, _)| *o <= offset) {
+                apply(&mut attrs, change);
+            }
+            cells.push_back(Cell {
+                c,
+                attrs: attrs.clone(),
+                link: self.link_at(offset),
+                struck,
+            });
+        }
+        let rest = mem::take(&mut self.overwritten);
+        self.rewind(to);
+        cells.extend(rest);
+        self.overwritten = cells;
+    }
+
+    // Draws c at the cursor, over whatever the cursor moved back over there
+    fn draw(&mut self, c: char) {
+        let offset = self.text.len();
+        let overstruck = self
+            .overwritten
+            .pop_front()
+            .and_then(|cell| cell.overstrike(c));
+        match overstruck {
+            Some((c, attrs)) => {
+                let current = self.attrs.current.clone();
+                self.last_char = Some(LastChar {
+                    offset,
+                    mark: Some(self.attrs.mark()),
+                });
+                self.attrs.push(offset, Change::AllAttributes(attrs));
+                self.text.push(c);
+                self.attrs
+                    .push(self.text.len(), Change::AllAttributes(current));
+            }
+            None => {
+                self.last_char = Some(LastChar { offset, mark: None });
+                self.text.push(c);
+            }
+        }
+    }
+
+    // Puts back what the cursor moved back over and nothing was drawn over, as it's left on the
+    // line once the cursor leaves it
+    fn keep_overwritten(&mut self) {
+        self.keep_overwritten_cells(usize::MAX);
+    }
+
+    // Puts back the first count cells the cursor moved back over, as when it moves forward over
+    // them without drawing
+    fn keep_overwritten_cells(&mut self, count: usize) {
+        if self.overwritten.is_empty() || count == 0 {
+            return;
+        }
+        let current = self.attrs.current.clone();
+        // The characters kept are in the links they were in before
+        let partial = self.partial_link.take();
+        if let Some((start, link)) = &partial {
+            if *start < self.text.len() {
+                self.links.push(LinkRange {
+                    start: *start,
+                    end: self.text.len(),
+                    link: link.clone(),
+                });
+            }
+        }
+        let count = count.min(self.overwritten.len());
+        let kept: Vec<Cell> = self.overwritten.drain(..count).collect();
+        for cell in kept {
+            let offset = self.text.len();
+            self.attrs.push(offset, Change::AllAttributes(cell.attrs));
+            self.text.push(cell.c);
+            let end = self.text.len();
+            if let Some(link) = cell.link {
+                match self.links.last_mut() {
+                    Some(last) if last.end == offset && last.link == link => last.end = end,
+                    _ => self.links.push(LinkRange {
+                        start: offset,
+                        end,
+                        link,
+                    }),
                 }
-                _ => (),
-            };
-        });
-        if let Some((start, link)) = partial_link {
-            complete_link(start, link, text.len());
+            }
         }
+        self.attrs
+            .push(self.text.len(), Change::AllAttributes(current));
+        self.partial_link = partial.map(|(_, link)| (self.text.len(), link));
+        self.last_char = None;
+    }
 
-        Ok(Document { text, attrs, links })
+    // Goes to where the cursor was moved to before drawing there
+    fn move_to_cursor(&mut self) {
+        if let Some(restored) = self.restored_cursor.take() {
+            // Whatever was drawn since the cursor was saved, like a status line or the last frame
+            // of a progress bar, is replaced rather than left in the middle of the text it
+            // interrupted
+            self.rewind(&restored);
+            if self.line.offset > restored.offset {
+                self.line = restored;
+            }
+            self.returned = false;
+        } else if self.returned {
+            let line = self.line.clone();
+            if self.options.collapse_overwrites {
+                // Anything drawn after this line started is replaced
+                self.rewind(&line);
+            } else {
+                self.move_back(&line, false);
+            }
+            self.returned = false;
+        }
+    }
+
+    fn perform(&mut self, action: Action) {
+        match action {
+            Print(c) => {
+                if let Some(c) = self.options.sanitize.filter(c) {
+                    self.move_to_cursor();
+                    self.draw(c);
+                }
+            }
+            Control(HorizontalTab) if self.options.tab_width > 0 => {
+                self.move_to_cursor();
+                let tab_width = self.options.tab_width;
+                let column = unicode_column_width(&self.text[self.line.offset..], None);
+                let spaces = tab_width - column % tab_width;
+                // Tabs move the cursor over what it moved back over without erasing it
+                let kept = spaces.min(self.overwritten.len());
+                self.keep_overwritten_cells(kept);
+                for _ in kept..spaces {
+                    self.draw(' ');
+                }
+            }
+            Control(LineFeed) => {
+                self.keep_overwritten();
+                self.text.push('\n');
+                self.line = self.checkpoint();
+                self.returned = false;
+                self.restored_cursor = None;
+                self.last_char = None;
+            }
+            // Backspacing over more than the last character isn't followed, as output only does
+            // that to overstrike
+            Control(Backspace) if !self.returned => {
+                if let Some(last) = self.last_char.take() {
+                    let attrs = last.mark.unwrap_or_else(|| self.attrs.mark());
+                    let to = Checkpoint {
+                        offset: last.offset,
+                        attrs,
+                    };
+                    self.move_back(&to, true);
+                }
+            }
+            Action::CSI(CSI::Edit(Edit::EraseInLine(
+                EraseInLine::EraseToEndOfLine | EraseInLine::EraseLine,
+            ))) => {
+                if self.returned {
+                    let line = self.line.clone();
+                    self.rewind(&line);
+                    self.returned = false;
+                }
+                self.overwritten.clear();
+            }
+            // Saving and restoring the cursor on the alternate screen is dropped with the rest
+            // of it
+            Action::Esc(Esc::Code(EscCode::DecSaveCursorPosition))
+            | Action::CSI(CSI::Cursor(Cursor::SaveCursor))
+            | Action::CSI(CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                DecPrivateModeCode::SaveCursor,
+            )))) if self.alternate_screen.is_none() => {
+                self.keep_overwritten();
+                self.saved_cursor = Some(self.checkpoint());
+            }
+            Action::Esc(Esc::Code(EscCode::DecRestoreCursorPosition))
+            | Action::CSI(CSI::Cursor(Cursor::RestoreCursor))
+            | Action::CSI(CSI::Mode(Mode::ResetDecPrivateMode(DecPrivateMode::Code(
+                DecPrivateModeCode::SaveCursor,
+            )))) if self.alternate_screen.is_none() => {
+                self.restored_cursor.clone_from(&self.saved_cursor);
+            }
+            Action::CSI(CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                DecPrivateModeCode::ClearAndEnableAlternateScreen
+                | DecPrivateModeCode::EnableAlternateScreen
+                | DecPrivateModeCode::OptEnableAlternateScreen,
+            )))) if self.alternate_screen.is_none() => {
+                // Nothing drawn there can overwrite what came before
+                self.keep_overwritten();
+                self.last_char = None;
+                self.line = self.checkpoint();
+                self.alternate_screen = Some(self.line.clone());
+                self.restored_cursor = None;
+            }
+            Action::CSI(CSI::Mode(Mode::ResetDecPrivateMode(DecPrivateMode::Code(
+                DecPrivateModeCode::ClearAndEnableAlternateScreen
+                | DecPrivateModeCode::EnableAlternateScreen
+                | DecPrivateModeCode::OptEnableAlternateScreen,
+            )))) => {
+                if let Some(alternate) = self.alternate_screen.take() {
+                    let skipped_bytes = self.text.len() - alternate.offset;
+                    self.rewind(&alternate);
+                    diagnose(
+                        &mut self.diagnostics,
+                        format!(
+                            "Skipped {} bytes of text drawn on the alternate screen at {}",
+                            skipped_bytes,
+                            self.text.len()
+                        ),
+                    );
+                    // A line of its own marks where the output was
+                    if !self.text.is_empty() && !self.text.ends_with('\n') {
+                        self.text.push('\n');
+                    }
+                    self.text.push_str(SKIPPED_FULL_SCREEN);
+                    self.text.push('\n');
+                    self.line = self.checkpoint();
+                    self.returned = false;
+                    self.restored_cursor = None;
+                }
+            }
+            Control(CarriageReturn) => {
+                self.returned = true;
+                self.last_char = None;
+            }
+            Action::CSI(CSI::Cursor(Cursor::CharacterAbsolute(col))) if col.as_one_based() == 1 => {
+                self.returned = true;
+                self.last_char = None;
+            }
+            Action::CSI(CSI::Sgr(s)) => {
+                let change = match s {
+                    Sgr::Reset => Change::AllAttributes(CellAttributes::default()),
+                    Sgr::Intensity(i) => Change::Attribute(AttributeChange::Intensity(i)),
+                    Sgr::Background(b) => Change::Attribute(AttributeChange::Background(b.into())),
+                    Sgr::Underline(u) => Change::Attribute(AttributeChange::Underline(u)),
+                    Sgr::Blink(b) => Change::Attribute(AttributeChange::Blink(b)),
+                    Sgr::Italic(i) => Change::Attribute(AttributeChange::Italic(i)),
+                    Sgr::Invisible(i) => Change::Attribute(AttributeChange::Invisible(i)),
+                    Sgr::StrikeThrough(s) => Change::Attribute(AttributeChange::StrikeThrough(s)),
+                    Sgr::Foreground(f) => Change::Attribute(AttributeChange::Foreground(f.into())),
+                    Sgr::Inverse(i) => Change::Attribute(AttributeChange::Reverse(i)),
+                    // termwiz has no AttributeChange for these, so they're changed along with
+                    // everything else in effect
+                    Sgr::UnderlineColor(c) => {
+                        let mut attrs = self.attrs.current.clone();
+                        attrs.set_underline_color(c);
+                        Change::AllAttributes(attrs)
+                    }
+                    Sgr::Overline(o) => {
+                        let mut attrs = self.attrs.current.clone();
+                        attrs.set_overline(o);
+                        Change::AllAttributes(attrs)
+                    }
+                    // TODO - add an Attribute change to termwiz for vertical align
+                    unsupported @ (Sgr::VerticalAlign(_) | Sgr::Font(_)) => {
+                        diagnose(
+                            &mut self.diagnostics,
+                            format!(
+                                "Ignored unsupported SGR {:?} at {}",
+                                unsupported,
+                                self.text.len()
+                            ),
+                        );
+                        return;
+                    }
+                };
+                // This isn't parsing by grapheme, which may put this change in the middle of one.
+                // We render by grapheme and changes in the middle of one will be applied
+                // afterwards.
+                // It's nonsensical to change graphical representation in the middle of a
+                // grapheme, so I don't think that's an issue.
+                // We do need to make sure to apply all graphical changes, not just those
+                // that land on grapheme boundaries
+                if let Some(last) = &mut self.last_char {
+                    // A backspace goes back to the attributes before the last character
+                    if last.mark.is_none() {
+                        last.mark = Some(self.attrs.mark());
+                    }
+                }
+                self.attrs.push(self.text.len(), change);
+            }
+            Action::OperatingSystemCommand(osc) => {
+                if let OperatingSystemCommand::SetHyperlink(parsed_link) = *osc {
+                    // SetHyperlink may have the current partial link in it.
+                    // We may have just ended the link that's in there, too.
+                    // We don't try to collapse repeated links into a single range.
+                    // Instead we assume the output repeated links for some reason and
+                    // faithfully recreate it, and starts_link groups them into one link.
+                    if let Some((start, link)) = self.partial_link.take() {
+                        self.links.push(LinkRange {
+                            start,
+                            link,
+                            end: self.text.len(),
+                        });
+                    }
+                    self.partial_link = parsed_link.map(|l| (self.text.len(), l));
+                }
+            }
+            _ => (),
+        };
     }
 }
 
@@ -104,13 +987,445 @@ impl Document {
 mod tests {
     use std::io::Cursor;
 
+    use termwiz::cell::Intensity;
+    use termwiz::color::{AnsiColor, ColorAttribute};
+
     use super::*;
 
+    const STRICT: ParseOptions = ParseOptions {
+        sanitize: Sanitize::Strict,
+        collapse_overwrites: false,
+        detect_links: false,
+        tab_width: 8,
+    };
+
     fn parse_links(input: &str) -> Vec<LinkRange> {
-        let doc = Document::new(Box::new(Cursor::new(input.to_string()))).unwrap();
+        let doc = Document::new(
+            Box::new(Cursor::new(input.to_string())),
+            ParseOptions::default(),
+        )
+        .unwrap();
         doc.links
     }
 
+    // The attributes the character at byte is drawn with
+    fn attrs_at(doc: &Document, byte: usize) -> CellAttributes {
+        let mut attrs = CellAttributes::default();
+        for (_, change) in doc.attrs.iter().take_while(|(b, _)| *b <= byte) {
+            apply(&mut attrs, change);
+        }
+        attrs
+    }
+
+    fn parse_attrs(input: &str) -> Vec<(usize, Change)> {
+        let doc = Document::new(
+            Box::new(Cursor::new(input.to_string())),
+            ParseOptions::default(),
+        )
+        .unwrap();
+        doc.attrs
+    }
+
+    #[test]
+    fn merge_repeated_attributes() {
+        // Every character resets and reapplies the same color
+        let attrs = parse_attrs("\x1b[m\x1b[31ma\x1b[m\x1b[31mb\x1b[m\x1b[31mc\x1b[m");
+        assert_eq!(
+            vec![
+                (0, Change::AllAttributes(CellAttributes::default())),
+                (
+                    0,
+                    Change::Attribute(AttributeChange::Foreground(AnsiColor::Maroon.into()))
+                ),
+                (3, Change::AllAttributes(CellAttributes::default())),
+            ],
+            attrs
+        );
+    }
+
+    #[test]
+    fn keep_distinct_attributes() {
+        let attrs = parse_attrs("a\x1b[1mb\x1b[31mc\x1b[32md");
+        assert_eq!(4, attrs.len());
+        assert_eq!(1, attrs[1].0);
+        assert_eq!(2, attrs[2].0);
+        assert_eq!(3, attrs[3].0);
+    }
+
+    #[test]
+    fn collapse_overwrites() {
+        let collapse = ParseOptions {
+            collapse_overwrites: true,
+            ..Default::default()
+        };
+        let input =
+            "start\r\n\x1b[32mWorking |\x1b[m\rWorking /\r\x1b[2K\x1b[1GWorking -\x1b[31m\n\
+            \x1b]8;;a\x1b\\1%\x1b]8;;\x1b\\ \x1b]8;;b\x1b\\of\r\x1b]8;;c\x1b\\done\x1b]8;;\x1b\\";
+        let doc = Document::new(Box::new(Cursor::new(input)), collapse).unwrap();
+        assert_eq!("start\nWorking -\ndone", doc.text);
+        // The green from the first frame is reset, and the red set after the last carries on
+        assert_eq!(
+            vec![
+                (0, Change::AllAttributes(CellAttributes::default())),
+                (
+                    15,
+                    Change::Attribute(AttributeChange::Foreground(AnsiColor::Maroon.into()))
+                ),
+            ],
+            doc.attrs
+        );
+        let links: Vec<_> = doc
+            .links
+            .iter()
+            .map(|l| (l.start, l.end, l.link.uri()))
+            .collect();
+        assert_eq!(vec![(16, 20, "c")], links);
+
+        // Without the option each frame draws over the last like on a terminal, leaving the end
+        // of a longer one
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        assert_eq!("start\nWorking -\ndonef", doc.text);
+        let links: Vec<_> = doc
+            .links
+            .iter()
+            .map(|l| (l.start, l.end, l.link.uri()))
+            .collect();
+        assert_eq!(vec![(16, 20, "c"), (20, 21, "b")], links);
+    }
+
+    #[test]
+    fn erase_over_saved_cursor() {
+        // Erasing the line drops where the cursor was saved on it, so restoring it can't go back
+        // past what's left
+        let input = "ab\x1b7\r\x1b[K\u{2501}\u{2501}\x1b8 done\n";
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        assert_eq!("\u{2501}\u{2501} done\n", doc.text);
+    }
+
+    #[test]
+    fn collapse_over_saved_cursor() {
+        let collapse = ParseOptions {
+            collapse_overwrites: true,
+            ..Default::default()
+        };
+        let input = "ab\x1b7\r\u{2501}\u{2501}\x1b8 done\n";
+        let doc = Document::new(Box::new(Cursor::new(input)), collapse).unwrap();
+        assert_eq!("\u{2501}\u{2501} done\n", doc.text);
+    }
+
+    #[test]
+    fn restore_attributes_at_saved_cursor() {
+        let input = "\x1b[1m\x1b7\x1b[mstatus\x1b8\x1b[32mok\n";
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        assert_eq!("ok\n", doc.text);
+        let offsets: Vec<usize> = doc.attrs.iter().map(|(offset, _)| *offset).collect();
+        assert!(offsets.is_sorted(), "{:?}", offsets);
+        assert!(offsets.iter().all(|&o| o < doc.text.len()), "{:?}", offsets);
+        let mut green = CellAttributes::default();
+        green.set_foreground(AnsiColor::Green);
+        assert_eq!(green, attrs_at(&doc, 0));
+    }
+
+    #[test]
+    fn expand_tabs() {
+        let parse = |input: &str, tab_width| {
+            let options = ParseOptions {
+                tab_width,
+                ..Default::default()
+            };
+            Document::new(Box::new(Cursor::new(input.to_string())), options)
+                .unwrap()
+                .text
+        };
+        let input = "a\tb\n\tc\n12345678\td\n\u{4e2d}\te\nabc\r\tf\n";
+        assert_eq!(
+            "a       b\n        c\n12345678        d\n\u{4e2d}      e\nabc     f\n",
+            parse(input, 8)
+        );
+        assert_eq!("a   b\n", parse("a\tb\n", 4));
+        assert_eq!("ab\n", parse("a\tb\n", 0));
+    }
+
+    #[test]
+    fn overstrike_and_return() {
+        let input = "N\x08NA\x08A _\x08fo\x08_ _\x08b\x08b a\x08c\n\
+            \x1b[31mabc\x1b[m\rX\r\n";
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        assert_eq!("NA fo b c\nXbc\n", doc.text);
+        let mut bold = CellAttributes::default();
+        bold.set_intensity(Intensity::Bold);
+        let mut underlined = CellAttributes::default();
+        underlined.set_underline(Underline::Single);
+        let mut both = bold.clone();
+        both.set_underline(Underline::Single);
+        let mut red = CellAttributes::default();
+        red.set_foreground(AnsiColor::Maroon);
+        let plain = CellAttributes::default();
+        let drawn: Vec<_> = (0..doc.text.len()).map(|b| attrs_at(&doc, b)).collect();
+        assert_eq!(
+            vec![
+                &bold,
+                &bold,
+                &plain,
+                &underlined,
+                &underlined,
+                &plain,
+                &both,
+                &plain,
+                &plain,
+                &plain,
+                &plain,
+                &red,
+                &red,
+                &plain,
+            ],
+            drawn.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn parse_incrementally() {
+        let start = "é\x1b[1mbold\x1b]8;;a\x1b\\link";
+        let end = " more\x1b]8;;\x1b\\ after\n";
+        let mut builder = DocumentBuilder::new(ParseOptions::default());
+        // Split in the middle of every character and escape sequence
+        for b in start.bytes() {
+            builder.feed(&[b]);
+        }
+        let doc = builder.snapshot();
+        assert_eq!("éboldlink", doc.text);
+        // The link so far ends where the text does
+        assert_eq!(
+            vec![(6, 10, "a")],
+            doc.links
+                .iter()
+                .map(|l| (l.start, l.end, l.link.uri()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, doc.line_count);
+
+        builder.feed(end.as_bytes());
+        let doc = builder.finish();
+        let whole = Document::new(
+            Box::new(Cursor::new(format!("{start}{end}"))),
+            ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(whole.text, doc.text);
+        assert_eq!(whole.attrs, doc.attrs);
+        assert_eq!(
+            vec![(6, 15)],
+            doc.links
+                .iter()
+                .map(|l| (l.start, l.end))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(whole.input_bytes, doc.input_bytes);
+        assert_eq!(1, doc.line_count);
+    }
+
+    #[test]
+    fn report_where_snapshots_were_rewritten() {
+        let mut builder = DocumentBuilder::new(ParseOptions::default());
+        builder.feed(b"done\n10%");
+        assert_eq!(0, builder.snapshot().unchanged_before);
+        builder.feed(b"\r20%");
+        assert_eq!(5, builder.snapshot().unchanged_before);
+        builder.feed(b"\n");
+        assert_eq!(8, builder.snapshot().unchanged_before);
+
+        // A dropped snapshot's rewrite counts for the next one
+        builder.feed(b"30%\r");
+        let dropped = builder.snapshot();
+        builder.feed(b"40%");
+        builder.unsent(&dropped);
+        builder.feed(b" more");
+        let doc = builder.finish();
+        assert_eq!("done\n20%\n40% more", doc.text);
+        assert_eq!(9, doc.unchanged_before);
+    }
+
+    #[test]
+    fn detect_plain_links() {
+        let options = ParseOptions {
+            detect_links: true,
+            ..Default::default()
+        };
+        let mut builder = DocumentBuilder::new(options);
+        builder.feed(b"see https://a.b and \x1b]8;;x\x1b\\https://c.d\x1b]8;;\x1b\\ then http");
+        // Links are detected in what's been read so far, and again once more has been
+        let uris = |doc: &Document| -> Vec<String> {
+            doc.links.iter().map(|l| l.link.uri().to_string()).collect()
+        };
+        assert_eq!(vec!["https://a.b", "x"], uris(&builder.snapshot()));
+        builder.feed(b"://e.f");
+        assert_eq!(
+            vec!["https://a.b", "x", "http://e.f"],
+            uris(&builder.finish())
+        );
+
+        // Or once the text's been parsed without them
+        let mut doc = Document::new(
+            Box::new(Cursor::new(
+                "see https://a.b and \x1b]8;;x\x1b\\https://c.d\x1b]8;;\x1b\\",
+            )),
+            ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(vec!["x"], uris(&doc));
+        doc.detect_links();
+        assert_eq!(vec!["https://a.b", "x"], uris(&doc));
+    }
+
+    #[test]
+    fn skip_out_of_band_output() {
+        // A status line drawn between a save and restore is overwritten by what follows,
+        // while the last frame of an in-place progress bar is kept
+        let input = "log \x1b7\x1b[1;1H\x1b[1mstatus\x1b8line\n\x1b[s10%\x1b[u50%\x1b[udone\n\
+            before\x1b[?1049h\x1b]8;;a\x1b\\menu\x1b]8;;\x1b\\\x1b7x\x1b8\x1b[?1049lafter\x1b7left";
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        assert_eq!(
+            format!("log line\ndone\nbefore\n{SKIPPED_FULL_SCREEN}\nafterleft"),
+            doc.text
+        );
+        assert!(doc.links.is_empty());
+        // The bold from the status line remains in effect
+        let mut bold = CellAttributes::default();
+        bold.set_intensity(Intensity::Bold);
+        assert_eq!(
+            vec![
+                (0, Change::AllAttributes(CellAttributes::default())),
+                (4, Change::AllAttributes(bold)),
+            ],
+            doc.attrs
+        );
+        assert_eq!(1, doc.diagnostics.len());
+
+        // Output that never leaves the alternate screen is kept
+        let doc = Document::new(
+            Box::new(Cursor::new("\x1b[?1049hmenu")),
+            ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!("menu", doc.text);
+    }
+
+    #[test]
+    fn record_unsupported_sgr() {
+        // Font 1, superscript
+        let doc = Document::new(
+            Box::new(Cursor::new("a\x1b[11mb\x1b[73mc")),
+            ParseOptions::default(),
+        )
+        .unwrap();
+        assert_eq!("abc", doc.text);
+        assert_eq!(2, doc.diagnostics.len());
+    }
+
+    #[test]
+    fn parse_underline_color_and_overline() {
+        let input = "a\x1b[4;58;5;1mb\x1b[53mc\x1b[59md\x1b[55me\x1b[mf";
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        assert!(doc.diagnostics.is_empty(), "{:?}", doc.diagnostics);
+        let mut red = CellAttributes::default();
+        red.set_underline(Underline::Single)
+            .set_underline_color(AnsiColor::Maroon);
+        let mut over = red.clone();
+        over.set_overline(true);
+        let mut plain_over = over.clone();
+        plain_over.set_underline_color(ColorAttribute::Default);
+        let mut underlined = CellAttributes::default();
+        underlined.set_underline(Underline::Single);
+        let plain = CellAttributes::default();
+        assert_eq!(
+            vec![plain.clone(), red, over, plain_over, underlined, plain],
+            (0..doc.text.len())
+                .map(|b| attrs_at(&doc, b))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn sanitize_controls() {
+        let input = "a\x7fb\u{9c}c\u{9b}31md\u{202e}e";
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        assert_eq!("abcd\u{202e}e", doc.text);
+        let doc = Document::new(Box::new(Cursor::new(input)), STRICT).unwrap();
+        assert_eq!("abcd\u{fffd}e", doc.text);
+        // Joiners are needed for emoji
+        let input = "\u{1f468}\u{200d}\u{1f469}\u{200b}";
+        let doc = Document::new(Box::new(Cursor::new(input)), STRICT).unwrap();
+        assert_eq!("\u{1f468}\u{200d}\u{1f469}\u{fffd}", doc.text);
+    }
+
+    #[test]
+    fn strip_message_controls() {
+        assert_eq!("[31mred", strip_controls("\x1b[31mred\u{9b}"));
+    }
+
+    #[test]
+    fn slice_between_chars() {
+        let doc = Document::new(Box::new(Cursor::new("aé中b")), ParseOptions::default()).unwrap();
+        assert_eq!("aé中b", doc.text_between(0, 7));
+        assert_eq!("é", doc.text_between(2, 2));
+        assert_eq!("é中", doc.text_between(2, 4));
+        assert_eq!("中b", doc.text_between(4, 100));
+        assert_eq!("", doc.text_between(100, 200));
+        assert_eq!("", doc.text_between(5, 3));
+
+        // Offsets inside a cluster widen to all of it
+        let text = "ae\u{301}b 👩\u{200d}💻\nx";
+        let doc = Document::new(Box::new(Cursor::new(text)), ParseOptions::default()).unwrap();
+        assert_eq!("e\u{301}", doc.text_between(2, 3));
+        assert_eq!("e\u{301}b", doc.text_between(3, 5));
+        assert_eq!((1, 4), (doc.grapheme_start(3), doc.grapheme_end(2)));
+        assert_eq!((4, 4), (doc.grapheme_start(4), doc.grapheme_end(4)));
+        assert_eq!("👩\u{200d}💻", doc.text_between(10, 11));
+        assert_eq!("👩\u{200d}💻\n", doc.text_between(16, 18));
+        assert_eq!("x", doc.text_between(18, 19));
+    }
+
+    #[test]
+    fn parse_malformed_input() {
+        let inputs: [&[u8]; 6] = [
+            b"\xff\xfe invalid utf-8 \xc3",
+            b"truncated \x1b[",
+            b"truncated link \x1b]8;;http://a.b",
+            b"\x1b[99999999999999999999999m huge parameter",
+            b"\x1b[38;5;999999m\x1b[48;2;1m bad colors",
+            b"\x1b]8;id=;\x1b\\\x1b]8;;\x1b\\ empty link",
+        ];
+        for input in inputs {
+            Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        }
+    }
+
+    #[test]
+    fn parse_arbitrary_bytes() {
+        // xorshift so the bytes are arbitrary but the same every run.
+        // Weight toward bytes that show up in escape sequences to get through the parser more.
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let alphabet = b"\x1b\x07\x9b\x9d\\[]();:?0123456789m8;hHab_\x08\n\r\t\xc3\xa9\xff";
+        for _ in 0..200 {
+            let mut input = vec![];
+            for _ in 0..256 {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                let byte = (seed >> 32) as u8;
+                input.push(if byte & 1 == 0 {
+                    alphabet[byte as usize % alphabet.len()]
+                } else {
+                    byte
+                });
+            }
+            let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+            for link in doc.links {
+                assert!(link.start <= link.end && link.end <= doc.text.len());
+            }
+        }
+    }
+
     #[test]
     fn parse_zero_length_link() {
         let links = parse_links("\x1b]8;;http://a.b\x1b\\\x1b]8;;\x1b\\After zero length link");
@@ -129,6 +1444,30 @@ mod tests {
         assert_eq!(11, links[1].end);
     }
 
+    #[test]
+    fn group_split_links() {
+        let input = "\x1b]8;id=1;a\x1b\\one\x1b]8;;\x1b\\ x\n\
+            \x1b]8;id=1;a\x1b\\ more\x1b]8;;\x1b\\ \
+            \x1b]8;id=1;b\x1b\\b\x1b]8;;\x1b\\ \
+            \x1b]8;;c\x1b\\c\x1b[1mc\x1b]8;;c\x1b\\c\x1b]8;;\x1b\\ \x1b]8;;c\x1b\\c\x1b]8;;\x1b\\";
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        let starts: Vec<usize> = (0..doc.links.len())
+            .filter(|&i| doc.starts_link(i))
+            .collect();
+        // The same id carries a link on past unlinked text, but only to the same address
+        assert_eq!(vec![0, 2, 3, 5], starts);
+        assert_eq!(0..2, doc.link_group(1));
+        assert_eq!("one more", doc.link_text(0));
+        assert_eq!(vec![(0, 3), (6, 11)], doc.link_spans(1));
+        assert_eq!("b", doc.link_text(2));
+
+        // Ranges to the same address without an id are one link while they touch
+        assert_eq!(3..5, doc.link_group(4));
+        assert_eq!("ccc", doc.link_text(3));
+        assert_eq!(vec![(14, 17)], doc.link_spans(3));
+        assert_eq!("c", doc.link_text(5));
+    }
+
     #[test]
     fn parse_a_link() {
         let input = "Before\x1b]8;;http://example.com\x1b\\Link to example";
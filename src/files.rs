@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+
+// The files given on the command line, which are viewed one at a time.
+// Empty when viewing standard input.
+#[derive(Default)]
+pub struct Files {
+    paths: Vec<PathBuf>,
+    current: usize,
+    // Where the top of each file was when it was last left, to go back to on returning to it
+    positions: Vec<Option<usize>>,
+    // The file a command asked to switch to, until it's opened
+    requested: Option<usize>,
+}
+
+impl Files {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            positions: vec![None; paths.len()],
+            paths,
+            current: 0,
+            requested: None,
+        }
+    }
+
+    // Picks up viewing paths at current, going back to where each was left once it's shown
+    pub fn resume(paths: Vec<PathBuf>, positions: Vec<Option<usize>>, current: usize) -> Self {
+        Self {
+            paths,
+            current,
+            positions,
+            requested: None,
+        }
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    // Where each file was left, with the current one at top
+    pub fn positions(&self, top: usize) -> Vec<Option<usize>> {
+        let mut positions = self.positions.clone();
+        if let Some(position) = positions.get_mut(self.current) {
+            *position = Some(top);
+        }
+        positions
+    }
+
+    pub fn path(&self, file: usize) -> &Path {
+        &self.paths[file]
+    }
+
+    // The file being viewed, or None when viewing standard input
+    pub fn current_path(&self) -> Option<&Path> {
+        self.paths.get(self.current).map(PathBuf::as_path)
+    }
+
+    // The file being viewed and where it is among the others, like "a.txt (2 of 3)"
+    pub fn describe(&self) -> Option<String> {
+        let path = self.paths.get(self.current)?.display();
+        Some(match self.paths.len() {
+            1 => path.to_string(),
+            len => format!("{path} ({} of {len})", self.current + 1),
+        })
+    }
+
+    // Asks to switch to the file offset after the current one.
+    // Returns false if there's no file there.
+    pub fn request(&mut self, offset: isize) -> bool {
+        match self.current.checked_add_signed(offset) {
+            Some(file) if file < self.paths.len() => {
+                self.requested = Some(file);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn take_requested(&mut self) -> Option<usize> {
+        self.requested.take()
+    }
+
+    // Switches to file, leaving the current one with top at the top of the screen
+    pub fn switch(&mut self, file: usize, top: usize) {
+        self.positions[self.current] = Some(top);
+        self.current = file;
+    }
+
+    // Where the top of the current file was when it was last left, if it's been viewed before
+    pub fn left_at(&self) -> Option<usize> {
+        self.positions.get(self.current).copied().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_between_files() {
+        let mut files = Files::new(vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")]);
+        assert_eq!(Some("a.txt (1 of 2)".to_string()), files.describe());
+        assert!(!files.request(-1));
+        assert_eq!(None, files.take_requested());
+        assert!(files.request(1));
+        assert_eq!(Some(1), files.take_requested());
+        assert_eq!(None, files.take_requested());
+
+        files.switch(1, 30);
+        assert_eq!(None, files.left_at());
+        assert_eq!(Some("b.txt (2 of 2)".to_string()), files.describe());
+        assert!(!files.request(1));
+        files.switch(0, 5);
+        assert_eq!(Some(30), files.left_at());
+        files.switch(1, 0);
+        assert_eq!(Some(5), files.left_at());
+        assert_eq!(vec![Some(0), Some(7)], files.positions(7));
+
+        let resumed = Files::resume(files.paths().to_vec(), vec![None, Some(9)], 1);
+        assert_eq!(Some(9), resumed.left_at());
+        assert_eq!(Some("b.txt (2 of 2)".to_string()), resumed.describe());
+
+        assert_eq!(None, Files::default().describe());
+        let one = Files::new(vec![PathBuf::from("a.txt")]);
+        assert_eq!(Some("a.txt".to_string()), one.describe());
+    }
+}
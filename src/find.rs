@@ -0,0 +1,255 @@
+use regex::{Regex, RegexBuilder};
+
+/// A query compiled for matching.
+/// Queries are regexes when searching with regexes and otherwise matched as they're written, and
+/// they match regardless of case unless they have a capital letter.
+///
+/// ```
+/// use ate_pager::Pattern;
+///
+/// assert!(Pattern::new("error", false).is_match("ERROR: disk full"));
+/// assert!(!Pattern::new("Error", false).is_match("ERROR: disk full"));
+/// assert_eq!(vec![(0, 3), (4, 7)], Pattern::new("a.c", true).find_all("abc a.c"));
+/// // Invalid regexes are matched as they're written
+/// assert_eq!("invalid regex", Pattern::new("a(b", true).mode());
+/// ```
+pub struct Pattern {
+    regex: Regex,
+    // Whether the query is matched as a regex
+    is_regex: bool,
+    // Whether the query was meant as a regex but isn't a valid one, so it's matched as written
+    invalid: bool,
+    case_sensitive: bool,
+}
+
+impl Pattern {
+    /// Compiles query, as a regex if regex is set
+    pub fn new(query: &str, regex: bool) -> Self {
+        let case_sensitive = has_capital(query, regex);
+        let build = |pattern: &str| {
+            // ^ and $ match at the start and end of each line of the text searched
+            RegexBuilder::new(pattern)
+                .case_insensitive(!case_sensitive)
+                .multi_line(true)
+                .build()
+        };
+        let literal = || build(&regex::escape(query)).expect("escaped text is a valid regex");
+        let (compiled, invalid) = if regex {
+            match build(query) {
+                Ok(r) => (r, false),
+                // Likely a regex that's only partly typed, like "a(b"
+                Err(_) => (literal(), true),
+            }
+        } else {
+            (literal(), false)
+        };
+        Self {
+            regex: compiled,
+            is_regex: regex && !invalid,
+            invalid,
+            case_sensitive,
+        }
+    }
+
+    /// Whether the query matches anywhere in text
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+
+    /// Byte ranges of each match in text, in order and leaving out empty matches
+    pub fn find_all(&self, text: &str) -> Vec<(usize, usize)> {
+        self.regex
+            .find_iter(text)
+            .filter(|m| m.start() < m.end())
+            .map(|m| (m.start(), m.end()))
+            .collect()
+    }
+
+    /// How the query is being matched for showing in prompts, like "regex, case-sensitive".
+    /// Empty for text matched as written regardless of case.
+    pub fn mode(&self) -> String {
+        let mut modes = vec![];
+        if self.is_regex {
+            modes.push("regex");
+        } else if self.invalid {
+            modes.push("invalid regex");
+        }
+        if self.case_sensitive {
+            modes.push("case-sensitive");
+        }
+        modes.join(", ")
+    }
+}
+
+// Whether query has a capital letter, skipping the letters of escapes like \S in regexes
+fn has_capital(query: &str, regex: bool) -> bool {
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        if regex && c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+/// Where some text occurs anywhere in the document, for finding text that isn't in a link
+///
+/// ```
+/// use ate_pager::TextSearch;
+///
+/// let mut search = TextSearch::new("ab".to_string(), false, "ab cd ab");
+/// assert_eq!(&[(0, 2), (6, 8)], search.matches());
+/// search.select_from(3);
+/// assert_eq!(Some((6, 8)), search.current());
+/// // Moving on past the last match wraps around to the first
+/// search.select_next();
+/// assert_eq!(Some(1), search.position());
+/// ```
+pub struct TextSearch {
+    query: String,
+    pattern: Pattern,
+    // Byte ranges of each occurrence, in order and not overlapping
+    matches: Vec<(usize, usize)>,
+    // The index of the match n and N last moved to
+    current: Option<usize>,
+}
+
+impl TextSearch {
+    /// Finds query in text, as a regex if regex is set.
+    /// No match is current until one's selected.
+    pub fn new(query: String, regex: bool, text: &str) -> Self {
+        let pattern = Pattern::new(&query, regex);
+        let matches = find_all(&query, &pattern, text);
+        Self {
+            query,
+            pattern,
+            matches,
+            current: None,
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Byte ranges of each match, in order and not overlapping
+    pub fn matches(&self) -> &[(usize, usize)] {
+        &self.matches
+    }
+
+    /// The byte range of the match n and N last moved to
+    pub fn current(&self) -> Option<(usize, usize)> {
+        self.current.map(|idx| self.matches[idx])
+    }
+
+    /// Where the current match is among all of them, counting from 1
+    pub fn position(&self) -> Option<usize> {
+        self.current.map(|idx| idx + 1)
+    }
+
+    /// Moves to the first match starting at or after byte, wrapping around to the first match
+    pub fn select_from(&mut self, byte: usize) {
+        if self.matches.is_empty() {
+            return;
+        }
+        let idx = self.matches.partition_point(|&(start, _)| start < byte);
+        self.current = Some(if idx == self.matches.len() { 0 } else { idx });
+    }
+
+    /// Moves to the match after the current one, wrapping around to the first
+    pub fn select_next(&mut self) {
+        let len = self.matches.len();
+        if len > 0 {
+            self.current = Some(self.current.map_or(0, |idx| (idx + 1) % len));
+        }
+    }
+
+    /// Moves to the match before the current one, wrapping around to the last
+    pub fn select_prev(&mut self) {
+        let len = self.matches.len();
+        if len > 0 {
+            self.current = Some(self.current.map_or(len - 1, |idx| (idx + len - 1) % len));
+        }
+    }
+
+    /// Finds the matches again in text that's grown, keeping the current one
+    pub fn update(&mut self, text: &str) {
+        let current = self.current();
+        self.matches = find_all(&self.query, &self.pattern, text);
+        self.current = current.and_then(|m| self.matches.binary_search(&m).ok());
+    }
+}
+
+fn find_all(query: &str, pattern: &Pattern, text: &str) -> Vec<(usize, usize)> {
+    if query.is_empty() {
+        return vec![];
+    }
+    pattern.find_all(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_through_matches() {
+        let mut search = TextSearch::new("ab".to_string(), false, "ab abab\nxab");
+        assert_eq!(&[(0, 2), (3, 5), (5, 7), (9, 11)], search.matches());
+        assert_eq!(None, search.current());
+        search.select_from(4);
+        assert_eq!(Some((5, 7)), search.current());
+        assert_eq!(Some(3), search.position());
+        search.select_next();
+        search.select_next();
+        assert_eq!(Some((0, 2)), search.current());
+        search.select_prev();
+        assert_eq!(Some((9, 11)), search.current());
+        // Past the last match wraps around to the first
+        search.select_from(10);
+        assert_eq!(Some((0, 2)), search.current());
+
+        search.select_from(3);
+        search.update("ab abab\nxab ab");
+        assert_eq!(5, search.matches().len());
+        assert_eq!(Some((3, 5)), search.current());
+
+        let mut empty = TextSearch::new(String::new(), false, "ab");
+        empty.select_next();
+        assert!(empty.matches().is_empty());
+        assert_eq!(None, empty.current());
+    }
+
+    #[test]
+    fn match_regexes_and_case() {
+        let matched = |query: &str, regex: bool| {
+            let pattern = Pattern::new(query, regex);
+            (pattern.find_all("Ab ab a.b a(b"), pattern.mode())
+        };
+        let mode = |m: &str| m.to_string();
+        assert_eq!((vec![(0, 2), (3, 5)], mode("")), matched("ab", false));
+        assert_eq!((vec![(0, 2)], mode("case-sensitive")), matched("Ab", false));
+        assert_eq!((vec![(6, 9)], mode("")), matched("a.b", false));
+        assert_eq!(
+            (vec![(0, 2), (3, 5), (6, 9), (10, 13)], mode("regex")),
+            matched("a.?b", true)
+        );
+        assert_eq!(
+            (vec![(0, 2)], mode("regex, case-sensitive")),
+            matched("^Ab", true)
+        );
+        // Escapes like \S aren't capitals
+        assert_eq!(
+            (vec![(6, 9), (10, 13)], mode("regex")),
+            matched(r"a\Sb", true)
+        );
+        // Regexes that don't compile yet are matched as written
+        assert_eq!(
+            (vec![(10, 13)], mode("invalid regex")),
+            matched("a(b", true)
+        );
+        // Empty matches aren't matches
+        assert!(matched("x*", true).0.is_empty());
+    }
+}
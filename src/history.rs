@@ -0,0 +1,158 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use log::warn;
+
+// Queries beyond this many are dropped, oldest first
+pub const MAX_QUERIES: usize = 500;
+
+// Queries searched for, which the prompts can recall, oldest first.
+// Searches for links and text share the history, and a query is only kept once.
+#[derive(Default)]
+pub struct History {
+    // Where queries are saved for later runs, or None to only keep them in memory
+    path: Option<PathBuf>,
+    queries: Vec<String>,
+    // The query being recalled and what had been typed before recalling started
+    recalled: Option<(usize, String)>,
+}
+
+impl History {
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let queries = match &path {
+            Some(path) => read_queries(path).unwrap_or_else(|e| {
+                warn!("Unable to read search history from {:?}: {}", path, e);
+                vec![]
+            }),
+            None => vec![],
+        };
+        Self {
+            path,
+            queries,
+            recalled: None,
+        }
+    }
+
+    // Records query as the latest and saves it for the next run
+    pub fn add(&mut self, query: &str) -> Result<()> {
+        self.recalled = None;
+        if query.is_empty() {
+            return Ok(());
+        }
+        push(&mut self.queries, query);
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // Other runs may have searched since we loaded, so add to what's in the file
+        let mut saved = read_queries(path)?;
+        push(&mut saved, query);
+        let contents: String = saved.iter().map(|q| format!("{q}\n")).collect();
+        if let Err(e) = fs::write(path, contents) {
+            bail!("Unable to save search history to {:?}: {}", path, e);
+        }
+        Ok(())
+    }
+
+    // The query before the one being recalled, or the latest one if typed is what's in the
+    // prompt now.
+    // None if there's nothing older.
+    pub fn older(&mut self, typed: &str) -> Option<&str> {
+        let idx = match &self.recalled {
+            None => self.queries.len().checked_sub(1)?,
+            Some((idx, _)) => idx.checked_sub(1)?,
+        };
+        let typed = match self.recalled.take() {
+            Some((_, typed)) => typed,
+            None => typed.to_string(),
+        };
+        self.recalled = Some((idx, typed));
+        Some(&self.queries[idx])
+    }
+
+    // The query after the one being recalled, or what was typed before recalling past the
+    // latest.
+    // None if nothing's being recalled.
+    pub fn newer(&mut self) -> Option<String> {
+        let (idx, typed) = self.recalled.take()?;
+        if idx + 1 < self.queries.len() {
+            self.recalled = Some((idx + 1, typed));
+            Some(self.queries[idx + 1].clone())
+        } else {
+            Some(typed)
+        }
+    }
+
+    // Called when the query's edited, so the next recall starts from the latest query again
+    pub fn stop_recalling(&mut self) {
+        self.recalled = None;
+    }
+}
+
+fn push(queries: &mut Vec<String>, query: &str) {
+    queries.retain(|q| q != query);
+    queries.push(query.to_string());
+    if queries.len() > MAX_QUERIES {
+        queries.drain(..queries.len() - MAX_QUERIES);
+    }
+}
+
+// Each line is a query, which can't have a line break in it
+fn read_queries(path: &PathBuf) -> Result<Vec<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .filter(|q| !q.is_empty())
+            .map(str::to_string)
+            .collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => bail!("{}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recall_queries() {
+        let mut history = History::load(None);
+        assert_eq!(None, history.older("typed"));
+        assert_eq!(None, history.newer());
+        for query in ["a", "b", "", "c", "a"] {
+            history.add(query).unwrap();
+        }
+        // Searching again for a query moves it to the latest
+        assert_eq!(Some("a"), history.older("typed"));
+        assert_eq!(Some("c"), history.older("ignored"));
+        assert_eq!(Some("b"), history.older(""));
+        assert_eq!(None, history.older(""));
+        assert_eq!(Some("c".to_string()), history.newer());
+        assert_eq!(Some("a".to_string()), history.newer());
+        assert_eq!(Some("typed".to_string()), history.newer());
+        assert_eq!(None, history.newer());
+
+        history.older("x");
+        history.stop_recalling();
+        assert_eq!(Some("a"), history.older("y"));
+    }
+
+    #[test]
+    fn save_queries() {
+        let path = std::env::temp_dir().join(format!("ate-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut first = History::load(Some(path.clone()));
+        first.add("one").unwrap();
+        // Another run's searches are kept when this one saves
+        let mut second = History::load(Some(path.clone()));
+        second.add("two").unwrap();
+        first.add("three").unwrap();
+        assert_eq!("one\ntwo\nthree\n", fs::read_to_string(&path).unwrap());
+
+        let mut loaded = History::load(Some(path.clone()));
+        assert_eq!(Some("three"), loaded.older(""));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+use std::io::{self, ErrorKind, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::warn;
+use termwiz::terminal::TerminalWaker;
+
+use crate::doc::{Document, DocumentBuilder, ParseOptions, READ_SIZE};
+use crate::perf::timed;
+
+// The document read and parsed from input so far
+pub struct Loaded {
+    pub doc: Result<Document, termwiz::Error>,
+    // How long parsing has taken
+    pub parse_time: Duration,
+    // How long input has been read for, including waiting on it
+    pub read_time: Duration,
+    // Whether input has ended, so nothing more will be loaded
+    pub complete: bool,
+    // Whether the same text comes next with links found in it, as finding them in a big
+    // document takes a while
+    pub indexing_links: bool,
+}
+
+// The document as read so far is sent at most this often while input keeps arriving.
+// Copying a big document to send takes a while, so it's sent less often the longer that takes.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_SNAPSHOT_SHARE: u32 = 10;
+
+// Reads input on its own thread and parses it on another as it arrives, so the UI can show what's
+// arrived so far while a pipe or FIFO is slow to produce the rest.
+// The document so far is sent on the returned receiver as it grows, followed by the whole
+// document once input ends, waking the terminal's input poll each time.
+// When links are detected, each document is sent first without the ones detected, so the text
+// can be shown sooner, and again with them.
+pub fn load_in_background(
+    input: Box<dyn Read + Send>,
+    options: ParseOptions,
+    waker: TerminalWaker,
+) -> Result<Receiver<Loaded>> {
+    let (chunk_sender, chunks) = mpsc::channel();
+    thread::Builder::new()
+        .name("read".to_string())
+        .spawn(move || read_chunks(input, chunk_sender))?;
+    // Only holding one update means a busy UI gets the latest document rather than a backlog
+    let (sender, receiver) = mpsc::sync_channel(1);
+    thread::Builder::new()
+        .name("input".to_string())
+        .spawn(move || parse_chunks(chunks, options, sender, waker))?;
+    Ok(receiver)
+}
+
+fn read_chunks(mut input: Box<dyn Read + Send>, chunks: mpsc::Sender<io::Result<Vec<u8>>>) {
+    let mut buf = vec![0; READ_SIZE];
+    loop {
+        let chunk = match input.read(&mut buf) {
+            // Dropping the sender tells the parser input ended
+            Ok(0) => return,
+            Ok(read) => Ok(buf[..read].to_vec()),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => Err(e),
+        };
+        let failed = chunk.is_err();
+        // The parser has already gone away if nothing's receiving
+        if chunks.send(chunk).is_err() || failed {
+            return;
+        }
+    }
+}
+
+// Wakes the UI if parsing panics, so it finds the document isn't coming rather than awaiting it
+struct WakeOnPanic(TerminalWaker);
+
+impl Drop for WakeOnPanic {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            if let Err(e) = self.0.wake() {
+                warn!("Unable to wake for failed input: {}", e);
+            }
+        }
+    }
+}
+
+fn parse_chunks(
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    options: ParseOptions,
+    sender: SyncSender<Loaded>,
+    waker: TerminalWaker,
+) {
+    let started = Instant::now();
+    let detect_links = options.detect_links;
+    let mut builder = DocumentBuilder::new(ParseOptions {
+        detect_links: false,
+        ..options
+    });
+    let mut parse_time = Duration::ZERO;
+    // When the document was last sent, and whether it's grown since
+    let mut sent = started;
+    let mut changed = false;
+    let mut interval = UPDATE_INTERVAL;
+    let _woken_on_panic = WakeOnPanic(waker.clone());
+    let wake = || {
+        if let Err(e) = waker.wake() {
+            warn!("Unable to wake for loaded input: {}", e);
+        }
+    };
+    loop {
+        let received = if changed {
+            chunks.recv_timeout(interval.saturating_sub(sent.elapsed()))
+        } else {
+            chunks.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        match received {
+            Ok(Ok(chunk)) => {
+                parse_time += timed(|| builder.feed(&chunk)).1;
+                changed = true;
+            }
+            Ok(Err(e)) => {
+                let loaded = Loaded {
+                    doc: Err(e.into()),
+                    parse_time,
+                    read_time: started.elapsed(),
+                    complete: true,
+                    indexing_links: false,
+                };
+                if sender.send(loaded).is_ok() {
+                    wake();
+                }
+                return;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                let (doc, finish_time) = timed(|| builder.finish());
+                let (parse_time, read_time) = (parse_time + finish_time, started.elapsed());
+                let indexed = detect_links.then(|| doc.clone());
+                let loaded = Loaded {
+                    doc: Ok(doc),
+                    parse_time,
+                    read_time,
+                    complete: true,
+                    indexing_links: indexed.is_some(),
+                };
+                // The UI has already gone away if nothing's receiving
+                if sender.send(loaded).is_err() {
+                    return;
+                }
+                wake();
+                if let Some(doc) = indexed {
+                    let loaded = Loaded {
+                        doc: Ok(with_links(doc)),
+                        parse_time,
+                        read_time,
+                        complete: true,
+                        indexing_links: false,
+                    };
+                    if sender.send(loaded).is_ok() {
+                        wake();
+                    }
+                }
+                return;
+            }
+        }
+        if changed && sent.elapsed() >= interval {
+            let (doc, mut snapshot_time) = timed(|| builder.snapshot());
+            let indexed = detect_links.then(|| doc.clone());
+            let loaded = Loaded {
+                doc: Ok(doc),
+                parse_time,
+                read_time: started.elapsed(),
+                complete: false,
+                indexing_links: indexed.is_some(),
+            };
+            match sender.try_send(loaded) {
+                Ok(()) => {
+                    wake();
+                    changed = false;
+                    if let Some(doc) = indexed {
+                        let (doc, detect_time) = timed(|| with_links(doc));
+                        snapshot_time += detect_time;
+                        let loaded = Loaded {
+                            doc: Ok(doc),
+                            parse_time,
+                            read_time: started.elapsed(),
+                            complete: false,
+                            indexing_links: false,
+                        };
+                        match sender.try_send(loaded) {
+                            Ok(()) => wake(),
+                            // Send it all again after the next interval, even if no more arrives
+                            Err(TrySendError::Full(_)) => changed = true,
+                            Err(TrySendError::Disconnected(_)) => return,
+                        }
+                    }
+                }
+                // The UI hasn't taken the last update yet, so try again after the next interval
+                Err(TrySendError::Full(loaded)) => {
+                    if let Ok(doc) = &loaded.doc {
+                        builder.unsent(doc);
+                    }
+                }
+                Err(TrySendError::Disconnected(_)) => return,
+            }
+            interval = UPDATE_INTERVAL.max(snapshot_time * MAX_SNAPSHOT_SHARE);
+            sent = Instant::now();
+        }
+    }
+}
+
+// doc with the links in its text detected, to send after doc as it was
+fn with_links(mut doc: Document) -> Document {
+    doc.detect_links();
+    // The text is what was just sent
+    doc.unchanged_before = doc.text.len();
+    doc
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::config;
+
+// Kinds of text ate recognizes, which can have keys of their own
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    // Unified diffs, like from git diff
+    Diff,
+    // kubectl logs --prefix output, where every line starts with [pod/NAME/CONTAINER]
+    KubernetesLog,
+}
+
+// Only the start of the text is looked at to tell what kind it is
+const DETECT_LINES: usize = 100;
+
+impl ContentType {
+    pub const ALL: [ContentType; 2] = [ContentType::Diff, ContentType::KubernetesLog];
+
+    pub fn detect(text: &str) -> Option<Self> {
+        let (mut file_header, mut hunk_header, mut prefixed, mut lines) = (false, false, 0, 0);
+        for line in text.lines().take(DETECT_LINES) {
+            lines += 1;
+            file_header |= line.starts_with("+++ ");
+            hunk_header |= line.starts_with("@@ -");
+            if pod_prefix(line).is_some() {
+                prefixed += 1;
+            }
+        }
+        if file_header && hunk_header {
+            Some(ContentType::Diff)
+        } else if lines > 0 && prefixed == lines {
+            Some(ContentType::KubernetesLog)
+        } else {
+            None
+        }
+    }
+
+    // The environment variable with the keys for this kind of text
+    pub fn var(self) -> &'static str {
+        match self {
+            ContentType::Diff => "ATE_DIFF_KEYS",
+            ContentType::KubernetesLog => "ATE_KUBERNETES_LOG_KEYS",
+        }
+    }
+
+    // The values for placeholders in a binding run on the line between start and end.
+    // Placeholders with nothing on the line to fill them are left out.
+    pub fn fields(self, text: &str, start: usize, end: usize) -> HashMap<&'static str, String> {
+        let line = &text[start..end];
+        let mut fields = HashMap::from([("text", line.to_string())]);
+        match self {
+            ContentType::Diff => {
+                // The line's number in the new file counts the lines since the hunk header
+                // that are in the new file, and the file comes from the header above that
+                let mut hunk_offset = Some(0);
+                let above = text[..start].lines().rev();
+                for (i, l) in std::iter::once(line).chain(above).enumerate() {
+                    if let Some(file) = l.strip_prefix("+++ ") {
+                        let file = file.split('\t').next().unwrap_or(file);
+                        if file != "/dev/null" {
+                            let file = file.strip_prefix("b/").unwrap_or(file);
+                            fields.insert("file", file.to_string());
+                        }
+                        break;
+                    }
+                    if l.starts_with("diff ") {
+                        break;
+                    }
+                    let Some(offset) = hunk_offset else {
+                        continue;
+                    };
+                    if let Some(hunk_start) = l.strip_prefix("@@ ").and_then(hunk_new_start) {
+                        fields.insert("line", (hunk_start + offset).to_string());
+                        hunk_offset = None;
+                    } else if i > 0 && !l.starts_with('-') {
+                        hunk_offset = Some(offset + 1);
+                    }
+                }
+            }
+            ContentType::KubernetesLog => {
+                if let Some((pod, container)) = pod_prefix(line) {
+                    fields.insert("pod", pod.to_string());
+                    fields.insert("container", container.to_string());
+                }
+            }
+        }
+        fields
+    }
+}
+
+// The first line of the new file in the rest of a hunk header like "-1,3 +4,5 @@"
+fn hunk_new_start(header: &str) -> Option<usize> {
+    let new = header.split_whitespace().nth(1)?.strip_prefix('+')?;
+    new.split(',').next()?.parse().ok()
+}
+
+// The pod and container from the start of a line like "[pod/web-1/nginx] GET /"
+fn pod_prefix(line: &str) -> Option<(&str, &str)> {
+    let (prefix, _) = line.strip_prefix("[pod/")?.split_once(']')?;
+    prefix.split_once('/')
+}
+
+// What a bound key does with its template once the placeholders are filled in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindingAction {
+    // Runs ATE_OPENER with it like a link's address
+    Open,
+    Copy,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub key: char,
+    pub action: BindingAction,
+    // Text with placeholders in braces like {file}
+    template: String,
+}
+
+impl Binding {
+    // What the key does, like "open {file}:{line}", for the help screen
+    pub fn description(&self) -> String {
+        let action = match self.action {
+            BindingAction::Open => "Open",
+            BindingAction::Copy => "Copy",
+        };
+        format!("{action} {}", self.template)
+    }
+
+    pub fn expand(&self, fields: &HashMap<&str, String>) -> Result<String, String> {
+        let mut expanded = String::new();
+        let mut rest = self.template.as_str();
+        while let Some(open) = rest.find('{') {
+            expanded.push_str(&rest[..open]);
+            let Some(close) = rest[open..].find('}') else {
+                return Err(format!("Unclosed placeholder in {:?}", self.template));
+            };
+            let name = &rest[open + 1..open + close];
+            match fields.get(name) {
+                Some(value) => expanded.push_str(value),
+                None => return Err(format!("No {{{name}}} on this line")),
+            }
+            rest = &rest[open + close + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+}
+
+// Parses bindings separated by semicolons, like "o=open {file}:{line}; p=copy {pod}"
+pub fn parse_bindings(spec: &str) -> Result<Vec<Binding>, String> {
+    let mut bindings = vec![];
+    for binding in spec.split(';').map(str::trim).filter(|b| !b.is_empty()) {
+        let Some((key, command)) = binding.split_once('=') else {
+            return Err(format!("Expected KEY=ACTION TEMPLATE in {binding:?}"));
+        };
+        let mut chars = key.trim().chars();
+        let (Some(key), None) = (chars.next(), chars.next()) else {
+            return Err(format!("Expected a single key in {binding:?}"));
+        };
+        let (action, template) = command
+            .trim()
+            .split_once(' ')
+            .unwrap_or((command.trim(), ""));
+        let action = match action {
+            "open" => BindingAction::Open,
+            "copy" => BindingAction::Copy,
+            _ => return Err(format!("Unknown action {action:?} in {binding:?}")),
+        };
+        bindings.push(Binding {
+            key,
+            action,
+            template: template.trim().to_string(),
+        });
+    }
+    Ok(bindings)
+}
+
+// Keys bound for each kind of text, which take precedence over the base keys while viewing it
+#[derive(Default)]
+pub struct Keymaps {
+    keymaps: HashMap<ContentType, Vec<Binding>>,
+}
+
+impl Keymaps {
+    pub fn from_env() -> Self {
+        let mut keymaps = Self::default();
+        for content_type in ContentType::ALL {
+            let var = content_type.var();
+            if let Ok(spec) = config::var(var) {
+                match parse_bindings(&spec) {
+                    Ok(bindings) => keymaps.set(content_type, bindings),
+                    Err(e) => warn!("Ignoring {}: {}", var, e),
+                }
+            }
+        }
+        keymaps
+    }
+
+    pub fn set(&mut self, content_type: ContentType, bindings: Vec<Binding>) {
+        self.keymaps.insert(content_type, bindings);
+    }
+
+    pub fn bindings(&self, content_type: ContentType) -> &[Binding] {
+        self.keymaps.get(&content_type).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn binding(&self, content_type: ContentType, key: char) -> Option<&Binding> {
+        self.bindings(content_type).iter().find(|b| b.key == key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "diff --git a/src/main.rs b/src/main.rs\n\
+        --- a/src/main.rs\n\
+        +++ b/src/main.rs\n\
+        @@ -10,3 +12,4 @@ fn main() {\n \
+        context\n\
+        -removed\n\
+        +added\n";
+
+    fn fields_at(content_type: ContentType, text: &str, line: usize) -> HashMap<&str, String> {
+        let start: usize = text.split_inclusive('\n').take(line).map(str::len).sum();
+        let end = start + text[start..].find('\n').unwrap_or(text.len() - start);
+        content_type.fields(text, start, end)
+    }
+
+    #[test]
+    fn detect_content_types() {
+        assert_eq!(Some(ContentType::Diff), ContentType::detect(DIFF));
+        let log = "[pod/web-1/nginx] GET /\n[pod/web-2/nginx] GET /health\n";
+        assert_eq!(Some(ContentType::KubernetesLog), ContentType::detect(log));
+        assert_eq!(None, ContentType::detect(&format!("{log}plain\n")));
+        assert_eq!(None, ContentType::detect(""));
+
+        let fields = fields_at(ContentType::KubernetesLog, log, 1);
+        assert_eq!("web-2", fields["pod"]);
+        assert_eq!("nginx", fields["container"]);
+    }
+
+    #[test]
+    fn find_diff_positions() {
+        let hunk = fields_at(ContentType::Diff, DIFF, 3);
+        assert_eq!("src/main.rs", hunk["file"]);
+        assert_eq!("12", hunk["line"]);
+        // Removed lines aren't in the new file, so they don't count
+        assert_eq!("13", fields_at(ContentType::Diff, DIFF, 6)["line"]);
+        assert_eq!("+added", fields_at(ContentType::Diff, DIFF, 6)["text"]);
+        // A file header has a file but no line
+        let header = fields_at(ContentType::Diff, DIFF, 2);
+        assert_eq!(None, header.get("line"));
+        assert_eq!("src/main.rs", header["file"]);
+    }
+
+    #[test]
+    fn parse_and_expand_bindings() {
+        let bindings = parse_bindings("o=open {file}:{line}; p = copy pod {pod}").unwrap();
+        assert_eq!(
+            vec![('o', BindingAction::Open), ('p', BindingAction::Copy)],
+            bindings
+                .iter()
+                .map(|b| (b.key, b.action))
+                .collect::<Vec<_>>()
+        );
+        let fields = fields_at(ContentType::Diff, DIFF, 4);
+        assert_eq!(
+            Ok("src/main.rs:12".to_string()),
+            bindings[0].expand(&fields)
+        );
+        assert_eq!(
+            Err("No {pod} on this line".to_string()),
+            bindings[1].expand(&fields)
+        );
+
+        assert!(parse_bindings("oo=open {file}").is_err());
+        assert!(parse_bindings("o=run {file}").is_err());
+        assert!(parse_bindings("o").is_err());
+    }
+}
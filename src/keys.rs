@@ -0,0 +1,844 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use termwiz::input::{KeyCode, KeyEvent, Modifiers};
+
+use crate::bookmarks::LAST_POSITION;
+use crate::commands::{Command, PromptCommand, COMMANDS, PROMPT_COMMANDS};
+use crate::config;
+
+// Commands bound to a sequence of keys rather than a single one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chord {
+    // A command bound to a sequence, like gg
+    Command(Command),
+    // m followed by a letter
+    SetBookmark(char),
+    // ' followed by a letter or "
+    GoToBookmark(char),
+    // Q followed by a letter
+    Record(char),
+    // @ followed by a letter
+    Replay(char),
+    // A number followed by g or G
+    GoToLine(usize),
+    // A number followed by %
+    GoToPercent(usize),
+    // A number followed by a command's key, like 15j
+    Repeat(usize, Command),
+}
+
+// Chords that take a letter or number, which only have keys, with what they do for the help screen
+pub const CHORD_KEYS: &[(&str, &str)] = &[
+    ("m<letter>", "Bookmark the top line"),
+    ("'<letter>", "Go to bookmark"),
+    ("'\"", "Go to where ate last quit"),
+    ("Q<letter>", "Record keys"),
+    ("@<letter>", "Replay recorded keys"),
+    ("<number>g", "Go to line"),
+    ("<number>%", "Go to percentage"),
+    ("<number><key>", "Repeat a command, like 15j"),
+];
+
+// A chord's prefix is dropped if its next key doesn't come within this long
+pub const CHORD_TIMEOUT: Duration = Duration::from_secs(1);
+
+// What a key did to the keys pending in a chord
+#[derive(Debug, PartialEq, Eq)]
+pub enum Pressed {
+    // The key started or continued a chord
+    Pending,
+    Chord(Chord),
+    // The key couldn't continue the pending chord, so the chord and key were both dropped
+    Cancelled,
+    // Nothing was pending and the key doesn't start a chord, so it should be handled on its own
+    Unbound,
+}
+
+// The keys typed so far of a chord that hasn't been completed
+#[derive(Default)]
+pub struct PendingKeys {
+    keys: String,
+    // When the last key was pressed
+    since: Option<Instant>,
+}
+
+impl PendingKeys {
+    pub fn press(&mut self, event: &KeyEvent, now: Instant, bindings: &KeyBindings) -> Pressed {
+        self.expire(now);
+        let c = match event {
+            KeyEvent {
+                key: KeyCode::Char(c),
+                modifiers: Modifiers::NONE | Modifiers::SHIFT,
+            } => Some(*c),
+            _ => None,
+        };
+        let Some(c) = c else {
+            // A count can be followed by keys that aren't characters, like Down
+            let count = match self.keys.parse() {
+                Ok(count) => bindings.command(event).map(|command| (count, command)),
+                Err(_) => None,
+            };
+            if let Some((count, command)) = count {
+                self.clear();
+                return Pressed::Chord(Chord::Repeat(count, command));
+            }
+            return self.cancel_or_unbound();
+        };
+        self.keys.push(c);
+        match parse(&self.keys, bindings) {
+            Parse::Incomplete => {
+                self.since = Some(now);
+                Pressed::Pending
+            }
+            Parse::Chord(chord) => {
+                self.clear();
+                Pressed::Chord(chord)
+            }
+            Parse::Invalid => {
+                self.keys.pop();
+                self.cancel_or_unbound()
+            }
+        }
+    }
+
+    fn cancel_or_unbound(&mut self) -> Pressed {
+        if self.keys.is_empty() {
+            Pressed::Unbound
+        } else {
+            self.clear();
+            Pressed::Cancelled
+        }
+    }
+
+    fn clear(&mut self) {
+        self.keys.clear();
+        self.since = None;
+    }
+
+    // Drops the pending keys if CHORD_TIMEOUT has passed since the last one.
+    // Returns whether they were dropped.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        match self.since {
+            Some(since) if now.duration_since(since) >= CHORD_TIMEOUT => {
+                self.clear();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // How long until the pending keys expire, if any are pending
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        self.since
+            .map(|since| CHORD_TIMEOUT.saturating_sub(now.duration_since(since)))
+    }
+
+    // The keys typed so far, to show what's being waited on
+    pub fn keys(&self) -> &str {
+        &self.keys
+    }
+}
+
+enum Parse {
+    Incomplete,
+    Chord(Chord),
+    Invalid,
+}
+
+// Numbers before g, G, %, or a command with more digits than this can't be a line, percent, or
+// count
+const MAX_NUMBER_DIGITS: usize = 9;
+
+fn parse(keys: &str, bindings: &KeyBindings) -> Parse {
+    let digits = keys.len() - keys.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 {
+        let (number, rest) = keys.split_at(digits);
+        return match (number.parse(), rest) {
+            _ if digits > MAX_NUMBER_DIGITS => Parse::Invalid,
+            (Ok(_), "") => Parse::Incomplete,
+            (Ok(line), "g" | "G") => Parse::Chord(Chord::GoToLine(line)),
+            (Ok(percent), "%") => Parse::Chord(Chord::GoToPercent(percent)),
+            (Ok(count), rest) => match single_char(rest)
+                .and_then(|c| bindings.command(&char_key(c)))
+                .or_else(|| bindings.chord(rest))
+            {
+                Some(command) => Parse::Chord(Chord::Repeat(count, command)),
+                None if bindings.starts_chord(rest) => Parse::Incomplete,
+                None => Parse::Invalid,
+            },
+            _ => Parse::Invalid,
+        };
+    }
+    if let Some(command) = bindings.chord(keys) {
+        return Parse::Chord(Chord::Command(command));
+    }
+    let incomplete = if bindings.starts_chord(keys) {
+        Parse::Incomplete
+    } else {
+        Parse::Invalid
+    };
+    let mut chars = keys.chars();
+    let (Some(first), second) = (chars.next(), chars.next()) else {
+        return Parse::Invalid;
+    };
+    if chars.next().is_some() {
+        return incomplete;
+    }
+    let Some(second) = second else {
+        return match first {
+            'm' | '\'' | 'Q' | '@' => Parse::Incomplete,
+            _ => incomplete,
+        };
+    };
+    match (first, second) {
+        ('m', name) if name.is_alphabetic() => Parse::Chord(Chord::SetBookmark(name)),
+        ('\'', name) if name.is_alphabetic() || name == LAST_POSITION => {
+            Parse::Chord(Chord::GoToBookmark(name))
+        }
+        ('Q', register) if register.is_alphabetic() => Parse::Chord(Chord::Record(register)),
+        ('@', register) if register.is_alphabetic() => Parse::Chord(Chord::Replay(register)),
+        _ => incomplete,
+    }
+}
+
+fn single_char(keys: &str) -> Option<char> {
+    let mut chars = keys.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+fn char_key(c: char) -> KeyEvent {
+    KeyEvent {
+        key: KeyCode::Char(c),
+        modifiers: Modifiers::NONE,
+    }
+}
+
+// Keys that start the chords above, which can't be bound to commands on their own
+const CHORD_PREFIXES: &str = "m'Q@0123456789";
+
+// A key as it's written in COMMANDS and the config file, like "Ctrl-T", "Space", or "gg"
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Key {
+    Single(KeyEvent),
+    // Characters typed one after another
+    Chord(String),
+}
+
+impl Key {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut modifiers = Modifiers::NONE;
+        let mut rest = spec;
+        while let Some((modifier, after)) = rest.split_once('-') {
+            // A - on its own or after a modifier is the key itself
+            if after.is_empty() {
+                break;
+            }
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" => Modifiers::CTRL,
+                "alt" => Modifiers::ALT,
+                "shift" => Modifiers::SHIFT,
+                _ => return Err(format!("Unknown modifier {modifier:?} in {spec:?}")),
+            };
+            rest = after;
+        }
+        let mut chars = rest.chars();
+        let key = match (chars.next(), chars.next()) {
+            (None, _) => return Err("Empty key".to_string()),
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "space" => KeyCode::Char(' '),
+                "enter" => KeyCode::Enter,
+                "tab" => KeyCode::Tab,
+                "backspace" => KeyCode::Backspace,
+                "esc" => KeyCode::Escape,
+                "up" => KeyCode::UpArrow,
+                "down" => KeyCode::DownArrow,
+                "left" => KeyCode::LeftArrow,
+                "right" => KeyCode::RightArrow,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "insert" => KeyCode::Insert,
+                "delete" => KeyCode::Delete,
+                name => match name.strip_prefix('f').map(str::parse) {
+                    Some(Ok(n @ 1..=24)) => KeyCode::Function(n),
+                    _ if modifiers == Modifiers::NONE => {
+                        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                            return Err(format!("{rest:?} would start a number"));
+                        }
+                        return Ok(Key::Chord(rest.to_string()));
+                    }
+                    _ => return Err(format!("Unknown key {spec:?}")),
+                },
+            },
+        };
+        // Terminals send Ctrl with a letter as the lowercase letter, and Shift with one as the
+        // capital
+        let key = match key {
+            KeyCode::Char(c) if modifiers.contains(Modifiers::CTRL) => {
+                KeyCode::Char(c.to_ascii_lowercase())
+            }
+            KeyCode::Char(c) if modifiers.contains(Modifiers::SHIFT) => {
+                modifiers.remove(Modifiers::SHIFT);
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            key => key,
+        };
+        Ok(Key::Single(KeyEvent { key, modifiers }))
+    }
+
+    // Whether the event is this key.
+    // Characters without modifiers match with Shift, which some terminals report for capitals,
+    // and other keys without modifiers match with any.
+    fn matches(&self, event: &KeyEvent) -> bool {
+        let Key::Single(key) = self else {
+            return false;
+        };
+        key.key == event.key
+            && match key.key {
+                _ if key.modifiers != Modifiers::NONE => key.modifiers == event.modifiers,
+                KeyCode::Char(_) => matches!(event.modifiers, Modifiers::NONE | Modifiers::SHIFT),
+                _ => true,
+            }
+    }
+
+    // Whether pressing one of the keys would get in the way of the other
+    fn conflicts(&self, other: &Key) -> bool {
+        let starts = |key: &Key, chord: &str| match key {
+            Key::Single(KeyEvent {
+                key: KeyCode::Char(c),
+                modifiers: Modifiers::NONE,
+            }) => chord.starts_with(*c),
+            _ => false,
+        };
+        self == other
+            || match (self, other) {
+                (Key::Chord(chord), key) | (key, Key::Chord(chord)) => starts(key, chord),
+                _ => false,
+            }
+    }
+}
+
+// The keys that run each command, starting from the defaults in COMMANDS and PROMPT_COMMANDS
+pub struct KeyBindings {
+    // Each command with a key that runs it as it was written and the key
+    bindings: Vec<(Command, String, Key)>,
+    // The same for the prompts, whose keys can be the same as the document's
+    prompt_bindings: Vec<(PromptCommand, String, Key)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            bindings: default_bindings(COMMANDS),
+            prompt_bindings: default_bindings(PROMPT_COMMANDS),
+        }
+    }
+}
+
+fn default_bindings<C: Copy>(commands: &[(C, &str, &[&str])]) -> Vec<(C, String, Key)> {
+    commands
+        .iter()
+        .flat_map(|(command, _, keys)| {
+            keys.iter().map(|spec| {
+                let key = Key::parse(spec).expect("default keys are valid");
+                (*command, spec.to_string(), key)
+            })
+        })
+        .collect()
+}
+
+// Gives command the keys in place of its own.
+// Other commands lose the keys given away, so a key that's moved only does its new command.
+fn rebind<C: Copy + PartialEq>(
+    bindings: &mut Vec<(C, String, Key)>,
+    command: C,
+    keys: Vec<(String, Key)>,
+) {
+    bindings.retain(|(c, _, key)| *c != command && !keys.iter().any(|(_, k)| k.conflicts(key)));
+    bindings.extend(keys.into_iter().map(|(spec, key)| (command, spec, key)));
+}
+
+// The keys for command as they were written, for listing it
+fn listed<C: PartialEq>(bindings: &[(C, String, Key)], command: C) -> String {
+    let keys: Vec<&str> = bindings
+        .iter()
+        .filter(|(c, _, _)| *c == command)
+        .map(|(_, spec, _)| spec.as_str())
+        .collect();
+    keys.join(", ")
+}
+
+impl KeyBindings {
+    pub fn from_config() -> Self {
+        let (bindings, errors) = Self::with_keys(config::keys());
+        for e in errors {
+            warn!("Ignoring config file keys: {}", e);
+        }
+        bindings
+    }
+
+    // Gives the commands named in keys, like page_down or prompt_cancel, the keys with them in
+    // place of their defaults
+    pub fn with_keys(keys: &[(String, Vec<String>)]) -> (Self, Vec<String>) {
+        let mut bindings = Self::default();
+        let mut errors = vec![];
+        for (name, specs) in keys {
+            let command = COMMANDS
+                .iter()
+                .map(|(c, _, _)| *c)
+                .find(|c| c.id() == *name);
+            let prompt_command = PROMPT_COMMANDS
+                .iter()
+                .map(|(c, _, _)| *c)
+                .find(|c| c.id() == *name);
+            if command.is_none() && prompt_command.is_none() {
+                errors.push(format!("Unknown command {name}"));
+                continue;
+            }
+            let mut parsed = vec![];
+            for spec in specs {
+                match Key::parse(spec) {
+                    Ok(Key::Single(KeyEvent {
+                        key: KeyCode::Char(c),
+                        modifiers: Modifiers::NONE,
+                    })) if command.is_some() && CHORD_PREFIXES.contains(c) => {
+                        errors.push(format!("{spec} for {name} starts other keys"))
+                    }
+                    // Characters are typed into prompts
+                    Ok(
+                        Key::Chord(_)
+                        | Key::Single(KeyEvent {
+                            key: KeyCode::Char(_),
+                            modifiers: Modifiers::NONE,
+                        }),
+                    ) if prompt_command.is_some() => {
+                        errors.push(format!("{spec} for {name} would be typed into prompts"))
+                    }
+                    Ok(key) => parsed.push((spec.clone(), key)),
+                    Err(e) => errors.push(format!("{e} for {name}")),
+                }
+            }
+            if let Some(command) = command {
+                rebind(&mut bindings.bindings, command, parsed);
+            } else if let Some(command) = prompt_command {
+                rebind(&mut bindings.prompt_bindings, command, parsed);
+            }
+        }
+        (bindings, errors)
+    }
+
+    // The keys for command as they were written, for listing it
+    pub fn keys(&self, command: Command) -> String {
+        listed(&self.bindings, command)
+    }
+
+    pub fn prompt_keys(&self, command: PromptCommand) -> String {
+        listed(&self.prompt_bindings, command)
+    }
+
+    // Keys for editing what's typed into a prompt, handled before the characters typed
+    pub fn prompt_command(&self, event: &KeyEvent) -> Option<PromptCommand> {
+        self.prompt_bindings
+            .iter()
+            .find(|(_, _, key)| key.matches(event))
+            .map(|(command, _, _)| *command)
+    }
+
+    fn command(&self, event: &KeyEvent) -> Option<Command> {
+        self.bindings
+            .iter()
+            .find(|(_, _, key)| key.matches(event))
+            .map(|(command, _, _)| *command)
+    }
+
+    // Keys for moving around the document, handled while it has focus
+    pub fn document_command(&self, event: &KeyEvent) -> Option<Command> {
+        self.command(event).filter(|c| c.in_document())
+    }
+
+    // Keys handled by the main widget, including ones that weren't handled by a search or palette
+    // prompt.
+    // Ctrl-C always quits, so there's a way out whatever keys are set.
+    pub fn main_command(&self, event: &KeyEvent) -> Option<Command> {
+        if quits_from_anywhere(event) {
+            return Some(Command::Quit);
+        }
+        self.command(event).filter(|c| !c.in_document())
+    }
+
+    fn chord(&self, keys: &str) -> Option<Command> {
+        self.bindings
+            .iter()
+            .find_map(|(command, _, key)| match key {
+                Key::Chord(chord) if chord == keys => Some(*command),
+                _ => None,
+            })
+    }
+
+    fn starts_chord(&self, keys: &str) -> bool {
+        self.bindings
+            .iter()
+            .any(|(_, _, key)| matches!(key, Key::Chord(chord) if chord.starts_with(keys)))
+    }
+}
+
+// Ctrl-C, which quits whatever keys are set and whatever's on screen
+pub fn quits_from_anywhere(event: &KeyEvent) -> bool {
+    matches!(
+        event,
+        KeyEvent {
+            key: KeyCode::Char('c'),
+            modifiers: Modifiers::CTRL,
+        }
+    )
+}
+
+// Replaying stops after this many keys, since a macro that replays itself never ends
+pub const MAX_REPLAYED_KEYS: usize = 10_000;
+
+// Sequences of keys recorded into registers named by letters to be replayed later
+#[derive(Default)]
+pub struct Macros {
+    registers: HashMap<char, Vec<KeyEvent>>,
+    recording: Option<(char, Vec<KeyEvent>)>,
+    // Keys from a register waiting to be handled as if they were pressed
+    replay: Vec<KeyEvent>,
+}
+
+impl Macros {
+    pub fn start_recording(&mut self, register: char) {
+        self.recording = Some((register, vec![]));
+    }
+
+    // Saves the keys recorded except the last one, which was the key that stopped recording
+    pub fn stop_recording(&mut self) {
+        if let Some((register, mut keys)) = self.recording.take() {
+            keys.pop();
+            self.registers.insert(register, keys);
+        }
+    }
+
+    // The register being recorded into, if any
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+
+    // Call with every key pressed before handling it
+    pub fn record(&mut self, key: &KeyEvent) {
+        if let Some((_, keys)) = &mut self.recording {
+            keys.push(key.clone());
+        }
+    }
+
+    // Returns false if nothing has been recorded in register
+    pub fn replay(&mut self, register: char) -> bool {
+        match self.registers.get(&register) {
+            Some(keys) if !keys.is_empty() => {
+                self.replay.extend(keys.iter().cloned());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn take_replay(&mut self) -> Vec<KeyEvent> {
+        std::mem::take(&mut self.replay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(c: char) -> KeyEvent {
+        KeyEvent {
+            key: KeyCode::Char(c),
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    #[test]
+    fn press_chords() {
+        let now = Instant::now();
+        let mut pending = PendingKeys::default();
+        let bindings = KeyBindings::default();
+        assert_eq!(Pressed::Unbound, pending.press(&key('n'), now, &bindings));
+        assert_eq!(Pressed::Pending, pending.press(&key('g'), now, &bindings));
+        assert_eq!("g", pending.keys());
+        assert_eq!(
+            Pressed::Chord(Chord::Command(Command::Top)),
+            pending.press(&key('g'), now, &bindings)
+        );
+        assert_eq!("", pending.keys());
+
+        pending.press(&key('m'), now, &bindings);
+        assert_eq!(
+            Pressed::Chord(Chord::SetBookmark('a')),
+            pending.press(&key('a'), now, &bindings)
+        );
+        pending.press(&key('\''), now, &bindings);
+        assert_eq!(
+            Pressed::Chord(Chord::GoToBookmark(LAST_POSITION)),
+            pending.press(&key(LAST_POSITION), now, &bindings)
+        );
+
+        // Only ' takes " as a name
+        pending.press(&key('m'), now, &bindings);
+        assert_eq!(
+            Pressed::Cancelled,
+            pending.press(&key(LAST_POSITION), now, &bindings)
+        );
+        pending.press(&key('z'), now, &bindings);
+        let down = KeyEvent {
+            key: KeyCode::DownArrow,
+            modifiers: Modifiers::NONE,
+        };
+        assert_eq!(Pressed::Cancelled, pending.press(&down, now, &bindings));
+        assert_eq!(Pressed::Unbound, pending.press(&down, now, &bindings));
+    }
+
+    #[test]
+    fn press_numbers() {
+        let now = Instant::now();
+        let mut pending = PendingKeys::default();
+        let bindings = KeyBindings::default();
+        for c in "12".chars() {
+            assert_eq!(Pressed::Pending, pending.press(&key(c), now, &bindings));
+        }
+        assert_eq!("12", pending.keys());
+        assert_eq!(
+            Pressed::Chord(Chord::GoToLine(12)),
+            pending.press(&key('G'), now, &bindings)
+        );
+        pending.press(&key('5'), now, &bindings);
+        assert_eq!(
+            Pressed::Chord(Chord::GoToPercent(5)),
+            pending.press(&key('%'), now, &bindings)
+        );
+        pending.press(&key('3'), now, &bindings);
+        assert_eq!(Pressed::Cancelled, pending.press(&key('x'), now, &bindings));
+
+        // Numbers before other commands' keys are counts
+        for c in "15".chars() {
+            pending.press(&key(c), now, &bindings);
+        }
+        assert_eq!(
+            Pressed::Chord(Chord::Repeat(15, Command::LineDown)),
+            pending.press(&key('j'), now, &bindings)
+        );
+        pending.press(&key('2'), now, &bindings);
+        let event = |key| KeyEvent {
+            key,
+            modifiers: Modifiers::NONE,
+        };
+        assert_eq!(
+            Pressed::Chord(Chord::Repeat(2, Command::LineDown)),
+            pending.press(&event(KeyCode::DownArrow), now, &bindings)
+        );
+        pending.press(&key('3'), now, &bindings);
+        assert_eq!(Pressed::Pending, pending.press(&key('z'), now, &bindings));
+        assert_eq!(
+            Pressed::Chord(Chord::Repeat(3, Command::SelectionToTop)),
+            pending.press(&key('t'), now, &bindings)
+        );
+        pending.press(&key('4'), now, &bindings);
+        assert_eq!(
+            Pressed::Cancelled,
+            pending.press(&event(KeyCode::Escape), now, &bindings)
+        );
+
+        for _ in 0..MAX_NUMBER_DIGITS {
+            pending.press(&key('9'), now, &bindings);
+        }
+        assert_eq!(Pressed::Cancelled, pending.press(&key('9'), now, &bindings));
+    }
+
+    #[test]
+    fn record_macros() {
+        let mut macros = Macros::default();
+        assert!(!macros.replay('a'));
+        macros.start_recording('a');
+        assert_eq!(Some('a'), macros.recording());
+        for c in ['n', '\n', 'Q'] {
+            macros.record(&key(c));
+        }
+        macros.stop_recording();
+        assert_eq!(None, macros.recording());
+        macros.record(&key('x'));
+
+        assert!(macros.replay('a'));
+        assert!(macros.replay('a'));
+        assert_eq!(
+            vec![key('n'), key('\n'), key('n'), key('\n')],
+            macros.take_replay()
+        );
+        assert!(macros.take_replay().is_empty());
+    }
+
+    #[test]
+    fn expire_pending() {
+        let now = Instant::now();
+        let mut pending = PendingKeys::default();
+        let bindings = KeyBindings::default();
+        assert_eq!(None, pending.timeout(now));
+        pending.press(&key('g'), now, &bindings);
+        assert_eq!(Some(CHORD_TIMEOUT), pending.timeout(now));
+        assert!(!pending.expire(now + CHORD_TIMEOUT / 2));
+
+        // The second g starts a new chord rather than finishing the expired one
+        let later = now + CHORD_TIMEOUT;
+        assert_eq!(Pressed::Pending, pending.press(&key('g'), later, &bindings));
+        assert!(pending.expire(later + CHORD_TIMEOUT));
+        assert_eq!("", pending.keys());
+        assert_eq!(None, pending.timeout(later));
+    }
+
+    fn bindings(keys: &[(&str, &[&str])]) -> (KeyBindings, Vec<String>) {
+        let keys: Vec<(String, Vec<String>)> = keys
+            .iter()
+            .map(|(name, keys)| {
+                (
+                    name.to_string(),
+                    keys.iter().map(|k| k.to_string()).collect(),
+                )
+            })
+            .collect();
+        KeyBindings::with_keys(&keys)
+    }
+
+    fn event(key: KeyCode, modifiers: Modifiers) -> KeyEvent {
+        KeyEvent { key, modifiers }
+    }
+
+    #[test]
+    fn parse_keys() {
+        let single = |key, modifiers| Ok(Key::Single(event(key, modifiers)));
+        assert_eq!(
+            single(KeyCode::Char('f'), Modifiers::CTRL),
+            Key::parse("Ctrl-F")
+        );
+        assert_eq!(
+            single(KeyCode::Char('y'), Modifiers::ALT),
+            Key::parse("alt-y")
+        );
+        assert_eq!(
+            single(KeyCode::Char('G'), Modifiers::NONE),
+            Key::parse("Shift-g")
+        );
+        assert_eq!(
+            single(KeyCode::Char(' '), Modifiers::NONE),
+            Key::parse("Space")
+        );
+        assert_eq!(
+            single(KeyCode::Char('-'), Modifiers::CTRL),
+            Key::parse("Ctrl--")
+        );
+        assert_eq!(
+            single(KeyCode::Function(9), Modifiers::NONE),
+            Key::parse("F9")
+        );
+        assert_eq!(
+            single(KeyCode::PageDown, Modifiers::NONE),
+            Key::parse("PageDown")
+        );
+        assert_eq!(Ok(Key::Chord("gg".to_string())), Key::parse("gg"));
+        assert!(Key::parse("").is_err());
+        assert!(Key::parse("Hyper-x").is_err());
+        assert!(Key::parse("Ctrl-gg").is_err());
+        assert!(Key::parse("2g").is_err());
+
+        let y = Key::parse("y").unwrap();
+        assert!(y.matches(&event(KeyCode::Char('y'), Modifiers::SHIFT)));
+        assert!(!y.matches(&event(KeyCode::Char('y'), Modifiers::ALT)));
+        let down = Key::parse("Down").unwrap();
+        assert!(down.matches(&event(KeyCode::DownArrow, Modifiers::CTRL)));
+    }
+
+    #[test]
+    fn remap_keys() {
+        let defaults = KeyBindings::default();
+        let ctrl_e = event(KeyCode::Char('e'), Modifiers::CTRL);
+        let ctrl_f = event(KeyCode::Char('f'), Modifiers::CTRL);
+        assert_eq!(None, defaults.document_command(&ctrl_e));
+        assert_eq!("Space, Ctrl-F", defaults.keys(Command::PageDown));
+
+        let (remapped, errors) = bindings(&[
+            ("page_down", &["Space", "Ctrl-E"]),
+            ("line_down", &["j", "Down"]),
+            ("search", &["b"]),
+            ("top", &["g"]),
+        ]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!("Space, Ctrl-E", remapped.keys(Command::PageDown));
+        assert_eq!(Some(Command::PageDown), remapped.document_command(&ctrl_e));
+        assert_eq!(None, remapped.document_command(&ctrl_f));
+        assert_eq!(
+            Some(Command::LineDown),
+            remapped.document_command(&key('j'))
+        );
+        // Document keys aren't handled by the main widget
+        assert_eq!(None, remapped.main_command(&key('j')));
+        // b was taken from page_up for search
+        assert_eq!("Ctrl-B", remapped.keys(Command::PageUp));
+        assert_eq!(Some(Command::Search), remapped.main_command(&key('b')));
+        // g on its own replaces gg, so it doesn't wait for another key
+        assert_eq!("g", remapped.keys(Command::Top));
+        let mut pending = PendingKeys::default();
+        let now = Instant::now();
+        assert_eq!(Pressed::Unbound, pending.press(&key('g'), now, &remapped));
+
+        let (remapped, _) = bindings(&[("quit", &[])]);
+        assert_eq!(None, remapped.main_command(&key('q')));
+        let ctrl_c = event(KeyCode::Char('c'), Modifiers::CTRL);
+        assert_eq!(Some(Command::Quit), remapped.main_command(&ctrl_c));
+
+        let (remapped, errors) = bindings(&[("jump", &["j"]), ("top", &["m", "Home", "Super-x"])]);
+        assert_eq!(
+            vec![
+                "Unknown command jump",
+                "m for top starts other keys",
+                "Unknown modifier \"Super\" in \"Super-x\" for top",
+            ],
+            errors
+        );
+        assert_eq!("Home", remapped.keys(Command::Top));
+    }
+
+    #[test]
+    fn remap_prompt_keys() {
+        let ctrl_g = event(KeyCode::Char('g'), Modifiers::CTRL);
+        let esc = event(KeyCode::Escape, Modifiers::NONE);
+        let defaults = KeyBindings::default();
+        assert_eq!(Some(PromptCommand::Cancel), defaults.prompt_command(&esc));
+        assert_eq!(None, defaults.prompt_command(&key('x')));
+
+        let (remapped, errors) = bindings(&[
+            ("prompt_cancel", &["Ctrl-G", "x", "zz"]),
+            ("prompt_accept", &["Esc"]),
+            ("page_down", &["Ctrl-G"]),
+        ]);
+        assert_eq!(
+            vec![
+                "x for prompt_cancel would be typed into prompts",
+                "zz for prompt_cancel would be typed into prompts",
+            ],
+            errors
+        );
+        assert_eq!("Ctrl-G", remapped.prompt_keys(PromptCommand::Cancel));
+        assert_eq!(
+            Some(PromptCommand::Cancel),
+            remapped.prompt_command(&ctrl_g)
+        );
+        assert_eq!(Some(PromptCommand::Accept), remapped.prompt_command(&esc));
+        // Prompts and the document have keys of their own
+        assert_eq!(Some(Command::PageDown), remapped.document_command(&ctrl_g));
+        assert_eq!("Enter", remapped.keys(Command::OpenLink));
+    }
+}
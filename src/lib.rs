@@ -0,0 +1,39 @@
+//! Parsing the output of terminal programs into text with its colors and links, and flowing it to
+//! fit a terminal, as the `ate` pager does.
+//!
+//! [`Document`] parses input with its escape sequences, [`DocumentView`] wraps it to a width and
+//! keeps track of what's on screen, and [`Pattern`] and [`TextSearch`] find text in it.
+//!
+//! ```
+//! use std::io::Cursor;
+//! use std::rc::Rc;
+//!
+//! use ate_pager::{Document, DocumentView, ParseOptions, ViewOptions};
+//!
+//! let input = "See \x1b]8;;https://example.com\x1b\\the docs\x1b]8;;\x1b\\ for more\n";
+//! let doc = Rc::new(Document::new(Box::new(Cursor::new(input)), ParseOptions::default())?);
+//! assert_eq!("See the docs for more\n", doc.text);
+//! assert_eq!("https://example.com", doc.links[0].link.uri());
+//!
+//! // Three rows of ten columns, with long lines wrapping at the last column
+//! let view = DocumentView::new(Rc::clone(&doc), 10, 3, &ViewOptions::default());
+//! let rows: Vec<&str> = view
+//!     .visible_lines()
+//!     .iter()
+//!     .map(|&line| doc.text_between(view.line(line).start_byte, view.line_end(line)))
+//!     .collect();
+//! assert_eq!(vec!["See the do", "cs for mor", "e\n"], rows);
+//! # Ok::<(), termwiz::Error>(())
+//! ```
+
+pub mod doc;
+pub mod find;
+pub mod options;
+pub mod view;
+
+mod detect;
+
+pub use doc::{Document, DocumentBuilder, LinkRange, ParseOptions, Sanitize};
+pub use find::{Pattern, TextSearch};
+pub use options::{CellWidths, ViewOptions};
+pub use view::{DocumentView, Line, LineRef};
@@ -0,0 +1,87 @@
+use std::io::{Result, Write};
+
+use crate::doc::Document;
+
+// What to print about each link with --links
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LinkFormat {
+    // Only the address
+    #[default]
+    Uri,
+    // The address and the link's text, separated by a tab, for picking a link by its text with
+    // something like fzf
+    Text,
+    // The line the link starts on, its address, and its text, separated by tabs
+    Full,
+}
+
+impl LinkFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "uri" => Some(LinkFormat::Uri),
+            "text" => Some(LinkFormat::Text),
+            "full" => Some(LinkFormat::Full),
+            _ => None,
+        }
+    }
+}
+
+// Writes one line for every link in doc in the order they appear, once for a link split into
+// several ranges.
+// Line feeds in a link's text are written as spaces so each link stays on one line.
+pub fn print_links(doc: &Document, format: LinkFormat, out: &mut impl Write) -> Result<()> {
+    let mut line = 1;
+    let mut counted_to = 0;
+    for (idx, link) in doc.links.iter().enumerate() {
+        if !doc.starts_link(idx) {
+            continue;
+        }
+        let uri = link.link.uri();
+        let text = || doc.link_text(idx).replace('\n', " ");
+        match format {
+            LinkFormat::Uri => writeln!(out, "{uri}")?,
+            LinkFormat::Text => writeln!(out, "{uri}\t{}", text())?,
+            LinkFormat::Full => {
+                let start = link.start.min(doc.text.len());
+                // Links are in ascending order of start, so only count the lines since the last
+                line += doc.text_between(counted_to, start).matches('\n').count();
+                counted_to = start;
+                writeln!(out, "{line}\t{uri}\t{}", text())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::doc::ParseOptions;
+
+    #[test]
+    fn print_every_link() {
+        let input = "\x1b]8;;a\x1b\\first\x1b]8;;\x1b\\\n\n\
+            see \x1b]8;;b\x1b\\second link\nover lines\x1b]8;;\x1b\\ and \x1b]8;;a\x1b\\a\x1b]8;;\x1b\\";
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+
+        let mut out = vec![];
+        print_links(&doc, LinkFormat::Uri, &mut out).unwrap();
+        assert_eq!("a\nb\na\n", String::from_utf8(out).unwrap());
+
+        let mut out = vec![];
+        print_links(&doc, LinkFormat::Text, &mut out).unwrap();
+        assert_eq!(
+            "a\tfirst\nb\tsecond link over lines\na\ta\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = vec![];
+        print_links(&doc, LinkFormat::Full, &mut out).unwrap();
+        assert_eq!(
+            "1\ta\tfirst\n3\tb\tsecond link over lines\n4\ta\ta\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
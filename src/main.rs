@@ -1,69 +1,177 @@
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Result;
+use args::Args;
+use ate_pager::{doc, find, options, view};
 use backtrace::Backtrace;
-use log::error;
+use config::Options;
+use doc::{Document, ParseOptions};
+use files::Files;
+use history::History;
+use input::Loaded;
+use keymap::Keymaps;
 use log::{debug, info};
+use log::{error, warn};
+use opener::Openers;
+use session::Session;
+use signal_hook::consts::{SIGCONT, SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use state::Clipboard;
 use std::env;
 use std::env::VarError;
-use std::io::stdin;
+use std::fs::{File, OpenOptions};
+use std::io::{stdin, stdout, Read, Write};
+use std::mem;
 use std::panic;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::process::Command;
-use std::sync::Mutex;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
+use tee::Tee;
 use termwiz::caps::Capabilities;
+use termwiz::escape::osc::Selection;
+use termwiz::escape::OperatingSystemCommand;
 use termwiz::input::InputEvent;
 use termwiz::input::KeyCode;
 use termwiz::input::KeyEvent;
 use termwiz::input::Modifiers;
 use termwiz::surface::Change;
-use termwiz::terminal::SystemTerminal;
+use termwiz::terminal::{SystemTerminal, TerminalWaker};
 use ui::AteUi;
 use ui::StepNext;
+use view::DocumentView;
 
-use crate::widgets::WidgetEvent;
 use termwiz::terminal::buffered::BufferedTerminal;
 use termwiz::terminal::Terminal;
-mod doc;
+use widgets::WidgetEvent;
+mod args;
+mod bookmarks;
+mod color;
+mod commands;
+mod config;
+mod files;
+mod history;
+mod input;
+mod keymap;
+mod keys;
+mod links;
+mod opener;
+mod perf;
+mod print;
+mod restore;
+mod session;
 mod state;
+mod style;
+mod tee;
 mod ui;
 mod widgets;
 
-fn open(uri: &str) -> Result<()> {
-    let opener = match env::var("ATE_OPENER") {
+// Builds the command line in the environment variable var.
+// Clipboard tools take arguments for which clipboard to use, like xclip -o -sel clip, so it's
+// split on whitespace into the program and its arguments.
+fn clipboard_command(var: &str, command: &str) -> Result<Command> {
+    info!("Using {} {}", var, command);
+    let mut words = command.split_whitespace();
+    let Some(program) = words.next() else {
+        bail!("{} is empty", var);
+    };
+    let mut command = Command::new(program);
+    command.args(words);
+    Ok(command)
+}
+
+// Reads the clipboard by running ATE_PASTE_COMMAND and returning what it wrote
+fn paste() -> Result<String> {
+    let command = match config::var("ATE_PASTE_COMMAND") {
         Ok(val) => val,
         Err(e) => match e {
-            VarError::NotPresent => bail!("ATE_OPENER must be defined to open links"),
+            VarError::NotPresent => bail!("ATE_PASTE_COMMAND must be defined to paste"),
             _ => bail!(e),
         },
     };
-    info!("Using ATE_OPENER {}", opener);
-    // TODO - don't block forever waiting on this, complain if it takes too long
-    let output = match Command::new(&opener).arg(uri).output() {
+    let output = match clipboard_command("ATE_PASTE_COMMAND", &command)?
+        .stdin(Stdio::null())
+        .output()
+    {
         Ok(o) => o,
-        // Don't use anyhow::context as it adds newlines
-        Err(e) => bail!("Failed to run ATE_OPENER {}: {}", opener, e),
+        Err(e) => bail!("Failed to run ATE_PASTE_COMMAND {}: {}", command, e),
     };
-    info!("ATE_OPENER stdout={}", String::from_utf8(output.stdout)?);
-    let stderr = String::from_utf8(output.stderr)?;
-    info!("ATE_OPENER stderr={}", stderr);
-    match output.status.code() {
-        Some(0) | None => Ok(()),
-        Some(c) => {
-            bail!(
-                "ATE_OPENER {} failed with code={} stderr={}",
-                opener,
-                c,
-                stderr
-            );
+    if !output.status.success() {
+        bail!(
+            "ATE_PASTE_COMMAND {} failed with {} stderr={}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Puts text on the clipboard by writing it to ATE_COPY_COMMAND if that's defined.
+// Otherwise asks the terminal to with OSC 52, which works over SSH, but can't tell us if the
+// terminal ignored it.
+fn copy(text: &str) -> Result<()> {
+    let command = match config::var("ATE_COPY_COMMAND") {
+        Ok(val) => val,
+        Err(VarError::NotPresent) => {
+            let set = OperatingSystemCommand::SetSelection(Selection::CLIPBOARD, text.to_string());
+            write_to_tty(&set.to_string(), "copy to the clipboard");
+            return Ok(());
         }
+        Err(e) => bail!(e),
+    };
+    let mut child = match clipboard_command("ATE_COPY_COMMAND", &command)?
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(c) => c,
+        Err(e) => bail!("Failed to run ATE_COPY_COMMAND {}: {}", command, e),
+    };
+    // Closes stdin once written so the command knows it has everything
+    let written = child.stdin.take().unwrap().write_all(text.as_bytes());
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "ATE_COPY_COMMAND {} failed with {} stderr={}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    if let Err(e) = written {
+        bail!("Failed to write to ATE_COPY_COMMAND {}: {}", command, e);
     }
+    Ok(())
 }
 
 struct Ate<'a> {
     term: BufferedTerminal<SystemTerminal>,
     ui: AteUi<'a>,
+    signaled: Arc<Signaled>,
+    // Set when the whole screen needs drawing again rather than only what changed, like after
+    // returning from being suspended
+    repaint: bool,
+    // Gets the document once it's been read from input
+    loading: Option<Receiver<Loaded>>,
+    // What was loaded before the UI started, to show first
+    loaded_early: Option<Loaded>,
+    // Gets what went wrong with openers once they've finished running
+    opener_failures: Receiver<String>,
+    // Keys to press once the document has been loaded
+    startup_keys: Vec<KeyEvent>,
+    // Whether to notify when input that took a while has been loaded
+    notify: bool,
+    // How to parse files switched to
+    parse: ParseOptions,
+    // Terminal modes to set up again after being suspended
+    kitty_keyboard: bool,
+    cluster_graphemes: bool,
     // Fields are dropped in declaration order.
     // Sticking this here gets it to be dropped after term.
     _dl: DropLast,
@@ -71,20 +179,40 @@ struct Ate<'a> {
 
 impl<'a> Ate<'a> {
     fn run(&mut self) -> Result<()> {
-        while let StepNext::WAIT = self.ui.step(&mut self.term)? {
+        self.check_loaded()?;
+        while let StepNext::Wait = self.ui.step(&mut self.term)? {
+            if let Some((file, path)) = self.ui.take_requested_file() {
+                self.switch_file(file, &path)?;
+                continue;
+            }
+            if self.ui.take_suspend() {
+                self.suspend()?;
+                continue;
+            }
             // Compute an optimized delta to apply to the terminal and display it
-            self.term.flush()?;
+            if mem::take(&mut self.repaint) {
+                self.term.repaint()?;
+            } else {
+                self.term.flush()?;
+            }
 
-            // Wait for user input
-            match self.term.terminal().poll_input(None) {
+            // Wait for user input, or until a pending chord times out
+            match self.term.terminal().poll_input(self.ui.timeout()) {
                 Ok(Some(input)) => match input {
                     InputEvent::Resized { rows, cols } => {
-                        // FIXME: this is working around a bug where we don't realize
-                        // that we should redraw everything on resize in BufferedTerminal.
-                        self.term
-                            .add_change(Change::ClearScreen(Default::default()));
-                        self.term.resize(cols, rows);
-                        self.ui.queue_event(WidgetEvent::Input(input));
+                        let (rows, cols, next) = self.coalesce_resizes(rows, cols)?;
+                        self.resize(rows, cols);
+                        if let Some(next) = next {
+                            self.ui.queue_event(WidgetEvent::Input(next));
+                        }
+                    }
+                    InputEvent::Paste(text) => {
+                        let (text, next) = self.coalesce_pastes(text)?;
+                        self.ui
+                            .queue_event(WidgetEvent::Input(InputEvent::Paste(text)));
+                        if let Some(next) = next {
+                            self.ui.queue_event(WidgetEvent::Input(next));
+                        }
                     }
                     _ => {
                         // Feed input into the Ui
@@ -96,9 +224,257 @@ impl<'a> Ate<'a> {
                     return Err(anyhow!(e));
                 }
             }
+            if self.signaled.terminate.load(Ordering::Relaxed) {
+                info!("Quitting on signal");
+                self.ui.request_quit();
+            }
+            if self.signaled.continued.swap(false, Ordering::Relaxed) {
+                // Whatever stopped us may have left the terminal in cooked mode and drawn over
+                // the screen
+                self.term.terminal().set_raw_mode()?;
+                self.repaint = true;
+            }
+            self.check_loaded()?;
+            while let Ok(failure) = self.opener_failures.try_recv() {
+                self.ui.report_error(failure);
+            }
+        }
+        Ok(())
+    }
+
+    fn resize(&mut self, rows: usize, cols: usize) {
+        // FIXME: this is working around a bug where we don't realize
+        // that we should redraw everything on resize in BufferedTerminal.
+        self.term
+            .add_change(Change::ClearScreen(Default::default()));
+        self.term.resize(cols, rows);
+        self.ui
+            .queue_event(WidgetEvent::Input(InputEvent::Resized { rows, cols }));
+    }
+
+    // Hands the terminal back to the shell until we're continued, like Ctrl-Z in less.
+    // The terminal is put back how it was before we started, then set up again on returning.
+    fn suspend(&mut self) -> Result<()> {
+        info!("Suspending");
+        let terminal = self.term.terminal();
+        if self.kitty_keyboard {
+            // Pop the mode in the alternate screen it was pushed in, before leaving it
+            terminal.flush()?;
+            set_kitty_keyboard(false);
+        }
+        terminal.exit_alternate_screen()?;
+        terminal.flush()?;
+        restore::restore_terminal(false);
+        // Stop the whole job, as Ctrl-Z would if the terminal weren't in raw mode, or the shell
+        // keeps waiting on whatever's writing to our input.
+        // This returns once the shell continues us.
+        // SAFETY: kill has no memory safety requirements
+        if unsafe { libc::kill(0, libc::SIGTSTP) } != 0 {
+            warn!("Unable to suspend: {}", std::io::Error::last_os_error());
+        }
+        info!("Resuming");
+        let terminal = self.term.terminal();
+        terminal.set_raw_mode()?;
+        terminal.enter_alternate_screen()?;
+        // Get into the alternate screen before pushing the mode there
+        terminal.flush()?;
+        if self.kitty_keyboard {
+            set_kitty_keyboard(true);
+        }
+        if self.cluster_graphemes {
+            set_grapheme_clustering(true);
         }
+        // The terminal may have been resized while we were stopped
+        let size = terminal.get_screen_size()?;
+        if (size.cols, size.rows) != self.term.dimensions() {
+            self.resize(size.rows, size.cols);
+        }
+        self.repaint = true;
         Ok(())
     }
+
+    // Starts reading one of the files from the command line in place of the one being viewed
+    fn switch_file(&mut self, file: usize, path: &Path) -> Result<()> {
+        let input = match open_file(path) {
+            Ok(input) => input,
+            Err(e) => {
+                self.ui.report_error(e.to_string());
+                return Ok(());
+            }
+        };
+        let size = file_size(&input);
+        // Dropping the receiver for the file being left stops reading it
+        self.loading = Some(input::load_in_background(
+            Box::new(input),
+            self.parse,
+            self.term.terminal().waker(),
+        )?);
+        self.loaded_early = None;
+        self.ui.switch_file(file);
+        self.ui.set_input_size(size);
+        Ok(())
+    }
+
+    fn check_loaded(&mut self) -> Result<()> {
+        let loaded = match self.loaded_early.take() {
+            Some(loaded) => loaded,
+            None => {
+                let Some(loading) = &self.loading else {
+                    return Ok(());
+                };
+                match loading.try_recv() {
+                    Ok(loaded) => loaded,
+                    Err(TryRecvError::Empty) => return Ok(()),
+                    // The input thread only goes away without sending all of the input if it
+                    // panicked
+                    Err(TryRecvError::Disconnected) => {
+                        self.loading = None;
+                        self.ui
+                            .input_failed("Reading input failed unexpectedly".to_string());
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        let doc = loaded.doc?;
+        if !loaded.complete {
+            self.ui
+                .load(doc, loaded.parse_time, loaded.read_time, false);
+            return Ok(());
+        }
+        if !loaded.indexing_links {
+            self.loading = None;
+            if self.notify && loaded.read_time >= NOTIFY_AFTER {
+                notify(&format!(
+                    "ate finished reading {} lines with {} links",
+                    doc.line_count,
+                    doc.links.len()
+                ));
+            }
+        }
+        // All of the text is shown first, and then again with the links found in it
+        if self.ui.is_indexing_links() {
+            self.ui.links_found(doc);
+            return Ok(());
+        }
+        self.ui.load(doc, loaded.parse_time, loaded.read_time, true);
+        if loaded.indexing_links {
+            self.ui.indexing_links();
+        }
+        for key in self.startup_keys.drain(..) {
+            self.ui
+                .queue_event(WidgetEvent::Input(InputEvent::Key(key)));
+        }
+        Ok(())
+    }
+
+    // A big paste can arrive as several events, and each one runs the search again.
+    // Join the pastes that have already arrived into one.
+    // Returns the joined text, and the first non-paste event that ended the run if any.
+    fn coalesce_pastes(&mut self, mut text: String) -> Result<(String, Option<InputEvent>)> {
+        loop {
+            match self.term.terminal().poll_input(Some(Duration::ZERO)) {
+                Ok(Some(InputEvent::Paste(next))) => text.push_str(&next),
+                Ok(next) => return Ok((text, next)),
+                Err(e) => return Err(anyhow!(e)),
+            }
+        }
+    }
+
+    // Dragging a terminal corner sends a burst of resizes, and each one reflows the whole
+    // document. Keep reading until the terminal has been quiet for RESIZE_COALESCE_WINDOW and only
+    // use the last size we saw.
+    // Returns the final rows and cols, and the first non-resize event that ended the burst if any.
+    fn coalesce_resizes(
+        &mut self,
+        mut rows: usize,
+        mut cols: usize,
+    ) -> Result<(usize, usize, Option<InputEvent>)> {
+        loop {
+            match self
+                .term
+                .terminal()
+                .poll_input(Some(RESIZE_COALESCE_WINDOW))
+            {
+                Ok(Some(InputEvent::Resized {
+                    rows: next_rows,
+                    cols: next_cols,
+                })) => {
+                    rows = next_rows;
+                    cols = next_cols;
+                }
+                Ok(next) => return Ok((rows, cols, next)),
+                Err(e) => return Err(anyhow!(e)),
+            }
+        }
+    }
+}
+
+// Waits for input until there's more of it than fits in the rows of the screen above the status
+// line, returning what's been loaded by then.
+// Input that ends while it still fits is printed to standard output instead, returning None.
+fn print_if_one_screen(
+    loading: &Receiver<Loaded>,
+    caps: Capabilities,
+    (cols, rows): (usize, usize),
+    options: &Options,
+) -> Result<Option<Loaded>> {
+    loop {
+        let loaded = loading.recv()?;
+        let Ok(doc) = &loaded.doc else {
+            return Ok(Some(loaded));
+        };
+        if !DocumentView::fits(doc, cols, rows.saturating_sub(1), &options.view) {
+            return Ok(Some(loaded));
+        }
+        // Links are printed too, so wait for them to be found
+        if loaded.complete && !loaded.indexing_links {
+            print::print_document(doc, caps, options.colors, cols, &mut stdout().lock())?;
+            return Ok(None);
+        }
+    }
+}
+
+// Input read faster than this was likely already finished when ate started, so there's nothing
+// worth notifying about
+const NOTIFY_AFTER: Duration = Duration::from_secs(5);
+
+// How long to wait for another resize before acting on the last one
+const RESIZE_COALESCE_WINDOW: Duration = Duration::from_millis(20);
+
+// What signals have asked the main loop to do
+#[derive(Default)]
+struct Signaled {
+    // Set on signals asking us to exit
+    terminate: AtomicBool,
+    // Set when we're continued after being stopped
+    continued: AtomicBool,
+}
+
+// Quits cleanly on signals that ask us to exit so dropping SystemTerminal restores the terminal.
+// The terminal's in raw mode, so Ctrl-C comes through as a key rather than SIGINT, but these can
+// still be sent by kill or by a terminal multiplexer closing our pane.
+// Stopping is left to the default action, so it stops us along with the rest of the job.
+fn handle_signals(waker: TerminalWaker) -> Result<Arc<Signaled>> {
+    let signaled = Arc::new(Signaled::default());
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP, SIGCONT])?;
+    let flags = signaled.clone();
+    thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            for signal in signals.forever() {
+                info!("Received signal {}", signal);
+                match signal {
+                    SIGCONT => flags.continued.store(true, Ordering::Relaxed),
+                    _ => flags.terminate.store(true, Ordering::Relaxed),
+                }
+                // Gets poll_input to return so the main loop sees the flag
+                if let Err(e) = waker.wake() {
+                    warn!("Unable to wake for signal: {}", e);
+                }
+            }
+        })?;
+    Ok(signaled)
 }
 
 // A message created by our panic hook if it ran
@@ -116,8 +492,99 @@ impl Drop for DropLast {
     }
 }
 
+fn write_to_tty(sequence: &str, description: &str) {
+    let written = OpenOptions::new()
+        .write(true)
+        .open("/dev/tty")
+        .and_then(|mut tty| tty.write_all(sequence.as_bytes()));
+    if let Err(e) = written {
+        warn!("Unable to {}: {}", description, e);
+    }
+}
+
+// OSC 9 asks the terminal to show a desktop notification.
+// Whether it does so while its window is focused is up to the terminal, since ate can't tell.
+// Terminals that don't support it ignore it.
+fn notify(message: &str) {
+    write_to_tty(&format!("\x1b]9;{message}\x1b\\"), "send notification");
+}
+
+// Mode 2027 asks the terminal to measure multi-codepoint graphemes as a single unit like we do.
+// Terminals that don't know the mode ignore it.
+fn set_grapheme_clustering(enable: bool) {
+    let set = if enable { 'h' } else { 'l' };
+    write_to_tty(&format!("\x1b[?2027{set}"), "set grapheme clustering mode");
+}
+
+// Asks the terminal to disambiguate keys with the kitty keyboard protocol, so keys like Esc and
+// Shift-Enter arrive as their own escape codes instead of bytes that are shared with other keys.
+// The main and alternate screens keep separate stacks of keyboard modes, so this must be pushed
+// and popped while we're in the alternate screen.
+// Terminals that don't support the protocol ignore it.
+fn set_kitty_keyboard(enable: bool) {
+    write_to_tty(
+        if enable { "\x1b[>1u" } else { "\x1b[<u" },
+        "set kitty keyboard mode",
+    );
+}
+
+fn open_file(path: &Path) -> Result<File> {
+    match File::open(path) {
+        Ok(file) => Ok(file),
+        Err(e) => bail!("Unable to open {}: {}", path.display(), e),
+    }
+}
+
+// How many bytes there are to read from file, unless it's something like a FIFO
+fn file_size(file: &File) -> Option<u64> {
+    file.metadata()
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+}
+
+// Where to keep a file that persists between runs, creating its directory if needed
+fn state_file(name: &str) -> Result<PathBuf> {
+    Ok(xdg::BaseDirectories::with_prefix("ate")?.place_state_file(name)?)
+}
+
+fn bookmarks_path() -> Option<PathBuf> {
+    match state_file("bookmarks") {
+        Ok(path) => Some(path),
+        Err(e) => {
+            warn!("Bookmarks won't be saved: {}", e);
+            None
+        }
+    }
+}
+
+fn sessions_dir() -> Option<PathBuf> {
+    let dir = xdg::BaseDirectories::with_prefix("ate")
+        .map_err(anyhow::Error::from)
+        .and_then(|dirs| Ok(dirs.create_state_directory("sessions")?));
+    match dir {
+        Ok(dir) => Some(dir),
+        Err(e) => {
+            warn!("Sessions won't be saved: {}", e);
+            None
+        }
+    }
+}
+
+fn search_history_path() -> Option<PathBuf> {
+    if config::var("ATE_NO_SAVE_SEARCHES").is_ok() {
+        return None;
+    }
+    match state_file("search_history") {
+        Ok(path) => Some(path),
+        Err(e) => {
+            warn!("Searches won't be saved: {}", e);
+            None
+        }
+    }
+}
+
 fn setup_logging() -> Result<()> {
-    let xdg_dirs = xdg::BaseDirectories::with_prefix("ate")?;
     fern::Dispatch::new()
         .format(|out, message, record| {
             out.finish(format_args!(
@@ -129,21 +596,95 @@ fn setup_logging() -> Result<()> {
             ))
         })
         .level(log::LevelFilter::Debug)
-        .chain(fern::log_file(xdg_dirs.place_state_file("log")?)?)
+        .chain(fern::log_file(state_file("log")?)?)
         .apply()?;
     Ok(())
 }
 fn main() -> Result<()> {
     setup_logging()?;
     info!("ate started");
-    if atty::is(atty::Stream::Stdin) {
-        eprintln!("ate displays data from stdin i.e. pipe or redirect to ate");
+    config::load();
+    let mut args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    };
+    let sessions_dir = sessions_dir();
+    let session = match (&args.session, &sessions_dir) {
+        (Some(name), Some(dir)) => match Session::load(dir, name) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                eprintln!("{e}");
+                process::exit(1);
+            }
+        },
+        (Some(_), None) => {
+            eprintln!("Sessions can't be read without a state directory");
+            process::exit(1);
+        }
+        (None, _) => None,
+    };
+    if let Some(session) = &session {
+        args.files = session.files.clone();
+    }
+    if args.files.is_empty() && atty::is(atty::Stream::Stdin) {
+        eprintln!("ate displays files or data from stdin i.e. pipe or redirect to ate");
         process::exit(1);
     }
+    // A session is picked up at the file it was left in
+    let first = session.as_ref().map_or(0, |session| session.current);
+    let mut input_size = None;
+    let input: Box<dyn Read + Send> = match (&args.tee, args.files.get(first)) {
+        (None, Some(path)) => {
+            let file = open_file(path)?;
+            input_size = file_size(&file);
+            Box::new(file)
+        }
+        // Args::parse rejects this, but files are never copied if it gets here some other way
+        (Some(_), Some(_)) => bail!("--tee only copies standard input\n{}", args::USAGE),
+        (Some(path), None) => match File::create(path) {
+            Ok(file) => Box::new(Tee::new(stdin(), file)),
+            Err(e) => bail!("Unable to create {:?} to copy input to: {}", path, e),
+        },
+        (None, None) => Box::new(stdin()),
+    };
+    if let Some(format) = args.links {
+        let parse = config::options().parse;
+        let doc = Document::new(input, parse)?;
+        links::print_links(&doc, format, &mut stdout().lock())?;
+        for path in args.files.iter().skip(1) {
+            let doc = Document::new(Box::new(open_file(path)?), parse)?;
+            links::print_links(&doc, format, &mut stdout().lock())?;
+        }
+        return Ok(());
+    }
 
     let caps = Capabilities::new_from_env()?;
-    let underlying_term = SystemTerminal::new(caps)?;
+    let colors = caps.color_level();
+    restore::save_terminal(caps.mouse_reporting())?;
+    let underlying_term = SystemTerminal::new(caps.clone())?;
     let mut term = BufferedTerminal::new(underlying_term)?;
+    let size = term.terminal().get_screen_size()?;
+
+    let mut options = config::options();
+    options.colors = colors;
+    options.view.follow |= args.follow;
+    options.quit_if_one_screen |= args.quit_if_one_screen;
+    options.view.chop_long_lines |= args.chop_long_lines;
+    // The other files are still to be viewed when the first fits on the screen
+    options.quit_if_one_screen &= args.files.len() <= 1;
+    let loading = input::load_in_background(input, options.parse, term.terminal().waker())?;
+    // Whether input fits on the screen is only known once enough of it has been read, so wait to
+    // take over the screen until it's clear ate isn't going to print it instead
+    let mut loaded_early = None;
+    if options.quit_if_one_screen {
+        match print_if_one_screen(&loading, caps, (size.cols, size.rows), &options)? {
+            Some(loaded) => loaded_early = Some(loaded),
+            None => return Ok(()),
+        }
+    }
     term.terminal().set_raw_mode()?;
     term.terminal().enter_alternate_screen()?;
 
@@ -182,35 +723,89 @@ fn main() -> Result<()> {
         };
         // Log the panic message we created
         error!("{formatted}");
+        // The UI carries on when another thread panics, and says what stopped working
+        if thread != "main" {
+            return;
+        }
+        // Unwinding will restore the terminal when it drops SystemTerminal, but that won't happen
+        // if we abort, e.g. by panicking again while unwinding.
+        restore::restore_terminal(true);
         // Store the message for to print to stderr after exiting the alternate screen
         let mut pmsg = PANIC_MESSAGE.lock().unwrap();
         *pmsg = Some(formatted);
     }));
 
-    let size = term.terminal().get_screen_size()?;
+    let signaled = handle_signals(term.terminal().waker())?;
 
-    let mut ui = ui::create_ui(Box::new(stdin()), size.cols, size.rows, Box::new(open))?;
+    if options.view.widths.cluster_graphemes {
+        set_grapheme_clustering(true);
+    }
+    let (openers, opener_failures) = Openers::new(term.terminal().waker());
+    let mut ui = ui::create_awaiting_ui(
+        size.cols,
+        size.rows,
+        Box::new(move |uri| openers.open(uri)),
+        Clipboard {
+            read: Box::new(paste),
+            write: Box::new(copy),
+        },
+        options,
+        Keymaps::from_env(),
+        bookmarks_path(),
+    )?;
+    match session {
+        Some(session) => ui.resume_session(session),
+        None => ui.set_files(Files::new(args.files)),
+    }
+    ui.set_sessions_dir(sessions_dir);
+    ui.set_search_history(History::load(search_history_path()));
+    ui.set_input_size(input_size);
 
-    if env::var("ATE_OPEN_FIRST").is_ok() {
+    let mut startup_keys = vec![];
+    if config::var("ATE_OPEN_FIRST").is_ok() {
         debug!("Opening first link");
-        ui.queue_event(WidgetEvent::Input(InputEvent::Key(KeyEvent {
+        startup_keys.push(KeyEvent {
             key: KeyCode::Enter,
             modifiers: Modifiers::NONE,
-        })));
+        });
     }
 
-    if env::var("ATE_GOTO_LAST").is_ok() {
+    if config::var("ATE_GOTO_LAST").is_ok() {
         debug!("Going to last link");
-        ui.queue_event(WidgetEvent::Input(InputEvent::Key(KeyEvent {
+        startup_keys.push(KeyEvent {
             key: KeyCode::Char('N'),
             modifiers: Modifiers::NONE,
-        })));
+        });
     }
 
-    Ate {
+    if options.kitty_keyboard {
+        // Get into the alternate screen before pushing the mode there
+        term.terminal().flush()?;
+        set_kitty_keyboard(true);
+    }
+    let mut ate = Ate {
         term,
         ui,
+        signaled,
+        repaint: false,
+        loading: Some(loading),
+        loaded_early,
+        opener_failures,
+        startup_keys,
+        notify: options.notify,
+        parse: options.parse,
+        kitty_keyboard: options.kitty_keyboard,
+        cluster_graphemes: options.view.widths.cluster_graphemes,
         _dl: DropLast {},
+    };
+    let result = ate.run();
+    restore::reset_cursor_style();
+    if options.kitty_keyboard {
+        set_kitty_keyboard(false);
+    }
+    drop(ate);
+    if options.view.widths.cluster_graphemes {
+        set_grapheme_clustering(false);
     }
-    .run()
+    result
 }
@@ -0,0 +1,242 @@
+use std::io::Read;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+use termwiz::terminal::TerminalWaker;
+
+use crate::config;
+
+use crate::restore;
+
+// Openers still running after this long are killed.
+// Openers are expected to hand the link to something else, like an editor, and exit.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How often to check whether an opener has exited
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Starts ATE_OPENER, or the opener for the link's scheme, without waiting for it to finish, so
+// the UI keeps going while it runs.
+// Failures of openers that have finished are sent on the receiver from new, waking the terminal's
+// input poll.
+pub struct Openers {
+    timeout: Duration,
+    failures: Sender<String>,
+    waker: TerminalWaker,
+}
+
+impl Openers {
+    pub fn new(waker: TerminalWaker) -> (Self, Receiver<String>) {
+        let timeout = match config::var("ATE_OPENER_TIMEOUT").map(|t| t.parse::<f64>()) {
+            Ok(Ok(seconds)) if seconds > 0.0 => Duration::from_secs_f64(seconds),
+            Ok(_) => {
+                warn!("Ignoring ATE_OPENER_TIMEOUT that isn't a positive number of seconds");
+                DEFAULT_TIMEOUT
+            }
+            Err(_) => DEFAULT_TIMEOUT,
+        };
+        let (failures, receiver) = mpsc::channel();
+        let openers = Self {
+            timeout,
+            failures,
+            waker,
+        };
+        (openers, receiver)
+    }
+
+    pub fn open(&self, uri: &str) -> Result<()> {
+        let Some((var, opener)) = choose_opener(uri, |var| config::var(var).ok()) else {
+            bail!("ATE_OPENER must be defined to open links");
+        };
+        info!("Using {} {}", var, opener);
+        let child = match Command::new(&opener)
+            .arg(uri)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(c) => c,
+            // Don't use anyhow::context as it adds newlines
+            Err(e) => bail!("Failed to run {} {}: {}", var, opener, e),
+        };
+        let (timeout, failures, waker) = (self.timeout, self.failures.clone(), self.waker.clone());
+        thread::Builder::new()
+            .name("opener".to_string())
+            .spawn(move || {
+                // The opener may run an editor in this terminal
+                let _mouse = restore::pause_mouse_reporting();
+                let description = format!("{var} {opener}");
+                if let Err(failure) = wait(child, &description, timeout) {
+                    // Nothing's receiving once the UI has gone away
+                    if failures.send(failure).is_ok() {
+                        if let Err(e) = waker.wake() {
+                            warn!("Unable to wake for opener failure: {}", e);
+                        }
+                    }
+                }
+            })?;
+        Ok(())
+    }
+}
+
+// The variable naming the program to open uri with and the program.
+// Links with a scheme go to ATE_OPENER_ and the scheme in capitals if it's defined, with https
+// falling back to ATE_OPENER_HTTP, and everything else goes to ATE_OPENER.
+fn choose_opener(uri: &str, var: impl Fn(&str) -> Option<String>) -> Option<(String, String)> {
+    let mut vars = vec![];
+    if let Some((scheme, _)) = uri.split_once(':') {
+        let is_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+        if is_scheme {
+            let scheme = scheme.to_ascii_uppercase().replace(['+', '-', '.'], "_");
+            vars.push(format!("ATE_OPENER_{scheme}"));
+            if scheme == "HTTPS" {
+                vars.push("ATE_OPENER_HTTP".to_string());
+            }
+        }
+    }
+    vars.push("ATE_OPENER".to_string());
+    vars.into_iter()
+        .find_map(|name| var(&name).map(|value| (name, value)))
+}
+
+// Waits for the opener in child to exit, killing it if it takes longer than timeout.
+// Returns a description of what went wrong if it didn't exit successfully.
+fn wait(mut child: Child, description: &str, timeout: Duration) -> Result<(), String> {
+    let stdout = child.stdout.take().map(read_in_background);
+    let stderr = child.stderr.take().map(read_in_background);
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() >= timeout => {
+                if let Err(e) = child.kill().and_then(|()| child.wait().map(|_| ())) {
+                    warn!("Unable to kill {}: {}", description, e);
+                }
+                return Err(format!("{description} timed out after {timeout:?}"));
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(format!("Failed waiting for {description}: {e}")),
+        }
+    };
+    let output =
+        |reader: Option<JoinHandle<String>>| reader.and_then(|r| r.join().ok()).unwrap_or_default();
+    info!("{} stdout={}", description, output(stdout));
+    let stderr = output(stderr);
+    info!("{} stderr={}", description, stderr);
+    match (status.code(), status.signal()) {
+        (Some(0), _) => Ok(()),
+        (Some(c), _) => Err(format!(
+            "{description} failed with code={c} stderr={stderr}"
+        )),
+        (None, Some(signal)) => Err(format!(
+            "{description} was killed by signal {signal} stderr={stderr}"
+        )),
+        (None, None) => Err(format!("{description} failed stderr={stderr}")),
+    }
+}
+
+// Reads everything from a child's output so it can't block on a full pipe
+fn read_in_background(mut output: impl Read + Send + 'static) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut read = vec![];
+        if let Err(e) = output.read_to_end(&mut read) {
+            warn!("Unable to read opener output: {}", e);
+        }
+        String::from_utf8_lossy(&read).into_owned()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn choose_openers_by_scheme() {
+        let vars = |defined: &'static [&'static str]| {
+            move |var: &str| defined.contains(&var).then(|| var.to_lowercase())
+        };
+        let all = vars(&["ATE_OPENER", "ATE_OPENER_HTTP", "ATE_OPENER_FILE"]);
+        let chosen = |uri, var| choose_opener(uri, var).map(|(name, _)| name);
+        assert_eq!(
+            Some("ATE_OPENER_FILE".to_string()),
+            chosen("file://h/a#3", &all)
+        );
+        assert_eq!(
+            Some("ATE_OPENER_HTTP".to_string()),
+            chosen("https://a.b", &all)
+        );
+        // Paths with line numbers and unknown schemes go to the default
+        assert_eq!(Some("ATE_OPENER".to_string()), chosen("a.rs:3", &all));
+        assert_eq!(Some("ATE_OPENER".to_string()), chosen("mailto:a@b", &all));
+        assert_eq!(
+            Some(("ATE_OPENER_HTTP".to_string(), "ate_opener_http".to_string())),
+            choose_opener("https://a.b", vars(&["ATE_OPENER_HTTP"]))
+        );
+        assert_eq!(None, choose_opener("file://h/a", vars(&[])));
+    }
+
+    fn run(script: &str, timeout: Duration) -> Result<(), String> {
+        let child = Command::new("sh")
+            .args(["-c", script])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        wait(child, "test opener", timeout)
+    }
+
+    #[test]
+    fn wait_for_openers() {
+        let timeout = Duration::from_secs(10);
+        assert_eq!(Ok(()), run("echo opened", timeout));
+        assert_eq!(
+            Err("test opener failed with code=3 stderr=oops\n".to_string()),
+            run("echo oops >&2; exit 3", timeout)
+        );
+        assert_eq!(
+            Err("test opener was killed by signal 9 stderr=".to_string()),
+            run("kill -9 $$", timeout)
+        );
+        let started = Instant::now();
+        let timed_out = run("sleep 10", Duration::from_millis(100));
+        assert_eq!(
+            Err("test opener timed out after 100ms".to_string()),
+            timed_out
+        );
+        assert!(started.elapsed() < timeout);
+    }
+
+    #[test]
+    fn reap_openers() {
+        // Openers that finish, fail or time out are waited on, so none are left as zombies
+        for (script, timeout) in [
+            ("true", Duration::from_secs(10)),
+            ("exit 1", Duration::from_secs(10)),
+            ("sleep 10", Duration::from_millis(100)),
+        ] {
+            let child = Command::new("sh")
+                .args(["-c", script])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let pid = child.id() as libc::pid_t;
+            let _ = wait(child, "test opener", timeout);
+            // SAFETY: signal 0 only checks whether the process exists
+            assert_eq!(-1, unsafe { libc::kill(pid, 0) }, "{script}");
+            assert_eq!(
+                Some(libc::ESRCH),
+                std::io::Error::last_os_error().raw_os_error()
+            );
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use finl_unicode::grapheme_clusters::Graphemes;
+use termwiz::cell::{grapheme_column_width, UnicodeVersion, LATEST_UNICODE_VERSION};
+
+/// Settings that change how a [`DocumentView`](crate::DocumentView) flows and shows a document
+#[derive(Clone, Copy, Default)]
+pub struct ViewOptions {
+    /// Used for every width calculation so wrapping agrees with the terminal
+    pub widths: CellWidths,
+    /// Whether to start out reordering right to left text for display
+    pub bidi: bool,
+    /// Whether to indent wrapped lines to line up with the text they continue
+    pub hanging_indent: bool,
+    /// Whether to start out cutting long lines off at the edge of the screen instead of wrapping
+    /// them
+    pub chop_long_lines: bool,
+    /// Whether to start out keeping the end of the input on screen as more of it's read
+    pub follow: bool,
+}
+
+/// Measures how many terminal cells text takes up
+#[derive(Clone, Copy)]
+pub struct CellWidths {
+    pub unicode_version: UnicodeVersion,
+    /// Whether the terminal draws a multi-codepoint grapheme like an emoji ZWJ sequence as a
+    /// single unit, as terminals supporting mode 2027 do.
+    /// Terminals that don't give each codepoint its own width, so we have to sum them.
+    pub cluster_graphemes: bool,
+}
+
+impl Default for CellWidths {
+    fn default() -> Self {
+        Self {
+            unicode_version: LATEST_UNICODE_VERSION,
+            cluster_graphemes: true,
+        }
+    }
+}
+
+impl CellWidths {
+    /// Cells taken up by a single grapheme cluster
+    pub fn grapheme(&self, grapheme: &str) -> usize {
+        if self.cluster_graphemes || grapheme.len() == 1 {
+            return grapheme_column_width(grapheme, Some(self.unicode_version));
+        }
+        let mut buf = [0; 4];
+        grapheme
+            .chars()
+            .map(|c| grapheme_column_width(c.encode_utf8(&mut buf), Some(self.unicode_version)))
+            .sum()
+    }
+
+    /// Cells taken up by text, which is expected to be a single line
+    pub fn text(&self, text: &str) -> usize {
+        Graphemes::new(text).map(|g| self.grapheme(g)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measure_emoji_sequences() {
+        // Woman, zero width joiner, laptop
+        let technologist = "\u{1f469}\u{200d}\u{1f4bb}";
+        let clustered = CellWidths::default();
+        assert_eq!(2, clustered.grapheme(technologist));
+        let unclustered = CellWidths {
+            cluster_graphemes: false,
+            ..Default::default()
+        };
+        assert_eq!(4, unclustered.grapheme(technologist));
+        assert_eq!(5, unclustered.text(&format!("a{technologist}")));
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// How long the expensive phases of ate took most recently.
+// These are logged as they're measured and shown in the timings overlay so slowdowns are measurable.
+#[derive(Default, Clone, Copy)]
+pub struct Timings {
+    pub parse: Duration,
+    pub flow: Duration,
+    pub render: Duration,
+    pub event: Duration,
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "parse {:.1?} flow {:.1?} render {:.1?} event {:.1?}",
+            self.parse, self.flow, self.render, self.event
+        )
+    }
+}
+
+// Runs f and returns its result along with how long it took
+pub fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+// Approximate bytes held by the big structures of a document.
+// Capacities are counted rather than lengths because that's what's actually allocated.
+#[derive(Default, Clone, Copy)]
+pub struct MemoryUsage {
+    pub text: usize,
+    pub attrs: usize,
+    pub links: usize,
+    pub lines: usize,
+    pub matches: usize,
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "text {} attrs {} links {} lines {} matches {}",
+            format_bytes(self.text),
+            format_bytes(self.attrs),
+            format_bytes(self.links),
+            format_bytes(self.lines),
+            format_bytes(self.matches)
+        )
+    }
+}
+
+// The size and shape of the input, for eyeballing what a command produced
+#[derive(Default, Clone, Copy)]
+pub struct InputStats {
+    pub bytes: usize,
+    pub lines: usize,
+    pub links: usize,
+    // How long it took to read and parse all of the input
+    pub read_time: Duration,
+}
+
+impl fmt::Display for InputStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes ({}) {} lines {} links read in {:.1?}",
+            self.bytes,
+            format_bytes(self.bytes),
+            self.lines,
+            self.links,
+            self.read_time
+        )
+    }
+}
+
+// Formats a byte count with a binary unit suffix e.g. 1.5MiB
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_byte_counts() {
+        assert_eq!("0B", format_bytes(0));
+        assert_eq!("1023B", format_bytes(1023));
+        assert_eq!("1.0KiB", format_bytes(1024));
+        assert_eq!("1.5MiB", format_bytes(1024 * 1024 * 3 / 2));
+    }
+}
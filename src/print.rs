@@ -0,0 +1,190 @@
+use std::fs::OpenOptions;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use termwiz::caps::{Capabilities, ColorLevel, ProbeHints};
+use termwiz::cell::{AttributeChange, CellAttributes};
+use termwiz::render::terminfo::TerminfoRenderer;
+use termwiz::render::RenderTty;
+use termwiz::surface::Change;
+
+use crate::color::downgrade_change;
+use crate::doc::Document;
+
+// Writes doc to out with its attributes and links, for input that fits on the screen and is
+// printed instead of paged, like less -F -X
+pub fn print_document(
+    doc: &Document,
+    caps: Capabilities,
+    colors: ColorLevel,
+    width: usize,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut changes = vec![];
+    let mut attrs = doc.attrs.iter().peekable();
+    let mut links = doc.links.iter().peekable();
+    // The link being written, which ends at the byte it holds
+    let mut in_link = None;
+    let mut byte = 0;
+    while byte < doc.text.len() {
+        if in_link.is_some_and(|end| end <= byte) {
+            changes.push(Change::Attribute(AttributeChange::Hyperlink(None)));
+            in_link = None;
+        }
+        while let Some((_, change)) = attrs.next_if(|(b, _)| *b <= byte) {
+            changes.push(downgrade_change(change.clone(), colors));
+        }
+        if let Some(link) = links.next_if(|l| l.start <= byte) {
+            changes.push(Change::Attribute(AttributeChange::Hyperlink(Some(
+                Arc::new(link.link.clone()),
+            ))));
+            in_link = Some(link.end);
+        }
+        // Write up to whatever changes next
+        let next = [
+            attrs.peek().map(|(b, _)| *b),
+            links.peek().map(|l| l.start),
+            in_link,
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|&b| b > byte)
+        .fold(doc.text.len(), usize::min);
+        changes.push(Change::Text(doc.text[byte..next].to_string()));
+        byte = next;
+    }
+    if in_link.is_some() {
+        changes.push(Change::Attribute(AttributeChange::Hyperlink(None)));
+    }
+    // Leave the terminal's attributes as they were for whatever runs next
+    changes.push(Change::AllAttributes(CellAttributes::default()));
+    let mut tty = Tty { out, width };
+    TerminfoRenderer::new(caps).render_to(&changes, &mut tty)?;
+    tty.flush()?;
+    Ok(())
+}
+
+// Writes doc to a new file at path, so what was read isn't lost on quitting.
+// The file gets the text without escape sequences unless escapes is set, which keeps its colors and
+// links for viewing again in a terminal.
+pub fn save_document(doc: &Document, path: &Path, escapes: bool) -> Result<()> {
+    // Never overwrite, since the path is typed at a prompt without a chance to confirm
+    let file = match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            bail!("{} already exists", path.display())
+        }
+        Err(e) => bail!("Unable to save to {}: {}", path.display(), e),
+    };
+    let mut out = BufWriter::new(file);
+    let written = if escapes {
+        // Files aren't a terminal to probe, so write for any terminal that handles true color
+        // and links
+        Capabilities::new_with_hints(
+            ProbeHints::default()
+                .term(Some("xterm-256color".to_string()))
+                .hyperlinks(Some(true)),
+        )
+        .map_err(anyhow::Error::from)
+        .and_then(|caps| print_document(doc, caps, ColorLevel::TrueColor, 80, &mut out))
+    } else {
+        out.write_all(doc.text.as_bytes())
+            .and_then(|()| out.flush())
+            .map_err(anyhow::Error::from)
+    };
+    if let Err(e) = written {
+        bail!("Unable to save to {}: {}", path.display(), e);
+    }
+    Ok(())
+}
+
+struct Tty<'a, W: Write> {
+    out: &'a mut W,
+    width: usize,
+}
+
+impl<W: Write> Write for Tty<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<W: Write> RenderTty for Tty<'_, W> {
+    fn get_size_in_cells(&mut self) -> termwiz::Result<(usize, usize)> {
+        // Only positioning the cursor needs the height, and printing doesn't
+        Ok((self.width, 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use std::fs;
+
+    use super::*;
+    use crate::doc::ParseOptions;
+
+    #[test]
+    fn print_attributes_and_links() {
+        let input = "a \x1b[1mbold\x1b[m \x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\\nend\n";
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        let caps = Capabilities::new_with_hints(
+            ProbeHints::default()
+                .term(Some("xterm-256color".to_string()))
+                .hyperlinks(Some(true)),
+        )
+        .unwrap();
+        let mut out = vec![];
+        print_document(&doc, caps, ColorLevel::TrueColor, 80, &mut out).unwrap();
+        let printed = String::from_utf8(out).unwrap();
+        let stripped = strip_sequences(&printed);
+        assert_eq!("a bold link\nend\n", stripped, "{:?}", printed);
+        assert!(
+            printed.contains("\x1b]8;;http://x\x1b\\link"),
+            "{:?}",
+            printed
+        );
+        assert!(printed.contains("1mbold"), "{:?}", printed);
+    }
+
+    #[test]
+    fn save_with_and_without_escapes() {
+        let input = "a \x1b[1mbold\x1b[m \x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\\n";
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        let dir = std::env::temp_dir();
+        let plain = dir.join(format!("ate-save-plain-{}", std::process::id()));
+        let escaped = dir.join(format!("ate-save-escaped-{}", std::process::id()));
+        let _ = fs::remove_file(&plain);
+        let _ = fs::remove_file(&escaped);
+
+        save_document(&doc, &plain, false).unwrap();
+        assert_eq!("a bold link\n", fs::read_to_string(&plain).unwrap());
+        save_document(&doc, &escaped, true).unwrap();
+        let saved = fs::read_to_string(&escaped).unwrap();
+        assert_eq!("a bold link\n", strip_sequences(&saved));
+        assert!(saved.contains("\x1b]8;;http://x\x1b\\link"), "{:?}", saved);
+
+        let e = save_document(&doc, &plain, true).unwrap_err();
+        assert_eq!(format!("{} already exists", plain.display()), e.to_string());
+        assert_eq!("a bold link\n", fs::read_to_string(&plain).unwrap());
+        fs::remove_file(&plain).unwrap();
+        fs::remove_file(&escaped).unwrap();
+    }
+
+    // The text left after taking out CSI and OSC sequences
+    fn strip_sequences(s: &str) -> String {
+        let doc = Document::new(
+            Box::new(Cursor::new(s.to_string())),
+            ParseOptions::default(),
+        )
+        .unwrap();
+        doc.text
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::mem::MaybeUninit;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+use log::warn;
+
+// SystemTerminal puts the terminal back how it found it when it's dropped.
+// That doesn't happen if we abort or are killed before unwinding gets to it, so this undoes
+// everything we set up without needing access to the terminal.
+
+// The tty's mode from before we put it in raw mode
+static SAVED_TERMIOS: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+// Whether SystemTerminal turns on mouse reporting, which it does if the terminal supports it
+static MOUSE_REPORTING: AtomicBool = AtomicBool::new(false);
+
+// How many MousePauses are held, so reporting only comes back on when the last one's dropped
+static MOUSE_PAUSES: Mutex<usize> = Mutex::new(0);
+
+// SGR encoded reports of any mouse event, like SystemTerminal enables
+const MOUSE_REPORTING_ON: &[u8] = b"\x1b[?1003h\x1b[?1006h";
+const MOUSE_REPORTING_OFF: &[u8] = b"\x1b[?1006l\x1b[?1003l";
+
+fn open_tty() -> std::io::Result<File> {
+    OpenOptions::new().read(true).write(true).open("/dev/tty")
+}
+
+// Call before entering raw mode so restore_terminal knows what to go back to
+pub fn save_terminal(mouse_reporting: bool) -> Result<()> {
+    MOUSE_REPORTING.store(mouse_reporting, Ordering::Relaxed);
+    let tty = open_tty()?;
+    let mut termios = MaybeUninit::uninit();
+    // SAFETY: tcgetattr fills in termios when it returns 0
+    if unsafe { libc::tcgetattr(tty.as_raw_fd(), termios.as_mut_ptr()) } != 0 {
+        bail!(
+            "Unable to read tty mode: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    *SAVED_TERMIOS.lock().unwrap() = Some(unsafe { termios.assume_init() });
+    Ok(())
+}
+
+// Puts the terminal back in cooked mode on the main screen with the cursor showing and nothing
+// we enabled left on.
+// The kitty keyboard mode is only popped if pop_kitty_keyboard is set, since it has to come off
+// the stack of the screen it was pushed on. Callers that have already popped it pass false.
+// It's fine to call more than once or after SystemTerminal has already restored things.
+pub fn restore_terminal(pop_kitty_keyboard: bool) {
+    let tty = match open_tty() {
+        Ok(tty) => tty,
+        Err(e) => {
+            warn!("Unable to open tty to restore it: {}", e);
+            return;
+        }
+    };
+    // Reset modifyOtherKeys, SGR and any event mouse reporting, bracketed paste, grapheme
+    // clustering, and the cursor style, then show the cursor and leave the alternate screen
+    let pop = if pop_kitty_keyboard { "\x1b[<u" } else { "" };
+    let written = write!(
+        &tty,
+        "\x1b[0 q{pop}\x1b[>4;0m\x1b[?1006l\x1b[?1003l\x1b[?2004l\x1b[?2027l\x1b[?25h\x1b[?1049l"
+    );
+    if let Err(e) = written {
+        warn!("Unable to reset terminal modes: {}", e);
+    }
+    // Don't wait on the lock since a panic may have happened while it was held
+    let saved = match SAVED_TERMIOS.try_lock() {
+        Ok(saved) => *saved,
+        Err(_) => None,
+    };
+    if let Some(termios) = saved {
+        // SAFETY: termios came from tcgetattr
+        if unsafe { libc::tcsetattr(tty.as_raw_fd(), libc::TCSANOW, &termios) } != 0 {
+            warn!(
+                "Unable to restore tty mode: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+// Turns mouse reporting off until dropped, along with any others held at the same time.
+// Hold one while another program may be using the terminal in the foreground so clicks go to it
+// rather than being reported as escape codes it doesn't expect.
+pub struct MousePause {
+    paused: bool,
+}
+
+pub fn pause_mouse_reporting() -> MousePause {
+    let paused = MOUSE_REPORTING.load(Ordering::Relaxed);
+    if paused {
+        let mut pauses = MOUSE_PAUSES.lock().unwrap();
+        if *pauses == 0 {
+            write_modes(MOUSE_REPORTING_OFF, "pause mouse reporting");
+        }
+        *pauses += 1;
+    }
+    MousePause { paused }
+}
+
+impl Drop for MousePause {
+    fn drop(&mut self) {
+        if self.paused {
+            let mut pauses = MOUSE_PAUSES.lock().unwrap();
+            *pauses -= 1;
+            if *pauses == 0 {
+                write_modes(MOUSE_REPORTING_ON, "resume mouse reporting");
+            }
+        }
+    }
+}
+
+// The search prompt changes the cursor to a bar.
+// termwiz only resets the cursor style if terminfo says how, which many entries don't, so reset it
+// to the style the user configured for the terminal with DECSCUSR 0.
+pub fn reset_cursor_style() {
+    write_modes(b"\x1b[0 q", "reset cursor style");
+}
+
+fn write_modes(modes: &[u8], description: &str) {
+    if let Err(e) = open_tty().and_then(|mut tty| tty.write_all(modes)) {
+        warn!("Unable to {}: {}", description, e);
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+// The files being viewed and where each was left, along with the search and filter in the one
+// being viewed, saved under a name to pick up again with --session.
+// Piped input can't be read again, so only files can be saved.
+#[derive(Debug, Default, PartialEq)]
+pub struct Session {
+    pub files: Vec<PathBuf>,
+    // Where the top of each file was, or None for files that hadn't been viewed
+    pub positions: Vec<Option<usize>>,
+    // Which of the files was being viewed
+    pub current: usize,
+    // The text being stepped through with n and N
+    pub search: Option<String>,
+    // What only the lines matching were being shown for
+    pub filter: Option<String>,
+    // Whether the search and filter are regexes
+    pub regex: bool,
+}
+
+impl Session {
+    // Saves the session as name in dir, replacing any saved with that name before.
+    // Paths are saved in full so the session can be picked up from any directory.
+    pub fn save(&self, dir: &Path, name: &str) -> Result<()> {
+        let path = path(dir, name)?;
+        let mut contents = format!("current {}\n", self.current);
+        if self.regex {
+            contents.push_str("regex\n");
+        }
+        for (kind, query) in [("search", &self.search), ("filter", &self.filter)] {
+            if let Some(query) = query {
+                // Prompts don't take control characters, so queries are a line each
+                contents.push_str(&format!("{kind} {}\n", query.replace('\n', " ")));
+            }
+        }
+        for (file, position) in self.files.iter().zip(&self.positions) {
+            let file = fs::canonicalize(file).unwrap_or_else(|_| file.clone());
+            let file = file.to_string_lossy();
+            if file.contains('\n') {
+                bail!("Unable to save {:?} in a session", file);
+            }
+            match position {
+                Some(top) => contents.push_str(&format!("file {top} {file}\n")),
+                None => contents.push_str(&format!("file - {file}\n")),
+            }
+        }
+        if let Err(e) = fs::write(&path, contents) {
+            bail!("Unable to save session {} to {:?}: {}", name, path, e);
+        }
+        Ok(())
+    }
+
+    pub fn load(dir: &Path, name: &str) -> Result<Self> {
+        let path = path(dir, name)?;
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => bail!("No session named {}", name),
+            Err(e) => bail!("Unable to read session {} from {:?}: {}", name, path, e),
+        };
+        match Self::parse(&contents) {
+            Ok(session) => Ok(session),
+            Err(e) => bail!("Unable to read session {}: {}", name, e),
+        }
+    }
+
+    // Each line is a setting followed by its value, with a line for each file giving where it
+    // was left, or - if it wasn't viewed, then its path
+    fn parse(contents: &str) -> Result<Self> {
+        let mut session = Self::default();
+        for line in contents.lines() {
+            let (setting, value) = line.split_once(' ').unwrap_or((line, ""));
+            match setting {
+                "current" => match value.parse() {
+                    Ok(current) => session.current = current,
+                    Err(_) => bail!("Expected a file number in {:?}", line),
+                },
+                "regex" => session.regex = true,
+                "search" => session.search = Some(value.to_string()),
+                "filter" => session.filter = Some(value.to_string()),
+                "file" => {
+                    let Some((position, file)) = value.split_once(' ') else {
+                        bail!("Expected a position and a path in {:?}", line);
+                    };
+                    let position = match position {
+                        "-" => None,
+                        position => match position.parse() {
+                            Ok(top) => Some(top),
+                            Err(_) => bail!("Expected a position in {:?}", line),
+                        },
+                    };
+                    session.files.push(PathBuf::from(file));
+                    session.positions.push(position);
+                }
+                _ => bail!("Unknown setting in {:?}", line),
+            }
+        }
+        if session.current >= session.files.len() {
+            bail!("It has no file {}", session.current + 1);
+        }
+        Ok(session)
+    }
+}
+
+// Sessions are kept a file each, so their names can't go anywhere else
+fn path(dir: &Path, name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\n']) {
+        bail!("Session names can't be empty, start with ., or contain /");
+    }
+    Ok(dir.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("ate sessions {}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a file.txt");
+        fs::write(&file, "text\n").unwrap();
+        let session = Session {
+            files: vec![file.clone(), PathBuf::from("/gone/b.txt")],
+            positions: vec![Some(30), None],
+            current: 0,
+            search: Some("warning: ".to_string()),
+            filter: Some("^src/".to_string()),
+            regex: true,
+        };
+        session.save(&dir, "work").unwrap();
+        let mut expected = session;
+        expected.files[0] = fs::canonicalize(&file).unwrap();
+        assert_eq!(expected, Session::load(&dir, "work").unwrap());
+
+        let err = Session::load(&dir, "play").unwrap_err();
+        assert_eq!("No session named play", err.to_string());
+        assert!(Session::load(&dir, "../work").is_err());
+        assert!(expected.save(&dir, ".hidden").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reject_broken_sessions() {
+        assert!(Session::parse("current 0\nfile 0 /a\n").is_ok());
+        // There has to be a file to view
+        assert!(Session::parse("").is_err());
+        assert!(Session::parse("current 1\nfile 0 /a\n").is_err());
+        assert!(Session::parse("current 0\nfile x /a\n").is_err());
+        assert!(Session::parse("current 0\nfile 0 /a\nzoom 2\n").is_err());
+    }
+}
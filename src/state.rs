@@ -1,66 +1,719 @@
-use std::{cell::RefCell, cmp::min, rc::Rc};
+use std::{
+    cell::RefCell,
+    cmp::min,
+    collections::{BTreeMap, BTreeSet},
+    mem::{self, size_of},
+    ops::Range,
+    path::PathBuf,
+    rc::Rc,
+    time::Duration,
+};
 
-use crate::doc::Document;
+use crate::bookmarks::{Bookmarks, LAST_POSITION};
+use crate::commands::{Help, Palette};
+use crate::config;
+use crate::config::Options;
+use crate::doc::{Document, LinkRange, MAX_DIAGNOSTICS};
+use crate::files::Files;
+use crate::find::{Pattern, TextSearch};
+use crate::history::History;
+use crate::keymap::{BindingAction, ContentType, Keymaps};
+use crate::keys::{KeyBindings, Macros, PendingKeys};
+use crate::perf::{InputStats, MemoryUsage, Timings};
+use crate::print;
+use crate::session::Session;
+use crate::style::{LinkStyle, LinkStyles};
+use crate::view::DocumentView;
 use anyhow::Result;
-use finl_unicode::grapheme_clusters::Graphemes;
-use log::{debug, info};
-use termwiz::{
-    cell::{grapheme_column_width, CellAttributes},
-    surface::Change,
-};
+use log::{info, warn};
+use termwiz::{caps::ColorLevel, surface::Change};
+
+// Called with the URI of a link to open it
+pub type OpenLink = Box<dyn FnMut(&str) -> Result<()>>;
+
+// Called to get the text on the system clipboard to paste it
+pub type ReadClipboard = Box<dyn FnMut() -> Result<String>>;
+
+// Called with text to put on the system clipboard
+pub type WriteClipboard = Box<dyn FnMut(&str) -> Result<()>>;
+
+// Reaches the system clipboard, which works differently depending on the system and terminal
+pub struct Clipboard {
+    pub read: ReadClipboard,
+    pub write: WriteClipboard,
+}
+
+// A range of bytes in the document to draw with a style, like a selected link
+pub type Highlight = (usize, usize, LinkStyle);
 
 // Anything we need to share with the surrounding application goes in here
 // It's in a RefCell so we can mutate from either side
 pub struct Shared {
     pub searching: bool,
-    // We keep the raw term height to be able to do fixed size layout.
+    // We keep the raw term size to be able to do fixed size layout.
     // TODO - fix termwiz layout to get rid of this:
     // https://github.com/wez/wezterm/issues/2543
+    pub term_width: usize,
     pub term_height: usize,
     pub quit: bool,
+    // Set when asked to suspend to the shell, until the application does
+    pub suspend: bool,
+    pub timings: Timings,
+    // How long input has been read for, until it ended
+    pub read_time: Duration,
+    // Diagnostics to show in the status line instead of the usual status
+    pub overlay: Option<Overlay>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Overlay {
+    Stats,
+    Timings,
+    Memory,
+    Diagnostics,
+}
+
+// What the status line shows on the right, each only while there's something to show
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusItem {
+    // Which of the files given is shown
+    File,
+    // How far the view is scrolled sideways
+    Column,
+    // The register a macro is being recorded into
+    Recording,
+    Filtered,
+    Following,
+    // The start of a sequence of keys waiting for the rest of it
+    Keys,
+    // That links are still being looked for in the text
+    Indexing,
+    // How far through the text the view is, or how much has been read
+    Position,
+}
+
+const STATUS_ITEMS: &[(StatusItem, &str)] = &[
+    (StatusItem::File, "file"),
+    (StatusItem::Column, "column"),
+    (StatusItem::Recording, "recording"),
+    (StatusItem::Filtered, "filtered"),
+    (StatusItem::Following, "following"),
+    (StatusItem::Keys, "keys"),
+    (StatusItem::Indexing, "indexing"),
+    (StatusItem::Position, "position"),
+];
+
+// The items in the status line in the order they're shown, all of them unless ATE_STATUS_LINE
+// picks some
+#[derive(Debug, PartialEq)]
+pub struct StatusLine {
+    items: Vec<StatusItem>,
+}
+
+impl Default for StatusLine {
+    fn default() -> Self {
+        Self {
+            items: STATUS_ITEMS.iter().map(|(item, _)| *item).collect(),
+        }
+    }
+}
+
+impl StatusLine {
+    pub fn from_config() -> Self {
+        let Ok(names) = config::var("ATE_STATUS_LINE") else {
+            return Self::default();
+        };
+        let (status_line, errors) = Self::parse(&names);
+        for e in errors {
+            warn!("Ignoring ATE_STATUS_LINE: {}", e);
+        }
+        status_line
+    }
+
+    // Takes the names of items separated by spaces or commas, like "file position"
+    pub fn parse(names: &str) -> (Self, Vec<String>) {
+        let mut items = vec![];
+        let mut errors = vec![];
+        for name in names
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|n| !n.is_empty())
+        {
+            match STATUS_ITEMS.iter().find(|(_, n)| *n == name) {
+                Some((item, _)) if !items.contains(item) => items.push(*item),
+                Some(_) => errors.push(format!("{name} is listed twice")),
+                None => errors.push(format!("Unknown status line item {name}")),
+            }
+        }
+        (Self { items }, errors)
+    }
+
+    pub fn items(&self) -> &[StatusItem] {
+        &self.items
+    }
 }
 
 impl Shared {
-    fn new(term_height: usize) -> Self {
+    fn new(term_width: usize, term_height: usize) -> Self {
         Self {
             searching: false,
+            term_width,
             term_height,
             quit: false,
+            suspend: false,
+            timings: Timings::default(),
+            read_time: Duration::ZERO,
+            overlay: None,
         }
     }
 }
 
+// Things done with the document's links that . can repeat
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    NextLink,
+    PrevLink,
+    Open,
+}
+
 pub struct State {
     pub doc: Rc<Document>,
     pub view: DocumentView,
     pub search: Search,
     pub last_error: Option<String>,
+    // Shown in the status line until the next key if there's no error
+    pub notice: Option<String>,
     pub shared: Rc<RefCell<Shared>>,
+    pub colors: ColorLevel,
+    // Whether the scrollbar is shown beside the document
+    pub scrollbar: bool,
+    pub bookmarks: Bookmarks,
+    remember_position: bool,
+    // Set until all of the input has been read, while the document is what's been read so far
+    pub awaiting_input: bool,
+    // How many bytes there are to read, when the input is a file
+    pub input_size: Option<u64>,
+    // Set while links are being looked for in all of the input, once it's been read
+    pub indexing_links: bool,
+    pub pending_keys: PendingKeys,
+    pub files: Files,
+    // The keys for each command, with any set in the config file
+    pub key_bindings: KeyBindings,
+    pub status_line: StatusLine,
+    pub macros: Macros,
+    // The command palette, while it's open
+    pub palette: Option<Palette>,
+    // The list of keys, while it's shown
+    pub help: Option<Help>,
+    // What's been typed to search the text or filter its lines by, while the prompt is open
+    pub find_prompt: Option<FindPrompt>,
+    // The last text searched for, whose matches n and N step through in place of links until the
+    // next link search
+    pub text_search: Option<TextSearch>,
+    // Queries typed into the link searcher and the find prompt, for recalling with Ctrl-P
+    pub search_history: History,
+    // Where sessions are saved, or None if they can't be
+    pub sessions_dir: Option<PathBuf>,
+    // The search and filter of a session being picked up, until the file they're for is loaded
+    resumed_queries: Option<(Option<String>, Option<String>)>,
+    last_action: Option<Action>,
+    // The index of the link under the mouse pointer
+    pub hovered: Option<usize>,
+    link_styles: LinkStyles,
+    clipboard: Clipboard,
+    skip_duplicate_links: bool,
+    // What kind of text the document is, which picks the keys from keymaps it gets
+    content_type: Option<ContentType>,
+    pub keymaps: Keymaps,
 
-    // TODO - store the byte in case width changes and keep track of the selected search, too
-    search_activate_line: usize,
+    // The text at the top and the link selected when the link search was opened, to go back to if
+    // it's cancelled.
+    // A byte rather than a line so it survives reflowing for a new width while searching.
+    search_activate_byte: usize,
+    search_activate_link: Option<usize>,
+    // Where the view had last jumped from when the link search was opened
+    search_activate_jump: Option<usize>,
 }
 
 impl State {
     pub fn new(
         doc: Rc<Document>,
-        open_link: Box<dyn FnMut(&str) -> Result<()>>,
+        open_link: OpenLink,
+        clipboard: Clipboard,
         width: usize,
         height: usize,
+        options: Options,
+        bookmarks: Bookmarks,
     ) -> Self {
-        let search = Search::new(Rc::clone(&doc), open_link);
-        let view = DocumentView::new(Rc::clone(&doc), width, height);
+        let search = Search::new(Rc::clone(&doc), open_link, options.regex);
+        let view = DocumentView::new(Rc::clone(&doc), width, height, &options.view);
+        let content_type = ContentType::detect(&doc.text);
         Self {
             doc,
             view,
             search,
             last_error: None,
-            shared: Rc::new(RefCell::new(Shared::new(height))),
-            search_activate_line: 0,
+            notice: None,
+            shared: Rc::new(RefCell::new(Shared::new(width, height))),
+            colors: options.colors,
+            scrollbar: options.scrollbar,
+            bookmarks,
+            remember_position: options.remember_position,
+            awaiting_input: false,
+            input_size: None,
+            indexing_links: false,
+            pending_keys: PendingKeys::default(),
+            files: Files::default(),
+            key_bindings: KeyBindings::from_config(),
+            status_line: StatusLine::from_config(),
+            macros: Macros::default(),
+            palette: None,
+            help: None,
+            find_prompt: None,
+            text_search: None,
+            search_history: History::default(),
+            sessions_dir: None,
+            resumed_queries: None,
+            last_action: None,
+            hovered: None,
+            link_styles: options.link_styles,
+            clipboard,
+            skip_duplicate_links: options.skip_duplicate_links,
+            content_type,
+            keymaps: Keymaps::default(),
+            search_activate_byte: 0,
+            search_activate_link: None,
+            search_activate_jump: None,
+        }
+    }
+
+    // Replaces the document with more of the input, keeping the position, selection, and search
+    pub fn update(&mut self, doc: Rc<Document>) {
+        self.doc = Rc::clone(&doc);
+        self.view.update(Rc::clone(&doc));
+        self.search.update(doc);
+        if let Some(search) = &mut self.text_search {
+            search.update(&self.doc.text);
+        }
+        // Only keep showing a selection that's still there
+        let highlights = match &self.text_search {
+            Some(search) => search.current().into_iter().collect(),
+            None => self
+                .search
+                .selected_link_idx()
+                .map(|idx| self.doc.link_spans(idx))
+                .unwrap_or_default(),
+        };
+        self.view.set_highlights(highlights);
+        self.hovered = self.hovered.filter(|&i| i < self.doc.links.len());
+        self.content_type = ContentType::detect(&self.doc.text);
+    }
+
+    // Starts over with an empty document for reading a different input into, like another file
+    pub fn start_over(&mut self) {
+        let doc = Rc::new(Document::default());
+        self.doc = Rc::clone(&doc);
+        self.view.replace(Rc::clone(&doc));
+        self.search.replace(doc);
+        self.text_search = None;
+        self.last_action = None;
+        self.hovered = None;
+        self.content_type = None;
+        self.bookmarks = Bookmarks::load(None, None, "");
+        self.awaiting_input = true;
+        self.input_size = None;
+        self.indexing_links = false;
+        self.search_activate_byte = 0;
+        self.search_activate_link = None;
+        self.search_activate_jump = None;
+        self.resumed_queries = None;
+    }
+
+    // Called once all of the input has been read into the document.
+    // Bookmarks set while it was being read are kept along with the ones loaded for it.
+    // A file that was switched away from goes back to where it was left.
+    pub fn finish_loading(&mut self, bookmarks: Bookmarks) {
+        let earlier = mem::replace(&mut self.bookmarks, bookmarks);
+        if let Err(e) = self.bookmarks.merge(earlier) {
+            warn!("Saving bookmarks failed with {:?}", e);
+            self.last_error = Some(format!("{}", e));
+        }
+        self.awaiting_input = false;
+        match self.files.left_at() {
+            Some(top) => {
+                self.view.show_byte(top);
+                self.view.set_jumped_from(None);
+            }
+            None => self.offer_last_position(),
+        }
+        if let Some((search, filter)) = self.resumed_queries.take() {
+            if let Some(filter) = filter {
+                self.filter_lines(filter);
+            }
+            if let Some(search) = search {
+                self.find_text(search);
+            }
+        }
+    }
+
+    // Picks up a saved session, showing its current file where it was left and searching and
+    // filtering it again once it's loaded
+    pub fn resume_session(&mut self, session: Session) {
+        self.files = Files::resume(session.files, session.positions, session.current);
+        self.search.regex = session.regex;
+        self.resumed_queries = Some((session.search, session.filter));
+    }
+
+    // Saves the files being viewed, where each was left, and the search and filter in this one as
+    // the session name, for --session to pick up again
+    pub fn save_session(&mut self, name: &str) {
+        if name.is_empty() {
+            return;
+        }
+        let Some(dir) = &self.sessions_dir else {
+            self.last_error = Some("Sessions can't be saved without a state directory".to_string());
+            return;
+        };
+        if self.files.paths().is_empty() {
+            self.last_error = Some("Only files can be saved in a session".to_string());
+            return;
+        }
+        let session = Session {
+            files: self.files.paths().to_vec(),
+            positions: self.files.positions(self.view.top_byte()),
+            current: self.files.current(),
+            search: self.text_search.as_ref().map(|s| s.query().to_string()),
+            filter: self.view.filter_query().map(str::to_string),
+            regex: self.search.regex,
+        };
+        match session.save(dir, name) {
+            Ok(()) => self.notice = Some(format!("Saved session {name}")),
+            Err(e) => {
+                warn!("Saving session failed with {:?}", e);
+                self.last_error = Some(format!("{}", e));
+            }
+        }
+    }
+
+    // Offers to go back to where the document was left the last time it was viewed
+    pub fn offer_last_position(&mut self) {
+        if !self.remember_position {
+            return;
+        }
+        if let Some(byte) = self.bookmarks.get(LAST_POSITION) {
+            if byte > 0 {
+                self.notice = Some(format!(
+                    "Press '{LAST_POSITION} to go back to where you left off"
+                ));
+            }
+        }
+    }
+
+    // Saves where the document was left for offer_last_position
+    pub fn save_last_position(&mut self) {
+        if !self.remember_position {
+            return;
+        }
+        if let Err(e) = self.bookmarks.set(LAST_POSITION, self.view.top_byte()) {
+            warn!("Saving last position failed with {:?}", e);
+        }
+    }
+
+    pub fn perform(&mut self, action: Action) {
+        self.last_action = Some(action);
+        match (action, &mut self.text_search) {
+            (Action::NextLink, Some(search)) => {
+                search.select_next();
+                return self.show_text_match();
+            }
+            (Action::PrevLink, Some(search)) => {
+                search.select_prev();
+                return self.show_text_match();
+            }
+            _ => {}
+        }
+        // The links printed without escapes are gone to once they've been found
+        if matches!(action, Action::NextLink | Action::PrevLink)
+            && self.indexing_links
+            && self.search.matches().is_empty()
+        {
+            self.notice = Some("Still looking for links".to_string());
+            return;
+        }
+        match action {
+            Action::NextLink if self.skip_duplicate_links => self.search_mut().select_next_target(),
+            Action::PrevLink if self.skip_duplicate_links => self.search_mut().select_prev_target(),
+            Action::NextLink => self.search_mut().select_next(),
+            Action::PrevLink => self.search_mut().select_prev(),
+            Action::Open => {
+                if let Err(e) = self.search_mut().open_selected() {
+                    warn!("Opening selection failed with {:?}", e);
+                    self.last_error = Some(format!("{}", e));
+                }
+            }
         }
     }
 
-    pub fn search_mut(&mut self) -> SearchMutator {
+    // Shows only the lines of the text with a match for query in them.
+    // An empty query shows every line again.
+    pub fn filter_lines(&mut self, query: String) {
+        if query.is_empty() {
+            self.view.set_filter(None);
+            return;
+        }
+        let pattern = Pattern::new(&query, self.search.regex);
+        if !self.view.set_filter(Some((query.clone(), pattern))) {
+            self.last_error = Some(format!("Not found: {}", query));
+        }
+    }
+
+    // Searches the whole text for query and moves to the first match from the top of the screen.
+    // An empty query goes back to stepping through links.
+    pub fn find_text(&mut self, query: String) {
+        if query.is_empty() {
+            self.text_search = None;
+            self.view.set_highlights(vec![]);
+            return;
+        }
+        let mut search = TextSearch::new(query, self.search.regex, &self.doc.text);
+        if search.matches().is_empty() {
+            self.last_error = Some(format!("Not found: {}", search.query()));
+            return;
+        }
+        search.select_from(self.view.top_byte());
+        self.text_search = Some(search);
+        self.show_text_match();
+    }
+
+    fn show_text_match(&mut self) {
+        let Some(search) = &self.text_search else {
+            return;
+        };
+        if let (Some((start, end)), Some(position)) = (search.current(), search.position()) {
+            self.notice = Some(format!("Match {} of {}", position, search.matches().len()));
+            self.view.highlight(start, end);
+        }
+    }
+
+    // Selects and opens the link at link_idx in the document, like clicking on it
+    pub fn open_link_at(&mut self, link_idx: usize) {
+        if let Err(e) = self.search_mut().open_link(link_idx) {
+            warn!("Opening link failed with {:?}", e);
+            self.last_error = Some(format!("{}", e));
+        }
+    }
+
+    pub fn repeat_last_action(&mut self) {
+        if let Some(action) = self.last_action {
+            self.perform(action);
+        }
+    }
+
+    // Shows overlay in the status line or hides it if it's already showing
+    pub fn toggle_overlay(&self, overlay: Overlay) {
+        let mut shared = self.shared.borrow_mut();
+        shared.overlay = if shared.overlay == Some(overlay) {
+            None
+        } else {
+            Some(overlay)
+        };
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let links = &self.doc.links;
+        MemoryUsage {
+            text: self.doc.text.capacity(),
+            attrs: self.doc.attrs.capacity() * size_of::<(usize, Change)>(),
+            links: links.capacity() * size_of::<LinkRange>()
+                + links.iter().map(|l| l.link.uri().len()).sum::<usize>(),
+            lines: self.view.flowed_bytes(),
+            matches: self.search.matches.capacity() * size_of::<usize>(),
+        }
+    }
+
+    pub fn input_stats(&self) -> InputStats {
+        InputStats {
+            bytes: self.doc.input_bytes,
+            lines: self.doc.line_count,
+            links: (0..self.doc.links.len())
+                .filter(|&i| self.doc.starts_link(i))
+                .count(),
+            read_time: self.shared.borrow().read_time,
+        }
+    }
+
+    // Adds the clipboard's text to the search query like a paste, for terminals that don't send
+    // pastes
+    pub fn paste_clipboard(&mut self) -> Result<()> {
+        let text = (self.clipboard.read)()?;
+        self.search_mut().push_query_str(&text);
+        Ok(())
+    }
+
+    // Where the whole line containing the selected link, or the first line shown if nothing's
+    // selected, starts and ends before wrapping
+    fn current_line(&self) -> (usize, usize) {
+        let byte = self
+            .view
+            .highlights()
+            .first()
+            .map_or(self.view.top_byte(), |&(start, _)| start);
+        let before = self.doc.text_between(0, byte);
+        let start = before.rfind('\n').map_or(0, |i| i + 1);
+        let after = self.doc.text_between(byte, self.doc.text.len());
+        let end = after.find('\n').map_or(self.doc.text.len(), |i| byte + i);
+        (start, end)
+    }
+
+    pub fn copy_line(&mut self) {
+        let (start, end) = self.current_line();
+        let line = self.doc.text_between(start, end).to_string();
+        let number = self.doc.text_between(0, start).matches('\n').count() + 1;
+        self.copy(&line, format!("line {number}"));
+    }
+
+    // Copies the selected link's address, selecting the first link if none is
+    pub fn copy_link_address(&mut self) {
+        if let Some(link_idx) = self.search_mut().select_if_none() {
+            let address = self.doc.links[link_idx].link.uri().to_string();
+            self.copy(&address, "link address".to_string());
+        } else {
+            self.last_error = Some("No link to copy".to_string());
+        }
+    }
+
+    // Copies the text the selected link is shown as, selecting the first link if none is
+    pub fn copy_link_text(&mut self) {
+        if let Some(link_idx) = self.search_mut().select_if_none() {
+            let text = self.doc.link_text(link_idx);
+            self.copy(&text, "link text".to_string());
+        } else {
+            self.last_error = Some("No link to copy".to_string());
+        }
+    }
+
+    // Puts text on the clipboard, noting that what was copied if it worked
+    fn copy(&mut self, text: &str, what: String) {
+        match (self.clipboard.write)(text) {
+            Ok(()) => self.notice = Some(format!("Copied {what}")),
+            Err(e) => {
+                warn!("Copying failed with {:?}", e);
+                self.last_error = Some(format!("{}", e));
+            }
+        }
+    }
+
+    pub fn open_help(&mut self) {
+        let bindings = match self.content_type {
+            Some(content_type) => self.keymaps.bindings(content_type),
+            None => &[],
+        };
+        self.help = Some(Help::new(&self.key_bindings, bindings));
+    }
+
+    // Opens or copies what key is bound to in the keymap for the kind of text being viewed,
+    // filled in from the current line. Returns false if key isn't bound for it.
+    pub fn run_binding(&mut self, key: char) -> bool {
+        let Some(content_type) = self.content_type else {
+            return false;
+        };
+        let Some(binding) = self.keymaps.binding(content_type, key) else {
+            return false;
+        };
+        let (start, end) = self.current_line();
+        let fields = content_type.fields(&self.doc.text, start, end);
+        let result = match binding.expand(&fields) {
+            Ok(target) => match binding.action {
+                BindingAction::Open => {
+                    info!("Opening {}", target);
+                    (self.search.open_link)(&target)
+                }
+                BindingAction::Copy => (self.clipboard.write)(&target).map(|()| {
+                    self.notice = Some(format!("Copied {target}"));
+                }),
+            },
+            Err(e) => Err(anyhow::Error::msg(e)),
+        };
+        if let Err(e) = result {
+            warn!("Running the binding for {} failed with {:?}", key, e);
+            self.last_error = Some(format!("{}", e));
+        }
+        true
+    }
+
+    // The links to draw with a style and the style each gets from what's been done with it, in
+    // order
+    pub fn link_highlights(&self) -> Vec<Highlight> {
+        let mut styles: BTreeMap<(usize, usize), LinkStyle> = BTreeMap::new();
+        let mut add = |start, end, style| {
+            let entry = styles.entry((start, end)).or_default();
+            *entry = entry.union(style);
+        };
+        for &idx in &self.search.visited {
+            for (start, end) in self.doc.link_spans(idx) {
+                add(start, end, self.link_styles.visited);
+            }
+        }
+        if let Some(idx) = self.hovered.filter(|&i| i < self.doc.links.len()) {
+            for (start, end) in self.doc.link_spans(idx) {
+                add(start, end, self.link_styles.hovered);
+            }
+        }
+        for &(start, end) in self.view.highlights() {
+            add(start, end, self.link_styles.selected);
+        }
+        // Every match of a text search is shown, but only the ones on screen need drawing
+        if let Some(search) = &self.text_search {
+            let visible = self.view.visible_bytes();
+            let matches = search.matches();
+            let first = matches.partition_point(|&(_, end)| end <= visible.start);
+            for &(start, end) in matches[first..]
+                .iter()
+                .take_while(|&&(start, _)| start < visible.end)
+            {
+                add(start, end, self.link_styles.selected);
+            }
+        }
+        styles
+            .into_iter()
+            .filter(|(_, style)| *style != LinkStyle::default())
+            .map(|((start, end), style)| (start, end, style))
+            .collect()
+    }
+
+    // Summarizes input the document couldn't represent
+    pub fn diagnostics_summary(&self) -> String {
+        let diagnostics = &self.doc.diagnostics;
+        match diagnostics.first() {
+            None => "No diagnostics".to_string(),
+            Some(first) if diagnostics.len() == 1 => first.clone(),
+            Some(first) => {
+                let more = if diagnostics.len() == MAX_DIAGNOSTICS {
+                    "+"
+                } else {
+                    ""
+                };
+                format!(
+                    "{}{} diagnostics, first: {}",
+                    diagnostics.len(),
+                    more,
+                    first
+                )
+            }
+        }
+    }
+
+    // Switches link and text searches between matching regexes and matching text as it's written.
+    // The link search is matched again, and a text search prompt shows the new mode as it's typed.
+    pub fn toggle_regex(&mut self) {
+        self.search.regex = !self.search.regex;
+        if self.shared.borrow().searching {
+            self.search.update_matches(&mut self.view);
+        } else {
+            self.search.update(Rc::clone(&self.doc));
+        }
+    }
+
+    pub fn search_mut(&mut self) -> SearchMutator<'_> {
         SearchMutator {
             search: &mut self.search,
             view: &mut self.view,
@@ -69,21 +722,63 @@ impl State {
 
     pub fn open_search(&mut self) {
         self.shared.borrow_mut().searching = true;
-        self.search_activate_line = self.view.line;
+        self.search_activate_byte = self.view.top_byte();
+        self.search_activate_link = self.search.selected_link_idx();
+        self.search_activate_jump = self.view.jumped_from();
+        self.text_search = None;
         self.search_mut().activate();
     }
 
-    pub fn close_search(&self) {
+    // Finishes searching where the search went.
+    // The view moves as the query's typed, and that all counts as one jump from where searching
+    // started.
+    pub fn close_search(&mut self) {
         self.shared.borrow_mut().searching = false;
+        let query = self.search.query.clone();
+        self.remember_query(&query);
+        if self.view.top_byte() == self.search_activate_byte {
+            self.view.set_jumped_from(self.search_activate_jump);
+        } else {
+            self.view.set_jumped_from(Some(self.search_activate_byte));
+        }
     }
 
     pub fn cancel_search(&mut self) {
-        self.close_search();
-        self.view.line = self.search_activate_line;
+        self.search_history.stop_recalling();
+        self.shared.borrow_mut().searching = false;
+        self.view.show_byte(self.search_activate_byte);
+        self.view.set_jumped_from(self.search_activate_jump);
+        let link_idx = self.search_activate_link;
+        self.search_mut().restore_selection(link_idx);
+    }
+
+    // Writes the whole text to a new file at path, which may start with ~/ for the home directory
+    pub fn save(&mut self, path: &str, escapes: bool) {
+        if path.is_empty() {
+            return;
+        }
+        let path = match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+            (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+            _ => PathBuf::from(path),
+        };
+        match print::save_document(&self.doc, &path, escapes) {
+            Ok(()) => self.notice = Some(format!("Saved {}", path.display())),
+            Err(e) => {
+                warn!("Saving failed with {:?}", e);
+                self.last_error = Some(format!("{}", e));
+            }
+        }
+    }
+
+    // Adds a query that's been searched for to the history
+    pub fn remember_query(&mut self, query: &str) {
+        if let Err(e) = self.search_history.add(query) {
+            warn!("Saving search history failed with {:?}", e);
+        }
     }
 
     fn all_but_status_height(&self) -> u16 {
-        self.shared.borrow().term_height as u16 - 1
+        (self.shared.borrow().term_height as u16).saturating_sub(1)
     }
 
     pub fn search_height(&self) -> u16 {
@@ -97,225 +792,288 @@ impl State {
                 .min(self.search.matches.len() as u16 + chrome_height)
                 // but at least take 1 for search entry if that's all there is
                 .max(1)
+                // unless the terminal is too short to have anything but the status line
+                .min(self.all_but_status_height())
         } else {
             0
         }
     }
 
-    pub fn doc_height(&self) -> u16 {
-        self.all_but_status_height() - self.search_height()
-    }
-}
-
-// Only valid for a particular text width due to reflowing
-pub struct Line {
-    pub start_byte: usize,
-    // The full set of active attributes to let set up this line for rendering.
-    pub start_attributes: CellAttributes,
-}
-
-pub struct DocumentView {
-    // Reverses the reverse display of bytes in these ranges.
-    // If reverse is off for a byte, flips it on and vice versa.
-    highlights: Vec<(usize, usize)>,
-    // First displayed line
-    // Used for paging forward and backwards.
-    // In reflow, the start_byte of this line is kept in the first_displayed_line of the reflowed
-    // lines
-    line: usize,
-    width: usize,
-
-    doc: Rc<Document>,
-
-    height: usize,
-    // Cache of text flown at width.
-    // Will be recalculated if width changes.
-    // We cache the start point of the line so that when we go backwards, we can rerender without
-    // having to start from the start of the document to find where long lines break.
-    lines: Vec<Line>,
-}
-
-impl DocumentView {
-    fn new(doc: Rc<Document>, width: usize, height: usize) -> Self {
-        let lines = Self::flow(width, &doc.text, &doc.attrs);
-        Self {
-            doc,
-            width,
-            height,
-            line: 0,
-            highlights: vec![],
-            lines,
+    pub fn palette_height(&self) -> u16 {
+        match &self.palette {
+            // A line for each match and one for the prompt, taking at most half of the screen
+            Some(palette) => (palette.matches().len() as u16 + 1)
+                .min(self.all_but_status_height() / 2)
+                .max(1)
+                .min(self.all_but_status_height()),
+            None => 0,
         }
     }
 
-    pub fn highlight(&mut self, start: usize, end: usize) {
-        self.highlights = vec![(start, end)];
-        self.make_line_visible(self.find_line(start));
-    }
-
-    fn set_line(&mut self, line: usize) {
-        self.line = min(self.lines.len().saturating_sub(self.height), line);
-    }
-
-    pub fn backward(&mut self, lines: usize) {
-        self.set_line(self.line.saturating_sub(lines));
-    }
-
-    pub fn forward(&mut self, lines: usize) {
-        self.set_line(self.line + lines);
+    // The help takes everything above the status line
+    pub fn help_height(&self) -> u16 {
+        match self.help {
+            Some(_) => self.all_but_status_height(),
+            None => 0,
+        }
     }
 
-    fn make_line_visible(&mut self, line: usize) {
-        debug!("Current {} New {}, End {}", self.line, line, self.height);
-        if line.saturating_sub(3) < self.line || (line + 3) > (self.line + self.height) {
-            self.set_line(line.saturating_sub(3));
+    pub fn find_height(&self) -> u16 {
+        match self.find_prompt {
+            Some(_) => self.all_but_status_height().min(1),
+            None => 0,
         }
     }
 
-    pub fn set_size(&mut self, width: usize, height: usize) {
-        self.height = height;
-        if width == self.width {
-            return;
+    pub fn scrollbar_width(&self) -> u16 {
+        // The document keeps at least a column
+        match self.scrollbar && self.shared.borrow().term_width > 1 {
+            true => 1,
+            false => 0,
         }
-        // TODO - update line to keep current view position
-        self.width = width;
-        self.lines = Self::flow(width, &self.doc.text, &self.doc.attrs);
     }
 
-    pub fn highlights(&self) -> &[(usize, usize)] {
-        &self.highlights
+    pub fn doc_width(&self) -> u16 {
+        (self.shared.borrow().term_width as u16).saturating_sub(self.scrollbar_width())
     }
 
-    pub fn line(&self) -> usize {
-        self.line
+    // What each of height rows of the scrollbar shows, with the text from the first row on
+    // spread evenly over them by bytes like the percentage in the status line
+    pub fn scrollbar_rows(&self, height: usize) -> Vec<ScrollbarRow> {
+        let first = self.view.first_byte();
+        let span = self.doc.text.len().saturating_sub(first);
+        let mut rows = vec![ScrollbarRow::default(); height];
+        if span == 0 || height == 0 {
+            return rows;
+        }
+        let row_of = |byte: usize| (byte.saturating_sub(first) * height / span).min(height - 1);
+        let shown = self.view.visible_bytes();
+        // Text that ends on screen reaches the bottom even if it's shorter than the scrollbar
+        let last = match shown.end >= self.doc.text.len() {
+            true => height - 1,
+            false => row_of(shown.end.max(shown.start + 1) - 1),
+        };
+        for row in &mut rows[row_of(shown.start)..=last] {
+            row.shown = true;
+        }
+        // The starts of what n and N go to, in order
+        let starts: Vec<usize> = match &self.text_search {
+            Some(search) => search.matches().iter().map(|&(start, _)| start).collect(),
+            None => self
+                .search
+                .matches()
+                .iter()
+                .map(|&i| self.doc.links[i].start)
+                .collect(),
+        };
+        for (i, row) in rows.iter_mut().enumerate() {
+            let start = self.scrollbar_byte(i, height);
+            let end = match i + 1 {
+                next if next == height => self.doc.text.len(),
+                next => self.scrollbar_byte(next, height),
+            };
+            let idx = starts.partition_point(|&b| b < start);
+            row.marked = starts.get(idx).is_some_and(|&b| b < end);
+        }
+        rows
     }
 
-    pub fn width(&self) -> usize {
-        self.width
+    // The first byte of the text that row of a scrollbar height rows tall stands for
+    pub fn scrollbar_byte(&self, row: usize, height: usize) -> usize {
+        let first = self.view.first_byte();
+        let span = self.doc.text.len().saturating_sub(first);
+        first + (row * span).div_ceil(height.max(1))
     }
 
-    pub fn height(&self) -> usize {
-        self.height
+    pub fn doc_height(&self) -> u16 {
+        self.all_but_status_height()
+            .saturating_sub(self.search_height())
+            .saturating_sub(self.palette_height())
+            .saturating_sub(self.find_height())
+            .saturating_sub(self.help_height())
     }
+}
 
-    pub fn lines(&self) -> &[Line] {
-        &self.lines
-    }
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScrollbarRow {
+    // Whether the screen shows some of the text this row stands for
+    pub shown: bool,
+    // Whether a match or link n and N go to starts in the text this row stands for
+    pub marked: bool,
+}
 
-    pub fn find_line(&self, byte: usize) -> usize {
-        self.lines.partition_point(|l| l.start_byte <= byte) - 1
-    }
+// A query being typed into the prompt at the top of the screen
+pub struct FindPrompt {
+    pub kind: Find,
+    pub query: String,
+}
 
-    pub fn percent(&self) -> Option<u8> {
-        if self.line == 0 || self.lines.len() < self.height {
-            return Some(0);
-        }
-        let final_page_line = self.lines.len() - self.height;
-        if final_page_line == self.line {
-            Some(100)
-        } else {
-            let percent = (self.line as f64 / (final_page_line as f64)) * 100.0;
-            Some(percent.floor() as u8)
+impl FindPrompt {
+    pub fn new(kind: Find) -> Self {
+        Self {
+            kind,
+            query: String::new(),
         }
     }
+}
 
-    fn flow(width: usize, text: &str, attrs: &[(usize, Change)]) -> Vec<Line> {
-        // TODO - Only flow the lines necessary to render the screen.
-        // Read from the underlying stream if at the point of flowing.
-        let mut lines = vec![];
-
-        let mut byte = 0;
-        let graphemes = Graphemes::new(text).map(|g| (g, grapheme_column_width(g, None)));
-        let mut attr_idx = 0;
-        let mut cells_in_line = 0;
-        let mut attributes = CellAttributes::default();
-        lines.push(Line {
-            start_byte: byte,
-            start_attributes: attributes.clone(),
-        });
-        for (grapheme, cells) in graphemes {
-            if cells_in_line + cells > width || grapheme == "\n" {
-                lines.push(Line {
-                    start_byte: if grapheme == "\n" { byte + 1 } else { byte },
-                    start_attributes: attributes.clone(),
-                });
-                cells_in_line = 0;
-            }
-            if grapheme != "\n" {
-                while attr_idx < attrs.len() && byte >= attrs[attr_idx].0 {
-                    match &attrs[attr_idx].1 {
-                        Change::AllAttributes(_) => {
-                            attributes = CellAttributes::default();
-                        }
-                        Change::Attribute(a) => {
-                            attributes.apply_change(a);
-                        }
-                        _ => unreachable!(),
-                    }
-                    attr_idx += 1;
-                }
-                cells_in_line += cells;
-            }
-            byte += grapheme.len();
-        }
-        lines
-    }
+// What a query typed into the find prompt is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Find {
+    // Stepping through its matches anywhere in the text with n and N
+    Text,
+    // Showing only the lines it matches in
+    Lines,
+    // Saving the text to the file it names, with its colors and links as escape sequences if
+    // escapes is set
+    Save { escapes: bool },
+    // Saving the files being viewed as the session it names
+    Session,
 }
 
 pub struct Search {
     doc: Rc<Document>,
     query: String,
+    // Whether queries are regexes rather than text to match as it's written
+    regex: bool,
+    // The query compiled for matching links
+    pattern: Pattern,
     selected_idx: Option<usize>,
-    open_link: Box<dyn FnMut(&str) -> Result<()>>,
+    open_link: OpenLink,
+    // Indices of the links that have been opened
+    visited: BTreeSet<usize>,
     matches: Vec<usize>,
+    // The first match shown in the search panel.
+    // Kept between renders so the list only scrolls when the selection would leave it.
+    first_visible_idx: usize,
+    // The query matches was computed for, and whether it was a regex.
+    // If query extends it, matches can be narrowed instead of checking every link again.
+    matches_query: String,
+    matches_regex: bool,
 }
 
 impl Search {
-    fn new(doc: Rc<Document>, open_link: Box<dyn FnMut(&str) -> Result<()>>) -> Search {
-        let matches = doc.links.iter().enumerate().map(|(i, _)| i).collect();
+    fn new(doc: Rc<Document>, open_link: OpenLink, regex: bool) -> Search {
+        let matches = (0..doc.links.len())
+            .filter(|&i| doc.starts_link(i))
+            .collect();
         Search {
             doc,
             open_link,
+            visited: BTreeSet::new(),
             query: String::new(),
+            regex,
+            pattern: Pattern::new("", regex),
             selected_idx: None,
             matches,
+            first_visible_idx: 0,
+            matches_query: String::new(),
+            matches_regex: regex,
         }
     }
 
+    // Starts over with doc's links in place of a different document's
+    fn replace(&mut self, doc: Rc<Document>) {
+        self.query.clear();
+        self.selected_idx = None;
+        self.visited.clear();
+        self.first_visible_idx = 0;
+        self.update(doc);
+    }
+
+    // Matches the query typed so far against doc's links, keeping the selected link selected if
+    // it's still there
+    fn update(&mut self, doc: Rc<Document>) {
+        // The selection is left as it was when nothing matches
+        let selected = self
+            .selected_idx
+            .and_then(|idx| self.matches.get(idx).copied());
+        self.doc = doc;
+        self.pattern = Pattern::new(&self.query, self.regex);
+        let (doc, pattern) = (&self.doc, &self.pattern);
+        self.matches = (0..doc.links.len())
+            .filter(|&i| doc.starts_link(i) && pattern.is_match(&doc.link_text(i)))
+            .collect();
+        self.matches_query.clone_from(&self.query);
+        self.matches_regex = self.regex;
+        self.selected_idx =
+            selected.and_then(|link_idx| self.matches.binary_search(&link_idx).ok());
+        self.visited.retain(|&i| i < self.doc.links.len());
+    }
+
+    // The index in the document of the selected link
+    fn selected_link_idx(&self) -> Option<usize> {
+        self.selected_idx
+            .and_then(|idx| self.matches.get(idx).copied())
+    }
+
     pub fn query(&self) -> &str {
         &self.query
     }
 
-    pub fn selected_idx(&self) -> Option<usize> {
-        self.selected_idx
+    pub fn regex(&self) -> bool {
+        self.regex
+    }
+
+    // How the query is being matched, like "regex, case-sensitive"
+    pub fn mode(&self) -> String {
+        self.pattern.mode()
     }
 
     pub fn matches(&self) -> &[usize] {
         &self.matches
     }
 
+    // The indices into matches to show in a panel that's height lines tall.
+    // Worked out from the selection every time as the panel changes size with the terminal.
+    pub fn visible_matches(&mut self, height: usize) -> Range<usize> {
+        if height == 0 {
+            return 0..0;
+        }
+        let selected_idx = self.selected_idx.unwrap_or(0);
+        let mut first = self.first_visible_idx.min(selected_idx);
+        if selected_idx >= first + height {
+            first = selected_idx + 1 - height;
+        }
+        // Fill the panel if it's grown since the last render
+        first = first.min(self.matches.len().saturating_sub(height));
+        self.first_visible_idx = first;
+        first..min(first + height, self.matches.len())
+    }
+
     fn set_selected_idx(&mut self, selected_idx: usize, view: &mut DocumentView) {
         if selected_idx < self.matches.len() {
             self.selected_idx = Some(selected_idx);
-            let link = &self.doc.links[self.matches[selected_idx]];
-            view.highlight(link.start, link.end)
+            view.highlight_spans(self.doc.link_spans(self.matches[selected_idx]))
         }
     }
 
     fn update_matches(&mut self, view: &mut DocumentView) {
-        let previous_link_idx = if self.matches.len() > 0 {
-            self.matches[self.selected_idx.unwrap_or(0)]
+        let previous_link_idx = self
+            .matches
+            .get(self.selected_idx.unwrap_or(0))
+            .copied()
+            .unwrap_or(0);
+        // Anything matching the extended query must have matched the old one, even if a capital
+        // made it case-sensitive.
+        // Extending a regex can match more, like "a" to "a|b".
+        let narrowed =
+            !self.regex && !self.matches_regex && self.query.starts_with(&self.matches_query);
+        let candidates: Vec<usize> = if narrowed {
+            std::mem::take(&mut self.matches)
         } else {
-            0
+            (0..self.doc.links.len())
+                .filter(|&i| self.doc.starts_link(i))
+                .collect()
         };
-        self.matches = self
-            .doc
-            .links
-            .iter()
-            .enumerate()
-            .filter(|(_, l)| self.doc.text[l.start..l.end].contains(&self.query))
-            .map(|(i, _)| i)
+        self.pattern = Pattern::new(&self.query, self.regex);
+        let (doc, pattern) = (&self.doc, &self.pattern);
+        self.matches = candidates
+            .into_iter()
+            .filter(|&i| pattern.is_match(&doc.link_text(i)))
             .collect();
+        self.matches_query.clone_from(&self.query);
+        self.matches_regex = self.regex;
         let mut new_selected_idx = self
             .matches
             .partition_point(|link_idx| link_idx < &previous_link_idx);
@@ -339,8 +1097,17 @@ impl<'a> SearchMutator<'a> {
     }
 
     pub fn open_selected(&mut self) -> Result<()> {
-        if self.search.matches.len() == 0 {
-            return Ok(());
+        match self.select_if_none() {
+            Some(link_idx) => self.open(link_idx),
+            None => Ok(()),
+        }
+    }
+
+    // The index into the document's links of the selected link, selecting the first match if
+    // none is. None if nothing matches.
+    fn select_if_none(&mut self) -> Option<usize> {
+        if self.search.matches.is_empty() {
+            return None;
         }
         let selected_idx = match self.search.selected_idx {
             Some(idx) => idx,
@@ -349,10 +1116,40 @@ impl<'a> SearchMutator<'a> {
                 0
             }
         };
-        let link = &self.search.doc.links[self.search.matches[selected_idx]];
-        let addr = link.link.uri();
+        Some(self.search.matches[selected_idx])
+    }
+
+    // Opens the link at link_idx in the document, selecting it without scrolling since it's
+    // already on screen.
+    // Links the query filters out are highlighted without being selected.
+    pub fn open_link(&mut self, link_idx: usize) -> Result<()> {
+        let doc = &self.search.doc;
+        // Part of a link split into several spans stands for the whole link
+        let link_idx = doc.link_group(link_idx).start;
+        self.search.selected_idx = self.search.matches.binary_search(&link_idx).ok();
+        self.view.set_highlights(doc.link_spans(link_idx));
+        self.open(link_idx)
+    }
+
+    // Selects the link at link_idx in the document again, or nothing if it's None, without
+    // scrolling to it
+    fn restore_selection(&mut self, link_idx: Option<usize>) {
+        let Some(link_idx) = link_idx.filter(|&i| i < self.search.doc.links.len()) else {
+            self.search.selected_idx = None;
+            self.view.set_highlights(vec![]);
+            return;
+        };
+        self.search.selected_idx = self.search.matches.binary_search(&link_idx).ok();
+        self.view
+            .set_highlights(self.search.doc.link_spans(link_idx));
+    }
+
+    fn open(&mut self, link_idx: usize) -> Result<()> {
+        let addr = self.search.doc.links[link_idx].link.uri();
         info!("Opening {}", addr);
-        (self.search.open_link)(addr)
+        (self.search.open_link)(addr)?;
+        self.search.visited.insert(link_idx);
+        Ok(())
     }
 
     pub fn select_next(&mut self) {
@@ -375,18 +1172,340 @@ impl<'a> SearchMutator<'a> {
         );
     }
 
+    // Like select_next, but steps over the links after the selected one that go to the same
+    // address, like the same link templated into every line
+    pub fn select_next_target(&mut self) {
+        self.select_target(true)
+    }
+
+    pub fn select_prev_target(&mut self) {
+        self.select_target(false)
+    }
+
+    fn select_target(&mut self, forward: bool) {
+        let search = &*self.search;
+        let len = search.matches.len();
+        if len == 0 {
+            return;
+        }
+        let step = |idx: usize| {
+            if forward {
+                (idx + 1) % len
+            } else {
+                (idx + len - 1) % len
+            }
+        };
+        let idx = match search.selected_idx {
+            None if forward => 0,
+            None => len - 1,
+            Some(selected) => {
+                let uri = |idx: usize| search.doc.links[search.matches[idx]].link.uri();
+                let mut idx = step(selected);
+                while idx != selected && uri(idx) == uri(selected) {
+                    idx = step(idx);
+                }
+                // Every match goes to the same address, so there's nothing to skip to
+                if idx == selected {
+                    step(selected)
+                } else {
+                    idx
+                }
+            }
+        };
+        self.search.set_selected_idx(idx, self.view);
+    }
+
     pub(crate) fn push_query_char(&mut self, c: char) {
+        if self.search.query.len() + c.len_utf8() > MAX_QUERY_BYTES {
+            return;
+        }
         self.search.query.push(c);
         self.search.update_matches(self.view);
     }
 
+    // Replaces the query with one recalled from the history
+    pub(crate) fn set_query(&mut self, query: &str) {
+        query.clone_into(&mut self.search.query);
+        self.search.update_matches(self.view);
+    }
+
     pub(crate) fn pop_query_char(&mut self) {
         self.search.query.pop();
         self.search.update_matches(self.view);
     }
 
+    // Adds pasted text to the query.
+    // Links are a single line, so line breaks and other control characters are dropped.
     pub(crate) fn push_query_str(&mut self, s: &str) {
-        self.search.query.push_str(s);
-        self.search.update_matches(self.view);
+        let before = self.search.query.len();
+        for c in s.chars().filter(|c| !c.is_control()) {
+            if self.search.query.len() + c.len_utf8() > MAX_QUERY_BYTES {
+                break;
+            }
+            self.search.query.push(c);
+        }
+        if self.search.query.len() != before {
+            self.search.update_matches(self.view);
+        }
+    }
+}
+
+// Longer queries are cut off.
+// An accidental paste of a whole file would otherwise be matched against every link.
+pub const MAX_QUERY_BYTES: usize = 1024;
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::doc::ParseOptions;
+    use crate::keymap::parse_bindings;
+
+    fn test_state(input: &str) -> State {
+        let doc = Document::new(
+            Box::new(Cursor::new(input.to_string())),
+            ParseOptions::default(),
+        )
+        .unwrap();
+        State::new(
+            Rc::new(doc),
+            Box::new(|_| Ok(())),
+            Clipboard {
+                read: Box::new(|| Ok(String::new())),
+                write: Box::new(|_| Ok(())),
+            },
+            80,
+            10,
+            Options::default(),
+            Bookmarks::load(None, None, input),
+        )
+    }
+
+    #[test]
+    fn scrollbar_rows() {
+        let rows = |state: &State, height| -> Vec<(bool, bool)> {
+            state
+                .scrollbar_rows(height)
+                .iter()
+                .map(|r| (r.shown, r.marked))
+                .collect()
+        };
+        // Text shorter than the scrollbar fills it
+        let mut state = test_state("a\n\x1b]8;;x\x1b\\b\x1b]8;;\x1b\\\n");
+        assert_eq!(
+            vec![(true, false), (true, false), (true, true), (true, false)],
+            rows(&state, 4)
+        );
+        // Links the search has filtered out aren't marked
+        state.open_search();
+        state.search_mut().push_query_char('z');
+        assert!(rows(&state, 4).iter().all(|&(_, marked)| !marked));
+        assert!(rows(&test_state(""), 3)
+            .iter()
+            .all(|&r| r == (false, false)));
+    }
+
+    #[test]
+    fn search_multibyte_links() {
+        let mut state =
+            test_state("中\x1b]8;;a\x1b\\é中\x1b]8;;\x1b\\\x1b]8;;b\x1b\\b\x1b]8;;\x1b\\");
+        state.open_search();
+        state.search_mut().push_query_str("中");
+        assert_eq!(vec![0], state.search.matches());
+        assert_eq!(&[(3, 8)], state.view.highlights());
+    }
+
+    #[test]
+    fn copy_line() {
+        let mut state = test_state(
+            "first\n\x1b[1msecond and \x1b]8;;a\x1b\\a link\x1b]8;;\x1b\\ that wraps\x1b[m\nthird",
+        );
+        let copied = Rc::new(RefCell::new(vec![]));
+        let write_copied = Rc::clone(&copied);
+        state.clipboard.write = Box::new(move |text| {
+            write_copied.borrow_mut().push(text.to_string());
+            Ok(())
+        });
+        state.view.set_size(8, 3);
+        // Without a selection the first line shown is copied
+        state.copy_line();
+        state.perform(Action::NextLink);
+        state.copy_line();
+        assert_eq!(Some("Copied line 2"), state.notice.as_deref());
+        assert_eq!(
+            vec!["first", "second and a link that wraps"],
+            *copied.borrow()
+        );
+
+        state.clipboard.write = Box::new(|_| anyhow::bail!("No clipboard"));
+        state.copy_line();
+        assert_eq!(Some("No clipboard"), state.last_error.as_deref());
+    }
+
+    #[test]
+    fn copy_links() {
+        let mut state = test_state(
+            "\x1b]8;;http://a\x1b\\first\x1b]8;;\x1b\\ and \x1b]8;;http://b\x1b\\\x1b[1msecond\x1b]8;;\x1b\\",
+        );
+        let copied = Rc::new(RefCell::new(vec![]));
+        let write_copied = Rc::clone(&copied);
+        state.clipboard.write = Box::new(move |text| {
+            write_copied.borrow_mut().push(text.to_string());
+            Ok(())
+        });
+        // Without a selection the first link is selected and copied, like opening one
+        state.copy_link_address();
+        assert_eq!(Some("Copied link address"), state.notice.as_deref());
+        assert_eq!(&[(0, 5)], state.view.highlights());
+        state.perform(Action::NextLink);
+        state.copy_link_address();
+        state.copy_link_text();
+        assert_eq!(Some("Copied link text"), state.notice.as_deref());
+        assert_eq!(vec!["http://a", "http://b", "second"], *copied.borrow());
+
+        let mut state = test_state("no links");
+        state.copy_link_text();
+        assert_eq!(Some("No link to copy"), state.last_error.as_deref());
+    }
+
+    #[test]
+    fn run_content_type_bindings() {
+        let mut state = test_state("+++ b/a.rs\n@@ -1 +1 @@\n-old\n+new\n");
+        let opened = Rc::new(RefCell::new(vec![]));
+        let write_opened = Rc::clone(&opened);
+        state.search.open_link = Box::new(move |target| {
+            write_opened.borrow_mut().push(target.to_string());
+            Ok(())
+        });
+        assert!(!state.run_binding('o'));
+        state.keymaps.set(
+            ContentType::Diff,
+            parse_bindings("o=open {file}:{line}; p=copy {pod}").unwrap(),
+        );
+        // The file header has no line
+        assert!(state.run_binding('o'));
+        assert_eq!(Some("No {line} on this line"), state.last_error.as_deref());
+
+        state.view.set_size(80, 1);
+        state.view.forward(3);
+        state.last_error = None;
+        assert!(state.run_binding('o'));
+        assert_eq!(None, state.last_error);
+        assert_eq!(vec!["a.rs:1"], *opened.borrow());
+        assert!(state.run_binding('p'));
+        assert_eq!(Some("No {pod} on this line"), state.last_error.as_deref());
+        // Keys bound for other kinds of text are left to the base keymap
+        state.keymaps = Keymaps::default();
+        state.keymaps.set(
+            ContentType::KubernetesLog,
+            parse_bindings("o=open {pod}").unwrap(),
+        );
+        assert!(!state.run_binding('o'));
+    }
+
+    #[test]
+    fn skip_duplicate_links() {
+        let link = |uri: &str, text: &str| format!("\x1b]8;;{uri}\x1b\\{text}\x1b]8;;\x1b\\\n");
+        let input = [
+            link("a", "1"),
+            link("a", "2"),
+            link("a", "3"),
+            link("b", "4"),
+            link("a", "5"),
+        ]
+        .concat();
+        let mut state = test_state(&input);
+        let selected = |state: &mut State, action| {
+            state.perform(action);
+            let (start, end) = state.view.highlights()[0];
+            state.doc.text_between(start, end).to_string()
+        };
+        assert_eq!("1", selected(&mut state, Action::NextLink));
+        assert_eq!("2", selected(&mut state, Action::NextLink));
+
+        state.skip_duplicate_links = true;
+        assert_eq!("4", selected(&mut state, Action::NextLink));
+        assert_eq!("5", selected(&mut state, Action::NextLink));
+        // Wrapping around steps over the run at the start as well
+        assert_eq!("4", selected(&mut state, Action::NextLink));
+        assert_eq!("3", selected(&mut state, Action::PrevLink));
+
+        // With a single address there's nothing to skip to, so n still moves
+        let mut state = test_state(&[link("a", "1"), link("a", "2")].concat());
+        state.skip_duplicate_links = true;
+        assert_eq!("1", selected(&mut state, Action::NextLink));
+        assert_eq!("2", selected(&mut state, Action::NextLink));
+    }
+
+    #[test]
+    fn select_split_links() {
+        let mut state = test_state(
+            "\x1b]8;id=x;a\x1b\\split\x1b]8;;\x1b\\\n\x1b]8;id=x;a\x1b\\ link\x1b]8;;\x1b\\ \
+            \x1b]8;;b\x1b\\other\x1b]8;;\x1b\\",
+        );
+        assert_eq!(&[0, 2], state.search.matches());
+        state.perform(Action::NextLink);
+        assert_eq!(&[(0, 5), (6, 11)], state.view.highlights());
+        state.perform(Action::NextLink);
+        assert_eq!(&[(12, 17)], state.view.highlights());
+
+        // Opening either span opens the whole link
+        state.open_link_at(1);
+        assert_eq!(&[(0, 5), (6, 11)], state.view.highlights());
+        assert_eq!(
+            vec![0],
+            state.search.visited.iter().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(2, state.link_highlights().len());
+
+        // Searches match the link's text across its spans
+        state.search_mut().push_query_str("split link");
+        assert_eq!(&[0], state.search.matches());
+    }
+
+    #[test]
+    fn normalize_pastes() {
+        let mut state = test_state("\x1b]8;;a\x1b\\ab\x1b]8;;\x1b\\");
+        state.search_mut().push_query_str("a\r\nb\t");
+        assert_eq!("ab", state.search.query());
+        assert_eq!(1, state.search.matches().len());
+
+        state
+            .search_mut()
+            .push_query_str(&"é".repeat(MAX_QUERY_BYTES));
+        assert_eq!(MAX_QUERY_BYTES, state.search.query().len());
+        state.search_mut().push_query_char('c');
+        assert_eq!(MAX_QUERY_BYTES, state.search.query().len());
+    }
+
+    #[test]
+    fn narrow_and_widen_search() {
+        let link = |text| format!("\x1b]8;;{text}\x1b\\{text}\x1b]8;;\x1b\\\n");
+        let mut state = test_state(&[link("ab"), link("ac"), link("b")].concat());
+        state.search_mut().push_query_char('a');
+        assert_eq!(&[0, 1], state.search.matches());
+        state.search_mut().push_query_str("c");
+        assert_eq!(&[1], state.search.matches());
+        // Shortening the query has to consider links the longer query filtered out
+        state.search_mut().pop_query_char();
+        state.search_mut().pop_query_char();
+        state.search_mut().push_query_char('b');
+        assert_eq!(&[0, 2], state.search.matches());
+    }
+
+    #[test]
+    fn parse_status_line() {
+        let (status_line, errors) = StatusLine::parse("position, file  keys,file clock");
+        assert_eq!(
+            &[StatusItem::Position, StatusItem::File, StatusItem::Keys],
+            status_line.items()
+        );
+        assert_eq!(
+            vec!["file is listed twice", "Unknown status line item clock"],
+            errors
+        );
+        assert!(StatusLine::parse("").0.items().is_empty());
+        assert_eq!(STATUS_ITEMS.len(), StatusLine::default().items().len());
     }
 }
@@ -0,0 +1,123 @@
+use termwiz::cell::{CellAttributes, Intensity, Underline};
+
+// Attributes to draw a link with on top of the document's own
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LinkStyle {
+    // Flips reverse video, so links the document already reversed still stand out
+    pub reverse: bool,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+}
+
+impl LinkStyle {
+    pub const REVERSE: Self = Self {
+        reverse: true,
+        bold: false,
+        dim: false,
+        italic: false,
+        underline: false,
+        strikethrough: false,
+    };
+
+    // Parses a comma separated list of attributes like "bold,underline", or "none" for no style
+    pub fn parse(style: &str) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for attr in style.split(',').map(str::trim) {
+            match attr {
+                "none" => {}
+                "reverse" => parsed.reverse = true,
+                "bold" => parsed.bold = true,
+                "dim" => parsed.dim = true,
+                "italic" => parsed.italic = true,
+                "underline" => parsed.underline = true,
+                "strikethrough" => parsed.strikethrough = true,
+                _ => return Err(format!("Unknown link style attribute {attr:?}")),
+            }
+        }
+        Ok(parsed)
+    }
+
+    // A style with the attributes of both
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            reverse: self.reverse || other.reverse,
+            bold: self.bold || other.bold,
+            dim: self.dim || other.dim,
+            italic: self.italic || other.italic,
+            underline: self.underline || other.underline,
+            strikethrough: self.strikethrough || other.strikethrough,
+        }
+    }
+
+    pub fn apply(self, attrs: &mut CellAttributes) {
+        if self.reverse {
+            attrs.set_reverse(!attrs.reverse());
+        }
+        if self.bold {
+            attrs.set_intensity(Intensity::Bold);
+        } else if self.dim {
+            attrs.set_intensity(Intensity::Half);
+        }
+        if self.italic {
+            attrs.set_italic(true);
+        }
+        if self.underline {
+            attrs.set_underline(Underline::Single);
+        }
+        if self.strikethrough {
+            attrs.set_strikethrough(true);
+        }
+    }
+}
+
+// How links are drawn depending on what's been done with them.
+// A link in more than one state gets the attributes of all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinkStyles {
+    // The link n, N, and the searcher select
+    pub selected: LinkStyle,
+    // The link under the mouse pointer
+    pub hovered: LinkStyle,
+    // Links that have been opened
+    pub visited: LinkStyle,
+}
+
+impl Default for LinkStyles {
+    fn default() -> Self {
+        Self {
+            selected: LinkStyle::REVERSE,
+            hovered: LinkStyle {
+                underline: true,
+                ..Default::default()
+            },
+            visited: LinkStyle {
+                dim: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_apply_styles() {
+        assert_eq!(Ok(LinkStyle::default()), LinkStyle::parse("none"));
+        let style = LinkStyle::parse("bold, underline").unwrap();
+        assert!(style.bold && style.underline && !style.reverse);
+        assert!(LinkStyle::parse("blink").is_err());
+
+        let mut attrs = CellAttributes::default();
+        attrs.set_reverse(true);
+        style.union(LinkStyle::REVERSE).apply(&mut attrs);
+        assert!(!attrs.reverse());
+        assert_eq!(Intensity::Bold, attrs.intensity());
+        assert_eq!(Underline::Single, attrs.underline());
+        assert!(!attrs.italic());
+    }
+}
@@ -0,0 +1,74 @@
+use std::io::{Read, Result, Write};
+
+use log::warn;
+
+// Copies everything read from input to output as it's read.
+// Failing to write doesn't stop reading, since the input is still worth viewing, so it's logged
+// and the copying stops.
+pub struct Tee<R, W> {
+    input: R,
+    output: Option<W>,
+}
+
+impl<R: Read, W: Write> Tee<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output: Some(output),
+        }
+    }
+}
+
+impl<R: Read, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.input.read(buf)?;
+        if let Some(output) = &mut self.output {
+            let written = if read == 0 {
+                output.flush()
+            } else {
+                output.write_all(&buf[..read])
+            };
+            if let Err(e) = written {
+                warn!("Stopped copying input after failing to write it: {}", e);
+                self.output = None;
+            }
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::*;
+
+    struct FailingWriter {}
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn copy_input() {
+        let mut copy = vec![];
+        let mut read = String::new();
+        Tee::new(Cursor::new("some\ninput"), &mut copy)
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!("some\ninput", read);
+        assert_eq!(b"some\ninput", &copy[..]);
+
+        let mut read = String::new();
+        Tee::new(Cursor::new("still read"), FailingWriter {})
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!("still read", read);
+    }
+}
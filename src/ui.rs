@@ -1,38 +1,78 @@
-use crate::doc::Document;
-use crate::state::{DocumentView, Shared, State};
+use crate::bookmarks::Bookmarks;
+use crate::color::downgrade_change;
+use crate::commands::{Command, PromptCommand};
+use crate::config::Options;
+use crate::doc::{strip_controls, Document};
+use crate::files::Files;
+use crate::find::Pattern;
+use crate::history::History;
+use crate::keymap::Keymaps;
+use crate::keys::{quits_from_anywhere, Chord, Pressed, MAX_REPLAYED_KEYS};
+use crate::options::CellWidths;
+use crate::perf::{format_bytes, timed};
+use crate::session::Session;
+use crate::state::{
+    Clipboard, Find, Highlight, OpenLink, Overlay, Shared, State, StatusItem, MAX_QUERY_BYTES,
+};
+use crate::view::{DocumentView, LineRef};
 use anyhow::Result;
 use finl_unicode::grapheme_clusters::Graphemes;
-use log::warn;
+use log::{debug, info, warn};
 use std::cell::RefCell;
-use std::cmp::{max, min};
-use std::io::Read;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::path::PathBuf;
 use std::rc::Rc;
-use termwiz::cell::{grapheme_column_width, unicode_column_width, AttributeChange};
+use std::time::{Duration, Instant};
+use termwiz::caps::ColorLevel;
+use termwiz::cell::{AttributeChange, CellAttributes, Intensity};
 use termwiz::color::{AnsiColor, ColorAttribute};
 use termwiz::input::Modifiers;
-use termwiz::input::{InputEvent, KeyCode, KeyEvent};
-use termwiz::surface::{Change, Position::Absolute};
+use termwiz::input::{InputEvent, KeyCode, KeyEvent, MouseButtons, MouseEvent};
+use termwiz::surface::{
+    Change,
+    Position::{Absolute, Relative},
+};
 use termwiz::surface::{CursorShape, CursorVisibility, Surface};
+use wezterm_bidi::{bidi_class_for_char, BidiClass, BidiContext, ParagraphDirectionHint};
 
 use crate::widgets::layout::{ChildOrientation, Constraints};
 use crate::widgets::{
     ParentRelativeCoords, RenderArgs, Ui, UpdateArgs, Widget, WidgetEvent, WidgetId,
 };
 
-pub fn create_ui<'a>(
-    input: Box<dyn Read>,
+// Creates a UI showing an empty document until the one read from input is passed to load
+pub fn create_awaiting_ui<'a>(
     width: usize,
     height: usize,
-    open_link: Box<dyn FnMut(&str) -> Result<()>>,
+    open_link: OpenLink,
+    clipboard: Clipboard,
+    options: Options,
+    keymaps: Keymaps,
+    bookmarks_path: Option<PathBuf>,
 ) -> Result<AteUi<'a>> {
-    let doc = Rc::new(Document::new(input)?);
-    let state = State::new(doc, open_link, width, height);
+    let mut state = State::new(
+        Rc::new(Document::default()),
+        open_link,
+        clipboard,
+        width,
+        height,
+        options,
+        Bookmarks::load(None, None, ""),
+    );
+    state.awaiting_input = true;
+    state.keymaps = keymaps;
     let shared = state.shared.clone();
     let mut ui = Ui::new(state);
     let root_id = ui.set_root(MainWidget {});
-    let doc_id = ui.add_child(root_id, DocumentWidget {});
+    let row_id = ui.add_child(root_id, DocumentRowWidget {});
+    let doc_id = ui.add_child(row_id, DocumentWidget::default());
     ui.set_focus(doc_id);
-    let search_id = ui.add_child(root_id, SearchWidget {});
+    ui.add_child(row_id, ScrollbarWidget::default());
+    let search_id = ui.add_child(root_id, SearchWidget::default());
+    let palette_id = ui.add_child(root_id, PaletteWidget::default());
+    let find_id = ui.add_child(root_id, FindWidget {});
+    let help_id = ui.add_child(root_id, HelpWidget::default());
     ui.add_child(root_id, StatusWidget {});
 
     // Send a resize event through to get us to do an initial layout
@@ -46,6 +86,11 @@ pub fn create_ui<'a>(
         shared,
         doc_id,
         search_id,
+        palette_id,
+        find_id,
+        help_id,
+        bookmarks_path,
+        events: VecDeque::new(),
     })
 }
 
@@ -54,30 +99,43 @@ pub struct AteUi<'a> {
     shared: Rc<RefCell<Shared>>,
     doc_id: WidgetId,
     search_id: WidgetId,
+    palette_id: WidgetId,
+    find_id: WidgetId,
+    help_id: WidgetId,
+    // Where to load bookmarks for each document from once it has been read
+    bookmarks_path: Option<PathBuf>,
+    // Events to hand to ui one at a time, and whether each came from replaying a macro
+    events: VecDeque<(WidgetEvent, bool)>,
 }
 
 pub enum StepNext {
-    QUIT,
-    WAIT,
+    Quit,
+    Wait,
 }
 
 impl<'a> AteUi<'a> {
     pub fn step(&mut self, surface: &mut Surface) -> Result<StepNext> {
+        self.ui.state_mut().pending_keys.expire(Instant::now());
         loop {
+            self.shared.borrow_mut().term_width = surface.dimensions().0;
             self.shared.borrow_mut().term_height = surface.dimensions().1;
-            self.ui.process_event_queue()?;
+            let (processed, event_time) = timed(|| self.process_events());
+            processed?;
+            self.shared.borrow_mut().timings.event = event_time;
             if self.shared.borrow().quit {
-                return Ok(StepNext::QUIT);
+                self.ui.state_mut().save_last_position();
+                return Ok(StepNext::Quit);
             }
-            self.ui.set_focus(if self.shared.borrow().searching {
-                self.search_id
-            } else {
-                self.doc_id
-            });
 
             // After updating and processing all of the widgets, compose them
             // and render them to the screen.
-            if self.ui.render_to_screen(surface)? {
+            let (rendered, render_time) = timed(|| self.ui.render_to_screen(surface));
+            debug!(
+                "Processed events in {:?} and rendered in {:?}",
+                event_time, render_time
+            );
+            self.shared.borrow_mut().timings.render = render_time;
+            if rendered? {
                 // We have more events to process immediately; don't block waiting
                 // for input below, but jump to the top of the loop to re-run the
                 // updates.
@@ -85,143 +143,608 @@ impl<'a> AteUi<'a> {
             }
             break;
         }
-        Ok(StepNext::WAIT)
+        Ok(StepNext::Wait)
+    }
+
+    // Hands events to ui one at a time so each key goes to the widget focused after the key before
+    // it, and records and replays macros on the way.
+    fn process_events(&mut self) -> Result<()> {
+        self.ui.process_event_queue()?;
+        self.update_focus();
+        let mut replayed = 0;
+        while let Some((event, from_macro)) = self.events.pop_front() {
+            if let (WidgetEvent::Input(InputEvent::Key(key)), false) = (&event, from_macro) {
+                self.ui.state_mut().macros.record(key);
+            }
+            self.ui.queue_event(event);
+            self.ui.process_event_queue()?;
+            self.update_focus();
+            if self.shared.borrow().quit {
+                return Ok(());
+            }
+            let replay = self.ui.state_mut().macros.take_replay();
+            replayed += replay.len();
+            if replayed > MAX_REPLAYED_KEYS {
+                self.events.retain(|(_, from_macro)| !from_macro);
+                self.ui.state_mut().last_error = Some(format!(
+                    "Stopped replaying after {} keys",
+                    MAX_REPLAYED_KEYS
+                ));
+                continue;
+            }
+            for key in replay.into_iter().rev() {
+                self.events
+                    .push_front((WidgetEvent::Input(InputEvent::Key(key)), true));
+            }
+        }
+        Ok(())
+    }
+
+    fn update_focus(&mut self) {
+        let state = self.ui.state_mut();
+        let (palette_open, finding) = (state.palette.is_some(), state.find_prompt.is_some());
+        let help_open = state.help.is_some();
+        self.ui.set_focus(if help_open {
+            self.help_id
+        } else if palette_open {
+            self.palette_id
+        } else if self.shared.borrow().searching {
+            self.search_id
+        } else if finding {
+            self.find_id
+        } else {
+            self.doc_id
+        });
+    }
+
+    // How long until something changes on its own without more input
+    pub fn timeout(&mut self) -> Option<Duration> {
+        self.ui.state_mut().pending_keys.timeout(Instant::now())
     }
 
     pub fn queue_event(&mut self, input: WidgetEvent) {
-        self.ui.queue_event(input);
+        let input = match input {
+            WidgetEvent::Input(InputEvent::Key(key)) => {
+                WidgetEvent::Input(InputEvent::Key(normalize_key(key)))
+            }
+            // termwiz passes on the 1-based positions in SGR mouse reports, but widgets are laid
+            // out from 0
+            WidgetEvent::Input(InputEvent::Mouse(mouse)) => {
+                WidgetEvent::Input(InputEvent::Mouse(MouseEvent {
+                    x: mouse.x.saturating_sub(1),
+                    y: mouse.y.saturating_sub(1),
+                    ..mouse
+                }))
+            }
+            input => input,
+        };
+        self.events.push_back((input, false));
+    }
+
+    // Shows the document read from input so far.
+    // complete says whether that's all of it.
+    pub fn load(
+        &mut self,
+        doc: Document,
+        parse_time: Duration,
+        read_time: Duration,
+        complete: bool,
+    ) {
+        {
+            let mut shared = self.shared.borrow_mut();
+            shared.timings.parse = parse_time;
+            shared.read_time = read_time;
+        }
+        let state = self.ui.state_mut();
+        state.update(Rc::new(doc));
+        if complete {
+            info!("Parsed {} bytes in {:?}", state.doc.text.len(), parse_time);
+            let file = state.files.current_path();
+            let bookmarks = Bookmarks::load(self.bookmarks_path.clone(), file, &state.doc.text);
+            state.finish_loading(bookmarks);
+        }
+    }
+
+    pub fn set_files(&mut self, files: Files) {
+        self.ui.state_mut().files = files;
+    }
+
+    pub fn resume_session(&mut self, session: Session) {
+        self.ui.state_mut().resume_session(session);
+    }
+
+    pub fn set_sessions_dir(&mut self, dir: Option<PathBuf>) {
+        self.ui.state_mut().sessions_dir = dir;
+    }
+
+    pub fn set_search_history(&mut self, history: History) {
+        self.ui.state_mut().search_history = history;
+    }
+
+    // The file a command asked to switch to, which should be opened and passed to switch_file
+    pub fn take_requested_file(&mut self) -> Option<(usize, PathBuf)> {
+        let files = &mut self.ui.state_mut().files;
+        let file = files.take_requested()?;
+        Some((file, files.path(file).to_path_buf()))
+    }
+
+    // Switches to showing file, which is empty until it's passed to load as it's read
+    pub fn switch_file(&mut self, file: usize) {
+        let state = self.ui.state_mut();
+        state.save_last_position();
+        let top = state.view.top_byte();
+        state.files.switch(file, top);
+        state.start_over();
+    }
+
+    // Shows what went wrong with something that finished in the background, like an opener
+    pub fn report_error(&mut self, error: String) {
+        warn!("{}", error);
+        self.ui.state_mut().last_error = Some(error);
+    }
+
+    // Shows how far through reading the input is by its size, for a file
+    pub fn set_input_size(&mut self, size: Option<u64>) {
+        self.ui.state_mut().input_size = size;
+    }
+
+    // Shows that links are being looked for in the document loaded
+    pub fn indexing_links(&mut self) {
+        self.ui.state_mut().indexing_links = true;
+    }
+
+    pub fn is_indexing_links(&mut self) -> bool {
+        self.ui.state_mut().indexing_links
+    }
+
+    // Shows doc, the document loaded with the links found in it
+    pub fn links_found(&mut self, doc: Document) {
+        let state = self.ui.state_mut();
+        state.update(Rc::new(doc));
+        state.indexing_links = false;
+    }
+
+    // Stops awaiting the rest of the input when reading it failed, showing what went wrong
+    pub fn input_failed(&mut self, error: String) {
+        self.report_error(error);
+        self.ui.state_mut().awaiting_input = false;
+    }
+
+    // Whether a key asked to suspend to the shell since this was last called
+    pub fn take_suspend(&mut self) -> bool {
+        std::mem::take(&mut self.shared.borrow_mut().suspend)
+    }
+
+    // Has the next step return Quit as if the user asked for it
+    pub fn request_quit(&mut self) {
+        self.shared.borrow_mut().quit = true;
+    }
+}
+
+// termwiz decodes kitty keyboard protocol reports for keys like Esc and Shift-Enter as the
+// control characters they'd otherwise send, so name them the way legacy input does
+fn normalize_key(event: KeyEvent) -> KeyEvent {
+    let key = match event.key {
+        KeyCode::Char('\r') => KeyCode::Enter,
+        KeyCode::Char('\x1b') => KeyCode::Escape,
+        KeyCode::Char('\t') => KeyCode::Tab,
+        KeyCode::Char('\x7f') => KeyCode::Backspace,
+        key => key,
+    };
+    KeyEvent { key, ..event }
+}
+
+// Walks the document's bytes in order, working out the changes needed to draw each grapheme with
+// its attributes and any highlight's style applied.
+struct AttrTracker<'a> {
+    attrs: &'a [(usize, Change)],
+    highlights: &'a [Highlight],
+    attr_idx: usize,
+    highlight_idx: usize,
+    highlight: Option<Highlight>,
+    // The attributes the document sets for byte.
+    // A highlight's style is applied on top of these and they're restored on leaving it.
+    base: CellAttributes,
+    // The attributes in effect after the changes produced so far
+    current: CellAttributes,
+}
+
+impl<'a> AttrTracker<'a> {
+    fn new(
+        attrs: &'a [(usize, Change)],
+        highlights: &'a [Highlight],
+        byte: usize,
+        line_attrs: CellAttributes,
+    ) -> Self {
+        Self {
+            attrs,
+            highlights,
+            attr_idx: attrs.partition_point(|(b, _)| *b < byte),
+            highlight_idx: highlights.partition_point(|(_, e, _)| *e <= byte),
+            highlight: None,
+            base: line_attrs.clone(),
+            current: line_attrs,
+        }
+    }
+
+    fn push(&mut self, change: Change, changes: &mut Vec<Change>) {
+        match &change {
+            Change::AllAttributes(a) => self.current = a.clone(),
+            Change::Attribute(a) => self.current.apply_change(a),
+            _ => {}
+        }
+        changes.push(change);
+    }
+
+    // Pushes the changes to make before drawing the grapheme starting at byte
+    fn advance(&mut self, byte: usize, changes: &mut Vec<Change>) {
+        let previous = self.highlight;
+        if self.highlight.is_some_and(|(_, end, _)| end <= byte) {
+            self.highlight = None;
+            self.highlight_idx += 1;
+        }
+        if self.highlight.is_none() {
+            // Skip past highlights that have already ended, like empty ones, and enter the next
+            // one if it covers byte.
+            // The one we just left may be directly followed by another.
+            while self.highlight_idx < self.highlights.len()
+                && self.highlights[self.highlight_idx].1 <= byte
+            {
+                self.highlight_idx += 1;
+            }
+            if self.highlight_idx < self.highlights.len()
+                && self.highlights[self.highlight_idx].0 <= byte
+            {
+                self.highlight = Some(self.highlights[self.highlight_idx]);
+            }
+        }
+        let mut base_changed = false;
+        while self.attr_idx < self.attrs.len() && byte >= self.attrs[self.attr_idx].0 {
+            let change = &self.attrs[self.attr_idx].1;
+            self.attr_idx += 1;
+            match change {
+                Change::AllAttributes(a) => self.base = a.clone(),
+                Change::Attribute(a) => self.base.apply_change(a),
+                _ => {}
+            }
+            // Outside highlights the document's changes can be passed along as they are
+            if self.highlight.is_none() && previous.is_none() {
+                self.push(change.clone(), changes);
+            } else {
+                base_changed = true;
+            }
+        }
+        if base_changed || self.highlight != previous {
+            let mut attrs = self.base.clone();
+            if let Some((_, _, style)) = self.highlight {
+                style.apply(&mut attrs);
+            }
+            self.push(Change::AllAttributes(attrs), changes);
+        }
     }
 }
 
+// Which part of a grapheme is drawn when only some columns of each row are shown
+enum Shown {
+    All,
+    // Blank cells in place of the part of a wide grapheme that's on screen
+    Blanks(usize),
+    // Off the left of the screen
+    Before,
+    // Off the right of the screen, like everything after it on the row
+    After,
+}
+
+// The columns of a row on screen while lines aren't wrapping, and how far across it drawing is
+struct Window {
+    columns: Range<usize>,
+    column: usize,
+}
+
+impl Window {
+    fn new(columns: Range<usize>) -> Self {
+        Self { columns, column: 0 }
+    }
+
+    // Where the next grapheme, which takes cells, falls
+    fn place(&mut self, cells: usize) -> Shown {
+        let start = self.column;
+        self.column += cells;
+        if self.column <= self.columns.start {
+            Shown::Before
+        } else if start >= self.columns.end {
+            Shown::After
+        } else if start < self.columns.start || self.column > self.columns.end {
+            Shown::Blanks(self.column.min(self.columns.end) - start.max(self.columns.start))
+        } else {
+            Shown::All
+        }
+    }
+}
+
+// Draws grapheme as much of it falls in window, if there is a window.
+// Returns false once it's past the window, when nothing after it on the row is drawn either.
+fn push_shown(
+    changes: &mut Vec<Change>,
+    grapheme: &str,
+    window: Option<&mut Window>,
+    widths: CellWidths,
+) -> bool {
+    // Zero width graphemes are given a cell by Surface, as in flowing
+    match window.map_or(Shown::All, |w| w.place(widths.grapheme(grapheme).max(1))) {
+        Shown::All => push_text(changes, grapheme),
+        Shown::Blanks(cells) => push_text(changes, &" ".repeat(cells)),
+        Shown::Before => {}
+        Shown::After => return false,
+    }
+    true
+}
+
+// Draws the flowed lines in shown, a row each
 fn render_lines(
     doc: &Document,
     view: &DocumentView,
-    mut line: usize,
-    height: usize,
-    highlights: &[(usize, usize)],
+    shown: impl IntoIterator<Item = LineRef>,
+    highlights: &[Highlight],
     changes: &mut Vec<Change>,
 ) {
-    let mut byte = view.lines()[line].start_byte;
-    let line_attrs = view.lines()[line].start_attributes.clone();
-    // Tracks the inverse sgr state for byte.
-    // We switch it when in a highlight and then go back to the set state when exiting
-    // the highlight
-    let mut reversed = line_attrs.reverse();
-    // Start with our line's state
-    changes.push(Change::AllAttributes(line_attrs));
-
-    let mut attr_idx = doc.attrs.partition_point(|(b, _)| *b < byte);
-    let mut highlight_idx = highlights.partition_point(|(_, e)| *e <= byte);
-    let mut highlight: Option<(usize, usize)> = None;
-    let mut cells_in_line = 0;
-    let last_displayed_line = min(view.lines().len(), line + height) - 1;
-    for (grapheme, cells) in
-        Graphemes::new(&doc.text[byte..]).map(|g| (g, grapheme_column_width(g, None)))
-    {
-        if cells_in_line + cells > view.width() || grapheme == "\n" {
-            if line == last_displayed_line {
-                break;
-            }
-            changes.push(Change::Text("\r\n".to_string()));
-            line += 1;
-            cells_in_line = 0;
+    let mut tracker: Option<AttrTracker> = None;
+    let mut previous: Option<LineRef> = None;
+    // Walk the flowed lines rather than rewrapping here.
+    // That keeps the work per line bounded by what flow decided fits on it.
+    for current in shown {
+        if previous.is_some() {
+            push_text(changes, "\r\n");
         }
-        if grapheme != "\n" {
-            if let Some(active_highlight) = highlight {
-                if active_highlight.1 <= byte {
-                    highlight = None;
-                    changes.push(Change::Attribute(AttributeChange::Reverse(reversed)));
-                    highlight_idx += 1;
-                }
-            } else if highlight_idx < highlights.len() && highlights[highlight_idx].0 <= byte {
-                highlight = Some(highlights[highlight_idx]);
-                changes.push(Change::Attribute(AttributeChange::Reverse(!reversed)));
-            }
-            while attr_idx < doc.attrs.len() && byte >= doc.attrs[attr_idx].0 {
-                let mut change = doc.attrs[attr_idx].1.clone();
-                attr_idx += 1;
-                if let Change::Attribute(AttributeChange::Reverse(new_reverse)) = change {
-                    reversed = new_reverse;
-                    if highlight.is_some() {
-                        change = Change::Attribute(AttributeChange::Reverse(!new_reverse));
-                    }
-                }
-                if let Change::AllAttributes(attr) = &mut change {
-                    reversed = attr.reverse();
-                    if highlight.is_some() {
-                        attr.set_reverse(!attr.reverse());
+        let line = view.line(current);
+        let mut byte = line.start_byte;
+        // Start with the line's state, unless it carries on from the line drawn before it
+        if previous.is_some_and(|p| view.line_end(p) != byte) {
+            tracker = None;
+        }
+        previous = Some(current);
+        let tracker = tracker.get_or_insert_with(|| {
+            let line_attrs = line.start_attributes.clone();
+            changes.push(Change::AllAttributes(line_attrs.clone()));
+            AttrTracker::new(&doc.attrs, highlights, byte, line_attrs)
+        });
+        if line.indent > 0 {
+            // Skip over the indent rather than drawing it so it doesn't take on the text's
+            // background
+            changes.push(Change::CursorPosition {
+                x: Relative(line.indent as isize),
+                y: Relative(0),
+            });
+        }
+        let text = doc.text_between(byte, view.line_end(current));
+        let mut window = view.shown_columns().map(Window::new);
+        if view.bidi() && has_rtl(text) {
+            render_bidi_line(text, byte, tracker, window.as_mut(), view.widths(), changes);
+        } else {
+            for grapheme in Graphemes::new(text) {
+                if grapheme != "\n" {
+                    tracker.advance(byte, changes);
+                    if !push_shown(changes, grapheme, window.as_mut(), view.widths()) {
+                        break;
                     }
                 }
-                changes.push(change);
+                byte += grapheme.len();
+            }
+        }
+    }
+}
+
+// The byte of the grapheme drawn at column x of row y of the document, if any
+fn byte_at(doc: &Document, view: &DocumentView, x: usize, y: usize) -> Option<usize> {
+    let &shown = view.visible_lines().get(y)?;
+    let line = view.line(shown);
+    let x = x.checked_sub(line.indent)? + view.shown_columns().map_or(0, |c| c.start);
+    let mut byte = line.start_byte;
+    let text = doc.text_between(byte, view.line_end(shown));
+    // Reordered lines aren't drawn in byte order, so don't guess
+    if view.bidi() && has_rtl(text) {
+        return None;
+    }
+    let mut column = 0;
+    for grapheme in Graphemes::new(text) {
+        if grapheme == "\n" {
+            break;
+        }
+        column += view.widths().grapheme(grapheme);
+        if x < column {
+            return Some(byte);
+        }
+        byte += grapheme.len();
+    }
+    None
+}
+
+fn has_rtl(text: &str) -> bool {
+    text.chars().any(|c| {
+        matches!(
+            bidi_class_for_char(c),
+            BidiClass::RightToLeft
+                | BidiClass::ArabicLetter
+                | BidiClass::RightToLeftEmbedding
+                | BidiClass::RightToLeftOverride
+                | BidiClass::RightToLeftIsolate
+        )
+    })
+}
+
+// Draws a flowed line in visual order so right to left runs read correctly.
+// Each flowed line is treated as its own paragraph, which is only an approximation of the bidi
+// algorithm for logical lines that wrap, but keeps the work per line bounded.
+fn render_bidi_line(
+    text: &str,
+    mut byte: usize,
+    tracker: &mut AttrTracker,
+    mut window: Option<&mut Window>,
+    widths: CellWidths,
+    changes: &mut Vec<Change>,
+) {
+    // Attribute changes are relative to the logical order, so work out each grapheme's full
+    // attributes before reordering them.
+    let mut graphemes = vec![];
+    let mut chars = vec![];
+    let mut char_graphemes = vec![];
+    let mut scratch = vec![];
+    for grapheme in Graphemes::new(text) {
+        if grapheme != "\n" {
+            tracker.advance(byte, &mut scratch);
+            scratch.clear();
+            for c in grapheme.chars() {
+                chars.push(c);
+                char_graphemes.push(graphemes.len());
             }
-            changes.push(Change::Text(grapheme.to_string()));
-            cells_in_line += cells;
+            graphemes.push((grapheme, tracker.current.clone()));
         }
         byte += grapheme.len();
     }
+    let mut context = BidiContext::new();
+    context.resolve_paragraph(&chars, ParagraphDirectionHint::AutoLeftToRight);
+    let (_, visual) = context.reorder_line(0..chars.len());
+    let mut drawn = vec![false; graphemes.len()];
+    let mut drawn_attrs = None;
+    for char_idx in visual {
+        let grapheme_idx = char_graphemes[char_idx];
+        if drawn[grapheme_idx] {
+            continue;
+        }
+        drawn[grapheme_idx] = true;
+        let (grapheme, attrs) = &graphemes[grapheme_idx];
+        if drawn_attrs != Some(attrs) {
+            changes.push(Change::AllAttributes(attrs.clone()));
+            drawn_attrs = Some(attrs);
+        }
+        if !push_shown(changes, grapheme, window.as_deref_mut(), widths) {
+            break;
+        }
+    }
+    // Continue the following lines from the attributes the logical order ended with
+    changes.push(Change::AllAttributes(tracker.current.clone()));
+}
+
+// Appends to the previous change if it's text.
+// Runs of graphemes with the same attributes then only allocate one String between them.
+fn push_text(changes: &mut Vec<Change>, text: &str) {
+    if let Some(Change::Text(previous)) = changes.last_mut() {
+        previous.push_str(text);
+    } else {
+        changes.push(Change::Text(text.to_string()));
+    }
+}
+
+// Moves changes to the surface while keeping their Vec's allocation for the next render
+fn flush_changes(surface: &mut Surface, changes: &mut Vec<Change>, colors: ColorLevel) {
+    for change in changes.drain(..) {
+        surface.add_change(downgrade_change(change, colors));
+    }
 }
 
-struct DocumentWidget {}
+// How far each step of the mouse wheel scrolls
+const WHEEL_LINES: usize = 3;
+
+#[derive(Default)]
+struct DocumentWidget {
+    // Reused across renders to avoid reallocating every frame
+    changes: Vec<Change>,
+    // Whether the left button was down at the last mouse report, since reports keep coming while
+    // it's held down and only pressing it clicks
+    left_down: bool,
+}
 
 impl DocumentWidget {
-    fn process_key(&mut self, event: &KeyEvent, state: &mut State) -> bool {
-        match event {
-            KeyEvent {
-                key: KeyCode::UpArrow,
-                ..
-            } => {
-                state.view.backward(1);
-                true
+    fn process_chord(&mut self, chord: Chord, state: &mut State) {
+        match chord {
+            Chord::Command(command) => command.run(state),
+            Chord::Repeat(count, command) => command.run_times(state, count),
+            Chord::SetBookmark(name) => {
+                if let Err(e) = state.bookmarks.set(name, state.view.top_byte()) {
+                    warn!("Saving bookmark failed with {:?}", e);
+                    state.last_error = Some(format!("{}", e));
+                }
             }
-            KeyEvent {
-                key: KeyCode::DownArrow,
-                ..
-            } => {
-                state.view.forward(1);
-                true
+            Chord::GoToBookmark(name) => match state.bookmarks.get(name) {
+                Some(byte) => state.view.show_byte(byte),
+                None => state.last_error = Some(format!("No bookmark {}", name)),
+            },
+            Chord::GoToLine(line) => state.view.show_source_line(line),
+            Chord::GoToPercent(percent) => state.view.show_percent(percent),
+            Chord::Record(register) => state.macros.start_recording(register),
+            Chord::Replay(register) => {
+                if !state.macros.replay(register) {
+                    state.last_error = Some(format!("No macro {}", register));
+                }
             }
-            KeyEvent {
-                key: KeyCode::Char(' '),
-                ..
-            } => {
-                state.view.forward(max(state.view.height() - 2, 1));
-                true
+        }
+    }
+
+    fn process_key(&mut self, event: &KeyEvent, state: &mut State) -> bool {
+        state.notice = None;
+        // The key that stops recording can also start a chord, so it's checked first
+        if state.macros.recording().is_some()
+            && state.pending_keys.keys().is_empty()
+            && state.key_bindings.main_command(event) == Some(Command::StopRecording)
+        {
+            Command::StopRecording.run(state);
+            return true;
+        }
+        match state
+            .pending_keys
+            .press(event, Instant::now(), &state.key_bindings)
+        {
+            Pressed::Pending | Pressed::Cancelled => return true,
+            Pressed::Chord(chord) => {
+                self.process_chord(chord, state);
+                return true;
+            }
+            Pressed::Unbound => {}
+        }
+        if let KeyEvent {
+            key: KeyCode::Char(c),
+            modifiers: Modifiers::NONE | Modifiers::SHIFT,
+        } = event
+        {
+            if state.run_binding(*c) {
+                return true;
             }
-            KeyEvent {
-                key: KeyCode::Char('b'),
-                ..
-            } => {
-                state.view.backward(state.view.height() - 2);
+        }
+        match state.key_bindings.document_command(event) {
+            Some(command) => {
+                command.run(state);
                 true
             }
-            KeyEvent { .. } => false,
+            None => false,
         }
     }
 }
 
 impl Widget<State> for DocumentWidget {
     fn render(&mut self, args: &mut RenderArgs, state: &mut State) {
+        args.cursor.visibility = CursorVisibility::Hidden;
         let (width, height) = args.surface.dimensions();
-        assert!(width > 0 && height > 0);
+        if width == 0 || height == 0 {
+            // Nothing fits, so don't bother reflowing to the degenerate size
+            return;
+        }
         state.view.set_size(width, height);
 
-        let mut changes = vec![
+        self.changes.extend([
             Change::ClearScreen(ColorAttribute::Default),
             Change::CursorPosition {
                 x: Absolute(0),
                 y: Absolute(0),
             },
-        ];
+        ]);
         render_lines(
             &state.doc,
             &state.view,
-            state.view.line(),
-            height,
-            state.view.highlights(),
-            &mut changes,
+            state.view.visible_lines().iter().copied(),
+            &state.link_highlights(),
+            &mut self.changes,
         );
-        args.surface.add_changes(changes);
-        args.cursor.visibility = CursorVisibility::Hidden;
+        flush_changes(args.surface, &mut self.changes, state.colors);
     }
 
     fn process_event(
@@ -233,6 +756,25 @@ impl Widget<State> for DocumentWidget {
         match event {
             WidgetEvent::Input(i) => match i {
                 InputEvent::Key(k) => self.process_key(k, state),
+                InputEvent::Mouse(m) => {
+                    if m.mouse_buttons.contains(MouseButtons::VERT_WHEEL) {
+                        if m.mouse_buttons.contains(MouseButtons::WHEEL_POSITIVE) {
+                            state.view.backward(WHEEL_LINES);
+                        } else {
+                            state.view.forward(WHEEL_LINES);
+                        }
+                    }
+                    state.hovered = byte_at(&state.doc, &state.view, m.x.into(), m.y.into())
+                        .and_then(|byte| state.doc.link_at(byte));
+                    let left_down = m.mouse_buttons.contains(MouseButtons::LEFT);
+                    if let (Some(link_idx), true, false) =
+                        (state.hovered, left_down, self.left_down)
+                    {
+                        state.open_link_at(link_idx);
+                    }
+                    self.left_down = left_down;
+                    true
+                }
                 _ => false,
             },
         }
@@ -240,9 +782,83 @@ impl Widget<State> for DocumentWidget {
 
     fn get_size_constraints(&self, state: &State) -> Constraints {
         let mut c = Constraints::default();
+        c.set_fixed_width(state.doc_width());
+        c.set_fixed_height(state.doc_height());
+        c
+    }
+}
+
+// Holds the document and the scrollbar beside it
+struct DocumentRowWidget {}
+
+impl Widget<State> for DocumentRowWidget {
+    fn render(&mut self, _args: &mut RenderArgs, _state: &mut State) {}
+
+    fn get_size_constraints(&self, state: &State) -> Constraints {
+        let mut c = Constraints::default();
+        c.set_fixed_height(state.doc_height());
+        c
+    }
+}
+
+// A column on the right edge showing how much of the text is on screen and where the matches or
+// links that n and N go to are
+#[derive(Default)]
+struct ScrollbarWidget {
+    changes: Vec<Change>,
+}
+
+impl Widget<State> for ScrollbarWidget {
+    fn render(&mut self, args: &mut RenderArgs, state: &mut State) {
+        let (width, height) = args.surface.dimensions();
+        if state.scrollbar_width() == 0 || width == 0 {
+            return;
+        }
+        for (y, row) in state.scrollbar_rows(height).into_iter().enumerate() {
+            self.changes.extend([
+                Change::CursorPosition {
+                    x: Absolute(0),
+                    y: Absolute(y),
+                },
+                Change::Attribute(AttributeChange::Reverse(row.shown)),
+                Change::Text(if row.marked { "━" } else { " " }.to_string()),
+            ]);
+        }
+        flush_changes(args.surface, &mut self.changes, state.colors);
+    }
+
+    fn get_size_constraints(&self, state: &State) -> Constraints {
+        let mut c = Constraints::default();
+        c.set_fixed_width(state.scrollbar_width());
         c.set_fixed_height(state.doc_height());
         c
     }
+
+    fn process_event(
+        &mut self,
+        event: &WidgetEvent,
+        _args: &mut UpdateArgs,
+        state: &mut State,
+    ) -> bool {
+        let WidgetEvent::Input(InputEvent::Mouse(m)) = event else {
+            return false;
+        };
+        if m.mouse_buttons.contains(MouseButtons::VERT_WHEEL) {
+            if m.mouse_buttons.contains(MouseButtons::WHEEL_POSITIVE) {
+                state.view.backward(WHEEL_LINES);
+            } else {
+                state.view.forward(WHEEL_LINES);
+            }
+        } else if m.mouse_buttons.contains(MouseButtons::LEFT) {
+            // Clicking or dragging shows the text the row stands for
+            let height = state.doc_height().into();
+            state
+                .view
+                .show_byte(state.scrollbar_byte(m.y.into(), height));
+        }
+        state.hovered = None;
+        true
+    }
 }
 
 // This is a little status line widget that we render at the bottom
@@ -257,18 +873,68 @@ impl Widget<State> for StatusWidget {
                 y: Absolute(0),
             },
         ];
-        let last_error: &Option<String> = &state.last_error;
-        let error_width = if let Some(err) = last_error {
-            changes.push(Change::Text(err.clone()));
-            unicode_column_width(&err, None)
+        let shared = state.shared.borrow();
+        let overlay = shared.overlay.map(|overlay| match overlay {
+            Overlay::Timings => {
+                let mut timings = shared.timings;
+                timings.flow = state.view.flow_time();
+                timings.to_string()
+            }
+            Overlay::Memory => state.memory_usage().to_string(),
+            Overlay::Stats => state.input_stats().to_string(),
+            Overlay::Diagnostics => state.diagnostics_summary(),
+        });
+        let error_width = if let Some(msg) = overlay
+            .as_ref()
+            .or(state.last_error.as_ref())
+            .or(state.notice.as_ref())
+        {
+            // Errors can include output from ATE_OPENER
+            let msg = strip_controls(msg);
+            let width = state.view.widths().text(&msg);
+            changes.push(Change::Text(msg));
+            width
         } else {
             0
         };
-        let progress = match state.view.percent() {
-            Some(p) => format!("{}%", p),
-            None => "?%".to_string(),
+        // How far through the text the view is means little until all of it's been read
+        let position = if !state.awaiting_input {
+            format!("{}%", state.view.percent())
+        } else if let Some(size) = state.input_size.filter(|&size| size > 0) {
+            let read = (state.doc.input_bytes as u64).min(size);
+            format!("{}% read", read * 100 / size)
+        } else if state.doc.text.is_empty() {
+            "Awaiting input".to_string()
+        } else {
+            format!("{} read so far", format_bytes(state.doc.input_bytes))
         };
-        let progress_width = unicode_column_width(&progress, None);
+        let items: Vec<String> = state
+            .status_line
+            .items()
+            .iter()
+            .filter_map(|item| match item {
+                StatusItem::File => state.files.describe(),
+                StatusItem::Column => state
+                    .view
+                    .shown_columns()
+                    .filter(|c| c.start > 0)
+                    .map(|c| format!("column {}", c.start + 1)),
+                StatusItem::Recording => state.macros.recording().map(|r| format!("recording {r}")),
+                StatusItem::Filtered => state
+                    .view
+                    .filter_query()
+                    .is_some()
+                    .then(|| "filtered".to_string()),
+                StatusItem::Following => state.view.following().then(|| "following".to_string()),
+                // Show the start of a chord while waiting for the rest of it
+                StatusItem::Keys => Some(state.pending_keys.keys().to_string()),
+                StatusItem::Indexing => state.indexing_links.then(|| "indexing links…".to_string()),
+                StatusItem::Position => Some(position.clone()),
+            })
+            .filter(|item| !item.is_empty())
+            .collect();
+        let progress = items.join(" ");
+        let progress_width = state.view.widths().text(&progress);
         let surface_width = args.surface.dimensions().0;
         if surface_width.saturating_sub(error_width + progress_width) >= 1 {
             changes.push(Change::CursorPosition {
@@ -289,74 +955,70 @@ impl Widget<State> for StatusWidget {
 
 impl SearchWidget {
     fn process_key(&mut self, event: &KeyEvent, state: &mut State) -> bool {
-        match event {
-            KeyEvent {
-                key: KeyCode::Enter,
-                ..
-            } => {
-                state.close_search();
-                true
-            }
-            KeyEvent {
-                key: KeyCode::Escape,
-                ..
-            } => {
-                state.cancel_search();
-                true
-            }
-            KeyEvent {
-                key: KeyCode::Char(c),
-                modifiers: Modifiers::NONE | Modifiers::SHIFT,
-            } => {
-                state.search_mut().push_query_char(*c);
-                true
+        let Some(command) = state.key_bindings.prompt_command(event) else {
+            return match event {
+                KeyEvent {
+                    key: KeyCode::Char(c),
+                    modifiers: Modifiers::NONE | Modifiers::SHIFT,
+                } => {
+                    state.search_history.stop_recalling();
+                    state.search_mut().push_query_char(*c);
+                    true
+                }
+                _ => false,
+            };
+        };
+        match command {
+            PromptCommand::Accept => state.close_search(),
+            PromptCommand::Cancel => state.cancel_search(),
+            PromptCommand::DeleteBack => {
+                state.search_history.stop_recalling();
+                state.search_mut().pop_query_char();
             }
-            KeyEvent {
-                key: KeyCode::Backspace,
-                ..
-            } => {
-                Some(state.search_mut().pop_query_char());
-                true
+            PromptCommand::SelectPrev => state.search_mut().select_prev(),
+            PromptCommand::SelectNext => state.search_mut().select_next(),
+            PromptCommand::RecallOlder => {
+                let recalled = state.search_history.older(state.search.query());
+                if let Some(query) = recalled.map(str::to_string) {
+                    state.search_mut().set_query(&query);
+                }
             }
-            KeyEvent {
-                key: KeyCode::UpArrow,
-                ..
-            } => {
-                Some(state.search_mut().select_prev());
-                true
+            PromptCommand::RecallNewer => {
+                if let Some(query) = state.search_history.newer() {
+                    state.search_mut().set_query(&query);
+                }
             }
-            KeyEvent {
-                key: KeyCode::DownArrow,
-                ..
-            } => {
-                Some(state.search_mut().select_next());
-                true
+            PromptCommand::ToggleRegex => state.toggle_regex(),
+            PromptCommand::Paste => {
+                state.search_history.stop_recalling();
+                if let Err(e) = state.paste_clipboard() {
+                    warn!("Pasting the clipboard failed with {:?}", e);
+                    state.last_error = Some(format!("{}", e));
+                }
             }
-            _ => false,
         }
+        true
     }
 
-    fn render_matches(&mut self, height: usize, changes: &mut Vec<Change>, state: &mut State) {
-        if state.search.matches().len() == 0 {
+    fn render_matches(height: usize, changes: &mut Vec<Change>, state: &mut State) {
+        if state.search.matches().is_empty() {
             return;
         }
-        let selected_idx = state.search.selected_idx().unwrap_or(0);
-        let first_visible_idx = selected_idx.saturating_sub(height - 1);
-        let selected = &state.doc.links[state.search.matches()[selected_idx]];
-        let highlights = vec![(selected.start, selected.end)];
-        for i in first_visible_idx..(first_visible_idx + height) {
-            if i >= state.search.matches().len() {
-                break;
-            }
+        let highlights = state.link_highlights();
+        for i in state.search.visible_matches(height) {
             let start = state.doc.links[state.search.matches()[i]].start;
             let line = state.view.find_line(start);
-            render_lines(&state.doc, &state.view, line, 1, &highlights, changes);
-            changes.push(Change::Text("\r\n".to_string()));
+            render_lines(&state.doc, &state.view, [line], &highlights, changes);
+            push_text(changes, "\r\n");
         }
     }
 }
 
-struct SearchWidget {}
+#[derive(Default)]
+struct SearchWidget {
+    // Reused across renders to avoid reallocating every frame
+    changes: Vec<Change>,
+}
 
 impl Widget<State> for SearchWidget {
     fn render(&mut self, args: &mut RenderArgs, state: &mut State) {
@@ -364,31 +1026,34 @@ impl Widget<State> for SearchWidget {
         if height == 0 {
             return;
         }
-        let mut changes = vec![
+        self.changes.extend([
             Change::ClearScreen(ColorAttribute::Default),
             Change::CursorPosition {
                 x: Absolute(0),
                 y: Absolute(0),
             },
-        ];
+        ]);
         if height > 3 {
-            changes.push(Change::Text(format!("{}\r\n", "━".repeat(width))));
-            self.render_matches(height - 2, &mut changes, state);
+            self.changes
+                .push(Change::Text(format!("{}\r\n", "━".repeat(width))));
+            Self::render_matches(height - 2, &mut self.changes, state);
         }
-        let search_label = format!("Search: {}", state.search.query());
+        let search_label = prompt_label("Search", &state.search.mode(), state.search.query());
         args.cursor.coords = ParentRelativeCoords {
-            x: search_label.len(),
+            x: state.view.widths().text(&search_label),
             y: height - 1,
         };
         args.cursor.shape = CursorShape::BlinkingBar;
-        changes.extend(vec![
+        self.changes.extend([
+            // The last match drawn may have left its highlight on
+            Change::AllAttributes(CellAttributes::default()),
             Change::CursorPosition {
                 x: Absolute(0),
                 y: Absolute(height - 1),
             },
             Change::Text(search_label),
         ]);
-        args.surface.add_changes(changes);
+        flush_changes(args.surface, &mut self.changes, state.colors);
     }
 
     fn get_size_constraints(&self, state: &State) -> Constraints {
@@ -407,7 +1072,8 @@ impl Widget<State> for SearchWidget {
             WidgetEvent::Input(i) => match i {
                 InputEvent::Key(k) => self.process_key(k, state),
                 InputEvent::Paste(s) => {
-                    state.search_mut().push_query_str(&s);
+                    state.search_history.stop_recalling();
+                    state.search_mut().push_query_str(s);
                     true
                 }
                 _ => false,
@@ -416,67 +1082,412 @@ impl Widget<State> for SearchWidget {
     }
 }
 
-/// This is the main container widget for the app
-struct MainWidget {}
+// Lists the commands matching what's been typed, to run one by name
+#[derive(Default)]
+struct PaletteWidget {
+    changes: Vec<Change>,
+}
 
-impl MainWidget {
+impl PaletteWidget {
     fn process_key(&mut self, event: &KeyEvent, state: &mut State) -> bool {
-        match event {
-            KeyEvent {
-                key: KeyCode::Char('/'),
-                ..
-            } => {
-                state.open_search();
-                true
-            }
-            KeyEvent {
-                key: KeyCode::Char('N'),
-                ..
-            } => {
-                state.search_mut().select_prev();
-                true
-            }
-            KeyEvent {
-                key: KeyCode::Char('n'),
-                ..
-            } => {
-                state.search_mut().select_next();
-                true
-            }
-            KeyEvent {
-                key: KeyCode::Enter,
-                ..
-            } => {
-                if let Err(e) = state.search_mut().open_selected() {
-                    warn!("Opening selection failed with {:?}", e);
-                    state.last_error = Some(format!("{}", e));
+        let Some(palette) = &mut state.palette else {
+            return false;
+        };
+        let Some(command) = state.key_bindings.prompt_command(event) else {
+            return match event {
+                KeyEvent {
+                    key: KeyCode::Char(c),
+                    modifiers: Modifiers::NONE | Modifiers::SHIFT,
+                } => {
+                    palette.push_query_char(*c);
+                    true
+                }
+                _ => false,
+            };
+        };
+        match command {
+            PromptCommand::Accept => {
+                // Close first so commands that open prompts of their own aren't hidden by it
+                let selected = palette.selected();
+                state.palette = None;
+                if let Some(command) = selected {
+                    command.run(state);
                 }
-                true
-            }
-            KeyEvent {
-                key: KeyCode::Char('c'),
-                modifiers: Modifiers::CTRL,
             }
-            | KeyEvent {
-                key: KeyCode::Char('q'),
-                ..
-            } => {
-                // Quit the app when Ctrl-c or q are pressed
-                state.shared.borrow_mut().quit = true;
-                true
+            PromptCommand::Cancel => state.palette = None,
+            PromptCommand::DeleteBack => palette.pop_query_char(),
+            PromptCommand::SelectPrev => palette.select_prev(),
+            PromptCommand::SelectNext => palette.select_next(),
+            // Commands are picked by name rather than recalled
+            PromptCommand::RecallOlder
+            | PromptCommand::RecallNewer
+            | PromptCommand::ToggleRegex
+            | PromptCommand::Paste => return false,
+        }
+        true
+    }
+}
+
+impl Widget<State> for PaletteWidget {
+    fn render(&mut self, args: &mut RenderArgs, state: &mut State) {
+        let (width, height) = args.surface.dimensions();
+        let Some(palette) = &state.palette else {
+            return;
+        };
+        if height == 0 {
+            return;
+        }
+        self.changes.extend([
+            Change::ClearScreen(ColorAttribute::Default),
+            Change::CursorPosition {
+                x: Absolute(0),
+                y: Absolute(0),
+            },
+        ]);
+        // Scroll the list just enough to show the selection
+        let rows = height - 1;
+        let first = (palette.selected_idx() + 1).saturating_sub(rows);
+        for (i, command) in palette.matches().iter().enumerate().skip(first).take(rows) {
+            let name = command.name();
+            let keys = state.key_bindings.keys(*command);
+            let gap = width.saturating_sub(name.len() + keys.len()).max(1);
+            self.changes.extend([
+                Change::Attribute(AttributeChange::Reverse(i == palette.selected_idx())),
+                Change::Text(format!("{name}{}{keys}", " ".repeat(gap))),
+                Change::AllAttributes(CellAttributes::default()),
+                Change::Text("\r\n".to_string()),
+            ]);
+        }
+        let label = format!("Command: {}", palette.query());
+        args.cursor.coords = ParentRelativeCoords {
+            x: state.view.widths().text(&label),
+            y: height - 1,
+        };
+        args.cursor.shape = CursorShape::BlinkingBar;
+        self.changes.extend([
+            Change::CursorPosition {
+                x: Absolute(0),
+                y: Absolute(height - 1),
+            },
+            Change::Text(label),
+        ]);
+        flush_changes(args.surface, &mut self.changes, state.colors);
+    }
+
+    fn get_size_constraints(&self, state: &State) -> Constraints {
+        let mut c = Constraints::default();
+        c.set_fixed_height(state.palette_height());
+        c
+    }
+
+    fn process_event(
+        &mut self,
+        event: &WidgetEvent,
+        _args: &mut UpdateArgs,
+        state: &mut State,
+    ) -> bool {
+        match event {
+            WidgetEvent::Input(InputEvent::Key(k)) => self.process_key(k, state),
+            _ => false,
+        }
+    }
+}
+
+// Lists every key and what it does, over the document
+#[derive(Default)]
+struct HelpWidget {
+    changes: Vec<Change>,
+    // How many keys fit when it was last rendered, for paging
+    rows: usize,
+}
+
+impl HelpWidget {
+    fn process_key(&mut self, event: &KeyEvent, state: &mut State) -> bool {
+        let bindings = &state.key_bindings;
+        let command = bindings
+            .main_command(event)
+            .or_else(|| bindings.document_command(event));
+        let cancelled = bindings.prompt_command(event) == Some(PromptCommand::Cancel);
+        let Some(help) = &mut state.help else {
+            return false;
+        };
+        let page = self.rows.saturating_sub(1).max(1);
+        match command {
+            // Leave quitting with Ctrl-C and suspending to the main widget
+            Some(Command::Quit) if quits_from_anywhere(event) => return false,
+            Some(Command::Suspend) => return false,
+            Some(Command::ShowHelp | Command::Quit) => state.help = None,
+            Some(Command::LineDown) => help.scroll_down(1, self.rows),
+            Some(Command::LineUp) => help.scroll_up(1),
+            Some(Command::HalfPageDown) => help.scroll_down(page / 2, self.rows),
+            Some(Command::HalfPageUp) => help.scroll_up(page / 2),
+            Some(Command::PageDown) => help.scroll_down(page, self.rows),
+            Some(Command::PageUp) => help.scroll_up(page),
+            _ if cancelled => state.help = None,
+            // Other keys would act on the document hidden behind the help
+            _ => {}
+        }
+        true
+    }
+}
+
+impl Widget<State> for HelpWidget {
+    fn render(&mut self, args: &mut RenderArgs, state: &mut State) {
+        args.cursor.visibility = CursorVisibility::Hidden;
+        let (_, height) = args.surface.dimensions();
+        let Some(help) = &mut state.help else {
+            return;
+        };
+        if height == 0 {
+            return;
+        }
+        self.rows = height - 1;
+        // Keep the screen full after a resize made it taller
+        help.scroll_down(0, self.rows);
+        let widths = state.view.widths();
+        let keys_width = help
+            .rows()
+            .iter()
+            .map(|(keys, _)| widths.text(keys))
+            .max()
+            .unwrap_or(0);
+        self.changes.extend([
+            Change::ClearScreen(ColorAttribute::Default),
+            Change::CursorPosition {
+                x: Absolute(0),
+                y: Absolute(0),
+            },
+        ]);
+        let shown = help.rows().iter().skip(help.first()).take(self.rows);
+        for (keys, name) in shown {
+            let gap = keys_width - widths.text(keys) + 2;
+            self.changes.extend([
+                Change::Attribute(AttributeChange::Intensity(Intensity::Bold)),
+                Change::Text(keys.clone()),
+                Change::AllAttributes(CellAttributes::default()),
+                Change::Text(format!("{}{name}\r\n", " ".repeat(gap))),
+            ]);
+        }
+        let last = (help.first() + self.rows).min(help.rows().len());
+        let close = match state.key_bindings.prompt_keys(PromptCommand::Cancel) {
+            keys if keys.is_empty() => state.key_bindings.keys(Command::ShowHelp),
+            keys => keys,
+        };
+        self.changes.extend([
+            Change::CursorPosition {
+                x: Absolute(0),
+                y: Absolute(height - 1),
+            },
+            Change::Attribute(AttributeChange::Reverse(true)),
+            Change::Text(format!(
+                "Keys {}-{} of {}, {close} closes",
+                help.first() + 1,
+                last,
+                help.rows().len()
+            )),
+            Change::AllAttributes(CellAttributes::default()),
+        ]);
+        flush_changes(args.surface, &mut self.changes, state.colors);
+    }
+
+    fn get_size_constraints(&self, state: &State) -> Constraints {
+        let mut c = Constraints::default();
+        c.set_fixed_height(state.help_height());
+        c
+    }
+
+    fn process_event(
+        &mut self,
+        event: &WidgetEvent,
+        _args: &mut UpdateArgs,
+        state: &mut State,
+    ) -> bool {
+        match event {
+            WidgetEvent::Input(InputEvent::Key(k)) => self.process_key(k, state),
+            _ => false,
+        }
+    }
+}
+
+// Takes the text to search the document or filter its lines for
+struct FindWidget {}
+
+impl FindWidget {
+    fn process_key(&mut self, event: &KeyEvent, state: &mut State) -> bool {
+        let Some(prompt) = &mut state.find_prompt else {
+            return false;
+        };
+        let saving = matches!(prompt.kind, Find::Save { .. } | Find::Session);
+        let query = &mut prompt.query;
+        let history = &mut state.search_history;
+        let Some(command) = state.key_bindings.prompt_command(event) else {
+            return match event {
+                KeyEvent {
+                    key: KeyCode::Char(c),
+                    modifiers: Modifiers::NONE | Modifiers::SHIFT,
+                } => {
+                    history.stop_recalling();
+                    push_find_str(query, &c.to_string());
+                    true
+                }
+                _ => false,
+            };
+        };
+        match command {
+            PromptCommand::Accept => {
+                let query = std::mem::take(query);
+                let kind = prompt.kind;
+                state.find_prompt = None;
+                match kind {
+                    Find::Text | Find::Lines => state.remember_query(&query),
+                    // Paths and names aren't searches to recall
+                    Find::Save { .. } | Find::Session => history.stop_recalling(),
+                }
+                match kind {
+                    Find::Text => state.find_text(query),
+                    Find::Lines => state.filter_lines(query),
+                    Find::Save { escapes } => state.save(&query, escapes),
+                    Find::Session => state.save_session(&query),
+                }
+            }
+            PromptCommand::Cancel => {
+                history.stop_recalling();
+                state.find_prompt = None;
+            }
+            PromptCommand::DeleteBack => {
+                history.stop_recalling();
+                query.pop();
             }
+            PromptCommand::ToggleRegex => match &mut prompt.kind {
+                Find::Save { escapes } => *escapes = !*escapes,
+                Find::Session => {}
+                _ => state.toggle_regex(),
+            },
+            // Nothing's listed under the prompt, so selecting recalls queries too
+            PromptCommand::RecallOlder | PromptCommand::SelectPrev => {
+                // Paths and names typed to save to aren't recalled like searches
+                if let Some(recalled) = (!saving).then(|| history.older(query)).flatten() {
+                    recalled.clone_into(query);
+                }
+            }
+            PromptCommand::RecallNewer | PromptCommand::SelectNext => {
+                if let Some(recalled) = (!saving).then(|| history.newer()).flatten() {
+                    *query = recalled;
+                }
+            }
+            PromptCommand::Paste => return false,
+        }
+        true
+    }
+}
+
+// The line to type a query into, with how it's being matched if that's not as written regardless
+// of case, like "Search (regex): a.b"
+fn prompt_label(name: &str, mode: &str, query: &str) -> String {
+    if mode.is_empty() {
+        format!("{name}: {query}")
+    } else {
+        format!("{name} ({mode}): {query}")
+    }
+}
+
+// Adds typed or pasted text to a text search, which like link searches is a single line
+fn push_find_str(query: &mut String, s: &str) {
+    for c in s.chars().filter(|c| !c.is_control()) {
+        if query.len() + c.len_utf8() > MAX_QUERY_BYTES {
+            break;
+        }
+        query.push(c);
+    }
+}
+
+impl Widget<State> for FindWidget {
+    fn render(&mut self, args: &mut RenderArgs, state: &mut State) {
+        let Some(prompt) = &state.find_prompt else {
+            return;
+        };
+        if args.surface.dimensions().1 == 0 {
+            return;
+        }
+        let (name, mode) = match prompt.kind {
+            Find::Text => ("Search text", None),
+            Find::Lines => ("Filter lines", None),
+            Find::Save { escapes } => (
+                "Save to",
+                Some(if escapes { "with colors and links" } else { "" }),
+            ),
+            Find::Session => ("Save session as", Some("")),
+        };
+        let mode = match mode {
+            Some(mode) => mode.to_string(),
+            None => Pattern::new(&prompt.query, state.search.regex()).mode(),
+        };
+        let label = prompt_label(name, &mode, &prompt.query);
+        args.cursor.coords = ParentRelativeCoords {
+            x: state.view.widths().text(&label),
+            y: 0,
+        };
+        args.cursor.shape = CursorShape::BlinkingBar;
+        args.surface.add_changes(vec![
+            Change::ClearScreen(ColorAttribute::Default),
+            Change::CursorPosition {
+                x: Absolute(0),
+                y: Absolute(0),
+            },
+            Change::Text(label),
+        ]);
+    }
+
+    fn get_size_constraints(&self, state: &State) -> Constraints {
+        let mut c = Constraints::default();
+        c.set_fixed_height(state.find_height());
+        c
+    }
+
+    fn process_event(
+        &mut self,
+        event: &WidgetEvent,
+        _args: &mut UpdateArgs,
+        state: &mut State,
+    ) -> bool {
+        match event {
+            WidgetEvent::Input(InputEvent::Key(k)) => self.process_key(k, state),
+            WidgetEvent::Input(InputEvent::Paste(s)) => match &mut state.find_prompt {
+                Some(prompt) => {
+                    state.search_history.stop_recalling();
+                    push_find_str(&mut prompt.query, s);
+                    true
+                }
+                None => false,
+            },
             _ => false,
         }
     }
 }
 
+/// This is the main container widget for the app
+struct MainWidget {}
+
+impl MainWidget {
+    fn process_key(&mut self, event: &KeyEvent, state: &mut State) -> bool {
+        match state.key_bindings.main_command(event) {
+            Some(command) => {
+                command.run(state);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 impl Widget<State> for MainWidget {
     fn render(&mut self, _args: &mut RenderArgs, _state: &mut State) {}
 
     fn get_size_constraints(&self, _state: &State) -> Constraints {
-        let mut c = Constraints::default();
-        c.child_orientation = ChildOrientation::Vertical;
-        c
+        Constraints {
+            child_orientation: ChildOrientation::Vertical,
+            ..Default::default()
+        }
     }
 
     fn process_event(
@@ -488,6 +1499,11 @@ impl Widget<State> for MainWidget {
         match event {
             WidgetEvent::Input(i) => match i {
                 InputEvent::Key(k) => self.process_key(k, state),
+                // The pointer is over the status line or a prompt rather than the document
+                InputEvent::Mouse(_) => {
+                    state.hovered = None;
+                    true
+                }
                 _ => false,
             },
         }
@@ -496,11 +1512,25 @@ impl Widget<State> for MainWidget {
 
 #[cfg(test)]
 mod tests {
-    use std::{cell::RefCell, io::Cursor, rc::Rc};
+    use std::{
+        cell::RefCell,
+        io::{Cursor, Read},
+        rc::Rc,
+    };
 
-    use termwiz::{color::ColorAttribute, input::Modifiers, surface::Surface};
+    use termwiz::{
+        cell::{Intensity, Underline},
+        color::ColorAttribute,
+        input::Modifiers,
+        surface::Surface,
+    };
 
     use super::*;
+    use crate::doc::ParseOptions;
+    use crate::keys::KeyBindings;
+    use crate::options::ViewOptions;
+    use crate::state::StatusLine;
+    use crate::style::LinkStyle;
 
     struct Context<'a> {
         ui: AteUi<'a>,
@@ -519,9 +1549,58 @@ mod tests {
             }
             self.ui.step(&mut self.surface).unwrap();
         }
+
+        fn queue_ctrl(&mut self, c: char) {
+            self.ui
+                .queue_event(WidgetEvent::Input(InputEvent::Key(KeyEvent {
+                    key: KeyCode::Char(c),
+                    modifiers: Modifiers::CTRL,
+                })));
+            self.ui.step(&mut self.surface).unwrap();
+        }
+    }
+
+    fn test_clipboard() -> Clipboard {
+        Clipboard {
+            read: Box::new(|| Ok(String::new())),
+            write: Box::new(|_| Ok(())),
+        }
     }
 
-    fn create_test_ui(input: &str, width: usize, height: usize) -> Context {
+    // Reads all of input before returning like main does on its input thread
+    fn create_ui<'a>(
+        input: Box<dyn Read>,
+        width: usize,
+        height: usize,
+        open_link: OpenLink,
+        options: Options,
+        bookmarks_path: Option<PathBuf>,
+    ) -> Result<AteUi<'a>> {
+        let (doc, parse_time) = timed(|| Document::new(input, options.parse));
+        let mut ui = create_awaiting_ui(
+            width,
+            height,
+            open_link,
+            test_clipboard(),
+            options,
+            Keymaps::default(),
+            bookmarks_path,
+        )?;
+        ui.load(doc?, parse_time, parse_time, true);
+        Ok(ui)
+    }
+
+    fn create_test_ui(input: &str, width: usize, height: usize) -> Context<'_> {
+        create_test_ui_with(input, width, height, Options::default(), None)
+    }
+
+    fn create_test_ui_with(
+        input: &str,
+        width: usize,
+        height: usize,
+        options: Options,
+        bookmarks_path: Option<PathBuf>,
+    ) -> Context<'_> {
         let visited = Rc::new(RefCell::new(vec![]));
         let ctx_visited = visited.clone();
         let mut ui = create_ui(
@@ -532,6 +1611,8 @@ mod tests {
                 visited.borrow_mut().push(uri.to_string());
                 Ok(())
             }),
+            options,
+            bookmarks_path,
         )
         .unwrap();
         let mut surface = Surface::new(width, height);
@@ -545,6 +1626,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn coalesce_text_changes() {
+        let mut changes = vec![];
+        push_text(&mut changes, "a");
+        push_text(&mut changes, "b");
+        changes.push(Change::Attribute(AttributeChange::Reverse(true)));
+        push_text(&mut changes, "c");
+        assert_eq!(3, changes.len());
+        assert_eq!(Change::Text("ab".to_string()), changes[0]);
+    }
+
     #[test]
     fn render_color() {
         let input = "D\x1b[31mR\x1b[mD";
@@ -565,6 +1657,87 @@ mod tests {
         assert_eq!(ctx.surface.screen_chars_to_string(), "Hi \nBye\n 0%\n");
     }
 
+    #[test]
+    fn tiny_terminals() {
+        let input = "\x1b]8;;1\x1b\\1\x1b]8;;\x1b\\\n2\n3";
+        for (width, height) in [(1, 1), (1, 2), (2, 1), (5, 2), (5, 3)] {
+            let mut ctx = create_test_ui(input, width, height);
+            ctx.press_keys(vec![
+                KeyCode::Char(' '),
+                KeyCode::Char('b'),
+                KeyCode::Char('n'),
+                KeyCode::DownArrow,
+                KeyCode::Char('/'),
+                KeyCode::Char('1'),
+                KeyCode::Escape,
+            ]);
+        }
+
+        // Shrinking to nothing and back shouldn't lose anything
+        let mut ctx = create_test_ui(input, 5, 3);
+        for (cols, rows) in [(0, 0), (5, 3)] {
+            ctx.surface.resize(cols, rows);
+            ctx.ui
+                .queue_event(WidgetEvent::Input(InputEvent::Resized { cols, rows }));
+            ctx.press_keys(vec![KeyCode::Char('n')]);
+        }
+        assert_eq!(
+            ctx.surface.screen_chars_to_string(),
+            "1    \n2    \n   0%\n"
+        );
+    }
+
+    #[test]
+    fn render_wide_at_boundary() {
+        let mut ctx = create_test_ui("ab中c", 3, 3);
+        let cells = &ctx.surface.screen_cells();
+        assert_eq!("b", cells[0][1].str());
+        assert_eq!(" ", cells[0][2].str());
+        assert_eq!("中", cells[1][0].str());
+        assert_eq!("c", cells[1][2].str());
+    }
+
+    #[test]
+    fn render_combining_at_boundary() {
+        let mut ctx = create_test_ui("abe\u{301}d\u{200b}ef", 3, 3);
+        let cells = &ctx.surface.screen_cells();
+        assert_eq!("e\u{301}", cells[0][2].str());
+        assert_eq!("d", cells[1][0].str());
+        assert_eq!("\u{200b}", cells[1][1].str());
+        assert_eq!("e", cells[1][2].str());
+    }
+
+    #[test]
+    fn render_rtl() {
+        // Hebrew alef, bet, gimel after some left to right text, with bet in red
+        let input = "ab \u{5d0}\x1b[31m\u{5d1}\x1b[m\u{5d2}\nab";
+        let mut ctx = create_test_ui(input, 6, 3);
+        // Logical order without bidi
+        let cells = &ctx.surface.screen_cells();
+        assert_eq!("\u{5d0}", cells[0][3].str());
+        assert_eq!("\u{5d2}", cells[0][5].str());
+
+        ctx.ui
+            .queue_event(WidgetEvent::Input(InputEvent::Key(KeyEvent {
+                key: KeyCode::Char('t'),
+                modifiers: Modifiers::CTRL,
+            })));
+        ctx.ui.step(&mut ctx.surface).unwrap();
+        let cells = &ctx.surface.screen_cells();
+        assert_eq!("a", cells[0][0].str());
+        assert_eq!("\u{5d2}", cells[0][3].str());
+        assert_eq!("\u{5d1}", cells[0][4].str());
+        assert_eq!(
+            ColorAttribute::PaletteIndex(1),
+            cells[0][4].attrs().foreground()
+        );
+        assert_eq!("\u{5d0}", cells[0][5].str());
+        assert_eq!(ColorAttribute::Default, cells[0][5].attrs().foreground());
+        // Following lines pick up where the logical order left off
+        assert_eq!("a", cells[1][0].str());
+        assert_eq!(ColorAttribute::Default, cells[1][0].attrs().foreground());
+    }
+
     #[test]
     fn page() {
         let input = "1\n2\n3\n4\n5\n6";
@@ -592,6 +1765,76 @@ mod tests {
         );
     }
 
+    #[test]
+    fn counts_and_vi_keys() {
+        let input: String = (0..40)
+            .map(|i| format!("\x1b]8;;{i}\x1b\\{i}\x1b]8;;\x1b\\ line\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 20, 6);
+        let press = |ctx: &mut Context, keys: &str| {
+            ctx.press_keys(keys.chars().map(KeyCode::Char).collect());
+            ctx.surface.screen_chars_to_string()
+        };
+        // The count so far shows in the status line
+        let screen = press(&mut ctx, "15");
+        assert!(screen.contains("15 0%"), "{}", screen);
+        assert!(press(&mut ctx, "j").starts_with("15 "));
+        assert!(press(&mut ctx, "k").starts_with("14 "));
+        assert!(press(&mut ctx, "d").starts_with("16 "));
+        assert!(press(&mut ctx, "2u").starts_with("12 "));
+
+        ctx.queue_ctrl('f');
+        assert!(ctx.surface.screen_chars_to_string().starts_with("15 "));
+        ctx.press_keys(vec![KeyCode::Char('2')]);
+        ctx.queue_ctrl('b');
+        assert!(ctx.surface.screen_chars_to_string().starts_with("9 "));
+
+        // Counts step through that many links, like pressing n that many times
+        press(&mut ctx, "3n");
+        assert_eq!(vec!["2"], reversed_rows_at(&mut ctx, 0));
+        press(&mut ctx, "2N");
+        assert_eq!(vec!["0"], reversed_rows_at(&mut ctx, 0));
+    }
+
+    #[test]
+    fn overlays() {
+        let mut ctx = create_test_ui("Hi", 60, 2);
+        ctx.press_keys(vec![KeyCode::Function(12)]);
+        let status = ctx.surface.screen_chars_to_string();
+        assert!(status.contains("parse "), "{}", status);
+        assert!(status.contains("render "), "{}", status);
+
+        // Switching overlays replaces the one that's showing
+        ctx.press_keys(vec![KeyCode::Function(11)]);
+        let status = ctx.surface.screen_chars_to_string();
+        assert!(status.contains("text "), "{}", status);
+        assert!(!status.contains("parse "), "{}", status);
+
+        ctx.press_keys(vec![KeyCode::Function(11)]);
+        assert!(!ctx.surface.screen_chars_to_string().contains("text "));
+    }
+
+    #[test]
+    fn stats_overlay() {
+        let mut ctx = create_test_ui("\x1b]8;;a\x1b\\a\x1b]8;;\x1b\\\nb", 80, 2);
+        ctx.press_keys(vec![KeyCode::Function(9)]);
+        let status = ctx.surface.screen_chars_to_string();
+        assert!(
+            status.contains("18 bytes (18B) 2 lines 1 links read in "),
+            "{}",
+            status
+        );
+    }
+
+    #[test]
+    fn diagnostics_overlay() {
+        let mut ctx = create_test_ui("\x1b[11mHi\x1b[10m", 80, 2);
+        ctx.press_keys(vec![KeyCode::Function(10)]);
+        let status = ctx.surface.screen_chars_to_string();
+        assert!(status.contains("2 diagnostics"), "{}", status);
+        assert!(status.contains("Font"), "{}", status);
+    }
+
     #[test]
     fn visit_link() {
         let input =
@@ -615,32 +1858,263 @@ mod tests {
         assert_eq!(vec!["http://a.b".to_string()], *ctx.visited.borrow());
     }
 
-    fn check_rev(ctx: &mut Context, reversed: usize) {
-        let cells = &ctx.surface.screen_cells();
-        for i in 0..6 {
-            assert_eq!(format!("{}", i), cells[i][0].str());
-            assert_eq!(i == reversed, cells[i][0].attrs().reverse(), "i = {}", i);
-        }
+    fn reversed_bytes(highlights: &[(usize, usize)], len: usize) -> Vec<bool> {
+        let highlights: Vec<Highlight> = highlights
+            .iter()
+            .map(|&(start, end)| (start, end, LinkStyle::REVERSE))
+            .collect();
+        let mut tracker = AttrTracker::new(&[], &highlights, 0, CellAttributes::default());
+        let mut changes = vec![];
+        (0..len)
+            .map(|byte| {
+                tracker.advance(byte, &mut changes);
+                tracker.current.reverse()
+            })
+            .collect()
     }
 
     #[test]
-    fn next_links() {
-        let input = "0
-\x1b]8;;1\x1b\\1\x1b]8;;2\x1b\\
-2
-\x1b]8;;3\x1b\\3
-\x1b]8;;\x1b\\4
-\x1b]8;;5\x1b\\5";
-        let mut ctx = create_test_ui(input, 10, 13);
-
-        // Nothing should be highlighted
-        check_rev(&mut ctx, usize::MAX);
+    fn track_adjacent_and_empty_highlights() {
+        assert_eq!(
+            vec![false, true, true, true, false],
+            reversed_bytes(&[(1, 2), (2, 4)], 5)
+        );
+        assert_eq!(
+            vec![false, false, true, false],
+            reversed_bytes(&[(1, 1), (2, 3)], 4)
+        );
+    }
 
-        ctx.press_keys(vec![KeyCode::Char('n'), KeyCode::Char('n')]);
-        check_rev(&mut ctx, 2);
+    fn reversed_cells(ctx: &mut Context) -> Vec<String> {
+        ctx.surface
+            .screen_cells()
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|c| if c.attrs().reverse() { 'R' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
 
-        ctx.press_keys(vec![KeyCode::Char('n'), KeyCode::Char('n')]);
-        check_rev(&mut ctx, 5);
+    // Marks each cell of the first row R if reversed, D if dim, U if underlined, or . otherwise
+    fn styled_cells(ctx: &mut Context) -> String {
+        ctx.surface.screen_cells()[0]
+            .iter()
+            .map(|c| {
+                let attrs = c.attrs();
+                if attrs.reverse() {
+                    'R'
+                } else if attrs.intensity() == Intensity::Half {
+                    'D'
+                } else if attrs.underline() == Underline::Single {
+                    'U'
+                } else {
+                    '.'
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn link_styles() {
+        let input = "ab \x1b]8;;1\x1b\\one\x1b]8;;\x1b\\ \x1b]8;;2\x1b\\two\x1b]8;;\x1b\\";
+        let mut ctx = create_test_ui(input, 12, 3);
+        ctx.press_keys(vec![KeyCode::Char('n'), KeyCode::Enter]);
+        assert_eq!("...RRR......", styled_cells(&mut ctx));
+        // Visited links are dimmed once they're no longer selected
+        ctx.press_keys(vec![KeyCode::Char('n')]);
+        assert_eq!("...DDD.RRR..", styled_cells(&mut ctx));
+
+        let mouse = |x, y| {
+            WidgetEvent::Input(InputEvent::Mouse(MouseEvent {
+                x,
+                y,
+                mouse_buttons: MouseButtons::NONE,
+                modifiers: Modifiers::NONE,
+            }))
+        };
+        ctx.press_keys(vec![KeyCode::Char('N')]);
+        // Mouse positions are 1-based
+        ctx.ui.queue_event(mouse(10, 1));
+        ctx.press_keys(vec![]);
+        assert_eq!("...RRR.UUU..", styled_cells(&mut ctx));
+        ctx.ui.queue_event(mouse(12, 1));
+        ctx.press_keys(vec![]);
+        assert_eq!("...RRR......", styled_cells(&mut ctx));
+        ctx.ui.queue_event(mouse(8, 1));
+        ctx.press_keys(vec![]);
+        // Moving onto the status line stops hovering
+        ctx.ui.queue_event(mouse(8, 3));
+        ctx.press_keys(vec![]);
+        assert_eq!("...RRR......", styled_cells(&mut ctx));
+    }
+
+    #[test]
+    fn mouse_wheel_and_clicks() {
+        let input: String = (0..20)
+            .map(|i| format!("{i} \x1b]8;;{i}\x1b\\link\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 20, 5);
+        let mouse = |ctx: &mut Context, x, y, mouse_buttons| {
+            ctx.ui
+                .queue_event(WidgetEvent::Input(InputEvent::Mouse(MouseEvent {
+                    x,
+                    y,
+                    mouse_buttons,
+                    modifiers: Modifiers::NONE,
+                })));
+            ctx.press_keys(vec![]);
+        };
+        mouse(&mut ctx, 1, 1, MouseButtons::VERT_WHEEL);
+        mouse(&mut ctx, 1, 1, MouseButtons::VERT_WHEEL);
+        assert!(ctx.surface.screen_chars_to_string().starts_with("6 "));
+        let up = MouseButtons::VERT_WHEEL | MouseButtons::WHEEL_POSITIVE;
+        mouse(&mut ctx, 1, 1, up);
+        assert!(ctx.surface.screen_chars_to_string().starts_with("3 "));
+
+        // Clicking off a link does nothing, and holding the button down only clicks once
+        mouse(&mut ctx, 1, 2, MouseButtons::LEFT);
+        mouse(&mut ctx, 5, 2, MouseButtons::LEFT);
+        mouse(&mut ctx, 5, 2, MouseButtons::NONE);
+        assert!(ctx.visited.borrow().is_empty());
+        mouse(&mut ctx, 4, 2, MouseButtons::LEFT);
+        mouse(&mut ctx, 5, 2, MouseButtons::LEFT);
+        assert_eq!(vec!["4"], *ctx.visited.borrow());
+        assert_eq!(vec!["4"], reversed_rows_at(&mut ctx, 2));
+        // The clicked link is selected, so n goes on from it
+        mouse(&mut ctx, 5, 2, MouseButtons::NONE);
+        ctx.press_keys(vec![KeyCode::Char('n')]);
+        assert_eq!(vec!["5"], reversed_rows_at(&mut ctx, 2));
+    }
+
+    #[test]
+    fn render_hanging_indents() {
+        let input = "- a \x1b]8;;x\x1b\\list\x1b]8;;\x1b\\ item\n  nested";
+        let options = Options {
+            view: ViewOptions {
+                hanging_indent: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut ctx = create_test_ui_with(input, 6, 5, options, None);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(
+            screen.starts_with("- a li\n  st i\n  tem \n  nest\n"),
+            "{}",
+            screen
+        );
+
+        // Hovering over an indent isn't over the text
+        let mouse = |x| {
+            WidgetEvent::Input(InputEvent::Mouse(MouseEvent {
+                x,
+                y: 2,
+                mouse_buttons: MouseButtons::NONE,
+                modifiers: Modifiers::NONE,
+            }))
+        };
+        ctx.ui.queue_event(mouse(2));
+        ctx.press_keys(vec![]);
+        assert_eq!(None, ctx.ui.ui.state_mut().hovered);
+        ctx.ui.queue_event(mouse(3));
+        ctx.press_keys(vec![]);
+        assert_eq!(Some(0), ctx.ui.ui.state_mut().hovered);
+    }
+
+    #[test]
+    fn chop_long_lines() {
+        let input = "short\n0123456789\x1b]8;;x\x1b\\link\x1b]8;;\x1b\\ after\n中文字\n";
+        let mut ctx = create_test_ui(input, 6, 5);
+        // Drawn on a fresh screen, as Surface's diffs misplace wide characters drawn over at an
+        // odd offset
+        let rows = |ctx: &mut Context| -> Vec<String> {
+            let mut screen = Surface::new(6, 5);
+            ctx.ui.step(&mut screen).unwrap();
+            screen
+                .screen_cells()
+                .iter()
+                .take(3)
+                .map(|row| row.iter().map(|c| c.str()).collect())
+                .collect()
+        };
+        assert_eq!(vec!["short ", "012345", "6789li"], rows(&mut ctx));
+        ctx.press_keys(vec![KeyCode::Char('S')]);
+        assert_eq!(vec!["short ", "012345", "中 文 字 "], rows(&mut ctx));
+
+        // Half the width at a time, blanking the part of a wide character that's on screen
+        ctx.press_keys(vec![KeyCode::RightArrow]);
+        assert_eq!(vec!["rt    ", "345678", " 字    "], rows(&mut ctx));
+
+        // Selecting a link off the side scrolls to it
+        ctx.press_keys(vec![KeyCode::Char('n')]);
+        assert_eq!(vec!["      ", "9link ", "      "], rows(&mut ctx));
+        assert_eq!(".RRRR.", reversed_cells(&mut ctx)[1]);
+        ctx.ui
+            .queue_event(WidgetEvent::Input(InputEvent::Mouse(MouseEvent {
+                x: 2,
+                y: 2,
+                mouse_buttons: MouseButtons::NONE,
+                modifiers: Modifiers::NONE,
+            })));
+        ctx.press_keys(vec![]);
+        assert_eq!(Some(0), ctx.ui.ui.state_mut().hovered);
+
+        // No further than leaves the end of the widest line at the right edge
+        ctx.press_keys(vec![KeyCode::RightArrow]);
+        ctx.press_keys(vec![KeyCode::RightArrow]);
+        assert_eq!(vec!["      ", " after", "      "], rows(&mut ctx));
+        ctx.press_keys(vec![KeyCode::LeftArrow]);
+        assert_eq!(vec!["      ", "ink af", "      "], rows(&mut ctx));
+
+        ctx.press_keys(vec![KeyCode::Char('S')]);
+        assert_eq!(vec!["short ", "012345", "6789li"], rows(&mut ctx));
+        ctx.press_keys(vec![KeyCode::RightArrow]);
+        assert!(ctx.ui.ui.state_mut().last_error.is_some());
+    }
+
+    #[test]
+    fn highlight_across_wraps() {
+        // The link wraps, resets attributes partway through, and reverses its last character
+        // itself
+        let input = "ab\x1b]8;;http://a.b\x1b\\c\x1b[1mde\x1b[mfg\x1b[7mh\x1b]8;;\x1b\\i\x1b[mj";
+        let mut ctx = create_test_ui(input, 4, 3);
+        assert_eq!(vec!["....", "...R", "...."], reversed_cells(&mut ctx));
+        ctx.press_keys(vec![KeyCode::Char('n')]);
+        assert_eq!(vec!["..RR", "RRR.", "...."], reversed_cells(&mut ctx));
+
+        // Start drawing in the middle of the highlight
+        ctx.press_keys(vec![KeyCode::DownArrow]);
+        assert_eq!(vec!["RRR.", "R...", "...."], reversed_cells(&mut ctx));
+    }
+
+    fn check_rev(ctx: &mut Context, reversed: usize) {
+        let cells = &ctx.surface.screen_cells();
+        for (i, line) in cells.iter().enumerate().take(6) {
+            assert_eq!(format!("{}", i), line[0].str());
+            assert_eq!(i == reversed, line[0].attrs().reverse(), "i = {}", i);
+        }
+    }
+
+    #[test]
+    fn next_links() {
+        let input = "0
+\x1b]8;;1\x1b\\1\x1b]8;;2\x1b\\
+2
+\x1b]8;;3\x1b\\3
+\x1b]8;;\x1b\\4
+\x1b]8;;5\x1b\\5";
+        let mut ctx = create_test_ui(input, 10, 13);
+
+        // Nothing should be highlighted
+        check_rev(&mut ctx, usize::MAX);
+
+        ctx.press_keys(vec![KeyCode::Char('n'), KeyCode::Char('n')]);
+        check_rev(&mut ctx, 2);
+
+        ctx.press_keys(vec![KeyCode::Char('n'), KeyCode::Char('n')]);
+        check_rev(&mut ctx, 5);
 
         // Wrap!
         ctx.press_keys(vec![KeyCode::Char('n')]);
@@ -668,12 +2142,884 @@ mod tests {
             );
         }
         assert_eq!(
-            if lines.len() > 0 { "━" } else { " " },
+            if lines.is_empty() { " " } else { "━" },
             cells[cells.len() - 3 - lines.len()][0].str()
         );
         assert_eq!("S", cells[cells.len() - 2][0].str());
     }
 
+    // The rows that have a highlighted first cell
+    fn reversed_rows(ctx: &mut Context) -> Vec<String> {
+        ctx.surface
+            .screen_cells()
+            .iter()
+            .filter(|line| line[0].attrs().reverse())
+            .map(|line| line[0].str().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn resize_while_searching() {
+        let input: String = ('a'..='t')
+            .map(|c| format!("\x1b]8;;{c}\x1b\\{c}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 10, 24);
+        ctx.press_keys(vec![KeyCode::Char('/')]);
+        // Select the last match in the panel and move back up a couple
+        ctx.press_keys(vec![KeyCode::DownArrow; 8]);
+        ctx.press_keys(vec![KeyCode::UpArrow; 2]);
+        assert_eq!(vec!["g", "g"], reversed_rows(&mut ctx));
+        check_search(&mut ctx, vec!["a", "b", "c", "d", "e", "f", "g", "h", "i"]);
+
+        // Shrinking scrolls both the document and the panel just enough to show the selection
+        for (rows, matches) in [
+            (14, vec!["d", "e", "f", "g"]),
+            (8, vec![]),
+            (24, vec!["d", "e", "f", "g", "h", "i", "j", "k", "l"]),
+        ] {
+            ctx.surface.resize(10, rows);
+            ctx.ui
+                .queue_event(WidgetEvent::Input(InputEvent::Resized { cols: 10, rows }));
+            ctx.press_keys(vec![]);
+            let expected = if matches.is_empty() {
+                vec!["g"]
+            } else {
+                vec!["g", "g"]
+            };
+            assert_eq!(expected, reversed_rows(&mut ctx), "rows = {}", rows);
+            check_search(&mut ctx, matches);
+        }
+    }
+
+    #[test]
+    fn keep_position_across_resizes() {
+        let input: String = (0..30)
+            .map(|i| format!("{i} {}\n", "x".repeat(12)))
+            .collect();
+        let mut ctx = create_test_ui(&input, 10, 6);
+        let resize = |ctx: &mut Context, cols| {
+            ctx.surface.resize(cols, 6);
+            ctx.ui
+                .queue_event(WidgetEvent::Input(InputEvent::Resized { cols, rows: 6 }));
+            ctx.press_keys(vec![]);
+            ctx.surface.screen_chars_to_string()
+        };
+        for c in "11g".chars() {
+            ctx.press_keys(vec![KeyCode::Char(c)]);
+        }
+        for cols in [20, 6, 10] {
+            let screen = resize(&mut ctx, cols);
+            assert!(screen.starts_with("10 "), "cols = {}\n{}", cols, screen);
+        }
+        // A row the line wrapped onto stays at the top with the text it continues
+        ctx.press_keys(vec![KeyCode::DownArrow]);
+        let screen = resize(&mut ctx, 20);
+        assert!(screen.starts_with("10 "), "{}", screen);
+    }
+
+    #[test]
+    fn cancel_search_keeps_selection() {
+        let input: String = ('a'..='t')
+            .map(|c| format!("\x1b]8;;{c}\x1b\\{c}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 10, 8);
+        ctx.press_keys(vec![KeyCode::Char('n')]);
+        ctx.press_keys(vec![KeyCode::Char('n')]);
+        assert_eq!(vec!["b"], reversed_rows(&mut ctx));
+        ctx.press_keys(vec![KeyCode::Char('/')]);
+        ctx.press_keys(vec![KeyCode::Char('q')]);
+        // Resizing while searching doesn't lose where to go back to
+        ctx.surface.resize(12, 8);
+        ctx.ui.queue_event(WidgetEvent::Input(InputEvent::Resized {
+            cols: 12,
+            rows: 8,
+        }));
+        ctx.press_keys(vec![KeyCode::Escape]);
+        assert!(ctx.surface.screen_chars_to_string().starts_with("a "));
+        assert_eq!(vec!["b"], reversed_rows(&mut ctx));
+    }
+
+    #[test]
+    fn kitty_keys() {
+        let mut ctx = create_test_ui("\x1b]8;;a\x1b\\a\x1b]8;;\x1b\\", 10, 5);
+        ctx.press_keys(vec![KeyCode::Char('/')]);
+        assert!(ctx.surface.screen_chars_to_string().contains("Search:"));
+        // Esc as reported by the kitty keyboard protocol
+        ctx.press_keys(vec![KeyCode::Char('\x1b')]);
+        assert!(!ctx.surface.screen_chars_to_string().contains("Search:"));
+
+        ctx.ui
+            .queue_event(WidgetEvent::Input(InputEvent::Key(KeyEvent {
+                key: KeyCode::Char('\r'),
+                modifiers: Modifiers::SHIFT,
+            })));
+        ctx.press_keys(vec![]);
+        assert_eq!(vec!["a".to_string()], *ctx.visited.borrow());
+    }
+
+    #[test]
+    fn bookmarks() {
+        let input: String = (0..20).map(|i| format!("{i}\n")).collect();
+        let mut ctx = create_test_ui(&input, 20, 5);
+        ctx.press_keys(vec![KeyCode::DownArrow; 5]);
+        ctx.press_keys(vec![KeyCode::Char('m'), KeyCode::Char('a')]);
+        ctx.press_keys(vec![KeyCode::DownArrow; 5]);
+        assert_eq!("10", ctx.surface.screen_chars_to_string()[..2].to_string());
+
+        ctx.press_keys(vec![KeyCode::Char('\''), KeyCode::Char('a')]);
+        assert_eq!("5 ", ctx.surface.screen_chars_to_string()[..2].to_string());
+
+        // A key that can't name a bookmark cancels without acting on the key
+        ctx.press_keys(vec![KeyCode::Char('m'), KeyCode::DownArrow]);
+        assert_eq!("5 ", ctx.surface.screen_chars_to_string()[..2].to_string());
+
+        ctx.press_keys(vec![KeyCode::Char('\''), KeyCode::Char('b')]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("No bookmark b"));
+    }
+
+    #[test]
+    fn keep_bookmarks_set_while_reading() {
+        let path = std::env::temp_dir().join(format!("ate-reading-marks-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let input: String = (0..20).map(|i| format!("{i}\n")).collect();
+        let parse = |input: &str| {
+            Document::new(
+                Box::new(Cursor::new(input.to_string())),
+                ParseOptions::default(),
+            )
+            .unwrap()
+        };
+        let mut ctx = create_test_ui("", 20, 5);
+        ctx.ui = create_awaiting_ui(
+            20,
+            5,
+            Box::new(|_| Ok(())),
+            test_clipboard(),
+            Options::default(),
+            Keymaps::default(),
+            Some(path.clone()),
+        )
+        .unwrap();
+        ctx.ui
+            .load(parse(&input[..20]), Duration::ZERO, Duration::ZERO, false);
+        ctx.press_keys(vec![KeyCode::DownArrow; 3]);
+        ctx.press_keys(vec![KeyCode::Char('m'), KeyCode::Char('a')]);
+        ctx.ui
+            .load(parse(&input), Duration::ZERO, Duration::ZERO, true);
+        ctx.press_keys(vec![KeyCode::Char('G')]);
+        ctx.press_keys(vec![KeyCode::Char('\''), KeyCode::Char('a')]);
+        assert_eq!("3 ", ctx.surface.screen_chars_to_string()[..2].to_string());
+
+        // It's saved for the next time the document's viewed too
+        let saved = Bookmarks::load(Some(path.clone()), None, &input);
+        assert_eq!(Some(6), saved.get('a'));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn jump_back() {
+        let input: String = (0..30)
+            .map(|i| format!("\x1b]8;;{i}\x1b\\{i}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 40, 5);
+        let top = |ctx: &mut Context| {
+            let screen = ctx.surface.screen_chars_to_string();
+            screen.split_whitespace().next().unwrap().to_string()
+        };
+        ctx.press_keys(vec![KeyCode::Char('\''), KeyCode::Char('\'')]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("No jump to go back from"));
+
+        ctx.press_keys(vec![KeyCode::DownArrow; 2]);
+        ctx.press_keys(vec![KeyCode::Char('G')]);
+        assert_eq!("27", top(&mut ctx));
+        // Going back goes to before the jump, and doing it again returns
+        ctx.press_keys(vec![KeyCode::Char('\''), KeyCode::Char('\'')]);
+        assert_eq!("2", top(&mut ctx));
+        ctx.press_keys(vec![KeyCode::Char('\''), KeyCode::Char('\'')]);
+        assert_eq!("27", top(&mut ctx));
+
+        // Everything a search moves through is one jump from where it started
+        ctx.press_keys(vec![KeyCode::Char('g'), KeyCode::Char('g')]);
+        ctx.press_keys(vec![KeyCode::Char('/')]);
+        for c in ['1', '5'] {
+            ctx.press_keys(vec![KeyCode::Char(c)]);
+        }
+        ctx.press_keys(vec![KeyCode::Enter]);
+        assert_eq!("12", top(&mut ctx));
+        ctx.press_keys(vec![KeyCode::Char('\''), KeyCode::Char('\'')]);
+        assert_eq!("0", top(&mut ctx));
+        // Stepping to a link off the screen jumps
+        ctx.press_keys(vec![KeyCode::Char('n')]);
+        ctx.press_keys(vec![KeyCode::Char('\''), KeyCode::Char('\'')]);
+        assert_eq!("0", top(&mut ctx));
+    }
+
+    #[test]
+    fn await_input() {
+        let mut ctx = create_test_ui("", 20, 10);
+        ctx.ui = create_awaiting_ui(
+            20,
+            10,
+            Box::new(|_| Ok(())),
+            test_clipboard(),
+            Options::default(),
+            Keymaps::default(),
+            None,
+        )
+        .unwrap();
+        ctx.press_keys(vec![]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("Awaiting input"));
+
+        // A search started while waiting applies to the document once it arrives
+        ctx.press_keys(vec![KeyCode::Char('/')]);
+        ctx.press_keys(vec![KeyCode::Char('b')]);
+        let input: String = ["a", "b", "cb"]
+            .iter()
+            .map(|l| format!("\x1b]8;;{l}\x1b\\{l}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        ctx.ui.load(doc, Duration::ZERO, Duration::ZERO, true);
+        ctx.press_keys(vec![]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(!screen.contains("Awaiting input"));
+        assert!(screen.starts_with("a "));
+        check_search(&mut ctx, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn stop_awaiting_failed_input() {
+        let mut ctx = create_test_ui("", 40, 10);
+        ctx.ui = create_awaiting_ui(
+            40,
+            10,
+            Box::new(|_| Ok(())),
+            test_clipboard(),
+            Options::default(),
+            Keymaps::default(),
+            None,
+        )
+        .unwrap();
+        ctx.ui.input_failed("Reading input failed".to_string());
+        ctx.press_keys(vec![]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Reading input failed"), "{}", screen);
+        assert!(!screen.contains("Awaiting input"), "{}", screen);
+    }
+
+    #[test]
+    fn go_to_links_once_found() {
+        let mut ctx = create_test_ui("", 40, 4);
+        let visited = Rc::clone(&ctx.visited);
+        ctx.ui = create_awaiting_ui(
+            40,
+            4,
+            Box::new(move |uri| {
+                visited.borrow_mut().push(uri.to_string());
+                Ok(())
+            }),
+            test_clipboard(),
+            Options::default(),
+            Keymaps::default(),
+            None,
+        )
+        .unwrap();
+        let doc = Document::new(
+            Box::new(Cursor::new("see https://a.b\n")),
+            ParseOptions::default(),
+        )
+        .unwrap();
+        ctx.ui
+            .load(doc.clone(), Duration::ZERO, Duration::ZERO, true);
+        ctx.ui.indexing_links();
+        ctx.press_keys(vec![]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("indexing links…"));
+        ctx.press_keys(vec![KeyCode::Char('n')]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("Still looking for links"));
+
+        let mut found = doc;
+        found.detect_links();
+        ctx.ui.links_found(found);
+        ctx.press_keys(vec![KeyCode::Char('n'), KeyCode::Enter]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(!screen.contains("indexing links…"), "{}", screen);
+        assert!(!screen.contains("Still looking"), "{}", screen);
+        assert_eq!(vec!["https://a.b"], *ctx.visited.borrow());
+    }
+
+    #[test]
+    fn grow_while_reading() {
+        let lines = |n: usize| -> String {
+            (0..n)
+                .map(|i| format!("\x1b]8;;{i}\x1b\\link {i}\x1b]8;;\x1b\\\n"))
+                .collect()
+        };
+        let parse = |input: String| {
+            Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap()
+        };
+        let mut ctx = create_test_ui("", 20, 4);
+        ctx.ui = create_awaiting_ui(
+            20,
+            4,
+            Box::new(|_| Ok(())),
+            test_clipboard(),
+            Options::default(),
+            Keymaps::default(),
+            None,
+        )
+        .unwrap();
+        ctx.ui
+            .load(parse(lines(5)), Duration::ZERO, Duration::ZERO, false);
+        // Keys are handled a step at a time
+        for key in [KeyCode::Char('n'), KeyCode::Char('n'), KeyCode::DownArrow] {
+            ctx.press_keys(vec![key]);
+        }
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.starts_with("link 1"));
+        // How far through the text the view is isn't known until all of it's been read
+        assert!(screen.contains("110B read so far"));
+        assert!(!screen.contains('%'), "{}", screen);
+
+        // The position and selection stay put as more arrives
+        ctx.ui
+            .load(parse(lines(10)), Duration::ZERO, Duration::ZERO, false);
+        ctx.press_keys(vec![]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.starts_with("link 1"));
+        assert!(screen.contains("220B read so far"));
+        assert!(!screen.contains('%'), "{}", screen);
+        assert_eq!(&[(7, 13)], ctx.ui.ui.state_mut().view.highlights());
+
+        // Input from a file shows how much of it's been read
+        ctx.ui.set_input_size(Some(880));
+        ctx.press_keys(vec![]);
+        assert!(ctx.surface.screen_chars_to_string().contains("25% read"));
+
+        ctx.ui
+            .load(parse(lines(10)), Duration::ZERO, Duration::ZERO, true);
+        ctx.press_keys(vec![]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("12%"));
+        assert!(!screen.contains("read so far"));
+    }
+
+    #[test]
+    fn jump_to_positions() {
+        // The first line wraps, so source lines and flowed lines differ
+        let input = (0..30)
+            .map(|i| format!("{i} {}", if i == 0 { "wraps onto more lines" } else { "" }))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut ctx = create_test_ui(&input, 10, 6);
+        let press = |ctx: &mut Context, keys: &str| {
+            for c in keys.chars() {
+                ctx.press_keys(vec![KeyCode::Char(c)]);
+            }
+            ctx.surface.screen_chars_to_string()
+        };
+        assert!(press(&mut ctx, "G").starts_with("25 "));
+        assert!(press(&mut ctx, "gg").starts_with("0 "));
+        assert!(press(&mut ctx, "16g").starts_with("15 "));
+        assert!(press(&mut ctx, "2G").starts_with("1 "));
+        assert!(press(&mut ctx, "0g").starts_with("0 "));
+        // Halfway down the 27 lines that can be at the top
+        assert!(press(&mut ctx, "50%").starts_with("11 "));
+        assert!(press(&mut ctx, "100%").starts_with("25 "));
+        assert!(press(&mut ctx, "999g").starts_with("25 "));
+    }
+
+    #[test]
+    fn follow_input() {
+        let load = |ctx: &mut Context, n: usize| {
+            let input: String = (0..n).map(|i| format!("{i}\n")).collect();
+            let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default());
+            ctx.ui
+                .load(doc.unwrap(), Duration::ZERO, Duration::ZERO, false);
+            ctx.press_keys(vec![]);
+            ctx.surface.screen_chars_to_string()
+        };
+        let mut ctx = create_test_ui("", 40, 4);
+        let options = Options {
+            view: ViewOptions {
+                follow: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        ctx.ui = create_awaiting_ui(
+            40,
+            4,
+            Box::new(|_| Ok(())),
+            test_clipboard(),
+            options,
+            Keymaps::default(),
+            None,
+        )
+        .unwrap();
+        let screen = load(&mut ctx, 5);
+        assert!(screen.starts_with("3 "), "{}", screen);
+        assert!(screen.contains("following"), "{}", screen);
+        assert!(load(&mut ctx, 10).starts_with("8 "));
+
+        // Moving stops following
+        ctx.press_keys(vec![KeyCode::UpArrow]);
+        let screen = load(&mut ctx, 15);
+        assert!(screen.starts_with("7 "), "{}", screen);
+        assert!(!screen.contains("following"), "{}", screen);
+
+        ctx.press_keys(vec![KeyCode::Char('F')]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.starts_with("13 "), "{}", screen);
+        assert!(load(&mut ctx, 20).starts_with("18 "));
+    }
+
+    #[test]
+    fn paste_clipboard() {
+        let input: String = ["a", "b", "cb"]
+            .iter()
+            .map(|l| format!("\x1b]8;;{l}\x1b\\{l}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui("", 60, 10);
+        let mut clipboard = vec![Err(anyhow::anyhow!("No clipboard")), Ok("b\n".to_string())];
+        ctx.ui = create_awaiting_ui(
+            60,
+            10,
+            Box::new(|_| Ok(())),
+            Clipboard {
+                read: Box::new(move || clipboard.pop().unwrap()),
+                ..test_clipboard()
+            },
+            Options::default(),
+            Keymaps::default(),
+            None,
+        )
+        .unwrap();
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        ctx.ui.load(doc, Duration::ZERO, Duration::ZERO, true);
+        let paste = KeyEvent {
+            key: KeyCode::Char('v'),
+            modifiers: Modifiers::CTRL,
+        };
+        ctx.press_keys(vec![KeyCode::Char('/')]);
+        ctx.ui
+            .queue_event(WidgetEvent::Input(InputEvent::Key(paste.clone())));
+        ctx.press_keys(vec![]);
+        // The line feed copied with the text is dropped
+        assert!(ctx.surface.screen_chars_to_string().contains("Search: b "));
+        check_search(&mut ctx, vec!["b", "c"]);
+
+        ctx.ui
+            .queue_event(WidgetEvent::Input(InputEvent::Key(paste)));
+        ctx.press_keys(vec![]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("No clipboard"));
+        check_search(&mut ctx, vec!["b", "c"]);
+    }
+
+    #[test]
+    fn chords() {
+        let input: String = (0..20)
+            .map(|i| format!("\x1b]8;;{i}\x1b\\{i}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 20, 5);
+        ctx.press_keys(vec![KeyCode::Char(' ')]);
+        ctx.press_keys(vec![KeyCode::Char('g')]);
+        assert!(ctx.surface.screen_chars_to_string().contains(" g 9%"));
+        ctx.press_keys(vec![KeyCode::Char('g')]);
+        assert_eq!("0 ", &ctx.surface.screen_chars_to_string()[..2]);
+        assert!(ctx.surface.screen_chars_to_string().contains("  0%"));
+
+        // zt scrolls the selected link to the top
+        ctx.press_keys(vec![KeyCode::Char('n'); 4]);
+        assert_eq!("0 ", &ctx.surface.screen_chars_to_string()[..2]);
+        ctx.press_keys(vec![KeyCode::Char('z'), KeyCode::Char('t')]);
+        assert_eq!("3 ", &ctx.surface.screen_chars_to_string()[..2]);
+
+        // A key that doesn't continue the chord is dropped along with it
+        ctx.press_keys(vec![KeyCode::Char('g'), KeyCode::Char('n')]);
+        assert_eq!(vec!["3"], reversed_rows(&mut ctx));
+    }
+
+    #[test]
+    fn repeat_last_action() {
+        let input: String = ('a'..='e')
+            .map(|c| format!("\x1b]8;;{c}\x1b\\{c}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 20, 8);
+        // Nothing to repeat yet
+        ctx.press_keys(vec![KeyCode::Char('.')]);
+        assert!(reversed_rows(&mut ctx).is_empty());
+
+        ctx.press_keys(vec![KeyCode::Char('n'), KeyCode::Char('.')]);
+        assert_eq!(vec!["b"], reversed_rows(&mut ctx));
+        // Scrolling doesn't replace the action to repeat
+        ctx.press_keys(vec![KeyCode::DownArrow, KeyCode::Char('.')]);
+        assert_eq!(vec!["c"], reversed_rows(&mut ctx));
+
+        ctx.press_keys(vec![KeyCode::Enter, KeyCode::Char('.')]);
+        assert_eq!(vec!["c", "c"], *ctx.visited.borrow());
+        ctx.press_keys(vec![KeyCode::Char('N'), KeyCode::Char('.')]);
+        assert_eq!(vec!["a"], reversed_rows(&mut ctx));
+    }
+
+    #[test]
+    fn macros() {
+        let input: String = ('a'..='e')
+            .map(|c| format!("\x1b]8;;{c}\x1b\\{c}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 60, 8);
+        ctx.press_keys(vec![KeyCode::Char('Q'), KeyCode::Char('a')]);
+        assert!(ctx.surface.screen_chars_to_string().contains("recording a"));
+        ctx.press_keys(vec![KeyCode::Char('n'), KeyCode::Enter, KeyCode::Char('Q')]);
+        assert!(!ctx.surface.screen_chars_to_string().contains("recording"));
+        assert_eq!(vec!["a"], *ctx.visited.borrow());
+
+        ctx.press_keys(vec![KeyCode::Char('@'), KeyCode::Char('a')]);
+        ctx.press_keys(vec![KeyCode::Char('@'), KeyCode::Char('a')]);
+        assert_eq!(vec!["a", "b", "c"], *ctx.visited.borrow());
+        assert_eq!(vec!["c"], reversed_rows(&mut ctx));
+
+        ctx.press_keys(vec![KeyCode::Char('@'), KeyCode::Char('c')]);
+        assert!(ctx.surface.screen_chars_to_string().contains("No macro c"));
+
+        // A macro that replays itself is stopped
+        ctx.press_keys(vec![KeyCode::Char('Q'), KeyCode::Char('b')]);
+        ctx.press_keys(vec![
+            KeyCode::Char('N'),
+            KeyCode::Char('@'),
+            KeyCode::Char('b'),
+        ]);
+        ctx.press_keys(vec![KeyCode::Char('Q')]);
+        ctx.press_keys(vec![KeyCode::Char('@'), KeyCode::Char('b')]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("Stopped replaying"));
+    }
+
+    #[test]
+    fn command_palette() {
+        let input: String = (0..20).map(|i| format!("{i}\n")).collect();
+        let mut ctx = create_test_ui(&input, 40, 10);
+        let open_palette = |ctx: &mut Context| {
+            ctx.ui
+                .queue_event(WidgetEvent::Input(InputEvent::Key(KeyEvent {
+                    key: KeyCode::Char('p'),
+                    modifiers: Modifiers::CTRL,
+                })));
+        };
+        ctx.press_keys(vec![KeyCode::Char(' ')]);
+        open_palette(&mut ctx);
+        ctx.press_keys("go to".chars().map(KeyCode::Char).collect());
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Go to top"), "{}", screen);
+        assert!(screen.contains("Command: go to"), "{}", screen);
+        assert!(!screen.contains("Page down"), "{}", screen);
+
+        ctx.press_keys(vec![KeyCode::Enter]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.starts_with("0 "), "{}", screen);
+        assert!(!screen.contains("Command:"), "{}", screen);
+
+        // Keys typed into the palette don't reach the document, and Esc closes it
+        open_palette(&mut ctx);
+        ctx.press_keys(vec![KeyCode::Char(' '), KeyCode::Escape]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.starts_with("0 "), "{}", screen);
+        assert!(!screen.contains("Command:"), "{}", screen);
+    }
+
+    #[test]
+    fn show_help() {
+        let input: String = (0..20).map(|i| format!("{i}\n")).collect();
+        let mut ctx = create_test_ui(&input, 40, 10);
+        ctx.press_keys(vec![KeyCode::Char('h')]);
+        let screen = ctx.surface.screen_chars_to_string();
+        let rows = ctx.ui.ui.state_mut().help.as_ref().unwrap().rows().len();
+        assert!(screen.starts_with("/  "), "{}", screen);
+        assert!(screen.contains("Search links"), "{}", screen);
+        assert!(
+            screen.contains(&format!("Keys 1-8 of {rows}, Esc closes")),
+            "{}",
+            screen
+        );
+
+        // Keys page through the help instead of the document, and stop at the last one
+        ctx.press_keys(vec![KeyCode::Char(' ')]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Keys 8-15"), "{}", screen);
+        ctx.press_keys(vec![KeyCode::Char(' '); 10]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Quit from anywhere"), "{}", screen);
+        assert!(
+            screen.contains(&format!("Keys {}-{rows} of {rows}", rows - 7)),
+            "{}",
+            screen
+        );
+
+        // q closes the help rather than quitting
+        ctx.press_keys(vec![KeyCode::Char('q')]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(!ctx.ui.shared.borrow().quit);
+        assert!(screen.starts_with("0 "), "{}", screen);
+        assert!(!screen.contains("Keys"), "{}", screen);
+    }
+
+    #[test]
+    fn suspend() {
+        let mut ctx = create_test_ui("text\n", 40, 10);
+        assert!(!ctx.ui.take_suspend());
+        ctx.queue_ctrl('z');
+        assert!(ctx.ui.take_suspend());
+        assert!(!ctx.ui.take_suspend());
+
+        // The help takes every other key, but leaves suspending to the main widget
+        ctx.press_keys(vec![KeyCode::Char('h')]);
+        ctx.queue_ctrl('z');
+        assert!(ctx.ui.take_suspend());
+        assert!(ctx.ui.ui.state_mut().help.is_some());
+    }
+
+    #[test]
+    fn remap_keys() {
+        let input: String = (0..20).map(|i| format!("{i}\n")).collect();
+        let mut ctx = create_test_ui(&input, 40, 10);
+        let keys = vec![("line_down".to_string(), vec!["e".to_string()])];
+        ctx.ui.ui.state_mut().key_bindings = KeyBindings::with_keys(&keys).0;
+        ctx.press_keys(vec![KeyCode::Char('e')]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.starts_with("1 "), "{}", screen);
+
+        // The help lists the keys as they've been set, and scrolls with them
+        ctx.press_keys(vec![KeyCode::Char('h')]);
+        ctx.press_keys(vec![KeyCode::DownArrow; 5]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Keys 1-8 of"), "{}", screen);
+        ctx.press_keys(vec![KeyCode::Char('e'); 5]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(
+            screen.contains("e              Scroll down a line"),
+            "{}",
+            screen
+        );
+    }
+
+    #[test]
+    fn remap_help_and_recording_keys() {
+        let input: String = ('a'..='c')
+            .map(|c| format!("\x1b]8;;{c}\x1b\\{c}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 60, 8);
+        let keys = vec![
+            ("stop_recording".to_string(), vec!["F2".to_string()]),
+            ("show_help".to_string(), vec!["?".to_string()]),
+            ("prompt_cancel".to_string(), vec!["Tab".to_string()]),
+        ];
+        ctx.ui.ui.state_mut().key_bindings = KeyBindings::with_keys(&keys).0;
+        ctx.press_keys(vec![KeyCode::Char('Q'), KeyCode::Char('a')]);
+        ctx.press_keys(vec![
+            KeyCode::Char('n'),
+            KeyCode::Enter,
+            KeyCode::Function(2),
+        ]);
+        assert!(!ctx.surface.screen_chars_to_string().contains("recording"));
+        ctx.press_keys(vec![KeyCode::Char('@'), KeyCode::Char('a')]);
+        assert_eq!(vec!["a", "b"], *ctx.visited.borrow());
+
+        // The help closes with the keys that show it or cancel prompts, and says so
+        ctx.press_keys(vec![KeyCode::Char('?')]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Tab closes"), "{}", screen);
+        ctx.press_keys(vec![KeyCode::Escape]);
+        assert!(ctx.ui.ui.state_mut().help.is_some());
+        ctx.press_keys(vec![KeyCode::Tab]);
+        assert!(ctx.ui.ui.state_mut().help.is_none());
+        ctx.press_keys(vec![KeyCode::Char('?'), KeyCode::Char('?')]);
+        assert!(ctx.ui.ui.state_mut().help.is_none());
+    }
+
+    #[test]
+    fn remap_prompt_keys() {
+        let mut ctx = create_test_ui("some text\n", 40, 5);
+        let keys = vec![("prompt_cancel".to_string(), vec!["Tab".to_string()])];
+        ctx.ui.ui.state_mut().key_bindings = KeyBindings::with_keys(&keys).0;
+        ctx.press_keys(vec![
+            KeyCode::Char('?'),
+            KeyCode::Char('x'),
+            KeyCode::Escape,
+        ]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Search text: x"), "{}", screen);
+        ctx.press_keys(vec![KeyCode::Tab]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(!screen.contains("Search text"), "{}", screen);
+    }
+
+    #[test]
+    fn pick_status_line_items() {
+        let input: String = (0..20).map(|i| format!("{i}\n")).collect();
+        let mut ctx = create_test_ui(&input, 40, 5);
+        ctx.ui.set_files(Files::new(vec![PathBuf::from("a.txt")]));
+        ctx.press_keys(vec![KeyCode::Char('F')]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("a.txt following 100%"), "{}", screen);
+
+        ctx.ui.ui.state_mut().status_line = StatusLine::parse("position file").0;
+        ctx.press_keys(vec![KeyCode::Char('g')]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("100% a.txt"), "{}", screen);
+        assert!(!screen.contains("following"), "{}", screen);
+    }
+
+    #[test]
+    fn switch_files() {
+        let mut ctx = create_test_ui("", 60, 5);
+        let paths = vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")];
+        ctx.ui.set_files(Files::new(paths));
+        let file = |prefix: &str| -> Document {
+            let text: String = (0..20).map(|i| format!("{prefix}{i}\n")).collect();
+            Document::new(Box::new(Cursor::new(text)), ParseOptions::default()).unwrap()
+        };
+        ctx.ui.load(file("a"), Duration::ZERO, Duration::ZERO, true);
+        ctx.press_keys(vec![KeyCode::DownArrow; 3]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.starts_with("a3 "), "{}", screen);
+        assert!(screen.contains("a.txt (1 of 2) 15%"), "{}", screen);
+
+        ctx.press_keys(vec![KeyCode::Char(':'), KeyCode::Char('p')]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("No previous file"));
+        assert_eq!(None, ctx.ui.take_requested_file());
+
+        ctx.press_keys(vec![KeyCode::Char(':'), KeyCode::Char('n')]);
+        assert_eq!(
+            Some((1, PathBuf::from("b.txt"))),
+            ctx.ui.take_requested_file()
+        );
+        ctx.ui.switch_file(1);
+        ctx.press_keys(vec![]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(
+            screen.contains("b.txt (2 of 2) Awaiting input"),
+            "{}",
+            screen
+        );
+        ctx.ui.load(file("b"), Duration::ZERO, Duration::ZERO, true);
+        ctx.press_keys(vec![]);
+        assert!(ctx.surface.screen_chars_to_string().starts_with("b0 "));
+
+        // Going back to a file goes back to where it was left
+        ctx.ui.switch_file(0);
+        ctx.ui.load(file("a"), Duration::ZERO, Duration::ZERO, true);
+        ctx.press_keys(vec![]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.starts_with("a3 "), "{}", screen);
+    }
+
+    #[test]
+    fn save_and_resume_session() {
+        let dir = std::env::temp_dir().join(format!("ate-sessions-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = |prefix: &str| -> Document {
+            let text: String = (0..20).map(|i| format!("{prefix}{i}\n")).collect();
+            Document::new(Box::new(Cursor::new(text)), ParseOptions::default()).unwrap()
+        };
+        let paths = vec![dir.join("a.txt"), dir.join("b.txt")];
+
+        // Piped input can't be picked up again
+        let mut ctx = create_test_ui("text\n", 60, 5);
+        ctx.ui.set_sessions_dir(Some(dir.clone()));
+        ctx.press_keys(vec![KeyCode::Char('W')]);
+        ctx.press_keys("work".chars().map(KeyCode::Char).collect());
+        ctx.press_keys(vec![KeyCode::Enter]);
+        assert_eq!(
+            Some("Only files can be saved in a session".to_string()),
+            ctx.ui.ui.state_mut().last_error
+        );
+
+        let mut ctx = create_test_ui("", 60, 5);
+        ctx.ui.set_sessions_dir(Some(dir.clone()));
+        ctx.ui.set_files(Files::new(paths.clone()));
+        ctx.ui.load(file("a"), Duration::ZERO, Duration::ZERO, true);
+        ctx.press_keys(vec![KeyCode::DownArrow; 3]);
+        ctx.press_keys(vec![KeyCode::Char('?')]);
+        ctx.press_keys("a5".chars().map(KeyCode::Char).collect());
+        ctx.press_keys(vec![KeyCode::Enter]);
+        let top = ctx.ui.ui.state_mut().view.top_byte();
+        ctx.press_keys(vec![KeyCode::Char('W')]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Save session as: "), "{}", screen);
+        ctx.press_keys("work".chars().map(KeyCode::Char).collect());
+        ctx.press_keys(vec![KeyCode::Enter]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Saved session work"), "{}", screen);
+
+        let session = Session::load(&dir, "work").unwrap();
+        assert_eq!(paths, session.files);
+        assert_eq!(vec![Some(top), None], session.positions);
+        assert_eq!(Some("a5".to_string()), session.search);
+
+        // Picking it up goes back to where the file was left and searches it again
+        let mut ctx = create_test_ui("", 60, 5);
+        ctx.ui.resume_session(session);
+        ctx.ui.load(file("a"), Duration::ZERO, Duration::ZERO, true);
+        ctx.press_keys(vec![]);
+        assert_eq!(top, ctx.ui.ui.state_mut().view.top_byte());
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Match 1 of 1"), "{}", screen);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remember_position() {
+        let path = std::env::temp_dir().join(format!("ate-last-position-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let input: String = (0..20).map(|i| format!("{i}\n")).collect();
+        let options = Options {
+            remember_position: true,
+            ..Default::default()
+        };
+        let mut ctx = create_test_ui_with(&input, 60, 5, options, Some(path.clone()));
+        assert!(!ctx.surface.screen_chars_to_string().contains("left off"));
+        ctx.press_keys(vec![KeyCode::DownArrow; 5]);
+        ctx.press_keys(vec![KeyCode::Char('q')]);
+
+        let mut ctx = create_test_ui_with(&input, 60, 5, options, Some(path.clone()));
+        assert_eq!("0 ", ctx.surface.screen_chars_to_string()[..2].to_string());
+        assert!(ctx.surface.screen_chars_to_string().contains("left off"));
+        ctx.press_keys(vec![KeyCode::Char('\''), KeyCode::Char('"')]);
+        assert_eq!("5 ", ctx.surface.screen_chars_to_string()[..2].to_string());
+        assert!(!ctx.surface.screen_chars_to_string().contains("left off"));
+
+        // Without the option nothing is offered and quitting doesn't move the saved position
+        let options = Options::default();
+        let mut ctx = create_test_ui_with(&input, 60, 5, options, Some(path.clone()));
+        assert!(!ctx.surface.screen_chars_to_string().contains("left off"));
+        ctx.press_keys(vec![KeyCode::Char('q')]);
+        let mut ctx = create_test_ui_with(&input, 60, 5, options, Some(path.clone()));
+        ctx.press_keys(vec![KeyCode::Char('\''), KeyCode::Char('"')]);
+        assert_eq!("5 ", ctx.surface.screen_chars_to_string()[..2].to_string());
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn search() {
         let input = "0
@@ -705,4 +3051,369 @@ mod tests {
         check_search(&mut ctx, vec![]);
         check_rev(&mut ctx, 3);
     }
+
+    #[test]
+    fn regex_search() {
+        let input: String = ["ab", "Ab", "a1b", "xyz"]
+            .iter()
+            .map(|l| format!("\x1b]8;;{l}\x1b\\{l}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 40, 13);
+        let toggle = KeyEvent {
+            key: KeyCode::Char('r'),
+            modifiers: Modifiers::CTRL,
+        };
+        let press_toggle = |ctx: &mut Context| {
+            ctx.ui
+                .queue_event(WidgetEvent::Input(InputEvent::Key(toggle.clone())));
+            ctx.press_keys(vec![]);
+            ctx.surface.screen_chars_to_string()
+        };
+        ctx.press_keys(vec![KeyCode::Char('/')]);
+        // Lowercase matches either case
+        ctx.press_keys(vec![KeyCode::Char('a'), KeyCode::Char('b')]);
+        check_search(&mut ctx, vec!["a", "A"]);
+        ctx.press_keys(vec![
+            KeyCode::Backspace,
+            KeyCode::Char('.'),
+            KeyCode::Char('b'),
+        ]);
+        check_search(&mut ctx, vec![]);
+
+        let screen = press_toggle(&mut ctx);
+        assert!(screen.contains("Search (regex): a.b"), "{}", screen);
+        assert_eq!(vec!["a1b"], reversed_rows_at(&mut ctx, 1));
+        // A capital makes it case-sensitive
+        ctx.press_keys(vec![
+            KeyCode::Backspace,
+            KeyCode::Backspace,
+            KeyCode::Char('B'),
+        ]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(
+            screen.contains("Search (regex, case-sensitive): aB"),
+            "{}",
+            screen
+        );
+        check_search(&mut ctx, vec![]);
+        ctx.press_keys(vec![KeyCode::Enter]);
+
+        // The mode carries over to text searches
+        ctx.press_keys(vec![KeyCode::Char('?')]);
+        ctx.press_keys("y|1".chars().map(KeyCode::Char).collect());
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Search text (regex): y|1"), "{}", screen);
+        ctx.press_keys(vec![KeyCode::Enter]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("Match 1 of 2"));
+        ctx.press_keys(vec![KeyCode::Char('?')]);
+        let screen = press_toggle(&mut ctx);
+        assert!(screen.contains("Search text: "), "{}", screen);
+    }
+
+    #[test]
+    fn filter_lines() {
+        let input: String = (0..20)
+            .map(|i| match i {
+                7 => "7 needle wraps onto the next row\n".to_string(),
+                _ if i % 4 == 3 => format!("{i} needle\n"),
+                _ => format!("{i} hay\n"),
+            })
+            .collect();
+        let mut ctx = create_test_ui(&input, 20, 6);
+        let press = |ctx: &mut Context, keys: &str| {
+            for c in keys.chars() {
+                ctx.press_keys(vec![KeyCode::Char(c)]);
+            }
+        };
+        let rows = |ctx: &mut Context| {
+            let screen = ctx.surface.screen_chars_to_string();
+            let words: Vec<String> = screen
+                .lines()
+                .take(5)
+                .map(|l| l.split(' ').next().unwrap_or_default().to_string())
+                .collect();
+            (words, screen)
+        };
+        press(&mut ctx, "&needle");
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Filter lines: needle"), "{}", screen);
+        ctx.press_keys(vec![KeyCode::Enter]);
+        // Every row of a matching line is shown
+        let (words, screen) = rows(&mut ctx);
+        assert_eq!(vec!["3", "7", "the", "11", "15"], words);
+        assert!(screen.contains("filtered 0%"), "{}", screen);
+
+        // Paging only goes as far as the last matching line
+        press(&mut ctx, " ");
+        let (words, screen) = rows(&mut ctx);
+        assert_eq!(vec!["7", "the", "11", "15", "19"], words);
+        assert!(screen.contains("filtered 100%"), "{}", screen);
+        press(&mut ctx, "gg");
+        assert_eq!("3", rows(&mut ctx).0[0]);
+
+        // An empty filter shows everything again from the same line
+        press(&mut ctx, "&");
+        ctx.press_keys(vec![KeyCode::Enter]);
+        let (words, screen) = rows(&mut ctx);
+        assert_eq!(vec!["3", "4", "5", "6", "7"], words);
+        assert!(!screen.contains("filtered"), "{}", screen);
+
+        press(&mut ctx, "&nope");
+        ctx.press_keys(vec![KeyCode::Enter]);
+        let (words, screen) = rows(&mut ctx);
+        assert_eq!(vec!["3", "4", "5", "6", "7"], words);
+        assert!(screen.contains("Not found: nope"), "{}", screen);
+    }
+
+    #[test]
+    fn find_text() {
+        let input: String = (0..20)
+            .map(|i| format!("{i} {}\n", if i % 4 == 3 { "needle" } else { "hay" }))
+            .collect();
+        let mut ctx = create_test_ui(&input, 20, 11);
+        ctx.press_keys(vec![KeyCode::Char('?')]);
+        ctx.press_keys("needle".chars().map(KeyCode::Char).collect());
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Search text: needle"), "{}", screen);
+
+        // Every match on screen is highlighted, and n and N step through them
+        ctx.press_keys(vec![KeyCode::Enter]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Match 1 of 5"), "{}", screen);
+        assert_eq!(vec!["3", "7"], reversed_rows_at(&mut ctx, 2));
+        ctx.press_keys(vec![KeyCode::Char('n')]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("Match 2 of 5"));
+        ctx.press_keys(vec![KeyCode::Char('N')]);
+        ctx.press_keys(vec![KeyCode::Char('N')]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Match 5 of 5"), "{}", screen);
+
+        // Text that isn't there leaves the last search in place
+        ctx.press_keys(vec![KeyCode::Char('?')]);
+        ctx.press_keys(vec![KeyCode::Char('x'), KeyCode::Enter]);
+        assert!(ctx
+            .surface
+            .screen_chars_to_string()
+            .contains("Not found: x"));
+        ctx.press_keys(vec![KeyCode::Char('n')]);
+        assert_eq!(vec!["3", "7"], reversed_rows_at(&mut ctx, 2));
+
+        // Esc leaves the prompt without searching
+        ctx.press_keys(vec![KeyCode::Char('?')]);
+        ctx.press_keys(vec![KeyCode::Char('h'), KeyCode::Escape]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(!screen.contains("Search text:"), "{}", screen);
+        ctx.press_keys(vec![KeyCode::Char('N')]);
+        assert_eq!(vec!["11", "15", "19"], reversed_rows_at(&mut ctx, 3));
+    }
+
+    #[test]
+    fn recall_searches() {
+        let input: String = ["ab", "cd"]
+            .iter()
+            .map(|l| format!("\x1b]8;;{l}\x1b\\{l}\x1b]8;;\x1b\\\n"))
+            .collect();
+        let mut ctx = create_test_ui(&input, 40, 13);
+        let ctrl = |ctx: &mut Context, c: char| {
+            ctx.ui
+                .queue_event(WidgetEvent::Input(InputEvent::Key(KeyEvent {
+                    key: KeyCode::Char(c),
+                    modifiers: Modifiers::CTRL,
+                })));
+            ctx.press_keys(vec![]);
+            ctx.surface.screen_chars_to_string()
+        };
+        ctx.press_keys("/cd".chars().map(KeyCode::Char).collect());
+        ctx.press_keys(vec![KeyCode::Enter]);
+        ctx.press_keys("?ab".chars().map(KeyCode::Char).collect());
+        ctx.press_keys(vec![KeyCode::Enter]);
+        // Cancelled searches aren't kept
+        ctx.press_keys("/xyz".chars().map(KeyCode::Char).collect());
+        ctx.press_keys(vec![KeyCode::Escape]);
+
+        // The link searcher recalls text searches too, matching links as they're recalled
+        ctx.press_keys(vec![KeyCode::Char('/'), KeyCode::Char('c')]);
+        let screen = ctrl(&mut ctx, 'p');
+        assert!(screen.contains("Search: ab"), "{}", screen);
+        assert_eq!(&[0], ctx.ui.ui.state_mut().search.matches());
+        let screen = ctrl(&mut ctx, 'p');
+        assert!(screen.contains("Search: cd"), "{}", screen);
+        assert_eq!(&[1], ctx.ui.ui.state_mut().search.matches());
+        ctrl(&mut ctx, 'n');
+        // Going past the latest query goes back to what was typed
+        let screen = ctrl(&mut ctx, 'n');
+        assert!(screen.contains("Search: c"), "{}", screen);
+        ctx.press_keys(vec![KeyCode::Escape]);
+
+        // Up and Down recall in the find prompt
+        ctx.press_keys(vec![KeyCode::Char('&'), KeyCode::UpArrow, KeyCode::UpArrow]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Filter lines: cd"), "{}", screen);
+        ctx.press_keys(vec![KeyCode::DownArrow]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Filter lines: ab"), "{}", screen);
+        // Editing a recalled query and recalling again starts from the latest
+        ctx.press_keys(vec![KeyCode::Backspace, KeyCode::UpArrow]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains("Filter lines: ab"), "{}", screen);
+    }
+
+    #[test]
+    fn save_to_file() {
+        let mut ctx = create_test_ui("\x1b[1mbold\x1b[m\nplain\n", 40, 10);
+        let path = std::env::temp_dir().join(format!("ate-save-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let typed = path.display().to_string();
+
+        ctx.press_keys(vec![KeyCode::Char('s')]);
+        ctx.queue_ctrl('r');
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(
+            screen.contains("Save to (with colors and links): "),
+            "{}",
+            screen
+        );
+        ctx.queue_ctrl('r');
+        ctx.press_keys(typed.chars().map(KeyCode::Char).collect());
+        ctx.press_keys(vec![KeyCode::Enter]);
+        assert_eq!("bold\nplain\n", std::fs::read_to_string(&path).unwrap());
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.contains(&format!("Saved {typed}")), "{}", screen);
+
+        // Saving again doesn't overwrite, and paths aren't recalled like searches
+        ctx.press_keys(vec![KeyCode::Char('s')]);
+        ctx.queue_ctrl('p');
+        ctx.press_keys(typed.chars().map(KeyCode::Char).collect());
+        ctx.press_keys(vec![KeyCode::Enter]);
+        assert_eq!(
+            Some(format!("{typed} already exists")),
+            ctx.ui.ui.state_mut().last_error
+        );
+        assert_eq!(None, ctx.ui.ui.state_mut().search_history.older(""));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn scrollbar() {
+        // Links on lines 10 and 90 of 100 equally long lines
+        let input: String = (0..100)
+            .map(|i| match i {
+                10 | 90 => format!("\x1b]8;;{i}\x1b\\l{i:03}\x1b]8;;\x1b\\\n"),
+                _ => format!("t{i:03}\n"),
+            })
+            .collect();
+        let mut ctx = create_test_ui(&input, 10, 11);
+        let bar = |ctx: &mut Context| -> Vec<(String, bool)> {
+            ctx.surface.screen_cells()[..10]
+                .iter()
+                .map(|line| (line[9].str().to_string(), line[9].attrs().reverse()))
+                .collect()
+        };
+        assert!(bar(&mut ctx).iter().all(|(c, r)| c == " " && !r));
+        ctx.press_keys(vec![KeyCode::Char('|')]);
+        assert_eq!(9, ctx.ui.ui.state_mut().view.width());
+        let row = |c: &str, shown: bool| (c.to_string(), shown);
+        let mut expected = vec![row(" ", false); 10];
+        expected[0] = row(" ", true);
+        expected[1] = row("━", false);
+        expected[9] = row("━", false);
+        assert_eq!(expected, bar(&mut ctx));
+
+        // The shown rows follow the view
+        ctx.press_keys(vec![KeyCode::Char('G')]);
+        expected[0] = row(" ", false);
+        expected[9] = row("━", true);
+        assert_eq!(expected, bar(&mut ctx));
+
+        // Text matches take the links' place
+        ctx.press_keys("?t05".chars().map(KeyCode::Char).collect());
+        ctx.press_keys(vec![KeyCode::Enter]);
+        // The match is shown a little below the top
+        expected[1] = row(" ", false);
+        expected[4] = row(" ", true);
+        expected[5] = row("━", true);
+        expected[9] = row(" ", false);
+        assert_eq!(expected, bar(&mut ctx));
+
+        // Clicking the scrollbar goes to the text its row stands for
+        ctx.ui
+            .queue_event(WidgetEvent::Input(InputEvent::Mouse(MouseEvent {
+                x: 10,
+                y: 8,
+                mouse_buttons: MouseButtons::LEFT,
+                modifiers: Modifiers::NONE,
+            })));
+        ctx.press_keys(vec![]);
+        let screen = ctx.surface.screen_chars_to_string();
+        assert!(screen.starts_with("t070"), "{}", screen);
+
+        ctx.press_keys(vec![KeyCode::Char('|')]);
+        assert_eq!(10, ctx.ui.ui.state_mut().view.width());
+    }
+
+    // The first word of the rows with the cell at x highlighted
+    fn reversed_rows_at(ctx: &mut Context, x: usize) -> Vec<String> {
+        ctx.surface
+            .screen_cells()
+            .iter()
+            .filter(|line| line[x].attrs().reverse())
+            .map(|line| {
+                line.iter()
+                    .map(|c| c.str())
+                    .take_while(|&c| c != " ")
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Times drawing every screen of a large colored and linked input, like benches/flow.rs does
+    // for flowing it. render_lines is part of the binary, which benches can't reach.
+    // Run with cargo test --release --bin ate time_render_lines -- --ignored --nocapture.
+    #[test]
+    #[ignore]
+    fn time_render_lines() {
+        let input: String = (0..100_000)
+            .map(|i| {
+                let path = format!("src/module_{}.rs", i % 97);
+                let message = "expected one thing but found another ".repeat(i % 5 + 1);
+                format!(
+                    "\x1b[1;31merror\x1b[0m: \x1b]8;;file://host/{path}\x1b\\{path}:{i}\x1b]8;;\x1b\\ {message}\n"
+                )
+            })
+            .collect();
+        let doc =
+            Rc::new(Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap());
+        let highlights: Vec<Highlight> = doc
+            .links
+            .iter()
+            .map(|l| (l.start, l.end, LinkStyle::REVERSE))
+            .collect();
+        let mut view = DocumentView::new(Rc::clone(&doc), 80, 50, &ViewOptions::default());
+        let mut changes = vec![];
+        let mut screens = 0;
+        // Scrolling flows the lines coming into view, which benches/flow.rs times, so only the
+        // drawing is counted
+        let mut elapsed = Duration::ZERO;
+        loop {
+            let top = view.top_byte();
+            let shown = view.visible_lines().to_vec();
+            elapsed += timed(|| render_lines(&doc, &view, shown, &highlights, &mut changes)).1;
+            changes.clear();
+            screens += 1;
+            view.forward(50);
+            if view.top_byte() == top {
+                break;
+            }
+        }
+        println!(
+            "render_lines: {:?} per screen over {screens} screens",
+            elapsed / screens
+        );
+    }
 }
@@ -0,0 +1,1270 @@
+use std::{
+    mem::size_of,
+    ops::Range,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use finl_unicode::grapheme_clusters::Graphemes;
+use log::debug;
+use termwiz::{cell::CellAttributes, surface::Change};
+
+use crate::doc::Document;
+use crate::find::Pattern;
+use crate::options::{CellWidths, ViewOptions};
+
+/// Flowed lines are broken after this many bytes even if they haven't filled the width.
+/// That bounds the work of rendering a single line however wide lines are allowed to get.
+pub const MAX_LINE_BYTES: usize = 16 * 1024;
+
+/// A row of a document's text flowed to fit the view's width.
+/// Only valid for a particular text width due to reflowing
+pub struct Line {
+    /// Where the line's text starts in the document's text
+    pub start_byte: usize,
+    /// The full set of active attributes to let set up this line for rendering.
+    pub start_attributes: CellAttributes,
+    /// Blank cells to leave before the line's text, to line up a wrapped line with the text it
+    /// continues
+    pub indent: usize,
+    /// Which line of the text before wrapping this is part of, counting from 0
+    pub source_line: usize,
+}
+
+// Documents are flowed a chunk at a time as their chunks come into view, so only the text around
+// what's shown gets flowed however long the document is.
+// Each chunk after the first starts at the first line of the text before wrapping that's at least
+// this many bytes into the chunk before it.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Where a flowed line is, to look it up with DocumentView::line.
+/// Lines keep their place as the view scrolls, but not once the width or the document changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineRef {
+    chunk: usize,
+    // Which of the chunk's lines it is
+    idx: usize,
+}
+
+// Whole lines of the text that are flowed together
+struct Chunk {
+    start_byte: usize,
+    // Which line of the text before wrapping the chunk starts with
+    source_line: usize,
+    // The attributes in effect at start_byte
+    start_attributes: CellAttributes,
+    // The index into the document's attrs of the first change at or after start_byte
+    attr_idx: usize,
+    // The chunk's text flowed at the view's width.
+    // Empty until it's first needed.
+    lines: Vec<Line>,
+}
+
+impl Chunk {
+    fn first() -> Self {
+        Self {
+            start_byte: 0,
+            source_line: 0,
+            start_attributes: CellAttributes::default(),
+            attr_idx: 0,
+            lines: vec![],
+        }
+    }
+}
+
+// Splits the document from the start of chunk on into chunks.
+// Where chunks split doesn't depend on the width, so they're kept when it changes.
+fn split_chunks(doc: &Document, mut chunk: Chunk) -> Vec<Chunk> {
+    let mut chunks = vec![];
+    while let Some(end) = next_chunk_start(&doc.text, chunk.start_byte) {
+        let mut next = Chunk {
+            start_byte: end,
+            source_line: chunk.source_line + count_newlines(&doc.text[chunk.start_byte..end]),
+            start_attributes: chunk.start_attributes.clone(),
+            attr_idx: chunk.attr_idx,
+            lines: vec![],
+        };
+        apply_attrs_before(
+            &doc.attrs,
+            &mut next.attr_idx,
+            &mut next.start_attributes,
+            end,
+        );
+        chunks.push(chunk);
+        chunk = next;
+    }
+    chunks.push(chunk);
+    chunks
+}
+
+// Where the chunk after the one starting at start starts, if the text goes on past it
+fn next_chunk_start(text: &str, start: usize) -> Option<usize> {
+    let after = text.as_bytes().get(start + CHUNK_BYTES - 1..)?;
+    let newline = after.iter().position(|&b| b == b'\n')?;
+    let next = start + CHUNK_BYTES + newline;
+    (next < text.len()).then_some(next)
+}
+
+fn count_newlines(text: &str) -> usize {
+    text.bytes().filter(|&b| b == b'\n').count()
+}
+
+// Applies the changes in attrs from attr_idx on that come before byte
+fn apply_attrs_before(
+    attrs: &[(usize, Change)],
+    attr_idx: &mut usize,
+    attributes: &mut CellAttributes,
+    byte: usize,
+) {
+    while *attr_idx < attrs.len() && attrs[*attr_idx].0 < byte {
+        match &attrs[*attr_idx].1 {
+            Change::AllAttributes(a) => {
+                *attributes = a.clone();
+            }
+            Change::Attribute(a) => {
+                attributes.apply_change(a);
+            }
+            _ => unreachable!(),
+        }
+        *attr_idx += 1;
+    }
+}
+
+/// A document flowed to fit a width, and the rows of it that fit on a screen of some height.
+/// Only the text around what's shown gets flowed, however long the document is.
+pub struct DocumentView {
+    // Reverses the reverse display of bytes in these ranges.
+    // If reverse is off for a byte, flips it on and vice versa.
+    highlights: Vec<(usize, usize)>,
+    // First displayed row.
+    // Rows are the flowed lines that can be shown, which is all of them unless filtering.
+    // In reflow, the text at the start of this row is kept at the top.
+    top: LineRef,
+    // The first row, and the top row of the last page, which is as far down as the view goes
+    first_row: LineRef,
+    end_top: LineRef,
+    // The rows on screen, starting with top
+    shown: Vec<LineRef>,
+    // Where the row after the ones on screen starts
+    shown_end: usize,
+    width: usize,
+
+    doc: Rc<Document>,
+
+    height: usize,
+    // The document split into chunks, each flown at width once it's needed.
+    // Flowed chunks are kept so going back over them doesn't flow them again, until the width
+    // changes or the document does.
+    chunks: Vec<Chunk>,
+    // How long flowing has taken since the document or the width last changed
+    flow_time: Duration,
+    widths: CellWidths,
+    // Whether to reorder right to left text for display
+    bidi: bool,
+    // Whether to indent wrapped lines to line up with the text they continue
+    hanging_indent: bool,
+    // Whether long lines wrap onto more rows rather than going off the side of the screen
+    wrap: bool,
+    // The first column of each row on screen, while not wrapping
+    left: usize,
+    // Whether to keep the last line on screen as the document grows, until the view is moved
+    following: bool,
+    // Only the lines matching this are shown, while filtering
+    filter: Option<LineFilter>,
+    // Where the top was before the view last jumped somewhere else, for '' to go back to
+    jumped_from: Option<usize>,
+}
+
+// Lines of the text before wrapping that match a query, shown in place of the whole text like
+// less's &
+struct LineFilter {
+    query: String,
+    pattern: Pattern,
+    // The lines of the text before wrapping with a match in them, in order
+    source_lines: Vec<usize>,
+}
+
+impl LineFilter {
+    // Finds the lines of text with a match in them, counting those a match continues onto
+    fn refilter(&mut self, text: &str) {
+        self.source_lines.clear();
+        // The line the text up to counted is on
+        let (mut line, mut counted) = (0, 0);
+        for (start, end) in self.pattern.find_all(text) {
+            line += count_newlines(&text[counted..start]);
+            counted = start;
+            // A line feed ending the match is part of the line it ends
+            let last = line + count_newlines(text.get(start..end - 1).unwrap_or(""));
+            let first = self.source_lines.last().map_or(line, |&l| line.max(l + 1));
+            self.source_lines.extend(first..=last);
+        }
+    }
+}
+
+impl DocumentView {
+    /// Shows doc from the top, or from the end when options says to follow it
+    pub fn new(doc: Rc<Document>, width: usize, height: usize, options: &ViewOptions) -> Self {
+        let first = LineRef { chunk: 0, idx: 0 };
+        let mut view = Self {
+            chunks: split_chunks(&doc, Chunk::first()),
+            doc,
+            width,
+            height,
+            top: first,
+            first_row: first,
+            end_top: first,
+            shown: vec![],
+            shown_end: 0,
+            highlights: vec![],
+            flow_time: Duration::ZERO,
+            widths: options.widths,
+            bidi: options.bidi,
+            hanging_indent: options.hanging_indent,
+            wrap: !options.chop_long_lines,
+            left: 0,
+            following: options.follow,
+            filter: None,
+            jumped_from: None,
+        };
+        view.relayout(0);
+        view
+    }
+
+    /// Shows doc in place of the one it grew from, keeping the same text at the top
+    pub fn update(&mut self, doc: Rc<Document>) {
+        let top_byte = self.top_byte();
+        // The chunks that end where doc is unchanged and more text follows keep their lines and
+        // the rest are split again. The last one always is, as the text may have gone on from
+        // its end.
+        let unchanged = doc.unchanged_before.min(doc.text.len().saturating_sub(1));
+        let changed = self.chunks[1..].partition_point(|next| next.start_byte <= unchanged);
+        self.chunks.truncate(changed + 1);
+        let from = self
+            .chunks
+            .pop()
+            .expect("changed is less than the chunk count");
+        self.chunks.extend(split_chunks(
+            &doc,
+            Chunk {
+                lines: vec![],
+                ..from
+            },
+        ));
+        self.doc = doc;
+        self.flow_time = Duration::ZERO;
+        if let Some(filter) = &mut self.filter {
+            filter.refilter(&self.doc.text);
+            // Text it matched may have been rewound, like an overwritten progress line
+            if filter.source_lines.is_empty() {
+                self.filter = None;
+            }
+        }
+        self.relayout(top_byte);
+    }
+
+    /// Shows doc from the top in place of a different document
+    pub fn replace(&mut self, doc: Rc<Document>) {
+        self.chunks = split_chunks(&doc, Chunk::first());
+        self.doc = doc;
+        self.highlights.clear();
+        self.flow_time = Duration::ZERO;
+        self.left = 0;
+        self.filter = None;
+        self.jumped_from = None;
+        self.relayout(0);
+    }
+
+    /// Highlights the bytes from start up to end in place of what was highlighted, scrolling to
+    /// show them
+    pub fn highlight(&mut self, start: usize, end: usize) {
+        self.highlight_spans(vec![(start, end)]);
+    }
+
+    /// Highlights spans in place of what was highlighted without scrolling to them
+    pub fn set_highlights(&mut self, spans: Vec<(usize, usize)>) {
+        self.highlights = spans;
+    }
+
+    /// Like highlight for a link split into several spans, going to the first
+    pub fn highlight_spans(&mut self, spans: Vec<(usize, usize)>) {
+        let Some(&(start, _)) = spans.first() else {
+            return;
+        };
+        self.following = false;
+        self.highlights = spans;
+        let from = self.top_byte();
+        let row = self.find_row(start);
+        self.make_line_visible(row);
+        self.make_column_visible(start);
+        self.record_jump(from);
+    }
+
+    /// Goes to the end and stays there as more of the document is read, like tail -f
+    pub fn follow(&mut self) {
+        self.following = true;
+        self.jump(self.end_top);
+    }
+
+    /// Goes back to where the view last jumped from, like less's ''.
+    /// Going back is a jump too, so doing it again returns.
+    /// Returns false if the view hasn't jumped yet.
+    pub fn jump_back(&mut self) -> bool {
+        let Some(byte) = self.jumped_from else {
+            return false;
+        };
+        self.following = false;
+        let row = self.find_row(byte.min(self.doc.text.len()));
+        self.jump(row);
+        true
+    }
+
+    /// Where the view last jumped from, if it has
+    pub fn jumped_from(&self) -> Option<usize> {
+        self.jumped_from
+    }
+
+    pub fn set_jumped_from(&mut self, byte: Option<usize>) {
+        self.jumped_from = byte;
+    }
+
+    // Shows the rows from top on like place, noting where the view was if that moves it
+    fn jump(&mut self, top: LineRef) {
+        let from = self.top_byte();
+        self.place(top);
+        self.record_jump(from);
+    }
+
+    fn record_jump(&mut self, from: usize) {
+        if self.top_byte() != from {
+            self.jumped_from = Some(from);
+        }
+    }
+
+    pub fn following(&self) -> bool {
+        self.following
+    }
+
+    /// Shows only the lines of the text before wrapping that pattern matches in, or every line if
+    /// filter is None, keeping the top line or the first shown after it on screen.
+    /// Returns false and leaves the view as it was if no lines match.
+    pub fn set_filter(&mut self, filter: Option<(String, Pattern)>) -> bool {
+        let mut filter = filter.map(|(query, pattern)| LineFilter {
+            query,
+            pattern,
+            source_lines: vec![],
+        });
+        if let Some(filter) = &mut filter {
+            filter.refilter(&self.doc.text);
+            if filter.source_lines.is_empty() {
+                return false;
+            }
+        }
+        let top = self.top;
+        self.filter = filter;
+        self.find_ends();
+        let row = if self.shows(top) {
+            top
+        } else {
+            self.next_row(top).unwrap_or(self.end_top)
+        };
+        if self.following {
+            self.place(self.end_top);
+        } else {
+            self.place(row);
+        }
+        true
+    }
+
+    /// What the shown lines are filtered by, while filtering
+    pub fn filter_query(&self) -> Option<&str> {
+        self.filter.as_ref().map(|f| f.query.as_str())
+    }
+
+    // Whether row is shown, which every flowed line is unless filtering
+    fn shows(&self, row: LineRef) -> bool {
+        let source_line = self.line(row).source_line;
+        self.filter
+            .as_ref()
+            .is_none_or(|f| f.source_lines.binary_search(&source_line).is_ok())
+    }
+
+    // The row after row, if there is one, flowing the text it's in if need be
+    fn next_row(&mut self, row: LineRef) -> Option<LineRef> {
+        let source_line = self.line(row).source_line;
+        if self.shows(row) {
+            // The rest of a line of the text that wrapped
+            let lines = &self.chunks[row.chunk].lines;
+            if lines
+                .get(row.idx + 1)
+                .is_some_and(|l| l.source_line == source_line)
+            {
+                return Some(LineRef {
+                    idx: row.idx + 1,
+                    ..row
+                });
+            }
+        }
+        let next = match &self.filter {
+            Some(f) => *f
+                .source_lines
+                .get(f.source_lines.partition_point(|&l| l <= source_line))?,
+            None => source_line + 1,
+        };
+        self.first_row_of(next)
+    }
+
+    // The row before row, if there is one, flowing the text it's in if need be
+    fn prev_row(&mut self, row: LineRef) -> Option<LineRef> {
+        let source_line = self.line(row).source_line;
+        if self.shows(row) && row.idx > 0 {
+            let lines = &self.chunks[row.chunk].lines;
+            if lines[row.idx - 1].source_line == source_line {
+                return Some(LineRef {
+                    idx: row.idx - 1,
+                    ..row
+                });
+            }
+        }
+        let prev = match &self.filter {
+            Some(f) => {
+                *f.source_lines[..f.source_lines.partition_point(|&l| l < source_line)].last()?
+            }
+            None => source_line.checked_sub(1)?,
+        };
+        Some(self.last_row_of(prev))
+    }
+
+    // Walks up to count rows back from row, returning where it got to and how many rows that was
+    fn rows_back(&mut self, mut row: LineRef, count: usize) -> (LineRef, usize) {
+        for walked in 0..count {
+            match self.prev_row(row) {
+                Some(prev) => row = prev,
+                None => return (row, walked),
+            }
+        }
+        (row, count)
+    }
+
+    // The first flowed line of source_line, a line of the text before wrapping, if there's
+    // such a line
+    fn first_row_of(&mut self, source_line: usize) -> Option<LineRef> {
+        let chunk = self.chunk_with_source_line(source_line);
+        let lines = &self.chunks[chunk].lines;
+        let idx = lines.partition_point(|l| l.source_line < source_line);
+        (idx < lines.len()).then_some(LineRef { chunk, idx })
+    }
+
+    // The last flowed line of source_line, or of the last line of the text if it's past that
+    fn last_row_of(&mut self, source_line: usize) -> LineRef {
+        let chunk = self.chunk_with_source_line(source_line);
+        let lines = &self.chunks[chunk].lines;
+        let idx = lines.partition_point(|l| l.source_line <= source_line) - 1;
+        LineRef { chunk, idx }
+    }
+
+    // The chunk holding source_line, a line of the text before wrapping, flowed
+    fn chunk_with_source_line(&mut self, source_line: usize) -> usize {
+        let chunk = self
+            .chunks
+            .partition_point(|c| c.source_line <= source_line)
+            - 1;
+        self.flow_chunk(chunk);
+        chunk
+    }
+
+    /// The flowed line containing byte, flowing the text around it if need be
+    pub fn find_line(&mut self, byte: usize) -> LineRef {
+        let chunk = self.chunks.partition_point(|c| c.start_byte <= byte) - 1;
+        self.flow_chunk(chunk);
+        let idx = self.chunks[chunk]
+            .lines
+            .partition_point(|l| l.start_byte <= byte)
+            - 1;
+        LineRef { chunk, idx }
+    }
+
+    // The row showing byte, or the last one before it while filtering it out
+    fn find_row(&mut self, byte: usize) -> LineRef {
+        let line = self.find_line(byte);
+        if self.shows(line) {
+            line
+        } else {
+            self.prev_row(line).unwrap_or(self.first_row)
+        }
+    }
+
+    // Works out the first row and the top of the last page, which change with the text, the
+    // filter and the size
+    fn find_ends(&mut self) {
+        let first_source_line = self.filter.as_ref().map_or(0, |f| f.source_lines[0]);
+        self.first_row = self
+            .first_row_of(first_source_line)
+            .expect("the text has a row for every line");
+        let last_source_line = self
+            .filter
+            .as_ref()
+            .map_or(usize::MAX, |f| f.source_lines[f.source_lines.len() - 1]);
+        let last_row = self.last_row_of(last_source_line);
+        self.end_top = self.rows_back(last_row, self.height.saturating_sub(1)).0;
+    }
+
+    // Lays the rows out again after the document or the size changed, keeping the text at
+    // top_byte at the top as far as the new layout leaves room to
+    fn relayout(&mut self, top_byte: usize) {
+        self.find_ends();
+        let top = if self.following {
+            self.end_top
+        } else {
+            self.find_row(top_byte.min(self.doc.text.len()))
+        };
+        self.place(top);
+    }
+
+    // Shows the rows from top on, or the last page if top is further down than that
+    fn place(&mut self, top: LineRef) {
+        self.top = if self.line(top).start_byte > self.line(self.end_top).start_byte {
+            self.end_top
+        } else {
+            top
+        };
+        self.shown.clear();
+        let mut row = Some(self.top);
+        while let Some(shown) = row.filter(|_| self.shown.len() < self.height) {
+            self.shown.push(shown);
+            row = self.next_row(shown);
+        }
+        self.shown_end = row.map_or(self.doc.text.len(), |r| self.line(r).start_byte);
+        // Flow the page after this one too, so paging on doesn't wait on flowing
+        let mut lookahead = row;
+        for _ in 0..self.height {
+            let Some(row) = lookahead else { break };
+            lookahead = self.next_row(row);
+        }
+    }
+
+    // Flows chunk if it hasn't been flowed at the current width
+    fn flow_chunk(&mut self, chunk: usize) {
+        if !self.chunks[chunk].lines.is_empty() {
+            return;
+        }
+        let end = self
+            .chunks
+            .get(chunk + 1)
+            .map_or(self.doc.text.len(), |c| c.start_byte);
+        let started = Instant::now();
+        let lines = Self::flow_range(
+            self.flow_width(),
+            &self.doc.text,
+            &self.doc.attrs,
+            self.widths,
+            self.hanging_indent && self.wrap,
+            &self.chunks[chunk],
+            end,
+        );
+        let elapsed = started.elapsed();
+        debug!(
+            "Flowed {} lines from byte {} at width {} in {:?}",
+            lines.len(),
+            self.chunks[chunk].start_byte,
+            self.flow_width(),
+            elapsed
+        );
+        self.flow_time += elapsed;
+        self.chunks[chunk].lines = lines;
+    }
+
+    // The width lines are flowed at, which is unbounded while not wrapping so each line of the
+    // text gets one row, short of MAX_LINE_BYTES
+    fn flow_width(&self) -> usize {
+        if self.wrap {
+            self.width
+        } else {
+            usize::MAX
+        }
+    }
+
+    /// Where the first row scrolling can reach starts, which is after lines filtered out
+    pub fn first_byte(&self) -> usize {
+        self.line(self.first_row).start_byte
+    }
+
+    /// The byte offset of the first character shown
+    pub fn top_byte(&self) -> usize {
+        self.line(self.top).start_byte
+    }
+
+    /// The bytes of the lines on screen.
+    /// While filtering this includes the lines between them that are filtered out.
+    pub fn visible_bytes(&self) -> Range<usize> {
+        self.top_byte()..self.shown_end
+    }
+
+    /// The flowed lines to draw on screen, in order
+    pub fn visible_lines(&self) -> &[LineRef] {
+        &self.shown
+    }
+
+    /// The flowed line line refers to
+    pub fn line(&self, line: LineRef) -> &Line {
+        &self.chunks[line.chunk].lines[line.idx]
+    }
+
+    /// Where the text of line ends, which is where the line after it starts
+    pub fn line_end(&self, line: LineRef) -> usize {
+        let chunk = &self.chunks[line.chunk];
+        chunk.lines.get(line.idx + 1).map_or_else(
+            || {
+                self.chunks
+                    .get(line.chunk + 1)
+                    .map_or(self.doc.text.len(), |c| c.start_byte)
+            },
+            |l| l.start_byte,
+        )
+    }
+
+    /// Scrolls to put the line containing byte at the top
+    pub fn show_byte(&mut self, byte: usize) {
+        self.following = false;
+        let row = self.find_row(byte);
+        self.jump(row);
+    }
+
+    /// Scrolls to put the start of number, a line of the text before wrapping counting from 1, at
+    /// the top, or the first line shown after it while filtering
+    pub fn show_source_line(&mut self, number: usize) {
+        self.following = false;
+        let source_line = number.saturating_sub(1);
+        let shown = match &self.filter {
+            Some(f) => f
+                .source_lines
+                .get(f.source_lines.partition_point(|&l| l < source_line))
+                .copied(),
+            None => Some(source_line),
+        };
+        let row = shown
+            .and_then(|l| self.first_row_of(l))
+            .unwrap_or(self.end_top);
+        self.jump(row);
+    }
+
+    /// Scrolls to where the status line shows percent
+    pub fn show_percent(&mut self, percent: usize) {
+        self.following = false;
+        let (first, end) = self.scroll_bytes();
+        let target = first + (end - first) * percent.min(100) / 100;
+        let row = self.find_row(target);
+        // Go to the first row from target on, so the percentage shown isn't any less
+        let row = if self.line(row).start_byte < target {
+            self.next_row(row).unwrap_or(row)
+        } else {
+            row
+        };
+        self.jump(row);
+    }
+
+    /// Scrolls to the last page
+    pub fn show_end(&mut self) {
+        self.following = false;
+        self.jump(self.end_top);
+    }
+
+    /// Scrolls back lines rows, stopping at the first
+    pub fn backward(&mut self, lines: usize) {
+        self.following = false;
+        let top = self.rows_back(self.top, lines).0;
+        self.place(top);
+    }
+
+    /// Scrolls on lines rows, stopping at the last page
+    pub fn forward(&mut self, lines: usize) {
+        self.following = false;
+        let mut top = self.top;
+        // Going past the last page would only come back to it
+        for _ in 0..lines {
+            match self.next_row(top) {
+                Some(next) if top != self.end_top => top = next,
+                _ => break,
+            }
+        }
+        self.place(top);
+    }
+
+    // Scrolls sideways to show the column byte is drawn at, while not wrapping
+    fn make_column_visible(&mut self, byte: usize) {
+        if self.wrap {
+            return;
+        }
+        let line = self.find_line(byte);
+        let start = self.line(line).start_byte;
+        let column = self.widths.text(self.doc.text_between(start, byte));
+        if column < self.left || column >= self.left + self.width {
+            // Leave some of what comes before it in view
+            self.left = column.saturating_sub(self.width / 4);
+        }
+    }
+
+    fn make_line_visible(&mut self, line: LineRef) {
+        debug!(
+            "Current {} New {}, End {}",
+            self.top_byte(),
+            self.line(line).start_byte,
+            self.height
+        );
+        // Keep 3 rows around it where there are that many
+        let (above, rows_above) = self.rows_back(line, 3);
+        let row = self.shown.iter().position(|&r| r == line);
+        if !row.is_some_and(|row| row >= rows_above && row + 3 <= self.height) {
+            self.place(above);
+        }
+    }
+
+    /// Flows the document again for a new screen size, keeping the same text at the top
+    pub fn set_size(&mut self, width: usize, height: usize) {
+        if width == self.width && height == self.height {
+            return;
+        }
+        let top_byte = self.top_byte();
+        self.height = height;
+        if width != self.width {
+            self.width = width;
+            // Lines are flowed at the same width at every size while not wrapping
+            if self.wrap {
+                self.unflow();
+            }
+        }
+        self.relayout_keeping_selection(top_byte);
+    }
+
+    // Drops the flowed lines after the width they're flowed at changed.
+    // Chunks split the same at any width, so only their lines need flowing again, as they're
+    // shown.
+    fn unflow(&mut self) {
+        for chunk in &mut self.chunks {
+            chunk.lines = vec![];
+        }
+        self.flow_time = Duration::ZERO;
+    }
+
+    // Keeps the same text at the top, or the end if following, and the selection on screen
+    fn relayout_keeping_selection(&mut self, top_byte: usize) {
+        self.relayout(top_byte);
+        if !self.following {
+            if let Some(&(start, _)) = self.highlights.first() {
+                let row = self.find_row(start);
+                self.make_line_visible(row);
+            }
+        }
+    }
+
+    /// The byte ranges highlighted, in order
+    pub fn highlights(&self) -> &[(usize, usize)] {
+        &self.highlights
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn wraps(&self) -> bool {
+        self.wrap
+    }
+
+    /// Switches between wrapping long lines and cutting them off at the edge of the screen
+    pub fn toggle_wrap(&mut self) {
+        let top_byte = self.top_byte();
+        self.wrap = !self.wrap;
+        self.left = 0;
+        self.unflow();
+        self.relayout_keeping_selection(top_byte);
+        if let Some(&(start, _)) = self.highlights.first() {
+            self.make_column_visible(start);
+        }
+    }
+
+    /// The columns of each row that are on screen, while not wrapping
+    pub fn shown_columns(&self) -> Option<Range<usize>> {
+        (!self.wrap).then(|| self.left..self.left + self.width)
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        self.left = self.left.saturating_sub(columns);
+    }
+
+    /// Scrolls right, as far as leaves the end of the widest row on screen at the right edge
+    pub fn scroll_right(&mut self, columns: usize) {
+        let widest = self
+            .shown
+            .iter()
+            .map(|&row| {
+                let start = self.line(row).start_byte;
+                let text = self.doc.text_between(start, self.line_end(row));
+                self.widths.text(text.strip_suffix('\n').unwrap_or(text))
+            })
+            .max()
+            .unwrap_or(0);
+        let furthest = widest.saturating_sub(self.width);
+        if self.left < furthest {
+            self.left = (self.left + columns).min(furthest);
+        }
+    }
+
+    pub fn widths(&self) -> CellWidths {
+        self.widths
+    }
+
+    pub fn bidi(&self) -> bool {
+        self.bidi
+    }
+
+    pub fn toggle_bidi(&mut self) {
+        self.bidi = !self.bidi;
+    }
+
+    pub fn flow_time(&self) -> Duration {
+        self.flow_time
+    }
+
+    /// Bytes the flowed lines take, for the memory overlay
+    pub fn flowed_bytes(&self) -> usize {
+        self.chunks.capacity() * size_of::<Chunk>()
+            + self
+                .chunks
+                .iter()
+                .map(|c| c.lines.capacity() * size_of::<Line>())
+                .sum::<usize>()
+    }
+
+    // Where the first row and the top row of the last page start, which is as far as scrolling
+    // goes each way
+    fn scroll_bytes(&self) -> (usize, usize) {
+        (
+            self.line(self.first_row).start_byte,
+            self.line(self.end_top).start_byte,
+        )
+    }
+
+    /// How far the top of the screen is between the first row and the last page, by bytes so it
+    /// doesn't need the rows between them flowed
+    pub fn percent(&self) -> u8 {
+        let (first, end) = self.scroll_bytes();
+        let top = self.top_byte();
+        if top <= first {
+            0
+        } else if top >= end {
+            100
+        } else {
+            let percent = ((top - first) as f64 / ((end - first) as f64)) * 100.0;
+            percent.floor() as u8
+        }
+    }
+
+    /// Whether all of doc fits in height rows at width, so it can be seen without scrolling
+    pub fn fits(doc: &Document, width: usize, height: usize, options: &ViewOptions) -> bool {
+        // Every line of the text takes at least a row
+        if doc.line_count > height {
+            return false;
+        }
+        let width = if options.chop_long_lines {
+            usize::MAX
+        } else {
+            width
+        };
+        let lines = Self::flow(
+            width,
+            &doc.text,
+            &doc.attrs,
+            options.widths,
+            options.hanging_indent,
+        );
+        // A final line feed leaves an empty line after it that doesn't need a row
+        lines.len() - usize::from(doc.text.ends_with('\n')) <= height
+    }
+
+    // Flows all of text at once
+    fn flow(
+        width: usize,
+        text: &str,
+        attrs: &[(usize, Change)],
+        widths: CellWidths,
+        hanging_indent: bool,
+    ) -> Vec<Line> {
+        Self::flow_range(
+            width,
+            text,
+            attrs,
+            widths,
+            hanging_indent,
+            &Chunk::first(),
+            text.len(),
+        )
+    }
+
+    // Flows the text of chunk, which ends at end
+    fn flow_range(
+        width: usize,
+        text: &str,
+        attrs: &[(usize, Change)],
+        widths: CellWidths,
+        hanging_indent: bool,
+        chunk: &Chunk,
+        end: usize,
+    ) -> Vec<Line> {
+        let mut lines = vec![];
+
+        let mut byte = chunk.start_byte;
+        // Surface gives zero-width graphemes like a lone combining mark a cell of their own.
+        // Count them the same way or lines containing them overflow the Surface and wrap there.
+        let graphemes = Graphemes::new(&text[byte..end]).map(|g| (g, widths.grapheme(g).max(1)));
+        let mut attr_idx = chunk.attr_idx;
+        let mut cells_in_line = 0;
+        let mut attributes = chunk.start_attributes.clone();
+        // What continuations of the current logical line are indented by
+        let indent_for = |start: usize| {
+            let indent = if hanging_indent {
+                measure_hanging_indent(&text[start..], widths)
+            } else {
+                0
+            };
+            // Leave most of the width for text
+            if indent * 2 > width {
+                0
+            } else {
+                indent
+            }
+        };
+        let mut indent = indent_for(byte);
+        let mut source_line = chunk.source_line;
+        lines.push(Line {
+            start_byte: byte,
+            start_attributes: attributes.clone(),
+            indent: 0,
+            source_line,
+        });
+        for (grapheme, cells) in graphemes {
+            let line = &lines[lines.len() - 1];
+            let line_bytes = byte - line.start_byte;
+            // A wide grapheme that would land on the last column moves to the next line, leaving
+            // that column blank, rather than being split between lines.
+            // If it doesn't fit even on an empty line, it keeps the line to itself instead of
+            // leaving an empty line in front of it.
+            let overflows = cells_in_line > line.indent && cells_in_line + cells > width;
+            if grapheme == "\n" {
+                // Changes made at the line feed carry on into the next line
+                apply_attrs_before(attrs, &mut attr_idx, &mut attributes, byte + 1);
+                indent = indent_for(byte + 1);
+                source_line += 1;
+                // The line after the chunk's last line feed starts the next chunk
+                if byte + 1 < end || end == text.len() {
+                    lines.push(Line {
+                        start_byte: byte + 1,
+                        start_attributes: attributes.clone(),
+                        indent: 0,
+                        source_line,
+                    });
+                }
+                cells_in_line = 0;
+            } else {
+                if overflows || line_bytes >= MAX_LINE_BYTES {
+                    lines.push(Line {
+                        start_byte: byte,
+                        start_attributes: attributes.clone(),
+                        indent,
+                        source_line,
+                    });
+                    cells_in_line = indent;
+                }
+                apply_attrs_before(attrs, &mut attr_idx, &mut attributes, byte + 1);
+                cells_in_line += cells;
+            }
+            byte += grapheme.len();
+        }
+        lines
+    }
+}
+
+// The cells taken by the spaces and any list bullet like "- " or "1. " at the start of line.
+// Rows a line wraps onto are indented by this much so they line up with its text.
+fn measure_hanging_indent(line: &str, widths: CellWidths) -> usize {
+    let rest = line.trim_start_matches(' ');
+    let after_bullet = ["- ", "* ", "+ ", "• "]
+        .iter()
+        .find_map(|bullet| rest.strip_prefix(bullet))
+        .or_else(|| {
+            let number = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+            if number.len() == rest.len() {
+                return None;
+            }
+            number
+                .strip_prefix(". ")
+                .or_else(|| number.strip_prefix(") "))
+        })
+        .unwrap_or(rest);
+    let text = after_bullet.trim_start_matches(' ');
+    widths.text(&line[..line.len() - text.len()])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::doc::ParseOptions;
+
+    #[test]
+    fn flow_breaks_oversized_lines() {
+        let text = "a".repeat(MAX_LINE_BYTES * 2 + 1);
+        let lines = DocumentView::flow(usize::MAX, &text, &[], CellWidths::default(), false);
+        assert_eq!(3, lines.len());
+        assert_eq!(MAX_LINE_BYTES, lines[1].start_byte);
+        assert_eq!(MAX_LINE_BYTES * 2, lines[2].start_byte);
+    }
+
+    #[test]
+    fn fit_on_one_screen() {
+        let fits = |input: &str, width, height| {
+            let doc = Document::new(
+                Box::new(Cursor::new(input.to_string())),
+                ParseOptions::default(),
+            )
+            .unwrap();
+            DocumentView::fits(&doc, width, height, &ViewOptions::default())
+        };
+        assert!(fits("a\nb\n", 10, 2));
+        assert!(fits("a\nb", 10, 2));
+        assert!(!fits("a\nb\nc", 10, 2));
+        // Wrapped lines take a row for each part
+        assert!(!fits("abcdef\nb\n", 4, 2));
+        assert!(fits("", 10, 1));
+    }
+
+    #[test]
+    fn flow_zero_width_graphemes() {
+        // The zero width space gets a cell of its own
+        assert_eq!(vec![0, 4], line_starts("a\u{200b}b", 2));
+        // A combining mark at the start of a line does too
+        assert_eq!(vec![0, 2, 4], line_starts("a\n\u{301}b", 1));
+    }
+
+    #[test]
+    fn flow_combining_marks_at_boundary() {
+        // e followed by a combining acute accent stays one grapheme on the first line
+        let accented = "e\u{301}";
+        let text = format!("ab{accented}d");
+        assert_eq!(vec![0, 2 + accented.len()], line_starts(&text, 3));
+        assert_eq!(vec![0, 2], line_starts(&text, 2));
+        // Lots of marks piled on one base still don't wrap on their own
+        let zalgo = format!("a{}", "\u{301}\u{302}\u{303}".repeat(10));
+        assert_eq!(vec![0, zalgo.len()], line_starts(&format!("{zalgo}b"), 1));
+    }
+
+    #[test]
+    fn flow_ambiguous_width() {
+        let text = "±±±";
+        let narrow = DocumentView::flow(4, text, &[], CellWidths::default(), false);
+        assert_eq!(1, narrow.len());
+        let mut wide = CellWidths::default();
+        wide.unicode_version.ambiguous_are_wide = true;
+        let lines = DocumentView::flow(4, text, &[], wide, false);
+        assert_eq!(2, lines.len());
+        assert_eq!("±±".len(), lines[1].start_byte);
+    }
+
+    fn line_starts(text: &str, width: usize) -> Vec<usize> {
+        DocumentView::flow(width, text, &[], CellWidths::default(), false)
+            .iter()
+            .map(|l| l.start_byte)
+            .collect()
+    }
+
+    #[test]
+    fn flow_wide_graphemes_at_boundary() {
+        // Each of these takes two cells and only one is left on the first line
+        for wide in ["中", "\u{1f44d}", "\u{1f469}\u{200d}\u{1f4bb}"] {
+            let text = format!("ab{wide}c");
+            assert_eq!(vec![0, 2], line_starts(&text, 3), "{}", wide);
+            // It fits exactly at width 4
+            assert_eq!(vec![0, 2 + wide.len()], line_starts(&text, 4), "{}", wide);
+        }
+    }
+
+    #[test]
+    fn flow_hanging_indents() {
+        let text = "  - abcdefgh\n1. xyz uvw\nplain text here\n      too indented";
+        let lines: Vec<(usize, usize)> =
+            DocumentView::flow(8, text, &[], CellWidths::default(), true)
+                .iter()
+                .map(|l| (l.start_byte, l.indent))
+                .collect();
+        assert_eq!(
+            vec![
+                (0, 0),
+                (8, 4),
+                (13, 0),
+                (21, 3),
+                (24, 0),
+                (32, 0),
+                (40, 0),
+                (48, 0),
+                (56, 0)
+            ],
+            lines
+        );
+
+        let widths = CellWidths::default();
+        assert_eq!(4, measure_hanging_indent("10) x", widths));
+        assert_eq!(4, measure_hanging_indent(" •  x", widths));
+        assert_eq!(1, measure_hanging_indent(" -x", widths));
+        assert_eq!(0, measure_hanging_indent("1.x", widths));
+    }
+
+    // Every row from the first to the last, walking them one at a time
+    fn walk_rows(view: &mut DocumentView) -> Vec<(usize, usize, usize, CellAttributes)> {
+        let mut rows = vec![];
+        let mut row = Some(view.first_row);
+        while let Some(r) = row {
+            let line = view.line(r);
+            rows.push((
+                line.start_byte,
+                line.indent,
+                line.source_line,
+                line.start_attributes.clone(),
+            ));
+            row = view.next_row(r);
+        }
+        rows
+    }
+
+    fn flowed_chunks(view: &DocumentView) -> usize {
+        view.chunks.iter().filter(|c| !c.lines.is_empty()).count()
+    }
+
+    #[test]
+    fn flow_in_chunks() {
+        let input: String = (0..6_000)
+            .map(|i| match i % 6 {
+                0 => format!("\x1b[1mbold {i}\x1b[m\n"),
+                1 => format!("  - item {i} that wraps onto more than one row\n"),
+                2 => format!("中文{i}中文字符\n"),
+                // Changes at the line feed carry on into the next line
+                3 => format!("plain {i} \x1b[4m\n"),
+                4 if i == 4_504 => format!("{}\x1b[m\n", "long ".repeat(CHUNK_BYTES / 4)),
+                _ => "\x1b[m\n".to_string(),
+            })
+            .collect();
+        let doc = Document::new(Box::new(Cursor::new(input)), ParseOptions::default()).unwrap();
+        let options = ViewOptions {
+            hanging_indent: true,
+            ..ViewOptions::default()
+        };
+        let whole: Vec<_> = DocumentView::flow(20, &doc.text, &doc.attrs, options.widths, true)
+            .into_iter()
+            .map(|l| (l.start_byte, l.indent, l.source_line, l.start_attributes))
+            .collect();
+        let mut view = DocumentView::new(Rc::new(doc), 20, 10, &options);
+        assert!(view.chunks.len() > 2);
+        assert_eq!(whole, walk_rows(&mut view));
+
+        // Walking back flows the chunks the same way
+        view.set_size(30, 10);
+        view.set_size(20, 10);
+        view.show_end();
+        let mut backwards = vec![view.top_byte()];
+        let mut row = view.top;
+        while let Some(prev) = view.prev_row(row) {
+            backwards.push(view.line(prev).start_byte);
+            row = prev;
+        }
+        backwards.reverse();
+        let starts: Vec<usize> = whole.iter().map(|l| l.0).collect();
+        assert_eq!(starts[..backwards.len()], backwards);
+    }
+
+    #[test]
+    fn flow_only_what_is_shown() {
+        let text: String = (0..100_000)
+            .map(|i| format!("line {i:06} {}\n", "x".repeat(40)))
+            .collect();
+        let line_start = |number: usize| (number - 1) * "line 000000 \n".len() + (number - 1) * 40;
+        let doc = Document {
+            line_count: 100_000,
+            text,
+            ..Document::default()
+        };
+        let mut view = DocumentView::new(Rc::new(doc), 80, 10, &ViewOptions::default());
+        assert!(view.chunks.len() > 50);
+        // The first page and the last, to know how far scrolling goes
+        assert_eq!(2, flowed_chunks(&view));
+        assert_eq!(0, view.percent());
+
+        view.show_source_line(50_001);
+        assert_eq!(line_start(50_001), view.top_byte());
+        assert_eq!(50, view.percent());
+        assert_eq!(3, flowed_chunks(&view));
+
+        // Only the ends and what's shown are flowed again at a new width, keeping the top line
+        view.set_size(40, 10);
+        assert_eq!(3, flowed_chunks(&view));
+        assert_eq!(line_start(50_001), view.top_byte());
+        view.forward(1);
+        assert_eq!(line_start(50_001) + 40, view.top_byte());
+
+        // Going back past the start of a chunk flows the one before it
+        let chunk_start = view.chunks[10].start_byte;
+        view.show_byte(chunk_start);
+        view.backward(1);
+        assert_eq!(chunk_start - 13, view.top_byte());
+
+        view.show_end();
+        assert_eq!(100, view.percent());
+        view.show_percent(25);
+        assert_eq!(25, view.percent());
+        assert!(flowed_chunks(&view) <= 6);
+    }
+
+    #[test]
+    fn keep_chunks_as_the_document_grows() {
+        let lines = |count: usize| -> String {
+            (0..count)
+                .map(|i| format!("line {i:06} {}\n", "x".repeat(40)))
+                .collect()
+        };
+        let doc = |text: String, unchanged_before: usize| {
+            Rc::new(Document {
+                text,
+                unchanged_before,
+                ..Document::default()
+            })
+        };
+        let mut view = DocumentView::new(doc(lines(10_000), 0), 20, 10, &ViewOptions::default());
+        let middle = view.chunks[3].start_byte;
+        view.show_byte(middle);
+        view.show_byte(0);
+        assert!(!view.chunks[3].lines.is_empty());
+
+        view.update(doc(lines(20_000), lines(10_000).len()));
+        assert!(!view.chunks[3].lines.is_empty());
+        assert_eq!(0, view.top_byte());
+        view.show_end();
+        assert_eq!(100, view.percent());
+        assert_eq!(lines(20_000).len(), view.visible_bytes().end);
+
+        // Anything rewritten before a chunk's end flows it again
+        let mut changed = lines(20_000);
+        changed.replace_range(middle..middle + 4, "LINE");
+        view.update(doc(changed, middle));
+        assert!(!view.chunks[0].lines.is_empty());
+        assert!(view.chunks[3].lines.is_empty());
+        view.show_byte(middle);
+        assert_eq!("LINE", &view.doc.text[view.top_byte()..view.top_byte() + 4]);
+    }
+
+    #[test]
+    fn flow_graphemes_wider_than_line() {
+        assert_eq!(vec![0, 3, 6], line_starts("中中a", 1));
+        assert_eq!(vec![0], line_starts("中", 1));
+    }
+}
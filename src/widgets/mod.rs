@@ -366,6 +366,11 @@ impl<'widget, State> Ui<'widget, State> {
         self.focused = Some(id);
     }
 
+    /// The state shared with the widgets.
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
     /// Helper for applying the surfaces from the widgets to the target
     /// screen in the correct order (from the root to the leaves)
     fn render_recursive(
@@ -391,7 +396,7 @@ impl<'widget, State> Ui<'widget, State> {
                 abs_coords.x + render_data.coordinates.x,
                 abs_coords.y + render_data.coordinates.y,
             );
-            surface.flush_changes_older_than(SequenceNo::max_value());
+            surface.flush_changes_older_than(SequenceNo::MAX);
             render_data.coordinates
         };
 
@@ -416,7 +421,7 @@ impl<'widget, State> Ui<'widget, State> {
         let mut changed = false;
 
         // Clippy is dead wrong about this iterator being an identity_conversion
-        #[cfg_attr(feature = "cargo-clippy", allow(clippy::identity_conversion))]
+        #[allow(clippy::useless_conversion)]
         for result in layout.compute_constraints(width, height, root)? {
             let render_data = self.render.get_mut(&result.widget).unwrap();
             let coords = ParentRelativeCoords::new(result.rect.x, result.rect.y);